@@ -0,0 +1,71 @@
+// Benchmarks for what live sessions spend most of their
+// time on, run with `cargo bench`. Cities are generated
+// from the fixtures and stepped a few months first, so
+// the market and tenants are as they'd be mid-session
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use doma_sim::config::{self, Config};
+use doma_sim::generate;
+use doma_sim::sim::Simulation;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const FIXTURES: [&str; 2] = ["small", "large"];
+const WARMUP_STEPS: usize = 3;
+
+fn fixture(name: &str) -> Simulation {
+    let params = generate::load_params(&format!("benches/fixtures/{}.yaml", name));
+    let conf: Config = config::load_config("config.yaml");
+    let mut rng: StdRng = SeedableRng::seed_from_u64(conf.seed);
+    let mut sim = Simulation::new(generate::generate(&params), conf, &mut rng);
+    for _ in 0..WARMUP_STEPS {
+        sim.step();
+    }
+    sim
+}
+
+fn hot_paths(c: &mut Criterion) {
+    for name in FIXTURES {
+        let mut sim = fixture(name);
+        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+
+        // Every tenant scoring one unit
+        let unit = &sim.city.units[sim.city.vacant_units().first().cloned().unwrap_or_default()];
+        c.bench_with_input(BenchmarkId::new("tenant_desirability", name), &sim, |b, sim| {
+            b.iter(|| sim.tenants.iter().fold(0., |acc, t| acc + t.desirability(unit, &sim.city, &sim.conf)))
+        });
+
+        // Every tenant deciding where to live, one after another
+        c.bench_with_input(BenchmarkId::new("tenant_consider", name), &sim, |b, sim| {
+            b.iter(|| {
+                for tenant in sim.tenants.iter().filter(|t| !t.departed) {
+                    tenant.consider(&sim.city, sim.time, None, &mut rng, &sim.conf);
+                }
+            })
+        });
+
+        // Every landlord surveying the market's rents
+        c.bench_function(&format!("landlord_estimate_rents/{}", name), |b| {
+            b.iter(|| {
+                let noise = sim.conf.market_noise;
+                for landlord in &mut sim.landlords {
+                    landlord.estimate_rents(&sim.city, &sim.market, &mut rng, noise);
+                }
+            })
+        });
+
+        // The whole state, as synced to the frontend
+        #[cfg(feature = "play")]
+        c.bench_with_input(BenchmarkId::new("sync_jsonify", name), &sim, |b, sim| {
+            b.iter(|| doma_sim::sync::jsonify(sim.time, &sim.city, &sim.tenants, &sim.design, serde_json::Value::Null))
+        });
+
+        c.bench_function(&format!("step/{}", name), |b| b.iter(|| sim.step()));
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = hot_paths
+}
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+// Records the commit the binary is built from, so
+// runs can say which code they ran with. Uncommitted
+// changes are marked, as the commit alone won't have them
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+fn main() {
+    let hash = match (git(&["rev-parse", "--short", "HEAD"]), git(&["status", "--porcelain", "--untracked-files=no"])) {
+        (Some(hash), Some(changes)) if !changes.is_empty() => format!("{}-dirty", hash),
+        (Some(hash), _) => hash,
+        (None, _) => "unknown".to_string(),
+    };
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+}
@@ -1,49 +1,168 @@
-use super::city::{City, Parcel, Unit};
+use super::arena::{Arena, TenantId, UnitId};
+use super::city::{City, Unit};
 use super::config::Config;
+use super::employment::Employment;
+use super::events::SimEvent;
+use super::finance::Mortgage;
 use super::grid::Position;
-use fnv::FnvHashMap;
+use super::market::{noisy, MarketStats};
+use super::screening::ScreeningConfig;
+use super::social::Hearsay;
 use linreg::linear_regression;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 use strum_macros::Display;
 
-fn distance(a: Position, b: Position) -> f32 {
-    (((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)) as f32).sqrt()
-}
-
-#[derive(Display, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Display, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum AgentType {
     Tenant,
     Landlord,
     DOMA,
+    Developer,
+    Speculator,
 }
 
+// A tenant's or household's
+// housing decision for a step
 #[derive(Debug)]
+pub struct Decision {
+    // Whether they've fallen behind on rent,
+    // if that was assessed this step
+    pub in_arrears: Option<bool>,
+    pub current_desirability: f32,
+    pub moving_penalty: f32,
+
+    // Units they'd move into
+    // and their desirability, best first
+    pub candidates: Vec<(UnitId, f32)>,
+
+    // Units they heard of through their ties
+    pub referrals: Vec<UnitId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Tenant {
-    pub id: usize,
+    pub id: TenantId,
     pub income: f32, // monthly
-    pub unit: Option<usize>,
+    pub wage: f32, // monthly, when employed
+    pub employed: bool,
+    pub savings: f32,
+    pub unit: Option<UnitId>,
     pub work: Position,
-    pub units: Vec<usize>,
+
+    // Index of the employment center at `work`
+    #[serde(default)]
+    pub center: usize,
+    pub units: Vec<UnitId>,
     pub last_dividend: f32,
-    pub player: bool
+    pub player: bool,
+
+    // Share of each month's savings a player
+    // has pledged to DOMA's funding rounds
+    pub pledge: f32,
+
+    // Months behind on rent
+    pub arrears: usize,
+
+    // Whether or not the tenant
+    // has an eviction on their record
+    pub evicted: bool,
+
+    // Roommate household, if any,
+    // and this tenant's share of the household's
+    // rent relative to an even split
+    pub household: Option<usize>,
+    pub rent_weight: f32,
+
+    // Months lived in their
+    // current neighborhood
+    pub tenure: usize,
+
+    // Months without a place to live, and
+    // whether they've given up and left the city.
+    // Tenants who leave keep their id, which isn't reused
+    pub months_homeless: usize,
+    pub departed: bool,
+
+    // Neighborhood of the shelter
+    // they're staying in while homeless
+    pub shelter: Option<usize>,
+
+    // Housing voucher, if they've been issued one
+    pub voucher: Option<Voucher>,
+
+    // Whether they heard of their
+    // current place through their ties
+    pub referred: bool,
+
+    // Which of `TENANT_GROUPS` they're in, if any
+    pub group: Option<usize>,
+}
+
+// A housing voucher's terms, as issued
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Voucher {
+    pub rent_burden: f32,
+    pub max_subsidy: f32,
+}
+
+impl Voucher {
+    // What the voucher pays toward a rent: whatever's
+    // over the tenant's share of income, up to the cap
+    pub fn subsidy(&self, rent: f32, income: f32) -> f32 {
+        f32::min(self.max_subsidy, f32::max(0., rent - self.rent_burden * income))
+    }
 }
 
 impl Tenant {
-    pub fn step(
-        &mut self,
-        city: &mut City,
+    pub fn new(id: TenantId, income: f32, savings: f32, work: Position) -> Tenant {
+        Tenant {
+            id: id,
+            unit: None,
+            units: Vec::new(),
+            income: income,
+            wage: income,
+            employed: true,
+            savings: savings,
+            work: work,
+            center: 0,
+            last_dividend: 0.,
+            player: false,
+            pledge: 0.,
+            arrears: 0,
+            evicted: false,
+            household: None,
+            rent_weight: 1.,
+            tenure: 0,
+            months_homeless: 0,
+            departed: false,
+            shelter: None,
+            voucher: None,
+            referred: false,
+            group: None,
+        }
+    }
+
+    // Decide where to live against a snapshot
+    // of the city. This doesn't modify anything,
+    // so tenants can decide in parallel
+    pub fn consider(
+        &self,
+        city: &City,
         month: usize,
-        vacant_units: &mut Vec<usize>,
+        hearsay: Option<&Hearsay>,
         rng: &mut StdRng,
         conf: &Config,
-    ) {
+    ) -> Decision {
         let mut reconsider;
         let mut current_desirability = 0.;
-        let mut moving_penalty = conf.moving_penalty;
+        let mut moving_penalty = conf.tenant_search.moving_penalty(conf);
+        let mut in_arrears = None;
 
         match self.unit {
             // If currently w/o home,
@@ -59,104 +178,195 @@ impl Tenant {
             // between leases or if their current
             // place is no longer affordable
             Some(u_id) => {
-                let unit = &mut city.units[u_id];
-                let elapsed = if month > unit.lease_month {
-                    month - unit.lease_month
-                } else {
-                    0
-                };
-                reconsider = elapsed > 0 && elapsed % 12 == 0;
+                let unit = &city.units[u_id];
+                reconsider = unit.lease_up(month);
                 if !reconsider {
-                    // No longer can afford
-                    let parcel = &city.parcels.get(&unit.pos).unwrap();
-                    current_desirability = self.desirability(unit, parcel);
-                    if current_desirability == 0. {
-                        reconsider = true;
-                        unit.tenants.remove(&self.id);
-                        vacant_units.push(u_id);
-                        self.unit = None;
-                    }
+                    // No longer can afford,
+                    // so they fall behind on rent
+                    // and look for somewhere cheaper
+                    current_desirability = self.desirability(unit, city, conf);
+                    reconsider = current_desirability == 0.;
+                    in_arrears = Some(reconsider);
                 }
             }
         }
-        if reconsider && vacant_units.len() > 0 {
-            let sample = vacant_units.choose_multiple(rng, conf.tenant_sample_size);
-            let (best_id, best_desirability) = sample.fold((0, 0.), |acc, &u_id| {
+
+        let mut candidates = Vec::new();
+        let referrals = hearsay.map_or_else(Vec::new, |hearsay| hearsay.referrals.clone());
+        if reconsider && city.vacant_units().len() > 0 {
+            let sample = conf.tenant_search.sample(&referrals, self.work, city, rng, conf);
+            for u_id in sample {
                 let u = &city.units[u_id];
-                let p = &city.parcels.get(&u.pos).unwrap();
-                if u.vacancies() <= 0 {
-                    acc
-                } else {
-                    let desirability = if conf.debug && u.is_doma() {
-                        // If playing (i.e. debug=false), bots
-                        // leave DOMA units for players to choose from
-                        0.
-                    } else {
-                        self.desirability(u, p)
-                    };
-                    if desirability > acc.1 {
-                        (u_id, desirability)
-                    } else {
-                        acc
-                    }
+                if u.vacancies() == 0 || self.savings < self.deposit(u, conf) {
+                    continue;
                 }
-            });
-            if best_desirability > 0. && best_desirability - moving_penalty > current_desirability {
-                match self.unit {
-                    Some(u_id) => {
-                        let unit = &mut city.units[u_id];
-                        unit.tenants.remove(&self.id);
-                        vacant_units.push(u_id);
-                    }
-                    None => {}
+                let desirability = match (hearsay, &conf.social) {
+                    (Some(hearsay), Some(social)) => hearsay.weigh(self.score(u, city, conf), u, city, social),
+                    _ => self.score(u, city, conf)
+                };
+                if desirability > 0. {
+                    candidates.push((u_id, desirability));
+                }
+                if conf.tenant_search.satisfied(desirability) {
+                    break;
                 }
+            }
 
-                self.unit = Some(best_id);
-                let unit = &mut city.units[best_id];
+            // Best first; other tenants may take
+            // the best unit before this tenant gets to it
+            candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        }
 
-                // If unit was vacant, this is a new lease
-                if unit.vacant() {
-                    unit.lease_month = month % 12;
-                }
+        Decision {
+            in_arrears: in_arrears,
+            current_desirability: current_desirability,
+            moving_penalty: moving_penalty,
+            candidates: candidates,
+            referrals: referrals,
+        }
+    }
+
+    pub fn update_arrears(&mut self, decision: &Decision) {
+        match decision.in_arrears {
+            Some(true) => self.arrears += 1,
+            Some(false) => self.arrears = 0,
+            None => {}
+        }
+    }
 
-                unit.tenants.insert(self.id);
+    pub fn update_income(&mut self, employment: &Employment, income_growth: f32, rng: &mut StdRng, conf: &Config) {
+        // Wages follow the economy
+        self.wage *= 1. + conf.time_step.growth(income_growth);
 
-                // Remove unit if it no longer has
-                // any vacancies
-                if unit.vacancies() == 0 {
-                    vacant_units.retain(|&u_id| u_id != best_id);
-                }
+        let step = conf.time_step;
+        let layoff_prob = step.chance(conf.layoff_prob);
+        let job_change_prob = step.chance(conf.job_change_prob);
+        let roll: f32 = rng.gen();
+        if self.employed {
+            if roll < layoff_prob + employment.cut(self.center) {
+                self.employed = false;
+            } else if roll < layoff_prob + job_change_prob {
+                // New job elsewhere, usually for better pay
+                let (center, work) = employment.job(rng);
+                self.center = center;
+                self.work = work;
+                self.wage *= 1. + rng.gen_range(-conf.raise_mean, 3. * conf.raise_mean);
+            } else if roll < layoff_prob + job_change_prob + step.chance(conf.raise_prob) {
+                self.wage *= 1. + rng.gen_range(0., 2. * conf.raise_mean);
             }
+        } else if roll < step.chance(conf.rehire_prob) {
+            // Find a new job, often at lower pay
+            self.employed = true;
+            let (center, work) = employment.job(rng);
+            self.center = center;
+            self.work = work;
+            self.wage *= 1. - rng.gen_range(0., 2. * conf.raise_mean);
+        }
+
+        self.income = if self.employed {
+            self.wage
+        } else {
+            self.wage * conf.unemployment_benefit
+        };
+
+        // Without a home it's harder to hold
+        // down work, if less so from a shelter
+        let penalty = match (self.unit, self.shelter) {
+            (Some(_), _) => 0.,
+            (None, Some(_)) => conf.sheltered_income_penalty,
+            (None, None) => conf.homeless_income_penalty,
+        };
+        self.income *= 1. - penalty;
+    }
+
+    // Desirability of a unit the tenant is considering
+    // moving into, accounting for how landlords see them
+    pub fn score(&self, unit: &Unit, city: &City, conf: &Config) -> f32 {
+        if conf.debug && unit.is_doma() {
+            // If playing (i.e. debug=false), bots
+            // leave DOMA units for players to choose from
+            0.
+        } else if self.evicted && unit.owner.0 == AgentType::Landlord {
+            // Landlords are wary of tenants
+            // with an eviction record
+            self.desirability(unit, city, conf) * (1. - conf.eviction_penalty)
+        } else {
+            self.desirability(unit, city, conf)
+        }
+    }
+
+    // Returns what's set aside for DOMA,
+    // if the tenant's player pledged any
+    pub fn update_savings(&mut self, city: &City, rng: &mut StdRng, conf: &Config) -> f32 {
+        let rent = match self.unit {
+            Some(u_id) => self.adjusted_rent(&city.units[u_id]),
+            None => 0.
+        };
+        let saved = (self.income - rent) * conf.savings_rate * conf.time_step.months();
+        let pledged = if self.player && saved > 0. { saved * self.pledge } else { 0. };
+        self.savings += saved - pledged;
+
+        // Unexpected expenses, e.g. medical bills,
+        // which overcrowding makes more likely
+        let health_factor = match (&conf.overcrowding, self.unit) {
+            (Some(overcrowding), Some(u_id)) => 1. + overcrowding.health_risk * city.units[u_id].overcrowding(conf) as f32,
+            _ => 1.
+        };
+        let roll: f32 = rng.gen();
+        if roll < conf.time_step.chance(conf.emergency_prob * health_factor) {
+            self.savings -= self.income * conf.emergency_cost;
         }
+        self.savings = f32::max(0., self.savings);
+        pledged
     }
 
+    // Security deposit required to move into a unit
+    pub fn deposit(&self, unit: &Unit, conf: &Config) -> f32 {
+        self.adjusted_rent(unit) * conf.deposit_months
+    }
+
+    // Rent less any voucher subsidy
     pub fn adjusted_rent(&self, unit: &Unit) -> f32 {
-        // let rent_per_tenant = f32::max(1., unit.rent / unit.tenants.len() as f32);
-        let rent_per_tenant = f32::max(1., unit.rent / unit.occupancy as f32);
-        rent_per_tenant - f32::min(rent_per_tenant, self.last_dividend)
+        let rent = self.rent_share(unit);
+        rent - self.voucher_subsidy(unit, rent)
     }
 
-    pub fn desirability(&self, unit: &Unit, parcel: &Parcel) -> f32 {
-        let n_tenants = (unit.tenants.len() + 1) as f32;
+    // Vouchers are only good where the owner takes them
+    pub fn voucher_subsidy(&self, unit: &Unit, rent: f32) -> f32 {
+        match self.voucher {
+            Some(voucher) if unit.accepts_vouchers => voucher.subsidy(rent, self.income),
+            _ => 0.
+        }
+    }
 
-        // Adjust rent by last DOMA dividend
-        // let rent_per_tenant = f32::max(1., unit.rent / n_tenants);
-        let rent_per_tenant = f32::max(1., unit.rent / unit.occupancy as f32);
-        let adjusted_rent_per_tenant = rent_per_tenant - f32::min(rent_per_tenant, self.last_dividend);
+    // The tenant's share of the rent,
+    // less any DOMA dividend
+    pub fn rent_share(&self, unit: &Unit) -> f32 {
+        // let rent_per_tenant = f32::max(1., unit.rent / unit.tenants.len() as f32);
+        let rent_per_tenant = f32::max(1., unit.rent / unit.occupancy as f32 * self.rent_weight);
 
-        // Tenants can spend at most 1/3 of income on rent
-        if self.income/3. < adjusted_rent_per_tenant {
-            0.
+        // Tenants of DOMA units become members,
+        // so can count on what members there get
+        let dividend = if unit.is_doma() {
+            f32::max(self.last_dividend, unit.dividend)
         } else {
-            let ratio = (self.income / adjusted_rent_per_tenant).sqrt();
-            let spaciousness = f32::max(unit.area / n_tenants, 0.).powf(1. / 32.);
-            let commute_distance = distance(self.work, unit.pos);
-            let commute: f32 = if commute_distance == 0. {
-                1.
-            } else {
-                1. / commute_distance
-            };
-            ratio * (spaciousness + parcel.desirability + unit.condition + commute)
+            self.last_dividend
+        };
+        rent_per_tenant - f32::min(rent_per_tenant, dividend)
+    }
+
+    pub fn desirability(&self, unit: &Unit, city: &City, conf: &Config) -> f32 {
+        let desirability = conf.tenant_model.model().desirability(self, unit, city);
+
+        // Less so if they'd be packed in
+        // beyond what the space allows
+        match &conf.overcrowding {
+            Some(overcrowding) => {
+                let n_tenants = unit.tenants.len() + if unit.tenants.contains(&self.id) { 0 } else { 1 };
+                let excess = overcrowding.excess(unit, n_tenants);
+                desirability * (1. - overcrowding.desirability_penalty).max(0.).powi(excess as i32)
+            }
+            None => desirability
         }
     }
 
@@ -164,7 +374,7 @@ impl Tenant {
         &mut self,
         city: &mut City,
         price_to_rent_ratio: f32,
-    ) -> Vec<(AgentType, usize, usize, f32)> {
+    ) -> Vec<(AgentType, usize, UnitId, f32)> {
         // If they own units,
         // check purchase offers
         let mut transfers = Vec::new();
@@ -196,7 +406,14 @@ impl Tenant {
                 if best_amount > 0. {
                     unit.value = best_amount;
                     unit.owner = (typ, landlord);
+                    unit.recently_sold = true;
                     transfers.push((typ, landlord, u, best_amount));
+                    city.events.push(SimEvent::UnitSold {
+                        unit: u,
+                        seller: (AgentType::Tenant, self.id.0),
+                        buyer: (typ, landlord),
+                        price: best_amount,
+                    });
                 }
             }
 
@@ -211,21 +428,137 @@ impl Tenant {
     }
 }
 
-#[derive(Debug)]
+// Kinds of landlords, which run
+// their units in different ways
+#[derive(Display, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Archetype {
+    // Small landlords who keep rents
+    // steady and look after their units
+    MomAndPop,
+
+    // Push rents as high as the market allows
+    Corporate,
+
+    // Let units fall apart
+    Slumlord,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ArchetypeConfig {
+    // Share of landlords of this kind
+    pub share: f32,
+
+    // Scales what's spent on maintenance,
+    // e.g. 0 for none at all
+    pub upkeep: f32,
+
+    // Scales how far rents can be raised
+    // at renewal, i.e. `RENT_INCREASE_RATE`
+    pub rent_increase_multiple: f32,
+
+    // Asking rent for vacant units,
+    // relative to comparable units
+    pub asking_rent_multiple: f32,
+
+    // Whether they take housing vouchers
+    pub accepts_vouchers: bool,
+
+    // Units sampled per neighborhood when looking
+    // to buy, if not `SAMPLE_SIZE`
+    #[serde(default)]
+    pub sample_size: Option<usize>,
+
+    // Who they turn away when choosing tenants
+    #[serde(default)]
+    pub screening: ScreeningConfig,
+}
+
+impl Default for ArchetypeConfig {
+    fn default() -> ArchetypeConfig {
+        ArchetypeConfig {
+            share: 1.,
+            upkeep: 1.,
+            rent_increase_multiple: 1.,
+            asking_rent_multiple: 1.,
+            accepts_vouchers: true,
+            sample_size: None,
+            screening: ScreeningConfig::default(),
+        }
+    }
+}
+
+// Archetypes for `n` landlords in the configured proportions.
+// They're dealt out in order rather than sampled, so the
+// proportions hold for few landlords and don't use the RNG
+pub fn assign_archetypes(n: usize, archetypes: &BTreeMap<Archetype, ArchetypeConfig>) -> Vec<Archetype> {
+    let total: f32 = archetypes.values().map(|a| a.share).sum();
+    (0..n).map(|i| {
+        let p = (i as f32 + 0.5) / n as f32 * total;
+        let mut cumulative = 0.;
+        for (&archetype, conf) in archetypes {
+            cumulative += conf.share;
+            if p < cumulative {
+                return archetype;
+            }
+        }
+        archetypes.keys().last().cloned().unwrap_or(Archetype::MomAndPop)
+    }).collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Landlord {
     pub id: usize,
-    pub units: Vec<usize>,
+    pub archetype: Archetype,
+    pub units: Vec<UnitId>,
+
+    // Baseline maintenance effort,
+    // and this month's budget per unit
     pub maintenance: f32,
-    pub rent_obvs: FnvHashMap<usize, Vec<f32>>,
-    pub trend_ests: FnvHashMap<usize, f32>,
-    pub invest_ests: FnvHashMap<usize, f32>,
+    pub unit_maintenance: BTreeMap<UnitId, f32>,
+    pub rent_obvs: BTreeMap<usize, Vec<f32>>,
+    pub trend_ests: BTreeMap<usize, f32>,
+    pub invest_ests: BTreeMap<usize, f32>,
+    pub cash: f32,
+    pub mortgages: Vec<Mortgage>,
+
+    // Number of units this step where
+    // the rent increase was limited by rent control
+    pub n_rent_capped: usize,
+
+    // Number of units foreclosed on this step
+    pub n_foreclosures: usize,
+
+    // Number of units subdivided, merged
+    // away, and retrofitted this step
+    pub n_subdivided: usize,
+    pub n_merged: usize,
+    pub n_retrofitted: usize,
+
+    // Last month's net income per unit,
+    // and consecutive months each unit lost money
+    pub net_income: BTreeMap<UnitId, f32>,
+    pub months_unprofitable: BTreeMap<UnitId, usize>,
+    pub taxes_paid: f32,
+    pub vacancy_tax_paid: f32,
+
+    // Whether a player controls this landlord.
+    // Players set asking rents and maintenance
+    // budgets per unit, which take the place of
+    // the landlord's own decisions, and list units for sale
+    pub player: bool,
+    pub player_rents: BTreeMap<UnitId, f32>,
+    pub player_maintenance: BTreeMap<UnitId, f32>,
+    pub for_sale: Vec<UnitId>,
 }
 
 impl Landlord {
-    pub fn new(id: usize, n_neighborhoods: usize) -> Landlord {
-        let mut rent_obvs = FnvHashMap::default();
-        let mut trend_ests = FnvHashMap::default();
-        let mut invest_ests = FnvHashMap::default();
+    pub fn new(id: usize, archetype: Archetype, n_neighborhoods: usize, cash: f32) -> Landlord {
+        let mut rent_obvs = BTreeMap::default();
+        let mut trend_ests = BTreeMap::default();
+        let mut invest_ests = BTreeMap::default();
         for id in 0..n_neighborhoods {
             rent_obvs.insert(id, Vec::new());
             trend_ests.insert(id, 0.);
@@ -234,62 +567,206 @@ impl Landlord {
 
         Landlord {
             id: id,
+            archetype: archetype,
             units: Vec::new(),
             rent_obvs: rent_obvs,
             trend_ests: trend_ests,
             invest_ests: invest_ests,
             maintenance: 0.01,
+            unit_maintenance: BTreeMap::default(),
+            cash: cash,
+            mortgages: Vec::new(),
+            n_rent_capped: 0,
+            n_foreclosures: 0,
+            n_subdivided: 0,
+            n_merged: 0,
+            n_retrofitted: 0,
+            net_income: BTreeMap::default(),
+            months_unprofitable: BTreeMap::default(),
+            taxes_paid: 0.,
+            vacancy_tax_paid: 0.,
+            player: false,
+            player_rents: BTreeMap::default(),
+            player_maintenance: BTreeMap::default(),
+            for_sale: Vec::new(),
         }
     }
 
+    // Hand decisions back to the landlord
+    pub fn release_player(&mut self) {
+        self.player = false;
+        self.player_rents.clear();
+        self.player_maintenance.clear();
+        self.for_sale.clear();
+    }
+
     pub fn step(
         &mut self,
         city: &mut City,
         month: usize,
-        price_to_rent_ratio: f32,
         rent_freeze: bool,
         market_tax: bool,
+        market: &MarketStats,
         rng: &mut StdRng,
         conf: &Config,
     ) {
         // Update market estimates
-        self.estimate_rents(city, rng, conf.sample_size);
-        self.estimate_trends(conf.trend_months);
+        self.estimate_rents(city, market, rng, conf.market_noise);
+        self.estimate_trends(conf.steps(conf.trend_months));
+        let archetype = conf.landlord_archetypes.get(&self.archetype).cloned().unwrap_or_default();
+
+        // Landlords never cut rent at renewal
+        let max_rent_increase = f32::max(1., 1. + (conf.rent_increase_rate - 1.) * archetype.rent_increase_multiple);
+        for &u in &self.units {
+            city.units[u].accepts_vouchers = archetype.accepts_vouchers;
+        }
 
         // Maintenance
+        let year = conf.year(month);
         for &u in &self.units {
-            let mut unit = &mut city.units[u];
-            let decay: f32 = rng.gen();
-            unit.condition -= decay * 0.1; // TODO deterioration rate based on build year?
-            unit.condition += self.maintenance;
-            unit.condition = f32::min(f32::max(unit.condition, 0.), 1.);
+            let unit = &mut city.units[u];
+
+            // Spend more on units in poor condition
+            // and on vacant units to attract tenants,
+            // but only the baseline on units losing money
+            let budget = match self.player_maintenance.get(&u) {
+                Some(&budget) => budget,
+                None => {
+                    let unprofitable = self.months_unprofitable.get(&u).unwrap_or(&0) > &0;
+                    let mut budget = if unprofitable {
+                        self.maintenance
+                    } else {
+                        f32::max(self.maintenance, conf.target_condition - unit.condition)
+                    };
+                    if unit.vacant() && !unprofitable {
+                        budget += self.maintenance;
+                    }
+                    budget * archetype.upkeep
+                }
+            };
+            let budget = f32::min(budget, conf.max_maintenance);
+            self.unit_maintenance.insert(u, budget);
+
+            let decay: f32 = rng.gen::<f32>() * unit.deterioration(year, conf);
+            let step_months = conf.time_step.months();
+            unit.maintain(step_months * budget, step_months * decay);
         }
 
         // Manage units
+        self.n_rent_capped = 0;
         for &u in &self.units {
-            let mut unit = &mut city.units[u];
-            if unit.vacant() {
+            if city.units[u].short_term {
+                // Return to the long-term market
+                // once short-term rental no longer pays.
+                // Players only rent long-term
+                let unit = &city.units[u];
+                let long_term_rent = comparable_rent(unit, city, market, rng, conf.market_noise).unwrap_or(unit.rent);
+                if self.player || self.short_term_income(unit, city, conf) < long_term_rent {
+                    let unit = &mut city.units[u];
+                    unit.short_term = false;
+                    unit.months_vacant = 0;
+                    unit.rent = long_term_rent;
+                    city.refresh_vacancy(u);
+                }
+            } else if city.units[u].vacant() && self.player_rents.contains_key(&u) {
+                let unit = &mut city.units[u];
+                unit.months_vacant += 1;
+                unit.rent = self.player_rents[&u];
+            } else if city.units[u].vacant() {
+                // Ask what comparable units go for,
+                // coming down the longer it sits vacant
+                let comparable_rent = comparable_rent(&city.units[u], city, market, rng, conf.market_noise);
+
+                // Rent short-term instead if it pays
+                // better, and policy allows it
+                let unit = &city.units[u];
+                if !self.player
+                    && self.short_term_income(unit, city, conf) > comparable_rent.unwrap_or(unit.rent)
+                    && self.short_term_allowed(unit, city, conf) {
+                    city.units[u].short_term = true;
+                    city.refresh_vacancy(u);
+                    city.events.push(SimEvent::ListedShortTerm { unit: u });
+                    continue;
+                }
+
+                let unit = &mut city.units[u];
                 unit.months_vacant += 1;
-                if unit.months_vacant % 2 == 0 {
-                    unit.rent = unit.rent * 0.98;
-                    // TODO u.maintenance += 0.01
+                if let Some(rent) = comparable_rent {
+                    // Coming down by as much again as any vacancy
+                    // tax owed, since renting for less beats paying it
+                    let months_vacant = unit.months_vacant as f32 * conf.time_step.months();
+                    let tax_discount = conf.policies.vacancy_tax(unit, conf.time_step) / (rent * conf.time_step.months());
+                    let discount = f32::min(months_vacant * conf.vacancy_discount + tax_discount, conf.max_vacancy_discount);
+                    unit.rent = rent * archetype.asking_rent_multiple * (1. - discount) * unit.rent_premium(conf);
                 }
             } else {
-                // Year-long leases
-                let elapsed = month as i32 - unit.lease_month as i32;
-                if !rent_freeze && elapsed > 0 && elapsed % 12 == 0 {
-                    // TODO this can be smarter
-                    // i.e. depend on gap b/w
-                    // current rent and rent estimate/projection
-                    unit.rent = unit.rent * conf.rent_increase_rate;
-                    // TODO u.maintenance -= 0.01
+                let unit = &mut city.units[u];
+
+                // Rents change only when the lease is up
+                if !rent_freeze && unit.lease_up(month) {
+                    // Raise rent toward the estimated market rent,
+                    // but never lower it at renewal. Increases are
+                    // annual, so prorated to how often the lease renews
+                    let years = unit.lease.as_ref().map_or(1, |lease| lease.period(month)) as f32
+                        / conf.time_step.steps_per_year() as f32;
+                    let max_rent_increase = max_rent_increase.powf(years);
+                    let parcel = &city.parcels.get(&unit.pos).unwrap();
+                    let market_rent = match parcel.neighborhood {
+                        Some(neighb_id) => self.trend_ests[&neighb_id] * unit.area * unit.rent_premium(conf),
+                        None => 0.
+                    };
+                    // A unit let for nothing has no rent to raise
+                    // by a rate, so goes straight to the player's
+                    if unit.rent <= 0. {
+                        if let Some(&rent) = self.player_rents.get(&u) {
+                            unit.set_rent(rent);
+                        }
+                        continue;
+                    }
+                    let mut rent_increase_rate = if let Some(&rent) = self.player_rents.get(&u) {
+                        rent / unit.rent
+                    } else if market_rent > 0. {
+                        (market_rent / unit.rent).clamp(1., max_rent_increase)
+                    } else {
+                        max_rent_increase
+                    };
+
+                    // Respect rent control, if any
+                    let cap = match parcel.neighborhood {
+                        Some(neighb_id) => conf.policies.max_rent_increase(city.neighborhoods[neighb_id].id as usize),
+                        None => conf.policies.max_rent_increase,
+                    };
+                    if let Some(cap) = cap.map(|cap| cap.powf(years)) {
+                        if cap < rent_increase_rate {
+                            rent_increase_rate = cap;
+                            self.n_rent_capped += 1;
+                        }
+                    }
+                    if rent_increase_rate > 1. {
+                        city.events.push(SimEvent::RentRaised {
+                            unit: u,
+                            from: unit.rent,
+                            to: unit.rent * rent_increase_rate,
+                        });
+                    }
+                    unit.set_rent(unit.rent * rent_increase_rate);
                 }
             }
         }
 
+        self.n_subdivided = 0;
+        self.n_merged = 0;
+        self.n_retrofitted = 0;
+        if !self.player {
+            self.convert_units(city, market, conf);
+            self.retrofit_units(city, &archetype, conf);
+        }
+
+        self.manage_finances(city, conf);
+
         // Make purchase offers
         // Choose random neighborhood weighted by investment potential
-        if !market_tax {
+        if !market_tax && !self.player {
             let neighbs: Vec<usize> = self.invest_ests.keys().cloned().collect();
             let neighb_weights: Vec<f32> = neighbs
                 .iter()
@@ -301,22 +778,282 @@ impl Landlord {
                 let neighb_dist = WeightedIndex::new(&neighb_weights).unwrap();
                 neighbs[neighb_dist.sample(rng)]
             };
-            let est_future_rent = self.trend_ests[&neighb_id];
-            let sample = city.units_by_neighborhood[neighb_id].choose_multiple(rng, conf.sample_size);
+            let sample_size = archetype.sample_size.unwrap_or(conf.sample_size);
+            let sample = city.units_by_neighborhood[neighb_id].choose_multiple(rng, sample_size);
+            let mut committed = 0.;
             for &u_id in sample {
                 let unit = &mut city.units[u_id];
-                let parcel = &city.parcels.get(&unit.pos).unwrap();
-                let est_value =
-                    est_future_rent * unit.area * 12. * price_to_rent_ratio * parcel.desirability; // TODO was *100
+
+                // Skip units we already own
+                // and DOMA units, which are never sold
+                if unit.owner == (AgentType::Landlord, self.id) || unit.is_doma() {
+                    continue;
+                }
+
+                // Buy units worth more than they last went for
+                let est_value = noisy(unit.est_value, conf.market_noise, rng);
                 if est_value > 0. && est_value > unit.value {
+                    // Can only bid if we can cover the down payment
+                    let down_payment = est_value * (1. - conf.mortgage_ltv);
+                    if committed + down_payment > self.cash {
+                        continue;
+                    }
+                    committed += down_payment;
                     unit.offers.push((AgentType::Landlord, self.id, est_value));
                 }
             }
         }
     }
 
-    fn estimate_rents(&mut self, city: &City, rng: &mut StdRng, sample_size: usize) {
-        let mut neighborhoods: FnvHashMap<usize, Vec<f32>> = FnvHashMap::default();
+    // Respond to demand by reshaping vacant units: subdivide
+    // the largest where vacancies are scarce, or merge two in
+    // the same building into a roomier unit where they're
+    // plentiful. Converting renovates the units, and the
+    // landlord takes on at most one conversion a month
+    fn convert_units(&mut self, city: &mut City, market: &MarketStats, conf: &Config) {
+        let mut subdivide: Option<UnitId> = None;
+        let mut merge: Option<(UnitId, UnitId)> = None;
+        let convertible = |unit: &Unit| unit.vacant() && !unit.short_term;
+        for &u in &self.units {
+            let unit = &city.units[u];
+            if !convertible(unit) {
+                continue;
+            }
+            let neighb_id = match city.parcels.get(&unit.pos).unwrap().neighborhood {
+                Some(neighb_id) => neighb_id,
+                None => continue
+            };
+            let vacancy_rate = market.neighborhoods[neighb_id].vacancy_rate;
+            let building = city.buildings.get(&unit.pos).unwrap();
+            if vacancy_rate < conf.subdivide_vacancy_rate {
+                if unit.area >= 2. * conf.min_unit_area
+                    && building.units.len() < city.max_units(&unit.pos)
+                    && subdivide.is_none_or(|s| city.units[s].area < unit.area) {
+                    subdivide = Some(u);
+                }
+            } else if vacancy_rate > conf.merge_vacancy_rate && merge.is_none() {
+                // Mortgaged units are the bank's
+                // collateral, so they aren't merged away
+                if self.mortgages.iter().any(|m| m.unit == u) {
+                    continue;
+                }
+                merge = building.units.iter()
+                    .find(|&&other| other != u && city.units[other].owner == unit.owner && convertible(&city.units[other]))
+                    .map(|&other| (u, other));
+            }
+        }
+
+        if let Some(u) = subdivide {
+            let cost = city.units[u].area * conf.conversion_cost_per_area;
+            if cost <= self.cash {
+                self.cash -= cost;
+                let area = city.units[u].area / 2.;
+                let new_unit = city.subdivide_unit(u, area);
+                city.units[u].condition = city.units[u].max_condition;
+                city.units[new_unit].condition = city.units[new_unit].max_condition;
+                self.units.push(new_unit);
+                self.n_subdivided += 1;
+                city.events.push(SimEvent::UnitSubdivided {
+                    unit: u,
+                    new_unit: new_unit,
+                    landlord: self.id,
+                });
+                return;
+            }
+        }
+
+        if let Some((u, into)) = merge {
+            let cost = (city.units[u].area + city.units[into].area) * conf.conversion_cost_per_area;
+            if cost <= self.cash {
+                self.cash -= cost;
+                city.merge_units(u, into);
+                city.units[into].condition = city.units[into].max_condition;
+                self.units.retain(|&u_id| u_id != u);
+                self.player_rents.remove(&u);
+                self.player_maintenance.remove(&u);
+                self.net_income.remove(&u);
+                self.unit_maintenance.remove(&u);
+                self.months_unprofitable.remove(&u);
+                self.n_merged += 1;
+                city.events.push(SimEvent::UnitsMerged {
+                    unit: u,
+                    into: into,
+                    landlord: self.id,
+                });
+            }
+        }
+    }
+
+    // Retrofit the unit whose rent premium would pay
+    // back soonest, going by the landlord's estimate
+    // of market rents. Like conversions, at most one
+    // a month, and never by landlords who skimp on upkeep
+    fn retrofit_units(&mut self, city: &mut City, archetype: &ArchetypeConfig, conf: &Config) {
+        let retrofit = match &conf.retrofit {
+            Some(retrofit) if archetype.upkeep > 0. => retrofit,
+            _ => return
+        };
+        let best = self.units.iter()
+            .filter_map(|&u| {
+                let unit = &city.units[u];
+                if unit.short_term {
+                    return None;
+                }
+                let neighb_id = city.parcels.get(&unit.pos).unwrap().neighborhood?;
+                let market_rent = self.trend_ests[&neighb_id] * unit.area;
+                retrofit.payback(unit, market_rent).map(|months| (u, months))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((u, _)) = best {
+            let cost = retrofit.cost(&city.units[u]);
+            if cost <= self.cash {
+                self.cash -= cost;
+                retrofit.apply(&mut city.units[u]);
+                self.n_retrofitted += 1;
+                city.events.push(SimEvent::Retrofitted {
+                    unit: u,
+                    owner: (AgentType::Landlord, self.id),
+                    cost: cost,
+                });
+            }
+        }
+    }
+
+    fn manage_finances(&mut self, city: &mut City, conf: &Config) {
+        // Make mortgage payments, except on units
+        // the bank has foreclosed on and is selling
+        let mut payments: BTreeMap<UnitId, f32> = BTreeMap::default();
+        for mortgage in self.mortgages.iter_mut().filter(|m| !m.foreclosed) {
+            payments.insert(mortgage.unit, mortgage.pay(conf.time_step.months()));
+        }
+        self.mortgages.retain(|m| m.principal > 0.);
+
+        // Collect rent and pay operating costs,
+        // tracking net income per unit
+        self.taxes_paid = 0.;
+        self.vacancy_tax_paid = 0.;
+        for &u in &self.units {
+            let unit = &city.units[u];
+            let parcel = &city.parcels.get(&unit.pos).unwrap();
+            let step_months = conf.time_step.months();
+            let revenue = unit.fees + step_months * if unit.short_term {
+                self.short_term_income(unit, city, conf)
+            } else if unit.vacant() {
+                0.
+            } else {
+                unit.rent
+            };
+            let steps_per_year = conf.time_step.steps_per_year() as f32;
+            let taxes = unit.value * parcel.tax_rate / steps_per_year;
+            let vacancy_tax = conf.policies.vacancy_tax(unit, conf.time_step);
+            let insurance = unit.value * conf.insurance_rate / steps_per_year;
+            let maintenance = step_months * self.unit_maintenance.get(&u).unwrap_or(&self.maintenance) * unit.area * conf.maintenance_cost;
+            let debt = payments.get(&u).unwrap_or(&0.);
+            let net_income = revenue - taxes - vacancy_tax - insurance - maintenance - debt;
+            self.cash += net_income;
+            self.taxes_paid += taxes;
+            self.vacancy_tax_paid += vacancy_tax;
+            self.net_income.insert(u, net_income);
+            city.units[u].fees = 0.;
+
+            let months = self.months_unprofitable.entry(u).or_insert(0);
+            if net_income < 0. {
+                *months += 1;
+            } else {
+                *months = 0;
+            }
+        }
+
+        // If cash flow goes negative,
+        // the bank forecloses on the unit
+        // with the largest payment, putting
+        // it on the market at a discount. The
+        // landlord stops paying it, and the bank
+        // sells it to the best offer
+        self.n_foreclosures = 0;
+        if self.cash < 0. {
+            let mortgage = self.mortgages
+                .iter_mut()
+                .filter(|m| !m.foreclosed)
+                .max_by(|a, b| a.payment.partial_cmp(&b.payment).unwrap());
+            if let Some(mortgage) = mortgage {
+                mortgage.foreclosed = true;
+                let unit = &mut city.units[mortgage.unit];
+                unit.value *= conf.foreclosure_discount;
+                self.n_foreclosures += 1;
+                city.events.push(SimEvent::Foreclosed {
+                    unit: mortgage.unit,
+                    landlord: self.id,
+                });
+            }
+        }
+    }
+
+    // Expected monthly income from renting a unit short-term.
+    // Bookings fall as more of the neighborhood goes short-term
+    fn short_term_income(&self, unit: &Unit, city: &City, conf: &Config) -> f32 {
+        match city.parcels.get(&unit.pos).unwrap().neighborhood {
+            Some(neighb_id) => {
+                let demand = conf.short_term_demand(city.neighborhoods[neighb_id].id as usize);
+                let bookings = demand * (1. - city.short_term_share(neighb_id));
+                self.trend_ests[&neighb_id] * unit.area * conf.short_term_rent_multiple * bookings
+            }
+            None => 0.
+        }
+    }
+
+    // Whether converting the unit would keep its
+    // neighborhood within the short-term rental cap
+    fn short_term_allowed(&self, unit: &Unit, city: &City, conf: &Config) -> bool {
+        match (conf.policies.max_short_term_share, city.parcels.get(&unit.pos).unwrap().neighborhood) {
+            (Some(cap), Some(neighb_id)) => {
+                let n_units = city.units_by_neighborhood[neighb_id].len() as f32;
+                city.short_term_share(neighb_id) + 1. / n_units <= cap
+            }
+            _ => true
+        }
+    }
+
+    // Units that have lost money for long enough
+    // that the landlord is looking to get rid of them
+    pub fn unprofitable_units(&self, max_months: usize) -> Vec<UnitId> {
+        self.months_unprofitable
+            .iter()
+            .filter(|&(_, &months)| months > max_months)
+            .map(|(&u, _)| u)
+            .collect()
+    }
+
+    pub fn finance_purchase(&mut self, unit_id: UnitId, amount: f32, interest_rate: f32, conf: &Config) {
+        let principal = amount * conf.mortgage_ltv;
+        self.cash -= amount - principal;
+        self.mortgages.push(Mortgage::new(
+            unit_id,
+            principal,
+            interest_rate,
+            conf.mortgage_term_months,
+        ));
+    }
+
+    pub fn file_evictions(&self, city: &City, tenants: &Arena<TenantId, Tenant>, grace_months: usize) -> Vec<(UnitId, TenantId)> {
+        // Evict tenants who have been
+        // behind on rent for too long
+        let mut evictions = Vec::new();
+        for &u in &self.units {
+            let unit = &city.units[u];
+            for &t_id in &unit.tenants {
+                if tenants[t_id].arrears > grace_months {
+                    evictions.push((u, t_id));
+                }
+            }
+        }
+        evictions
+    }
+
+    // Rents at the top of each neighborhood's market,
+    // or what the landlord's own units get if more
+    pub fn estimate_rents(&mut self, city: &City, market: &MarketStats, rng: &mut StdRng, noise: f32) {
+        let mut neighborhoods: BTreeMap<usize, Vec<f32>> = BTreeMap::default();
         for &u in &self.units {
             let unit = &city.units[u];
             if !unit.vacant() {
@@ -333,10 +1070,7 @@ impl Landlord {
 
         for (&neighb_id, rent_history) in &mut self.rent_obvs {
             let n = neighborhoods.entry(neighb_id).or_insert(Vec::new());
-            let sample = city.units_by_neighborhood[neighb_id]
-                .choose_multiple(rng, sample_size)
-                .map(|&u_id| city.units[u_id].rent_per_area());
-            n.extend(sample);
+            n.push(noisy(market.neighborhoods[neighb_id].p90_rent, noise, rng));
             let max_rent = n.iter().cloned().fold(-1., f32::max);
             rent_history.push(max_rent);
         }
@@ -362,7 +1096,8 @@ impl Landlord {
         &mut self,
         city: &mut City,
         price_to_rent_ratio: f32,
-    ) -> Vec<(AgentType, usize, usize, f32)> {
+        max_unprofitable_months: usize,
+    ) -> Vec<(AgentType, usize, UnitId, f32)> {
         let mut transfers = Vec::new();
         for &u in &self.units {
             let mut unit = &mut city.units[u];
@@ -375,8 +1110,22 @@ impl Landlord {
                 // - maintenance costs become too much
                 let parcel = &city.parcels.get(&unit.pos).unwrap();
                 let est_future_rent = self.trend_ests[&parcel.neighborhood.unwrap()];
-                let est_value =
-                    est_future_rent * unit.area * 12. * price_to_rent_ratio * parcel.desirability;
+                let foreclosed = self.mortgages.iter().any(|m| m.unit == u && m.foreclosed);
+                let unprofitable = self.months_unprofitable.get(&u).unwrap_or(&0) > &max_unprofitable_months;
+                let est_value = if foreclosed {
+                    // The bank sells to the best offer
+                    0.
+                } else if unprofitable && !self.player {
+                    // Distressed sale, the landlord
+                    // takes whatever it can get
+                    unit.value
+                } else if self.player {
+                    // Players take the best offer
+                    // for units they've listed
+                    if self.for_sale.contains(&u) { 0. } else { f32::INFINITY }
+                } else {
+                    est_future_rent * unit.area * 12. * price_to_rent_ratio * parcel.desirability
+                };
 
                 // Find best offer, if any
                 // and mark offers as rejected or accepted
@@ -394,27 +1143,491 @@ impl Landlord {
                 if best_amount > 0. {
                     unit.value = best_amount;
                     unit.owner = (typ, landlord);
+                    unit.recently_sold = true;
                     transfers.push((typ, landlord, u, best_amount));
+                    city.events.push(SimEvent::UnitSold {
+                        unit: u,
+                        seller: (AgentType::Landlord, self.id),
+                        buyer: (typ, landlord),
+                        price: best_amount,
+                    });
                 }
             }
 
-            // TODO
-            // best_offer.landlord.property_fund -= best_offer.amount
             unit.offers.clear();
         }
 
-        for (_, _, unit_id, _) in &transfers {
-            self.units.retain(|u_id| u_id != unit_id);
+        for &(_, _, unit_id, amount) in &transfers {
+            self.units.retain(|&u_id| u_id != unit_id);
+            self.for_sale.retain(|&u_id| u_id != unit_id);
+            self.player_rents.remove(&unit_id);
+            self.player_maintenance.remove(&unit_id);
+            self.net_income.remove(&unit_id);
+            self.unit_maintenance.remove(&unit_id);
+            self.months_unprofitable.remove(&unit_id);
+
+            // Pay off any mortgage on the unit;
+            // for foreclosures the bank absorbs any shortfall
+            match self.mortgages.iter().position(|m| m.unit == unit_id) {
+                Some(i) => {
+                    let mortgage = self.mortgages.remove(i);
+                    if mortgage.foreclosed {
+                        self.cash += f32::max(0., amount - mortgage.principal);
+                    } else {
+                        self.cash += amount - mortgage.principal;
+                    }
+                },
+                None => self.cash += amount
+            }
         }
         transfers
     }
 }
 
+// Record the mean rent per area
+// of each neighborhood this month
+fn observe_rents(rent_obvs: &mut BTreeMap<usize, Vec<f32>>, market: &MarketStats) {
+    for (&neighb_id, rent_history) in rent_obvs.iter_mut() {
+        rent_history.push(market.neighborhoods[neighb_id].mean_rent);
+    }
+}
+
+// Median rent of occupied units in the same
+// neighborhood, scaled to the unit's area
+fn comparable_rent(unit: &Unit, city: &City, market: &MarketStats, rng: &mut StdRng, noise: f32) -> Option<f32> {
+    let neighb_id = city.parcels.get(&unit.pos).unwrap().neighborhood?;
+    let market = &market.neighborhoods[neighb_id];
+    if market.occupied == 0 {
+        None
+    } else {
+        Some(noisy(market.median_rent, noise, rng) * unit.area)
+    }
+}
+
+// Extrapolate rent per area `ahead` months,
+// based on the trend of the last `window` months
+fn project_rent(rent_history: &[f32], window: usize, ahead: usize) -> Option<f32> {
+    if window == 0 || rent_history.len() < window {
+        return None;
+    }
+    let ys = &rent_history[rent_history.len() - window..];
+    let xs: Vec<f32> = (0..ys.len()).map(|v| v as f32).collect();
+    let (slope, intercept): (f32, f32) = linear_regression(&xs, &ys).unwrap();
+    Some((window + ahead) as f32 * slope + intercept)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Project {
+    pub pos: Position,
+    pub n_units: usize,
+    pub area: f32,
+    pub cost: f32,
+    pub months_left: usize,
+}
+
+// What developers read of the market: rents,
+// what units are worth relative to their rents,
+// and parcels other developers already hold
+pub struct DevelopmentMarket<'a> {
+    pub stats: &'a MarketStats,
+    pub price_to_rent_ratio: f32,
+    pub held: &'a [Position],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Developer {
+    pub id: usize,
+    pub cash: f32,
+    pub units: Vec<UnitId>,
+    pub projects: Vec<Project>,
+    pub rent_obvs: BTreeMap<usize, Vec<f32>>,
+
+    // What each unit cost to build,
+    // the minimum the developer will sell for
+    pub cost_basis: BTreeMap<UnitId, f32>,
+
+    // Parcels bought to build on
+    #[serde(default)]
+    pub parcels: Vec<Position>,
+
+    // Number of units completed this step
+    pub n_built: usize,
+}
+
+impl Developer {
+    pub fn new(id: usize, n_neighborhoods: usize, cash: f32) -> Developer {
+        let mut rent_obvs = BTreeMap::default();
+        for id in 0..n_neighborhoods {
+            rent_obvs.insert(id, Vec::new());
+        }
+
+        Developer {
+            id: id,
+            cash: cash,
+            units: Vec::new(),
+            projects: Vec::new(),
+            rent_obvs: rent_obvs,
+            cost_basis: BTreeMap::default(),
+            parcels: Vec::new(),
+            n_built: 0,
+        }
+    }
+
+    pub fn step(
+        &mut self,
+        city: &mut City,
+        month: usize,
+        market: &DevelopmentMarket,
+        rng: &mut StdRng,
+        conf: &Config,
+    ) {
+        let year = conf.year(month);
+        let price_to_rent_ratio = market.price_to_rent_ratio;
+
+        // Track mean rents per neighborhood
+        observe_rents(&mut self.rent_obvs, market.stats);
+
+        // Progress construction,
+        // completed units are listed at the projected rent
+        self.n_built = 0;
+        for project in &mut self.projects {
+            project.months_left = project.months_left.saturating_sub(1);
+            if project.months_left == 0 {
+                let parcel = &city.parcels.get(&project.pos).unwrap();
+                let desirability = parcel.desirability;
+                let rent_per_area = self.rent_obvs[&parcel.neighborhood.unwrap()].last().unwrap();
+                let rent = rent_per_area * project.area;
+                let value = rent * 12. * price_to_rent_ratio * desirability;
+                for _ in 0..project.n_units {
+                    let id = city.add_unit(project.pos, project.area, rent, value, year, (AgentType::Developer, self.id));
+                    self.units.push(id);
+                    self.cost_basis.insert(id, project.cost/project.n_units as f32);
+                    self.n_built += 1;
+                }
+            }
+        }
+        self.projects.retain(|p| p.months_left > 0);
+
+        // Look for a neighborhood where rents
+        // are projected to justify new construction
+        let mut best = None;
+        for (&neighb_id, rent_history) in &self.rent_obvs {
+            let est_rent_per_area = match project_rent(rent_history, conf.steps(conf.trend_months), conf.steps(conf.construction_months)) {
+                Some(est) => est,
+                None => continue
+            };
+            let est_value_per_area = est_rent_per_area * 12. * price_to_rent_ratio;
+            let margin = est_value_per_area / conf.construction_cost_per_area;
+            match best {
+                Some((_, best_margin, _)) if best_margin >= margin => {},
+                _ => best = Some((neighb_id, margin, est_value_per_area))
+            }
+        }
+
+        if let Some((neighb_id, margin, est_value_per_area)) = best {
+            if margin < conf.development_margin {
+                return;
+            }
+
+            // Build on the developer's own parcels first,
+            // then buy the lowest-density parcels in the
+            // neighborhood that zoning leaves room on,
+            // that other developers don't hold
+            let neighb = &city.neighborhoods[neighb_id];
+            let mut candidates: Vec<(Position, usize, usize)> = city.residential_parcels_by_neighborhood[neighb_id]
+                .iter()
+                .filter(|pos| !self.projects.iter().any(|p| p.pos == **pos))
+                .filter(|pos| self.parcels.contains(pos) || !market.held.contains(pos))
+                .map(|pos| {
+                    let n_units = match city.buildings.get(pos) {
+                        Some(b) => b.units.len(),
+                        None => 0
+                    };
+                    (*pos, n_units, city.max_units(pos))
+                })
+                .filter(|&(_, n_units, max_units)| n_units < max_units)
+                .collect();
+            // and of those, closest to jobs
+            candidates.shuffle(rng);
+            candidates.sort_by(|a, b| {
+                let to_buy = |pos| !self.parcels.contains(pos);
+                to_buy(&a.0).cmp(&to_buy(&b.0)).then(a.1.cmp(&b.1)).then_with(|| {
+                    city.commute_times.nearest_work(a.0).partial_cmp(&city.commute_times.nearest_work(b.0)).unwrap()
+                })
+            });
+            if let Some(&(pos, n_units, max_units)) = candidates.first() {
+                let area = rng.gen_range(neighb.min_area, neighb.max_area) as f32;
+                let room = max_units - n_units;
+
+                // Land is priced by what can be built on it
+                let land_cost = if self.parcels.contains(&pos) {
+                    0.
+                } else {
+                    let desirability = city.parcels.get(&pos).unwrap().desirability;
+                    conf.land_cost_share * est_value_per_area * desirability * area * room as f32
+                };
+                let unit_cost = area * conf.construction_cost_per_area;
+                let n_new = usize::min(room, (f32::max(0., self.cash - land_cost) / unit_cost).floor() as usize);
+                if n_new > 0 {
+                    if !self.parcels.contains(&pos) {
+                        self.parcels.push(pos);
+                    }
+                    let cost = n_new as f32 * unit_cost + land_cost;
+                    self.cash -= cost;
+                    self.projects.push(Project {
+                        pos: pos,
+                        n_units: n_new,
+                        area: area,
+                        cost: cost,
+                        months_left: conf.steps(conf.construction_months),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn check_purchase_offers(&mut self, city: &mut City) -> Vec<(AgentType, usize, UnitId, f32)> {
+        let mut transfers = Vec::new();
+        for &u in &self.units {
+            let unit = &mut city.units[u];
+            let min_price = self.cost_basis[&u];
+            let (typ, landlord, best_amount): (AgentType, usize, f32) =
+                unit.offers.iter().fold(
+                    (AgentType::Landlord, 0, 0.),
+                    |(t, l, best), &(typ, landlord, amount)| {
+                        if amount > min_price && amount > best {
+                            (typ, landlord, amount)
+                        } else {
+                            (t, l, best)
+                        }
+                    },
+                );
+            if best_amount > 0. {
+                unit.value = best_amount;
+                unit.owner = (typ, landlord);
+                unit.recently_sold = true;
+                transfers.push((typ, landlord, u, best_amount));
+                city.events.push(SimEvent::UnitSold {
+                    unit: u,
+                    seller: (AgentType::Developer, self.id),
+                    buyer: (typ, landlord),
+                    price: best_amount,
+                });
+            }
+            unit.offers.clear();
+        }
+
+        for &(_, _, unit_id, amount) in &transfers {
+            self.units.retain(|&u_id| u_id != unit_id);
+            self.cost_basis.remove(&unit_id);
+            self.cash += amount;
+        }
+        transfers
+    }
+}
+
+// Buys units expecting them to appreciate,
+// keeps them off the rental market,
+// and sells once they can be flipped at a markup
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Speculator {
+    pub id: usize,
+    pub cash: f32,
+    pub units: Vec<UnitId>,
+    pub rent_obvs: BTreeMap<usize, Vec<f32>>,
+
+    // Projected rent growth per neighborhood
+    // over the speculation horizon
+    pub trend_ests: BTreeMap<usize, f32>,
+
+    // What each unit was bought for
+    pub cost_basis: BTreeMap<UnitId, f32>,
+
+    pub vacancy_tax_paid: f32,
+}
+
+impl Speculator {
+    pub fn new(id: usize, n_neighborhoods: usize, cash: f32) -> Speculator {
+        let mut rent_obvs = BTreeMap::default();
+        let mut trend_ests = BTreeMap::default();
+        for id in 0..n_neighborhoods {
+            rent_obvs.insert(id, Vec::new());
+            trend_ests.insert(id, 1.);
+        }
+
+        Speculator {
+            id: id,
+            cash: cash,
+            units: Vec::new(),
+            rent_obvs: rent_obvs,
+            trend_ests: trend_ests,
+            cost_basis: BTreeMap::default(),
+            vacancy_tax_paid: 0.,
+        }
+    }
+
+    pub fn step(
+        &mut self,
+        city: &mut City,
+        market: &MarketStats,
+        rng: &mut StdRng,
+        conf: &Config,
+    ) {
+        observe_rents(&mut self.rent_obvs, market);
+
+        // Units are held empty, paying any vacancy tax,
+        // though break fees and kept deposits still come in
+        self.vacancy_tax_paid = 0.;
+        for &u_id in &self.units {
+            let unit = &mut city.units[u_id];
+            self.cash += unit.fees;
+            unit.fees = 0.;
+            if unit.vacant() {
+                unit.months_vacant += 1;
+            }
+            let vacancy_tax = conf.policies.vacancy_tax(unit, conf.time_step);
+            self.cash -= vacancy_tax;
+            self.vacancy_tax_paid += vacancy_tax;
+        }
+
+        for (&neighb_id, rent_history) in &self.rent_obvs {
+            let current = *rent_history.last().unwrap();
+            let growth = match project_rent(rent_history, conf.steps(conf.trend_months), conf.steps(conf.speculation_horizon)) {
+                Some(est) if current > 0. => est / current,
+                _ => 1.
+            };
+            self.trend_ests.insert(neighb_id, growth);
+        }
+
+        // Bid in the neighborhood with
+        // the strongest projected growth
+        let best = self
+            .trend_ests
+            .iter()
+            .fold(None, |best, (&neighb_id, &growth)| match best {
+                Some((_, best_growth)) if best_growth >= growth => best,
+                _ => Some((neighb_id, growth))
+            });
+        let (neighb_id, growth) = match best {
+            Some(best) => best,
+            None => return
+        };
+
+        // All-cash offers, paying up to what still
+        // leaves room to resell at the markup,
+        // after any vacancy tax while holding it
+        let sample = city.units_by_neighborhood[neighb_id].choose_multiple(rng, conf.sample_size);
+        let mut committed = 0.;
+        for &u_id in sample {
+            let unit = &mut city.units[u_id];
+            if unit.owner.0 == AgentType::Speculator || unit.is_doma() {
+                continue;
+            }
+            let vacancy_tax = conf.policies.vacancy_tax_over(unit.value, conf.speculation_horizon);
+            let offer = unit.value * growth / conf.speculator_markup - vacancy_tax;
+            if offer <= unit.value || committed + offer > self.cash {
+                continue;
+            }
+            committed += offer;
+            unit.offers.push((AgentType::Speculator, self.id, offer));
+        }
+    }
+
+    // Speculators don't renew leases,
+    // so units empty out as they come up.
+    // Returns (unit id, tenant id) pairs
+    pub fn end_leases(&self, city: &City, month: usize) -> Vec<(UnitId, TenantId)> {
+        let mut ended = Vec::new();
+        for &u_id in &self.units {
+            let unit = &city.units[u_id];
+            if unit.lease_up(month) {
+                ended.extend(unit.tenants.iter().map(|&t_id| (u_id, t_id)));
+            }
+        }
+        ended
+    }
+
+    pub fn acquire(&mut self, unit_id: UnitId, amount: f32) {
+        self.units.push(unit_id);
+        self.cost_basis.insert(unit_id, amount);
+        self.cash -= amount;
+    }
+
+    pub fn check_purchase_offers(&mut self, city: &mut City, markup: f32) -> Vec<(AgentType, usize, UnitId, f32)> {
+        let mut transfers = Vec::new();
+        for &u in &self.units {
+            let unit = &mut city.units[u];
+            let min_price = self.cost_basis[&u] * markup;
+            let (typ, landlord, best_amount): (AgentType, usize, f32) =
+                unit.offers.iter().fold(
+                    (AgentType::Landlord, 0, 0.),
+                    |(t, l, best), &(typ, landlord, amount)| {
+                        // Don't trade units between speculators
+                        if typ != AgentType::Speculator && amount >= min_price && amount > best {
+                            (typ, landlord, amount)
+                        } else {
+                            (t, l, best)
+                        }
+                    },
+                );
+            if best_amount > 0. {
+                unit.value = best_amount;
+                unit.owner = (typ, landlord);
+                unit.recently_sold = true;
+                transfers.push((typ, landlord, u, best_amount));
+                city.events.push(SimEvent::UnitSold {
+                    unit: u,
+                    seller: (AgentType::Speculator, self.id),
+                    buyer: (typ, landlord),
+                    price: best_amount,
+                });
+            }
+            unit.offers.clear();
+        }
+
+        for &(_, _, unit_id, amount) in &transfers {
+            self.units.retain(|&u_id| u_id != unit_id);
+            self.cost_basis.remove(&unit_id);
+            self.cash += amount;
+        }
+        transfers
+    }
+}
+
+// Contributions are pooled into rounds. When a round
+// reaches its target or runs out of time, what it
+// raised goes toward acquisitions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FundingRound {
+    pub number: usize,
+    pub target: f32,
+    pub raised: f32,
+    pub months_left: usize,
+}
+
+impl FundingRound {
+    pub fn new(number: usize, target: f32, months: usize) -> FundingRound {
+        FundingRound {
+            number: number,
+            target: target,
+            raised: 0.,
+            months_left: months,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct DOMA {
     pub funds: f32,
     pub raised: f32,
-    pub shares: FnvHashMap<usize, f32>,
-    pub units: Vec<usize>,
+    pub last_payout: f32,
+    pub shares: BTreeMap<TenantId, f32>,
+
+    // What each tenant has put in, and
+    // the round currently raising funds
+    pub contributions: BTreeMap<TenantId, f32>,
+    pub round: FundingRound,
+    pub units: Vec<UnitId>,
     maintenance: f32,
 
     // Percent of rent paid to DOMA
@@ -424,24 +1637,39 @@ pub struct DOMA {
     pub p_expenses: f32,
 
     pub rent_income_limit: Option<f32>,
+
+    // Set by member votes: the neighborhood
+    // to focus acquisitions on, and how far
+    // below cost to set rents for acquired units
+    pub target_neighborhood: Option<usize>,
+    pub rent_discount: f32,
+
+    // Number of units retrofitted this step
+    pub n_retrofitted: usize,
 }
 
 impl DOMA {
-    pub fn new(funds: f32, p_rent_share: f32, p_reserves: f32, p_expenses: f32, rent_income_limit: Option<f32>) -> DOMA {
+    pub fn new(funds: f32, p_rent_share: f32, p_reserves: f32, p_expenses: f32, rent_income_limit: Option<f32>, round: FundingRound) -> DOMA {
         DOMA {
             funds: funds,
             raised: 0.,
-            shares: FnvHashMap::default(),
+            last_payout: 0.,
+            shares: BTreeMap::default(),
+            contributions: BTreeMap::default(),
+            round: round,
             maintenance: 1.,
             units: Vec::new(),
             p_rent_share: p_rent_share,
             p_reserves: p_reserves,
             p_expenses: p_expenses,
-            rent_income_limit: rent_income_limit
+            rent_income_limit: rent_income_limit,
+            target_neighborhood: None,
+            rent_discount: 0.,
+            n_retrofitted: 0,
         }
     }
 
-    pub fn step(&mut self, city: &mut City, tenants: &mut Vec<Tenant>, rng: &mut StdRng) {
+    pub fn step(&mut self, city: &mut City, tenants: &mut Arena<TenantId, Tenant>, year: usize, rng: &mut StdRng, conf: &Config) {
         // Mean income, for setting rent limit
         let mean_income = tenants.iter().fold(0., |acc, t| acc + t.income)/tenants.len() as f32;
         let rent_cap = match self.rent_income_limit {
@@ -453,21 +1681,25 @@ impl DOMA {
             }
         };
 
-        // Collect rent
+        // Collect rent, for however many months the step covers
+        let step_months = conf.time_step.months();
         let mut rent = 0.;
         for &u_id in &self.units {
             let unit = &mut city.units[u_id];
 
+            // Break fees and kept deposits
+            // go to the property fund
+            self.funds += unit.fees;
+            unit.fees = 0.;
+
             // Maintenance
-            let decay: f32 = rng.gen();
-            unit.condition -= decay * 0.1; // TODO deterioration rate based on build year?
-            unit.condition += self.maintenance;
-            unit.condition = f32::min(f32::max(unit.condition, 0.), 1.);
+            let decay: f32 = rng.gen::<f32>() * unit.deterioration(year, conf);
+            unit.maintain(step_months * self.maintenance, step_months * decay);
 
             if !unit.vacant() {
-                rent += unit.rent;
-                // let rent_per_tenant = rent / unit.tenants.len() as f32;
-                let rent_per_tenant = rent / unit.occupancy as f32;
+                rent += step_months * unit.rent;
+                // let rent_per_tenant = unit.rent / unit.tenants.len() as f32;
+                let rent_per_tenant = step_months * unit.rent / unit.occupancy as f32;
                 for &t in &unit.tenants {
                     let share = self.shares.entry(t).or_insert(0.);
                     *share += rent_per_tenant * self.p_rent_share;
@@ -477,19 +1709,66 @@ impl DOMA {
             }
 
             // Adjust rents
-            unit.rent = f32::min(unit.rent, rent_cap);
+            unit.set_rent(f32::min(unit.rent, rent_cap));
         }
 
-        // Pay dividends
+        // Pay dividends. Members count them against
+        // their monthly rent, so they're kept monthly
         let p_dividend = 1.0 - self.p_reserves - self.p_expenses;
-        let dividends = rent * p_dividend;
+        let dividends = rent * p_dividend / step_months;
         let total_shares: f32 = self.shares.values().sum();
         for (&tenant_id, share) in &self.shares {
             let tenant = &mut tenants[tenant_id];
             tenant.last_dividend = dividends * share/total_shares;
         }
+        let n_members = self.shares.values().filter(|&&s| s > 0.).count();
+        let dividend_per_member = if n_members > 0 { dividends / n_members as f32 } else { 0. };
+        for &u_id in &self.units {
+            city.units[u_id].dividend = dividend_per_member;
+        }
+        self.last_payout = rent * p_dividend;
         self.funds += rent * self.p_reserves;
 
+        // Retrofit the unit that would pay back
+        // soonest from its own rent, out of the
+        // property fund, at most one a month
+        self.n_retrofitted = 0;
+        if let Some(retrofit) = &conf.retrofit {
+            let best = self.units.iter()
+                .filter_map(|&u_id| {
+                    let unit = &city.units[u_id];
+                    retrofit.payback(unit, unit.rent).map(|months| (u_id, months))
+                })
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            if let Some((u_id, _)) = best {
+                let unit = &mut city.units[u_id];
+                let cost = retrofit.cost(unit);
+                if cost <= self.funds {
+                    self.funds -= cost;
+                    retrofit.apply(unit);
+                    unit.set_rent(f32::min(unit.rent * retrofit.premium(unit), rent_cap));
+                    self.n_retrofitted += 1;
+                    city.events.push(SimEvent::Retrofitted {
+                        unit: u_id,
+                        owner: (AgentType::DOMA, 0),
+                        cost: cost,
+                    });
+                }
+            }
+        }
+
+        self.round.months_left = self.round.months_left.saturating_sub(1);
+        if self.round.raised >= self.round.target || self.round.months_left == 0 {
+            if self.round.raised > 0. {
+                city.events.push(SimEvent::DomaRoundClosed {
+                    round: self.round.number,
+                    raised: self.round.raised,
+                });
+            }
+            self.funds += self.round.raised;
+            self.round = FundingRound::new(self.round.number + 1, conf.doma_round_target, conf.steps(conf.doma_round_months));
+        }
+
         // TODO selling of properties
 
         // // Make offers on properties
@@ -529,13 +1808,20 @@ impl DOMA {
         //         .collect();
         // }
 
-        let mut candidates: Vec<(usize, f32, f32)> = city
+        let parcels = &city.parcels;
+        let mut candidates: Vec<(UnitId, f32, f32)> = city
             .units
             .iter_mut()
             .filter_map(|unit| {
                 // Ensure unit is affordable
-                if unit.owner.0 != AgentType::DOMA {
-                    Some((unit.id, unit.value, unit.rent))
+                // and in the targeted neighborhood, if any.
+                // Bid what it's worth, but no less than it last went for
+                let targeted = match self.target_neighborhood {
+                    Some(target) => parcels.get(&unit.pos).unwrap().neighborhood == Some(target),
+                    None => true
+                };
+                if unit.owner.0 != AgentType::DOMA && targeted {
+                    Some((unit.id, f32::max(unit.value, unit.est_value), unit.rent))
                 } else {
                     None
                 }
@@ -565,10 +1851,24 @@ impl DOMA {
         }
     }
 
-    pub fn add_funds(&mut self, tenant_id: usize, amount: f32) {
-        self.funds += amount;
+    pub fn acquire(&mut self, unit: &mut Unit, amount: f32, price_to_rent_ratio: f32) {
+        self.units.push(unit.id);
+        self.funds -= amount;
+
+        // DOMA rents are set at cost, i.e. only
+        // what's needed to recoup the purchase price,
+        // rather than whatever the market will bear
+        let rent_at_cost = amount / price_to_rent_ratio / 12.;
+        unit.set_rent(f32::min(unit.rent, rent_at_cost * (1. - self.rent_discount)));
+    }
+
+    // Contributors get shares in proportion
+    // to what they put in, which set their dividend
+    pub fn add_funds(&mut self, tenant_id: TenantId, amount: f32) {
+        self.round.raised += amount;
         self.raised += amount;
         let share = self.shares.entry(tenant_id).or_insert(0.);
         *share += amount;
+        *self.contributions.entry(tenant_id).or_insert(0.) += amount;
     }
 }
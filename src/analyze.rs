@@ -0,0 +1,250 @@
+use super::config::Config;
+use super::design::validate_or_exit;
+use super::generate::CityParams;
+use super::sim::Simulation;
+use super::stats;
+use super::sweep::{base_design, get_value, setup_run};
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serde_yaml::Value as YamlValue;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::{self, AssertUnwindSafe};
+use tracing::{info, warn};
+
+// Config keys that don't change the model,
+// left out when analyzing every parameter
+const NON_MODEL_PARAMS: &[&str] = &[
+    "SEED", "STEPS", "START_YEAR", "BURN_IN", "SESSIONS",
+    "PLAYER_TIMEOUT_SECS", "TURN_STEPS", "TURN_TIMEOUT_SECS",
+];
+
+// Perturbs each parameter up and down around
+// the baseline config, one at a time, to see
+// how much each moves the outputs
+#[derive(Deserialize, Debug)]
+pub struct Analysis {
+    // Path to a design JSON file, or parameters
+    // to generate a city from, as in sweeps
+    #[serde(default)]
+    pub design: Option<String>,
+    #[serde(default)]
+    pub generate: Option<CityParams>,
+
+    pub steps: usize,
+    pub seeds: Vec<u64>,
+
+    // Share of its baseline value each
+    // parameter is moved up and down by
+    #[serde(default = "default_perturbation")]
+    pub perturbation: f64,
+
+    // Numeric top-level stats to
+    // compare at the end of each run
+    #[serde(default = "default_outputs")]
+    pub outputs: Vec<String>,
+
+    // Config keys to perturb, as in sweeps.
+    // Every numeric top-level one if empty
+    #[serde(default)]
+    pub params: Vec<String>,
+}
+
+fn default_perturbation() -> f64 {
+    0.1
+}
+
+fn default_outputs() -> Vec<String> {
+    vec!["percent_homeless".to_string(), "median_rent_per_area".to_string(), "p_units_doma".to_string()]
+}
+
+pub fn load_analysis(path: &str) -> Analysis {
+    let file = File::open(path).expect("could not open analysis file");
+    let reader = BufReader::new(file);
+    serde_yaml::from_reader(reader).expect("error while reading yaml")
+}
+
+// A parameter's value scaled by `factor`. Integers are
+// rounded, moving at least one from the baseline, and
+// `None` if the value isn't a number or can't move
+fn perturb(val: &YamlValue, factor: f64) -> Option<YamlValue> {
+    let v = val.as_f64()?;
+    let scaled = if val.is_f64() {
+        YamlValue::Number((v * factor).into())
+    } else {
+        let mut p = (v * factor).round();
+        if p == v {
+            p += if factor > 1. { 1. } else { -1. };
+        }
+        if val.is_u64() && p < 0. {
+            return None;
+        }
+        YamlValue::Number((p as i64).into())
+    };
+    if scaled == *val { None } else { Some(scaled) }
+}
+
+fn mean_std(vals: &[f32]) -> (f32, f32) {
+    let n = vals.len() as f32;
+    let mean = vals.iter().sum::<f32>() / n;
+    let var = vals.iter().fold(0., |acc, v| acc + (v - mean).powi(2)) / n;
+    (mean, var.sqrt())
+}
+
+// A perturbed parameter's baseline, low, and high values
+struct Perturbed {
+    key: String,
+    base: f64,
+    low: f64,
+    high: f64,
+}
+
+pub fn run_analysis(analysis: &Analysis, conf: &Config) {
+    let design = base_design(&analysis.design, &analysis.generate);
+    let conf_val = serde_yaml::to_value(conf).unwrap();
+    let keys: Vec<String> = if analysis.params.is_empty() {
+        match &conf_val {
+            YamlValue::Mapping(map) => map.iter()
+                .filter(|(_, v)| v.is_number())
+                .filter_map(|(k, _)| k.as_str())
+                .filter(|k| !NON_MODEL_PARAMS.contains(k))
+                .map(|k| k.to_string())
+                .collect(),
+            _ => Vec::new(),
+        }
+    } else {
+        analysis.params.clone()
+    };
+
+    let mut perturbed = Vec::new();
+    let mut variants = vec![Vec::new()];
+    for key in keys {
+        let val = get_value(&conf_val, &key).unwrap_or_else(|| panic!("unknown analysis parameter: {}", key));
+        match (perturb(val, 1. - analysis.perturbation), perturb(val, 1. + analysis.perturbation)) {
+            (Some(low), Some(high)) => {
+                perturbed.push(Perturbed {
+                    key: key.clone(),
+                    base: val.as_f64().unwrap(),
+                    low: low.as_f64().unwrap(),
+                    high: high.as_f64().unwrap(),
+                });
+                variants.push(vec![(key.clone(), low)]);
+                variants.push(vec![(key, high)]);
+            }
+            _ => info!("Skipping {:?}, which can't be perturbed from {:?}", key, val),
+        }
+    }
+    for params in &variants {
+        validate_or_exit(&setup_run(&analysis.generate, conf, &design, params).1);
+    }
+
+    let runs: Vec<(usize, u64)> = (0..variants.len())
+        .flat_map(|i| analysis.seeds.iter().map(move |&seed| (i, seed)))
+        .collect();
+    info!("Running {:?} runs ({:?} parameters x 2 + baseline, x {:?} seeds)...", runs.len(), perturbed.len(), analysis.seeds.len());
+    // Perturbations can push parameters past what the
+    // model handles, so a variant with a run that panics
+    // is reported as failed rather than ending the analysis
+    let finals: Vec<Option<Vec<f32>>> = runs
+        .par_iter()
+        .map(|&(i, seed)| {
+            let (mut run_conf, run_design) = setup_run(&analysis.generate, conf, &design, &variants[i]);
+            run_conf.seed = seed;
+            run_conf.steps = analysis.steps;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut rng: StdRng = SeedableRng::seed_from_u64(seed);
+                let mut sim = Simulation::new(run_design, run_conf, &mut rng);
+                for _ in 0..analysis.steps {
+                    sim.step();
+                }
+                stats::stats(&sim)
+            }));
+            match result {
+                Ok(step_stats) => Some(analysis.outputs.iter().map(|name| {
+                    step_stats[name].as_f64().unwrap_or_else(|| panic!("unknown analysis output: {}", name)) as f32
+                }).collect()),
+                Err(_) => {
+                    warn!("Run failed (seed {:?}, params {:?})", seed, variants[i]);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Mean and standard deviation of
+    // each output across seeds, by variant
+    let summaries: Vec<Option<Vec<(f32, f32)>>> = (0..variants.len()).map(|i| {
+        let variant: Option<Vec<&Vec<f32>>> = runs.iter().zip(finals.iter())
+            .filter(|((v, _), _)| *v == i)
+            .map(|(_, f)| f.as_ref())
+            .collect();
+        variant.map(|variant| (0..analysis.outputs.len())
+            .map(|o| mean_std(&variant.iter().map(|f| f[o]).collect::<Vec<f32>>()))
+            .collect())
+    }).collect();
+
+    let now: DateTime<Utc> = Utc::now();
+    let dir = format!("runs/analyze_{}", now.format("%Y.%m.%d.%H.%M.%S"));
+    fs::create_dir_all(&dir).unwrap();
+    let mut csv = csv::Writer::from_path(format!("{}/report.csv", dir)).expect("Unable to write file");
+    csv.write_record([
+        "param", "output", "base_value", "low_value", "high_value",
+        "base_mean", "base_std", "low_mean", "low_std", "high_mean", "high_std", "elasticity",
+    ]).unwrap();
+
+    let baseline = summaries[0].as_ref().expect("baseline runs failed");
+    let params: Vec<Value> = perturbed.iter().enumerate().map(|(i, p)| {
+        let (low, high) = (&summaries[2*i + 1], &summaries[2*i + 2]);
+        let effects: serde_json::Map<String, Value> = analysis.outputs.iter().enumerate().map(|(o, name)| {
+            let low = low.as_ref().map(|s| s[o]);
+            let high = high.as_ref().map(|s| s[o]);
+
+            // Relative change in the output over
+            // relative change in the parameter
+            let elasticity = match (low, high) {
+                (Some(low), Some(high)) if baseline[o].0 != 0. => {
+                    Some(((high.0 - low.0) / baseline[o].0) as f64 / ((p.high - p.low) / p.base))
+                }
+                _ => None,
+            };
+            let cell = |v: Option<f32>| v.map_or(String::new(), |v| v.to_string());
+            csv.write_record([
+                p.key.clone(), name.clone(), p.base.to_string(), p.low.to_string(), p.high.to_string(),
+                baseline[o].0.to_string(), baseline[o].1.to_string(),
+                cell(low.map(|s| s.0)), cell(low.map(|s| s.1)),
+                cell(high.map(|s| s.0)), cell(high.map(|s| s.1)),
+                elasticity.map_or(String::new(), |e| e.to_string()),
+            ]).unwrap();
+            (name.clone(), json!({
+                "low": low.map(|(mean, std)| json!({"mean": mean, "std": std})),
+                "high": high.map(|(mean, std)| json!({"mean": mean, "std": std})),
+                "elasticity": elasticity,
+            }))
+        }).collect();
+        json!({
+            "param": p.key,
+            "base": p.base,
+            "low": p.low,
+            "high": p.high,
+            "effects": effects,
+        })
+    }).collect();
+    csv.flush().expect("Unable to write file");
+
+    let report = json!({
+        "perturbation": analysis.perturbation,
+        "steps": analysis.steps,
+        "seeds": analysis.seeds,
+        "baseline": analysis.outputs.iter().zip(baseline.iter()).map(|(name, (mean, std))| {
+            (name.clone(), json!({"mean": mean, "std": std}))
+        }).collect::<serde_json::Map<String, Value>>(),
+        "params": params,
+    });
+    fs::write(format!("{}/report.json", dir), report.to_string()).expect("Unable to write file");
+    info!("Wrote sensitivity report to {:?}", dir);
+}
@@ -0,0 +1,270 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+// Ids are indices into their arena, typed
+// so that e.g. a tenant's id can't be used
+// to look up a unit. They serialize as plain
+// numbers, so saved runs and the frontend
+// see the same ids as before
+pub trait Id: Copy {
+    fn new(i: usize) -> Self;
+    fn index(self) -> usize;
+}
+
+macro_rules! id_type {
+    ($name:ident) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(pub usize);
+
+        impl Id for $name {
+            fn new(i: usize) -> Self {
+                $name(i)
+            }
+
+            fn index(self) -> usize {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+id_type!(UnitId);
+id_type!(TenantId);
+
+// Parcels are indexed by their
+// position's place in the grid, row by row
+id_type!(ParcelId);
+
+// Items stored contiguously and never removed,
+// so ids stay valid for the whole run.
+// Removed units are instead marked as such
+// (see `City::live_units`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Arena<I: Id, T> {
+    items: Vec<T>,
+    #[serde(skip)]
+    _id: PhantomData<I>,
+}
+
+impl<I: Id, T> Default for Arena<I, T> {
+    fn default() -> Self {
+        Arena { items: Vec::new(), _id: PhantomData }
+    }
+}
+
+impl<I: Id, T> Arena<I, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The id the next item will get
+    pub fn next_id(&self) -> I {
+        I::new(self.items.len())
+    }
+
+    pub fn push(&mut self, item: T) -> I {
+        let id = self.next_id();
+        self.items.push(item);
+        id
+    }
+
+    pub fn get(&self, id: I) -> Option<&T> {
+        self.items.get(id.index())
+    }
+
+    pub fn get_mut(&mut self, id: I) -> Option<&mut T> {
+        self.items.get_mut(id.index())
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item=I> + ExactSizeIterator {
+        (0..self.items.len()).map(I::new)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.items.iter_mut()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.items
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl<I: Id, T> Index<I> for Arena<I, T> {
+    type Output = T;
+
+    fn index(&self, id: I) -> &T {
+        &self.items[id.index()]
+    }
+}
+
+impl<I: Id, T> IndexMut<I> for Arena<I, T> {
+    fn index_mut(&mut self, id: I) -> &mut T {
+        &mut self.items[id.index()]
+    }
+}
+
+impl<I: Id, T> From<Vec<T>> for Arena<I, T> {
+    fn from(items: Vec<T>) -> Self {
+        Arena { items: items, _id: PhantomData }
+    }
+}
+
+impl<I: Id, T> FromIterator<T> for Arena<I, T> {
+    fn from_iter<It: IntoIterator<Item=T>>(iter: It) -> Self {
+        Arena::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+impl<'a, I: Id, T> IntoIterator for &'a Arena<I, T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+impl<'a, I: Id, T> IntoIterator for &'a mut Arena<I, T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter_mut()
+    }
+}
+
+impl<'a, I: Id + Send + Sync, T: Sync + 'a> IntoParallelIterator for &'a Arena<I, T> {
+    type Item = &'a T;
+    type Iter = rayon::slice::Iter<'a, T>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.items.par_iter()
+    }
+}
+
+impl<'a, I: Id + Send + Sync, T: Send + 'a> IntoParallelIterator for &'a mut Arena<I, T> {
+    type Item = &'a mut T;
+    type Iter = rayon::slice::IterMut<'a, T>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.items.par_iter_mut()
+    }
+}
+
+// A set of ids with constant time insertion,
+// removal, and lookup, which can also be read
+// as a slice, e.g. for sampling. Removing
+// an id moves the last one into its place,
+// so the order isn't stable. It's saved in
+// its current order, so sampling from a
+// restored set draws the same ids
+#[derive(Debug, Clone)]
+pub struct IndexedSet<I: Id> {
+    ids: Vec<I>,
+
+    // Where each id is in `ids`, by id
+    slots: Vec<Option<usize>>,
+}
+
+impl<I: Id> Default for IndexedSet<I> {
+    fn default() -> Self {
+        IndexedSet { ids: Vec::new(), slots: Vec::new() }
+    }
+}
+
+impl<I: Id + Serialize> Serialize for IndexedSet<I> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.ids.serialize(serializer)
+    }
+}
+
+impl<'de, I: Id + Deserialize<'de>> Deserialize<'de> for IndexedSet<I> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = IndexedSet::new();
+        for id in Vec::<I>::deserialize(deserializer)? {
+            set.insert(id);
+        }
+        Ok(set)
+    }
+}
+
+impl<I: Id> IndexedSet<I> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, id: I) -> bool {
+        self.slots.get(id.index()).is_some_and(|slot| slot.is_some())
+    }
+
+    // Returns whether the id was added
+    pub fn insert(&mut self, id: I) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        if self.slots.len() <= id.index() {
+            self.slots.resize(id.index() + 1, None);
+        }
+        self.slots[id.index()] = Some(self.ids.len());
+        self.ids.push(id);
+        true
+    }
+
+    // Returns whether the id was there
+    pub fn remove(&mut self, id: I) -> bool {
+        let slot = match self.slots.get_mut(id.index()).and_then(|slot| slot.take()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        self.ids.swap_remove(slot);
+        if let Some(&moved) = self.ids.get(slot) {
+            self.slots[moved.index()] = Some(slot);
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.slots.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[I] {
+        &self.ids
+    }
+}
@@ -0,0 +1,877 @@
+use futures_util::{SinkExt, StreamExt};
+use redis::{Commands, Connection, ErrorKind, RedisError, RedisResult};
+use super::sync::Encoding;
+use serde::{Serialize, Serializer, Deserialize};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, info, warn};
+
+// How many updates a slow client can fall
+// behind before it's sent a fresh snapshot
+static UPDATE_BUFFER: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendType {
+    Redis,
+    Websocket,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct SyncConfig {
+    pub backend: BackendType,
+    pub redis_url: String,
+
+    // How many times to retry reconnecting to Redis,
+    // and the delay before the first retry, which
+    // doubles with each attempt
+    pub redis_retries: usize,
+    pub redis_retry_delay_ms: u64,
+
+    // Most writes to hold while Redis is
+    // unreachable; past it the oldest are dropped
+    pub redis_max_pending: usize,
+
+    pub websocket_addr: String,
+
+    // Syncs between full snapshots of the state;
+    // in between only changes are sent
+    pub keyframe_interval: usize,
+
+    // How many of the latest events
+    // to send for the activity feed
+    pub event_feed_size: usize,
+
+    // How payloads are encoded for the frontend
+    pub encoding: Encoding,
+
+    // Designs for the WebSocket backend to serve,
+    // as `<design id>.json` files
+    #[serde(default)]
+    pub designs_dir: Option<String>,
+
+    // Secret the operator signs admin commands, and
+    // commands that steer the whole session, e.g. `Run`
+    // or `Reset`, with. Without it, they're refused
+    #[serde(default)]
+    pub operator_token: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Redis(redis::RedisError),
+    Io(std::io::Error),
+    Replay(bincode::Error),
+
+    // Waiting to retry reconnecting
+    Disconnected,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::Redis(err) => write!(f, "redis error: {}", err),
+            SyncError::Io(err) => write!(f, "io error: {}", err),
+            SyncError::Replay(err) => write!(f, "replay error: {}", err),
+            SyncError::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<redis::RedisError> for SyncError {
+    fn from(err: redis::RedisError) -> SyncError {
+        SyncError::Redis(err)
+    }
+}
+
+impl From<std::io::Error> for SyncError {
+    fn from(err: std::io::Error) -> SyncError {
+        SyncError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for SyncError {
+    fn from(err: bincode::Error) -> SyncError {
+        SyncError::Replay(err)
+    }
+}
+
+pub type SyncResult<T> = Result<T, SyncError>;
+
+// Where state is shared with the frontend.
+// Operations are modeled on Redis: plain values,
+// lists (e.g. the command queue), and hashes.
+// Values are written as bytes so payloads may be
+// binary; what's read back (designs, commands,
+// votes) is always text
+pub trait SyncBackend {
+    fn get(&self, key: &str) -> SyncResult<Option<String>>;
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()>;
+    fn del(&self, key: &str) -> SyncResult<()>;
+
+    // Push to and pop from the front of a list
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()>;
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>>;
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()>;
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>>;
+}
+
+pub fn from_config(conf: &SyncConfig) -> SyncResult<Rc<dyn SyncBackend>> {
+    match conf.backend {
+        BackendType::Redis => Ok(Rc::new(RedisBackend::new(conf)?)),
+        BackendType::Websocket => Ok(Rc::new(WebSocketBackend::new(
+            &conf.websocket_addr,
+            conf.designs_dir.as_deref(),
+            conf.encoding,
+            1,
+        )?)),
+    }
+}
+
+// Backends for sessions hosted on their own threads.
+// Each Redis session gets its own connection,
+// and WebSocket sessions share the one server
+pub enum SessionBackends {
+    Redis(SyncConfig),
+    Websocket(WebSocketBackend),
+}
+
+impl SessionBackends {
+    pub fn from_config(conf: &SyncConfig, sessions: usize) -> SyncResult<SessionBackends> {
+        match conf.backend {
+            BackendType::Redis => Ok(SessionBackends::Redis(conf.clone())),
+            BackendType::Websocket => Ok(SessionBackends::Websocket(WebSocketBackend::new(
+                &conf.websocket_addr,
+                conf.designs_dir.as_deref(),
+                conf.encoding,
+                sessions,
+            )?)),
+        }
+    }
+
+    pub fn connect(&self) -> SyncResult<Rc<dyn SyncBackend>> {
+        match self {
+            SessionBackends::Redis(conf) => Ok(Rc::new(RedisBackend::new(conf)?)),
+            SessionBackends::Websocket(backend) => Ok(Rc::new(backend.clone())),
+        }
+    }
+}
+
+// Keys shared by all sessions: the designs,
+// the lobby, the leaderboard, and sync metadata
+fn shared(key: &str) -> bool {
+    key.starts_with("design:") || key == "sessions" || key == "leaderboard" || key == "meta"
+}
+
+// Strip the session from a key, if any
+fn unscoped(key: &str) -> &str {
+    key.strip_prefix("session:")
+        .and_then(|rest| rest.split_once(':'))
+        .map_or(key, |(_, key)| key)
+}
+
+// The session prefix of a key, if any
+fn scope(key: &str) -> &str {
+    &key[..key.len() - unscoped(key).len()]
+}
+
+// One of several concurrent sessions, with
+// its keys under `session:<id>:`, apart from
+// those shared by all sessions
+pub struct Namespaced {
+    backend: Rc<dyn SyncBackend>,
+    prefix: String,
+}
+
+impl Namespaced {
+    pub fn new(backend: Rc<dyn SyncBackend>, session: &str) -> Namespaced {
+        Namespaced {
+            backend: backend,
+            prefix: format!("session:{}:", session),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        if shared(key) {
+            key.to_string()
+        } else {
+            format!("{}{}", self.prefix, key)
+        }
+    }
+}
+
+impl SyncBackend for Namespaced {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        self.backend.get(&self.key(key))
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.backend.set(&self.key(key), value)
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.backend.del(&self.key(key))
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.backend.lpush(&self.key(key), value)
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        self.backend.lpop(&self.key(key))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.backend.hset(&self.key(key), field, value)
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        self.backend.hgetall(&self.key(key))
+    }
+}
+
+// A write to a backend, kept to replay later: when
+// Redis was unreachable, or in a session recording
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum Write {
+    Set(String, Vec<u8>),
+    Del(String),
+    Lpush(String, Vec<u8>),
+    Hset(String, String, Vec<u8>),
+}
+
+impl Write {
+    fn key(&self) -> &str {
+        match self {
+            Write::Set(key, _) | Write::Del(key) | Write::Lpush(key, _) | Write::Hset(key, _, _) => key,
+        }
+    }
+
+    pub(crate) fn apply_to(&self, backend: &dyn SyncBackend) -> SyncResult<()> {
+        match self {
+            Write::Set(key, value) => backend.set(key, value),
+            Write::Del(key) => backend.del(key),
+            Write::Lpush(key, value) => backend.lpush(key, value),
+            Write::Hset(key, field, value) => backend.hset(key, field, value),
+        }
+    }
+
+    fn apply(&self, con: &Connection) -> RedisResult<()> {
+        match self {
+            Write::Set(key, value) => con.set(key, value.as_slice()),
+            Write::Del(key) => con.del(key),
+            Write::Lpush(key, value) => con.lpush(key, value.as_slice()),
+            Write::Hset(key, field, value) => con.hset(key, field, value.as_slice()),
+        }
+    }
+}
+
+// Talks to Redis, reconnecting if the connection drops.
+// Reconnecting is tried at most once per command, never
+// waiting on Redis, with the delay until the next attempt
+// doubling for a bounded number of retries. Until it's
+// back, writes are buffered, up to a limit, and replayed
+// in order on reconnect, and reads fail
+pub struct RedisBackend {
+    client: redis::Client,
+    con: RefCell<Option<Connection>>,
+    pending: RefCell<VecDeque<Write>>,
+    max_pending: usize,
+    max_retries: usize,
+    retry_delay: Duration,
+
+    // Failed attempts since the connection dropped,
+    // and when the next one's due
+    attempts: Cell<usize>,
+    retry_at: Cell<Option<Instant>>,
+}
+
+impl RedisBackend {
+    pub fn new(conf: &SyncConfig) -> SyncResult<RedisBackend> {
+        let backend = RedisBackend {
+            client: redis::Client::open(conf.redis_url.as_str())?,
+            con: RefCell::new(None),
+            pending: RefCell::new(VecDeque::new()),
+            max_pending: conf.redis_max_pending,
+            max_retries: conf.redis_retries,
+            retry_delay: Duration::from_millis(conf.redis_retry_delay_ms),
+            attempts: Cell::new(0),
+            retry_at: Cell::new(None),
+        };
+
+        // Nothing's running yet, so it's
+        // fine to wait on Redis here
+        loop {
+            match backend.connect() {
+                Ok(()) => return Ok(backend),
+                Err(err) if backend.attempts.get() > backend.max_retries => return Err(err),
+                Err(_) => {
+                    let retry_at = backend.retry_at.get().unwrap_or_else(Instant::now);
+                    thread::sleep(retry_at.saturating_duration_since(Instant::now()));
+                }
+            }
+        }
+    }
+
+    fn connect(&self) -> SyncResult<()> {
+        if self.con.borrow().is_some() {
+            return Ok(());
+        }
+        if let Some(retry_at) = self.retry_at.get() {
+            if Instant::now() < retry_at {
+                return Err(SyncError::Disconnected);
+            }
+        }
+
+        match self.client.get_connection() {
+            Ok(con) => {
+                *self.con.borrow_mut() = Some(con);
+                self.attempts.set(0);
+                self.retry_at.set(None);
+                self.replay()
+            },
+            Err(err) => {
+                // Past the retries, keep trying
+                // at the longest delay
+                let attempts = self.attempts.get();
+                let delay = self.retry_delay * 2u32.pow(attempts.min(self.max_retries) as u32);
+                if attempts == self.max_retries {
+                    error!("Couldn't reach Redis after {} retries: {}", attempts, err);
+                }
+                self.attempts.set(attempts + 1);
+                self.retry_at.set(Some(Instant::now() + delay));
+                Err(err.into())
+            }
+        }
+    }
+
+    fn replay(&self) -> SyncResult<()> {
+        let n_pending = self.pending.borrow().len();
+        if n_pending == 0 {
+            return Ok(());
+        }
+        loop {
+            let write = match self.pending.borrow_mut().pop_front() {
+                Some(write) => write,
+                None => break,
+            };
+            let result = write.apply(self.con.borrow().as_ref().unwrap());
+            if let Err(err) = result {
+                let err = self.failed(err);
+                if RedisBackend::is_disconnect(&err) {
+                    self.pending.borrow_mut().push_front(write);
+                    return Err(err);
+                }
+                warn!("Dropped buffered write to {:?}: {}", write.key(), err);
+            }
+        }
+        info!("Reconnected to Redis, replayed {} writes", n_pending);
+        Ok(())
+    }
+
+    // Drop the connection if it's what failed,
+    // so the next command reconnects. A closed
+    // connection doesn't always surface as an
+    // IO error, so check that it still responds
+    fn failed(&self, err: RedisError) -> SyncError {
+        let dropped = err.kind() == ErrorKind::IoError || {
+            let con = self.con.borrow();
+            redis::cmd("PING").query::<String>(con.as_ref().unwrap()).is_err()
+        };
+        if dropped {
+            warn!("Lost connection to Redis: {}", err);
+            *self.con.borrow_mut() = None;
+            SyncError::Disconnected
+        } else {
+            err.into()
+        }
+    }
+
+    fn is_disconnect(err: &SyncError) -> bool {
+        match err {
+            SyncError::Disconnected => true,
+            SyncError::Redis(err) => err.kind() == ErrorKind::IoError,
+            SyncError::Io(_) | SyncError::Replay(_) => false,
+        }
+    }
+
+    // Run a command, reconnecting once
+    // if the connection has dropped
+    fn run<T, F>(&self, cmd: F) -> SyncResult<T> where F: Fn(&Connection) -> RedisResult<T> {
+        for _ in 0..2 {
+            self.connect()?;
+            let result = cmd(self.con.borrow().as_ref().unwrap());
+            match result {
+                Ok(val) => return Ok(val),
+                Err(err) => {
+                    let err = self.failed(err);
+                    if !RedisBackend::is_disconnect(&err) {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Err(SyncError::Disconnected)
+    }
+
+    // Connecting replays anything buffered,
+    // so writes still go out in order
+    fn write(&self, write: Write) -> SyncResult<()> {
+        match self.run(|con| write.apply(con)) {
+            Err(err) if RedisBackend::is_disconnect(&err) => {
+                self.buffer(write);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn buffer(&self, write: Write) {
+        let mut pending = self.pending.borrow_mut();
+
+        // Setting or deleting a key replaces
+        // whatever was buffered for it
+        if let Write::Set(..) | Write::Del(..) = write {
+            pending.retain(|w| w.key() != write.key());
+        }
+        pending.push_back(write);
+        if pending.len() > self.max_pending {
+            if let Some(dropped) = pending.pop_front() {
+                warn!("Too many writes held for Redis, dropped the oldest, to {:?}", dropped.key());
+            }
+        }
+    }
+}
+
+impl SyncBackend for RedisBackend {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        self.run(|con| con.get(key))
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.write(Write::Set(key.to_string(), value.to_vec()))
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.write(Write::Del(key.to_string()))
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.write(Write::Lpush(key.to_string(), value.to_vec()))
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        self.run(|con| con.lpop(key))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.write(Write::Hset(key.to_string(), field.to_string(), value.to_vec()))
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        self.run(|con| con.hgetall(key))
+    }
+}
+
+// A value sent to clients, as a string
+// for JSON and as raw bytes otherwise
+struct Payload<'a> {
+    bytes: &'a [u8],
+    encoding: Encoding,
+}
+
+impl<'a> Serialize for Payload<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.encoding {
+            Encoding::Json => serializer.serialize_str(&String::from_utf8_lossy(self.bytes)),
+            _ => serializer.serialize_bytes(self.bytes),
+        }
+    }
+}
+
+// Changes sent to clients. These describe the
+// current state of a key rather than increments,
+// so clients can apply them in any number
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Update<'a> {
+    Set { key: &'a str, value: Payload<'a> },
+    List { key: &'a str, values: Vec<Payload<'a>> },
+    Hash { key: &'a str, values: HashMap<&'a str, Payload<'a>> },
+    Del { key: &'a str },
+}
+
+struct Store {
+    encoding: Encoding,
+    values: HashMap<String, Vec<u8>>,
+    lists: HashMap<String, VecDeque<Vec<u8>>>,
+    hashes: HashMap<String, HashMap<String, Vec<u8>>>,
+}
+
+impl Store {
+    fn new(encoding: Encoding) -> Store {
+        Store {
+            encoding: encoding,
+            values: HashMap::new(),
+            lists: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    fn payload<'a>(&self, bytes: &'a [u8]) -> Payload<'a> {
+        Payload {
+            bytes: bytes,
+            encoding: self.encoding,
+        }
+    }
+
+    fn message(&self, update: &Update) -> Message {
+        let encoded = self.encoding.encode(update);
+        match self.encoding {
+            Encoding::Json => Message::Text(String::from_utf8(encoded).unwrap()),
+            _ => Message::Binary(encoded),
+        }
+    }
+
+    fn value_msg(&self, key: &str) -> Message {
+        self.message(&Update::Set {
+            key: key,
+            value: self.payload(&self.values[key]),
+        })
+    }
+
+    fn list_msg(&self, key: &str) -> Message {
+        self.message(&Update::List {
+            key: key,
+            values: self.lists[key].iter().map(|v| self.payload(v)).collect(),
+        })
+    }
+
+    fn hash_msg(&self, key: &str) -> Message {
+        self.message(&Update::Hash {
+            key: key,
+            values: self.hashes[key].iter().map(|(f, v)| (f.as_str(), self.payload(v))).collect(),
+        })
+    }
+
+    // Messages to bring a client up to date
+    fn snapshot(&self) -> Vec<Keyed> {
+        self.values.keys().map(|key| (key.clone(), self.value_msg(key)))
+            .chain(self.lists.keys().map(|key| (key.clone(), self.list_msg(key))))
+            .chain(self.hashes.keys().map(|key| (key.clone(), self.hash_msg(key))))
+            .collect()
+    }
+}
+
+// Messages are sent out with their key,
+// so players' private keys can be held back
+type Keyed = (String, Message);
+
+// Clients only see their own session's keys, and
+// those shared by all sessions. Players' own state
+// is under `player:<token>:...`, and only sent to
+// clients that have used that token. Queued commands
+// carry tokens, so aren't sent at all
+fn visible(key: &str, session: &str, tokens: &HashSet<String>) -> bool {
+    if shared(key) {
+        return true;
+    }
+    if scope(key) != session {
+        return false;
+    }
+    let key = unscoped(key);
+    match key.strip_prefix("player:") {
+        Some(rest) => rest.split(':').next().is_some_and(|token| tokens.contains(token)),
+        None => key != "cmds" && key != "admin_cmds",
+    }
+}
+
+// The full state snapshot is only sent to clients as
+// they connect or catch up, as deltas keep them current
+fn streamed(key: &str) -> bool {
+    unscoped(key) != "state:snapshot"
+}
+
+// The prefix of the session a client joins by
+// connecting to `/session/<id>`, or none for `/`.
+// Only hosted sessions can be joined, and with
+// several, clients at `/` only see the lobby
+fn client_session(path: &str, sessions: usize) -> Option<String> {
+    match path.trim_end_matches('/') {
+        "" => Some(String::new()),
+        path => path.strip_prefix("/session/")
+            .and_then(|id| id.parse::<usize>().ok())
+            .filter(|&id| sessions > 1 && id < sessions)
+            .map(|id| format!("session:{}:", id)),
+    }
+}
+
+// The token a command is sent with, or rejoins with
+fn command_token(cmd: &Value) -> Option<&str> {
+    cmd["token"].as_str().or_else(|| cmd["Rejoin"][1].as_str())
+}
+
+// Serves state directly to frontend clients.
+// Each change to a key is broadcast to connected
+// clients, and messages from clients are queued
+// as commands on the `cmds` list, or with several
+// sessions, on the list of the client's session
+#[derive(Clone)]
+pub struct WebSocketBackend {
+    store: Arc<Mutex<Store>>,
+    updates: broadcast::Sender<Keyed>,
+}
+
+impl WebSocketBackend {
+    pub fn new(addr: &str, designs_dir: Option<&str>, encoding: Encoding, sessions: usize) -> SyncResult<WebSocketBackend> {
+        let mut store = Store::new(encoding);
+        if let Some(dir) = designs_dir {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    let design_id = path.file_stem().unwrap().to_string_lossy().to_string();
+                    store.values.insert(format!("design:{}", design_id), fs::read(&path)?);
+                }
+            }
+        }
+        let store = Arc::new(Mutex::new(store));
+        let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+
+        // Bind here so that address
+        // errors surface immediately
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        let listener = runtime.block_on(TcpListener::bind(addr))?;
+        info!("Serving WebSocket clients on {:?}", addr);
+
+        let (server_store, server_updates) = (store.clone(), updates.clone());
+        thread::spawn(move || {
+            runtime.block_on(serve(listener, server_store, server_updates, sessions));
+        });
+
+        Ok(WebSocketBackend {
+            store: store,
+            updates: updates,
+        })
+    }
+
+    // Apply a change and broadcast the changed key.
+    // Broadcasting while holding the lock keeps
+    // updates in order with client snapshots
+    fn update<F>(&self, key: &str, change: F) where F: FnOnce(&mut Store) -> Option<Message> {
+        let mut store = self.store.lock().unwrap();
+        if let Some(msg) = change(&mut store) {
+            // Fails only if there are no clients
+            let _ = self.updates.send((key.to_string(), msg));
+        }
+    }
+}
+
+impl SyncBackend for WebSocketBackend {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        Ok(self.store.lock().unwrap().values.get(key).map(|v| String::from_utf8_lossy(v).to_string()))
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.update(key, |store| {
+            store.values.insert(key.to_string(), value.to_vec());
+            Some(store.value_msg(key))
+        });
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.update(key, |store| {
+            let existed = store.values.remove(key).is_some()
+                | store.lists.remove(key).is_some()
+                | store.hashes.remove(key).is_some();
+            if existed {
+                Some(store.message(&Update::Del { key: key }))
+            } else {
+                None
+            }
+        });
+        Ok(())
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.update(key, |store| {
+            store.lists.entry(key.to_string()).or_default().push_front(value.to_vec());
+            Some(store.list_msg(key))
+        });
+        Ok(())
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        let mut value = None;
+        self.update(key, |store| {
+            value = store.lists.get_mut(key).and_then(|list| list.pop_front());
+            if value.is_some() {
+                Some(store.list_msg(key))
+            } else {
+                None
+            }
+        });
+        Ok(value.map(|v| String::from_utf8_lossy(&v).to_string()))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.update(key, |store| {
+            store.hashes.entry(key.to_string()).or_default().insert(field.to_string(), value.to_vec());
+            Some(store.hash_msg(key))
+        });
+        Ok(())
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        let store = self.store.lock().unwrap();
+        Ok(store.hashes.get(key).map_or_else(HashMap::new, |hash| {
+            hash.iter().map(|(f, v)| (f.clone(), String::from_utf8_lossy(v).to_string())).collect()
+        }))
+    }
+}
+
+async fn serve(listener: TcpListener, store: Arc<Mutex<Store>>, updates: broadcast::Sender<Keyed>, sessions: usize) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_client(stream, store.clone(), updates.clone(), sessions));
+            }
+            Err(err) => warn!("Failed to accept WebSocket client: {:?}", err),
+        }
+    }
+}
+
+// Accepts clients of a hosted session, noting
+// which, and turns away the rest
+struct SessionHandshake<'a> {
+    session: &'a mut Option<String>,
+    sessions: usize,
+}
+
+impl<'a> Callback for SessionHandshake<'a> {
+    fn on_request(self, req: &Request, resp: Response) -> Result<Response, ErrorResponse> {
+        *self.session = client_session(req.uri().path(), self.sessions);
+        match self.session {
+            Some(_) => Ok(resp),
+            None => {
+                let mut resp = ErrorResponse::new(Some("No such session".to_string()));
+                *resp.status_mut() = StatusCode::NOT_FOUND;
+                Err(resp)
+            }
+        }
+    }
+}
+
+async fn handle_client(stream: TcpStream, store: Arc<Mutex<Store>>, updates: broadcast::Sender<Keyed>, sessions: usize) {
+    let mut session = None;
+    let handshake = tokio_tungstenite::accept_hdr_async(stream, SessionHandshake {
+        session: &mut session,
+        sessions: sessions,
+    });
+    let ws = match handshake.await {
+        Ok(ws) => ws,
+        Err(_) if session.is_none() => {
+            warn!("Rejected WebSocket client for an unknown session");
+            return;
+        }
+        Err(err) => {
+            warn!("WebSocket handshake failed: {:?}", err);
+            return;
+        }
+    };
+    let session = session.unwrap_or_default();
+    let (mut sink, mut source) = ws.split();
+
+    // Subscribe while holding the lock so
+    // no update is missed or sent twice
+    let (snapshot, mut receiver) = {
+        let store = store.lock().unwrap();
+        (store.snapshot(), updates.subscribe())
+    };
+    let mut tokens = HashSet::new();
+    for (key, msg) in snapshot {
+        if visible(&key, &session, &tokens) && sink.send(msg).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            update = receiver.recv() => {
+                let msgs = match update {
+                    Ok(msg) if !streamed(&msg.0) => vec![],
+                    Ok(msg) => vec![msg],
+
+                    // The client fell behind, so catch it up.
+                    // Messages are absolute, so a snapshot suffices
+                    Err(broadcast::error::RecvError::Lagged(_)) => store.lock().unwrap().snapshot(),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                for (key, msg) in msgs {
+                    if visible(&key, &session, &tokens) && sink.send(msg).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            msg = source.next() => match msg {
+                Some(Ok(Message::Text(cmd))) => {
+                    let parsed = serde_json::from_str::<Value>(&cmd).unwrap_or(Value::Null);
+
+                    // Clients in the lobby have
+                    // no session to send commands to
+                    if sessions > 1 && session.is_empty() {
+                        warn!("Rejected command from a client without a session");
+                        continue;
+                    }
+
+                    // Once a client uses a token, it's sent
+                    // that player's state, now and as it changes
+                    let mut private = Vec::new();
+                    if let Some(token) = command_token(&parsed) {
+                        if tokens.insert(token.to_string()) {
+                            let player = format!("player:{}:", token);
+                            private = store.lock().unwrap().snapshot().into_iter()
+                                .filter(|(key, _)| scope(key) == session && unscoped(key).starts_with(&player))
+                                .collect();
+                        }
+                    }
+
+                    // Admin commands are wrapped, as `{"Admin": <command>}`
+                    let (key, cmd) = match parsed {
+                        Value::Object(mut obj) if obj.len() == 1 && obj.contains_key("Admin") => {
+                            ("admin_cmds", obj.remove("Admin").unwrap().to_string())
+                        }
+                        _ => ("cmds", cmd.to_string()),
+                    };
+                    let key = format!("{}{}", session, key);
+                    store.lock().unwrap().lists.entry(key).or_default().push_back(cmd.into_bytes());
+                    for (_, msg) in private {
+                        if sink.send(msg).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+
+                // Pings are answered by tungstenite
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
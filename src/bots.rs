@@ -0,0 +1,147 @@
+use super::arena::{TenantId, UnitId};
+use super::play::Command;
+use super::sim::Simulation;
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Vacant units a bot looks at
+// when it thinks about moving
+static UNITS_CONSIDERED: usize = 20;
+
+static TOKEN_LENGTH: usize = 24;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct BotConfig {
+    // Bots join every play session
+    pub n: usize,
+
+    // Bots wait this long between actions,
+    // as if someone were thinking them over
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+
+    // Chance each turn that a bot
+    // looks for a better place to live
+    pub p_move: f32,
+
+    // Chance each turn that a bot puts
+    // this share of its savings into DOMA
+    pub p_contribute: f32,
+    pub contribute_share: f32,
+}
+
+// An AI player, which plays a tenant. Bots send
+// the same commands as players, with their own
+// token, so they go through the same checks
+pub struct Bot {
+    pub id: String,
+
+    // The key the bot registers with,
+    // then the token it's issued
+    pub token: String,
+    pub tenant: TenantId,
+    pub(crate) actions: VecDeque<(Instant, Command)>,
+}
+
+impl Bot {
+    // Keys don't come from the seeded rng,
+    // which anyone with the config could replay
+    pub fn new(i: usize, tenant: TenantId) -> Bot {
+        Bot {
+            id: format!("bot:{}", i),
+            token: rand::thread_rng().sample_iter(&Alphanumeric).take(TOKEN_LENGTH).collect(),
+            tenant: tenant,
+            actions: VecDeque::new(),
+        }
+    }
+
+    // Decide this turn's actions. Bots without
+    // a home always look for one
+    pub fn plan(&mut self, sim: &Simulation, conf: &BotConfig, rng: &mut StdRng) {
+        let tenant = &sim.tenants[self.tenant];
+        let mut commands = vec![Command::Heartbeat(self.id.clone())];
+        if tenant.unit.is_none() || rng.gen::<f32>() < conf.p_move {
+            if let Some(u_id) = self.better_unit(sim, rng) {
+                commands.push(Command::MoveTenant(self.id.clone(), u_id));
+            }
+        }
+        if tenant.savings > 0. && rng.gen::<f32>() < conf.p_contribute {
+            commands.push(Command::DOMAAdd(self.id.clone(), tenant.savings * conf.contribute_share));
+        }
+        let neighborhood = sim.city.neighborhoods.choose(rng).map(|n| n.id as usize);
+        commands.push(Command::DOMAVote(self.id.clone(), neighborhood, rng.gen_range(0., 0.2), rng.gen()));
+        commands.push(Command::EndTurn(self.id.clone()));
+
+        let mut at = Instant::now();
+        self.actions = commands.into_iter().map(|cmd| {
+            at += Duration::from_millis(rng.gen_range(conf.min_delay_ms, conf.max_delay_ms + 1));
+            (at, cmd)
+        }).collect();
+    }
+
+    // The most desirable of a sample of
+    // vacant units the tenant can afford,
+    // if it's better than where they are
+    fn better_unit(&self, sim: &Simulation, rng: &mut StdRng) -> Option<UnitId> {
+        let tenant = &sim.tenants[self.tenant];
+        let current = tenant.unit.map_or(-1., |u_id| tenant.desirability(&sim.city.units[u_id], &sim.city, &sim.conf));
+        let vacant: Vec<UnitId> = sim.city.vacant_units().iter().cloned()
+            .filter(|&u_id| tenant.adjusted_rent(&sim.city.units[u_id]) < tenant.income)
+            .collect();
+        vacant.choose_multiple(rng, UNITS_CONSIDERED)
+            .map(|&u_id| (u_id, tenant.desirability(&sim.city.units[u_id], &sim.city, &sim.conf)))
+            .filter(|&(_, desirability)| desirability > current)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(u_id, _)| u_id)
+    }
+
+    // Actions that are due
+    pub(crate) fn due(&mut self) -> Vec<Command> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while self.actions.front().is_some_and(|(at, _)| *at <= now) {
+            due.push(self.actions.pop_front().unwrap().1);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::fixture;
+    use rand::SeedableRng;
+
+    // A homeless bot always looks for a home,
+    // and only among units that are vacant
+    #[test]
+    fn plan_homeless() {
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        let tenant = &mut sim.tenants[t_id];
+        sim.city.move_out(tenant, &sim.conf);
+        sim.tenants[t_id].income = f32::MAX;
+
+        let mut conf = sim.conf.bots.clone();
+        conf.min_delay_ms = 0;
+        conf.max_delay_ms = 0;
+        conf.p_move = 0.;
+        let mut bot = Bot::new(0, t_id);
+        bot.plan(&sim, &conf, &mut SeedableRng::seed_from_u64(0));
+
+        let commands: Vec<&Command> = bot.actions.iter().map(|(_, cmd)| cmd).collect();
+        assert!(commands.iter().any(|cmd| match cmd {
+            Command::MoveTenant(_, u_id) => sim.city.has_vacancy(*u_id),
+            _ => false,
+        }));
+        assert!(matches!(commands.last(), Some(Command::EndTurn(_))));
+        let n_commands = commands.len();
+        assert_eq!(bot.due().len(), n_commands);
+    }
+}
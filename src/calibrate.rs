@@ -0,0 +1,373 @@
+use super::config::Config;
+use super::design::{validate_or_exit, Design};
+use super::events::SimEvent;
+use super::generate::CityParams;
+use super::sim::Simulation;
+use super::stats::{self, percentile};
+use super::sweep::{base_design, combinations, setup_run};
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serde_yaml::Value as YamlValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use tracing::info;
+
+// How to search the parameter space
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Search {
+    // Every combination of parameter values
+    #[default]
+    Grid,
+
+    // A population of parameter sets, keeping
+    // the best half each generation and mutating
+    // them to replace the rest
+    Evolve,
+}
+
+// Values a parameter can take: either a list,
+// or a range, which grid searches split into
+// `steps` evenly spaced values
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ParamSpace {
+    Values(Vec<YamlValue>),
+    Range {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        steps: Option<usize>,
+
+        // Round values, for integer parameters
+        #[serde(default)]
+        integer: bool,
+    },
+}
+
+impl ParamSpace {
+    fn grid(&self) -> Vec<YamlValue> {
+        match self {
+            ParamSpace::Values(vals) => vals.clone(),
+            ParamSpace::Range { min, max, steps, integer } => {
+                let steps = steps.unwrap_or(5).max(2);
+                (0..steps)
+                    .map(|i| number(min + (max - min) * i as f64 / (steps - 1) as f64, *integer))
+                    .collect()
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> YamlValue {
+        match self {
+            ParamSpace::Values(vals) => vals[rng.gen_range(0, vals.len())].clone(),
+            ParamSpace::Range { min, max, integer, .. } => number(rng.gen_range(*min, *max), *integer),
+        }
+    }
+
+    // A range value nudged by noise scaled to the range,
+    // or with probability `mutation`, a new list value
+    fn mutate(&self, val: &YamlValue, mutation: f64, rng: &mut StdRng) -> YamlValue {
+        match self {
+            ParamSpace::Values(_) => {
+                if rng.gen::<f64>() < mutation {
+                    self.sample(rng)
+                } else {
+                    val.clone()
+                }
+            }
+            ParamSpace::Range { min, max, integer, .. } => {
+                let noise = Normal::new(0., mutation * (max - min)).unwrap();
+                let v = val.as_f64().unwrap() + noise.sample(rng);
+                number(v.max(*min).min(*max), *integer)
+            }
+        }
+    }
+}
+
+fn number(v: f64, integer: bool) -> YamlValue {
+    if integer {
+        YamlValue::Number((v.round() as i64).into())
+    } else {
+        YamlValue::Number(v.into())
+    }
+}
+
+// A metric to match, weighted
+// against the other targets
+#[derive(Deserialize, Debug)]
+pub struct Target {
+    pub value: f32,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.
+}
+
+// Searches for the parameters that best reproduce
+// target metrics, e.g. observed vacancy rates and rent
+// growth, running each parameter set for every seed
+#[derive(Deserialize, Debug)]
+pub struct Calibration {
+    // Path to a design JSON file, or parameters
+    // to generate a city from, as in sweeps
+    #[serde(default)]
+    pub design: Option<String>,
+    #[serde(default)]
+    pub generate: Option<CityParams>,
+
+    pub steps: usize,
+    pub seeds: Vec<u64>,
+
+    #[serde(default)]
+    pub search: Search,
+
+    // For evolutionary searches: how many generations
+    // to run, parameter sets per generation, how far
+    // to mutate them, and the seed for doing so
+    #[serde(default = "default_generations")]
+    pub generations: usize,
+    #[serde(default = "default_population")]
+    pub population: usize,
+    #[serde(default = "default_mutation")]
+    pub mutation: f64,
+    #[serde(default)]
+    pub seed: u64,
+
+    // Metrics to match. Besides `vacancy_rate`,
+    // `rent_growth`, and `mobility_rate`, any numeric
+    // top-level stat can be a target
+    pub targets: BTreeMap<String, Target>,
+
+    // Parameters to search over, keyed as in sweeps
+    pub params: BTreeMap<String, ParamSpace>,
+}
+
+fn default_generations() -> usize {
+    10
+}
+
+fn default_population() -> usize {
+    8
+}
+
+fn default_mutation() -> f64 {
+    0.1
+}
+
+pub fn load_calibration(path: &str) -> Calibration {
+    let file = File::open(path).expect("could not open calibration file");
+    let reader = BufReader::new(file);
+    let cal: Calibration = serde_yaml::from_reader(reader).expect("error while reading yaml");
+    let errors = validate(&cal);
+    if !errors.is_empty() {
+        for err in errors {
+            eprintln!("Invalid calibration {}: {}", path, err);
+        }
+        std::process::exit(1);
+    }
+    cal
+}
+
+// Parameter spaces there's nothing to sample from,
+// which would otherwise panic partway through a search
+fn validate(cal: &Calibration) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (key, space) in &cal.params {
+        match space {
+            ParamSpace::Values(vals) if vals.is_empty() =>
+                errors.push(format!("{} has no values", key)),
+            ParamSpace::Range { min, max, .. } if !min.is_finite() || !max.is_finite() || min >= max =>
+                errors.push(format!("{} has range {} to {}, expected min less than max", key, min, max)),
+            _ => {},
+        }
+    }
+    if !(0. ..).contains(&cal.mutation) {
+        errors.push(format!("mutation is {}, expected at least 0", cal.mutation));
+    }
+    errors
+}
+
+type Params = Vec<(String, YamlValue)>;
+
+// A parameter set and how closely
+// its runs matched the targets
+#[derive(Clone)]
+struct Fit {
+    params: Params,
+    metrics: BTreeMap<String, f32>,
+    loss: f32,
+    generation: usize,
+}
+
+// Median rent per area of occupied units
+fn median_rent(sim: &Simulation) -> f32 {
+    let mut rents: Vec<f32> = sim.city.units.iter()
+        .filter(|u| !u.vacant())
+        .map(|u| u.rent_per_area())
+        .collect();
+    percentile(&mut rents, 0.5)
+}
+
+// Runs the simulation and measures each target.
+// Vacancy and stats targets are averaged over the last
+// year; rent growth is annualized over the whole run,
+// and mobility is moves per housed resident per year
+fn measure(cal: &Calibration, mut sim: Simulation) -> BTreeMap<String, f32> {
+    let steps_per_year = sim.conf.time_step.steps_per_year();
+    let last_year = cal.steps.saturating_sub(steps_per_year);
+    let start_rent = median_rent(&sim);
+    let mut moves = 0;
+    let mut resident_steps = 0;
+    let mut sums: BTreeMap<&String, f32> = BTreeMap::new();
+    for step in 0..cal.steps {
+        sim.step();
+        moves += sim.city.events.iter()
+            .filter(|ev| matches!(ev, SimEvent::MovedIn { from: Some(_), .. }))
+            .count();
+        resident_steps += sim.city.units.iter().fold(0, |acc, u| acc + u.tenants.len());
+        if step < last_year {
+            continue;
+        }
+        let step_stats = stats::stats(&sim);
+        for name in cal.targets.keys() {
+            let val = match name.as_str() {
+                "vacancy_rate" => step_stats["percent_vacant"].as_f64(),
+                "rent_growth" | "mobility_rate" => continue,
+                _ => step_stats[name].as_f64(),
+            };
+            let val = val.unwrap_or_else(|| panic!("unknown calibration target: {}", name));
+            *sums.entry(name).or_insert(0.) += val as f32;
+        }
+    }
+
+    let years = cal.steps as f32 / steps_per_year as f32;
+    let n = (cal.steps - last_year) as f32;
+    cal.targets.keys().map(|name| {
+        let val = match name.as_str() {
+            "rent_growth" => (median_rent(&sim) / start_rent).powf(1. / years) - 1.,
+            "mobility_rate" => moves as f32 / (resident_steps as f32 / steps_per_year as f32).max(1.),
+            _ => sums[name] / n,
+        };
+        (name.clone(), val)
+    }).collect()
+}
+
+// Weighted squared error relative to each target
+fn loss(cal: &Calibration, metrics: &BTreeMap<String, f32>) -> f32 {
+    cal.targets.iter().fold(0., |acc, (name, target)| {
+        let scale = if target.value != 0. { target.value.abs() } else { 1. };
+        acc + target.weight * ((metrics[name] - target.value) / scale).powi(2)
+    })
+}
+
+// Runs each parameter set for every seed,
+// averaging metrics across seeds
+fn evaluate(cal: &Calibration, conf: &Config, design: &Option<Design>, candidates: Vec<Params>, generation: usize) -> Vec<Fit> {
+    for params in &candidates {
+        validate_or_exit(&setup_run(&cal.generate, conf, design, params).1);
+    }
+    let runs: Vec<(usize, u64)> = (0..candidates.len())
+        .flat_map(|i| cal.seeds.iter().map(move |&seed| (i, seed)))
+        .collect();
+    let measured: Vec<BTreeMap<String, f32>> = runs
+        .par_iter()
+        .map(|&(i, seed)| {
+            let (mut run_conf, run_design) = setup_run(&cal.generate, conf, design, &candidates[i]);
+            run_conf.seed = seed;
+            run_conf.steps = cal.steps;
+            let mut rng: StdRng = SeedableRng::seed_from_u64(seed);
+            measure(cal, Simulation::new(run_design, run_conf, &mut rng))
+        })
+        .collect();
+
+    candidates.into_iter().enumerate().map(|(i, params)| {
+        let mut metrics: BTreeMap<String, f32> = BTreeMap::new();
+        for (_, m) in runs.iter().zip(measured.iter()).filter(|((c, _), _)| *c == i) {
+            for (name, val) in m {
+                *metrics.entry(name.clone()).or_insert(0.) += val / cal.seeds.len() as f32;
+            }
+        }
+        let loss = loss(cal, &metrics);
+        Fit { params: params, metrics: metrics, loss: loss, generation: generation }
+    }).collect()
+}
+
+fn by_loss(fits: &mut [Fit]) {
+    fits.sort_by(|a, b| a.loss.total_cmp(&b.loss));
+}
+
+fn evolve(cal: &Calibration, conf: &Config, design: &Option<Design>) -> Vec<Fit> {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(cal.seed);
+    let initial = (0..cal.population)
+        .map(|_| cal.params.iter().map(|(key, space)| (key.clone(), space.sample(&mut rng))).collect())
+        .collect();
+    let mut population = evaluate(cal, conf, design, initial, 0);
+    let mut all = population.clone();
+    for generation in 1..cal.generations {
+        by_loss(&mut population);
+        info!("Generation {:?}: best loss {:?}", generation - 1, population[0].loss);
+        population.truncate(cal.population.div_ceil(2));
+        let children = (0..cal.population - population.len())
+            .map(|_| {
+                let parent = &population[rng.gen_range(0, population.len())];
+                parent.params.iter().map(|(key, val)| {
+                    (key.clone(), cal.params[key].mutate(val, cal.mutation, &mut rng))
+                }).collect()
+            })
+            .collect();
+        let fits = evaluate(cal, conf, design, children, generation);
+        all.extend(fits.iter().cloned());
+        population.extend(fits);
+    }
+    all
+}
+
+pub fn run_calibration(cal: &Calibration, conf: &Config) {
+    let design = base_design(&cal.design, &cal.generate);
+    let now: DateTime<Utc> = Utc::now();
+    let dir = format!("runs/calibrate_{}", now.format("%Y.%m.%d.%H.%M.%S"));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut fits = match cal.search {
+        Search::Grid => {
+            let grid: BTreeMap<String, Vec<YamlValue>> = cal.params.iter().map(|(key, space)| (key.clone(), space.grid())).collect();
+            let combos = combinations(&grid);
+            info!("Running {:?} runs ({:?} combinations x {:?} seeds)...", combos.len() * cal.seeds.len(), combos.len(), cal.seeds.len());
+            evaluate(cal, conf, &design, combos, 0)
+        }
+        Search::Evolve => {
+            info!("Running {:?} generations of {:?} x {:?} seeds...", cal.generations, cal.population, cal.seeds.len());
+            evolve(cal, conf, &design)
+        }
+    };
+    by_loss(&mut fits);
+
+    let results: Vec<Value> = fits.iter().map(|fit| {
+        let param_vals: BTreeMap<&String, &YamlValue> = fit.params.iter().map(|(k, v)| (k, v)).collect();
+        json!({
+            "params": param_vals,
+            "metrics": fit.metrics,
+            "loss": fit.loss,
+            "generation": fit.generation,
+        })
+    }).collect();
+    fs::write(format!("{}/results.json", dir), Value::Array(results).to_string()).expect("Unable to write file");
+
+    // The config with the best parameters applied.
+    // Design and generation parameters are only in the results
+    let best = &fits[0];
+    let (best_conf, _) = setup_run(&cal.generate, conf, &design, &best.params);
+    fs::write(format!("{}/best.yaml", dir), serde_yaml::to_string(&best_conf).unwrap()).expect("Unable to write file");
+    info!("Best fit (loss {:?}): {:?}", best.loss, best.params);
+    info!("Wrote calibration output to {:?}", dir);
+}
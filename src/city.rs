@@ -1,15 +1,23 @@
 use rand::Rng;
 use std::cmp::{max, min};
+use std::collections::BTreeSet;
 use std::str::FromStr;
-use super::design::{Design, Neighborhood};
+use super::arena::{Arena, IndexedSet, TenantId, UnitId};
+use super::config::Config;
+use super::design::{Amenity, Design, Neighborhood, TransitLine};
 use super::grid::{HexGrid, Position};
-use super::agent::{AgentType};
+use super::agent::{AgentType, Tenant};
+use super::events::SimEvent;
+use super::lease::{Deposit, Lease};
+use super::transit::CommuteTimes;
 use strum_macros::{EnumString, Display};
-use fnv::{FnvHashMap, FnvHashSet};
+use fnv::FnvHashMap;
 use noise::{OpenSimplex, Seedable};
 use rand::rngs::StdRng;
 use rand_distr::{Beta, Distribution};
+use serde::{Serialize, Deserialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct PositionVector<T: Clone> {
     dims: (isize, isize),
     data: Vec<Option<T>>
@@ -57,6 +65,21 @@ impl<T: Clone> PositionVector<T> {
         self.data[i].as_mut()
     }
 
+    // Grow to new dimensions, keeping
+    // everything at its position
+    pub fn resize(&mut self, dims: (usize, usize)) {
+        let prev_cols = self.dims.1;
+        let data = std::mem::replace(&mut self.data, vec![None; dims.0 * dims.1]);
+        self.dims = (dims.0 as isize, dims.1 as isize);
+        for (i, val) in data.into_iter().enumerate() {
+            if let Some(val) = val {
+                let pos = (i as isize / prev_cols, i as isize % prev_cols);
+                let j = self.pos_to_index(&pos);
+                self.data[j] = Some(val);
+            }
+        }
+    }
+
     fn pos_to_index(&self, pos: &Position) -> usize {
         (self.dims.1 * pos.0 + pos.1) as usize
     }
@@ -67,7 +90,7 @@ impl<T: Clone> PositionVector<T> {
     }
 }
 
-#[derive(Display, PartialEq, Debug, EnumString, Clone)]
+#[derive(Display, PartialEq, Debug, EnumString, Clone, Serialize, Deserialize)]
 pub enum ParcelType {
     Residential,
     Industrial,
@@ -75,29 +98,117 @@ pub enum ParcelType {
     River
 }
 
-#[derive(Debug, Clone)]
+// What a parcel may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LandUse {
+    Residential,
+    Commercial,
+    #[default]
+    Mixed,
+}
+
+// Limits on what can be built on a parcel.
+// Buildings that predate them are left as they are
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Zoning {
+    // If none, the neighborhood's maxUnits
+    pub max_units: Option<usize>,
+
+    #[serde(rename = "use")]
+    pub land_use: LandUse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Parcel {
     pub typ: ParcelType,
     pub desirability: f32,
     pub neighborhood: Option<usize>,
-    pub pos: Position
+    pub pos: Position,
+    pub tax_rate: f32, // annual, percent of value
+
+    // Contribution of nearby
+    // amenities to desirability
+    pub amenities: f32,
+
+    pub zoning: Zoning
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct City {
     pub grid: HexGrid,
     pub buildings: PositionVector<Building>,
     pub parcels: PositionVector<Parcel>,
-    pub units: Vec<Unit>,
-    pub units_by_neighborhood: Vec<Vec<usize>>,
+    pub units: Arena<UnitId, Unit>,
+    pub units_by_neighborhood: Vec<Vec<UnitId>>,
     pub residential_parcels_by_neighborhood: Vec<Vec<Position>>,
     pub commercial: PositionVector<usize>,
+
+    // Where the jobs are, i.e. the design's employment
+    // centers, or if it has none, commercial space
+    pub work_positions: Vec<Position>,
     pub neighborhoods: Vec<Neighborhood>,
-    pub neighborhood_trends: Vec<OpenSimplex>
+    pub amenities: Vec<Amenity>,
+    pub transit: Vec<TransitLine>,
+    pub neighborhood_trend_seeds: Vec<u32>,
+
+    // Rebuilt from the seeds when restoring a checkpoint
+    #[serde(skip)]
+    pub neighborhood_trends: Vec<OpenSimplex>,
+
+    // Rebuilt from the transit lines
+    // when restoring a checkpoint
+    #[serde(skip)]
+    pub commute_times: CommuteTimes,
+
+    // Units listed with room for more tenants,
+    // citywide and by neighborhood. Kept up to date
+    // as tenants move in and out, and saved in
+    // their order so restored runs sample the same
+    vacant: IndexedSet<UnitId>,
+    vacant_by_neighborhood: Vec<IndexedSet<UnitId>>,
+
+    // Bumped whenever the map grows,
+    // so the frontend knows to redraw it
+    #[serde(default)]
+    pub map_version: usize,
+
+    // What happened this step
+    #[serde(skip)]
+    pub events: Vec<SimEvent>
 }
 
 
+fn neighborhood_trends(seeds: &[u32]) -> Vec<OpenSimplex> {
+    seeds.iter().map(|&seed| OpenSimplex::new().set_seed(seed)).collect()
+}
+
+// Rivers can't be walked across,
+// except by bridges, i.e. transit
+fn walkable(parcels: &PositionVector<Parcel>, pos: Position) -> bool {
+    parcels.get(&pos).is_none_or(|p| p.typ != ParcelType::River)
+}
+
+// Amenities within walking distance contribute
+// less the further away they are
+fn amenity_score(grid: &HexGrid, parcels: &PositionVector<Parcel>, pos: Position, amenities: &[Amenity], conf: &Config) -> f32 {
+    if amenities.is_empty() {
+        return 0.;
+    }
+    let dists = grid.reachable(pos, conf.amenity_radius as usize, |p| walkable(parcels, p));
+    amenities.iter().fold(0., |acc, a| {
+        match dists.get(&(a.row as isize, a.col as isize)) {
+            Some(&dist) => {
+                let weight = conf.amenity_weights.get(&a.typ).cloned().unwrap_or(0.);
+                acc + weight * (1. - dist as f32/(conf.amenity_radius + 1.))
+            }
+            None => acc
+        }
+    })
+}
+
 impl City {
-    pub fn new(design: &Design, rng: &mut StdRng) -> City {
+    pub fn new(design: &Design, conf: &Config, rng: &mut StdRng) -> City {
         let rows = design.map.layout.len();
         let cols = design.map.layout[0].len();
         let grid = HexGrid::new(rows, cols);
@@ -120,24 +231,32 @@ impl City {
                         let parts: Vec<&str> = parcel_str.split("|").collect();
                         let neighb_id: i32 = parts[0].parse().unwrap();
                         let parcel_type = ParcelType::from_str(parts[1]).unwrap();
+                        let neighborhood = match neighb_id {
+                            -1 => None,
+                            id => {
+                                // Sometimes parcels have neighborhood ids
+                                // which have no specification in the design,
+                                // return None in that case
+                                let k = id as usize;
+                                if !neighb_ids.contains_key(&k) {
+                                    None
+                                } else {
+                                    Some(neighb_ids[&k])
+                                }
+                            }
+                        };
+                        let tax_rate = match neighborhood {
+                            Some(n) => neighborhoods[n].tax_rate.unwrap_or(conf.property_tax_rate),
+                            None => conf.property_tax_rate
+                        };
                         let parcel = Parcel {
                             pos: (r as isize, c as isize),
                             typ: parcel_type,
                             desirability: 0.,
-                            neighborhood: match neighb_id {
-                                -1 => None,
-                                id => {
-                                    // Sometimes parcels have neighborhood ids
-                                    // which have no specification in the design,
-                                    // return None in that case
-                                    let k = id as usize;
-                                    if !neighb_ids.contains_key(&k) {
-                                        None
-                                    } else {
-                                        Some(neighb_ids[&k])
-                                    }
-                                }
-                            }
+                            neighborhood: neighborhood,
+                            tax_rate: tax_rate,
+                            amenities: 0.,
+                            zoning: Zoning::default()
                         };
                         let pos = (r as isize, c as isize);
                         parcels.insert(&pos, parcel);
@@ -147,7 +266,19 @@ impl City {
             }
         }
 
-        let mut units = Vec::new();
+        // Later zones take precedence
+        for zone in &design.zoning {
+            for &(r, c) in &zone.parcels {
+                if let Some(parcel) = parcels.get_mut(&(r as isize, c as isize)) {
+                    parcel.zoning = Zoning {
+                        max_units: zone.max_units,
+                        land_use: zone.land_use,
+                    };
+                }
+            }
+        }
+
+        let mut units: Arena<UnitId, Unit> = Arena::new();
         let mut buildings = PositionVector::new((rows, cols));
         let mut commercial = PositionVector::new((rows, cols));
         let mut units_by_neighborhood = Vec::new();
@@ -155,11 +286,9 @@ impl City {
 
         // Group units by neighborhood for lookup
         // and create neighborhood desirability trends
-        let mut neighborhood_trends = Vec::new();
+        let mut neighborhood_trend_seeds = Vec::new();
         for _ in neighb_ids.values() {
-            let mut noise = OpenSimplex::new();
-            noise = noise.set_seed(rng.gen());
-            neighborhood_trends.push(noise);
+            neighborhood_trend_seeds.push(rng.gen());
             units_by_neighborhood.push(Vec::new());
             residential_parcels_by_neighborhood.push(Vec::new());
         }
@@ -176,9 +305,14 @@ impl City {
                 nei_des_min = n.desirability;
             }
         }
+        // If they're all the same, they're all 1
         let nei_des_range = nei_des_max - nei_des_min;
         for n in &mut neighborhoods {
-            n.desirability = 1. + (n.desirability - nei_des_min)/(nei_des_range) - 0.5;
+            n.desirability = if nei_des_range > 0. {
+                1. + (n.desirability - nei_des_min)/(nei_des_range) - 0.5
+            } else {
+                1.
+            };
         }
 
         // Prepare buildings and units
@@ -203,7 +337,8 @@ impl City {
                         n_commercial = (total_floors - n_floors) as u32;
                     }
 
-                    let mut building_units: Vec<usize> = Vec::new();
+                    let year_built = conf.start_year - rng.gen_range(0, conf.max_building_age);
+                    let mut building_units: Vec<UnitId> = Vec::new();
                     for _ in 0..n_units {
                         let area = rng.gen_range(neighb.min_area, neighb.max_area) as f32;
                         let value = design.city.price_per_sqm*area*neighb.desirability;
@@ -214,7 +349,7 @@ impl City {
                         let sampled_occupancy = occupancy_dist.sample(rng) * design.city.max_bedrooms as f32;
                         let occupancy = max(1,
                                             min(area_div.round() as usize, sampled_occupancy.round() as usize));
-                        let id = units.len();
+                        let id = units.next_id();
                         let unit = Unit {
                             id: id,
                             pos: p.pos,
@@ -222,12 +357,22 @@ impl City {
                             occupancy: occupancy,
                             area: area,
                             value: value,
+                            est_value: value,
                             condition: 1.0,
-                            tenants: FnvHashSet::default(),
+                            max_condition: 1.0,
+                            retrofitted: false,
+                            year_built: year_built,
+                            maintenance_history: 0.,
+                            tenants: BTreeSet::new(),
                             offers: Vec::new(),
                             months_vacant: 0,
-                            lease_month: 0,
+                            lease: None,
+                            fees: 0.,
                             recently_sold: false,
+                            short_term: false,
+                            dividend: 0.,
+                            retired: false,
+                            accepts_vouchers: true,
                             owner: (AgentType::Landlord, 0) // Dummy placeholder
                         };
                         units_by_neighborhood[neighb_id].push(id);
@@ -252,9 +397,18 @@ impl City {
         let mut total = 0.;
         let mut count = 0;
         let parks: Vec<Position> = parcels.values().filter(|p| p.typ == ParcelType::Park).into_iter().map(|p| p.pos).collect();
-        for p in parcels.values_mut().filter(|p| p.typ == ParcelType::Residential) {
+        let park_dists = grid.distance_field(&parks, None, |pos| walkable(&parcels, pos));
+        let amenity_scores: Vec<f32> = parcels.values()
+            .filter(|p| p.typ == ParcelType::Residential)
+            .map(|p| amenity_score(&grid, &parcels, p.pos, &design.amenities, conf))
+            .collect();
+        for (p, amenities) in parcels.values_mut().filter(|p| p.typ == ParcelType::Residential).zip(amenity_scores) {
+            p.amenities = amenities;
+
+            // Parks across a river are
+            // as good as none at all
             let park_dist = if parks.len() > 0 {
-                parks.iter().map(|&o| grid.distance(p.pos, o)).fold(1./0., f32::min)
+                park_dists[grid.index(p.pos)].map_or(1./0., |dist| dist as f32)
             } else {
                 1.
             };
@@ -272,7 +426,7 @@ impl City {
                 Some(n) => neighborhoods[n].desirability,
                 _ => 0.
             };
-            p.desirability = (1./park_dist * 10.) + neighb + (n_commercial as f32)/10.;
+            p.desirability = (1./park_dist * 10.) + neighb + (n_commercial as f32)/10. + p.amenities;
             total += p.desirability;
             count += 1;
         }
@@ -288,22 +442,325 @@ impl City {
             for &u_id in b.units.iter() {
                 let u = &mut units[u_id];
                 u.value = design.city.price_to_rent_ratio * u.rent * 12. * parcels.get(&pos).unwrap().desirability;
+                u.est_value = u.value;
             }
         }
 
-        City {
+        let work_positions: Vec<Position> = if design.employment_centers.is_empty() {
+            commercial.iter().map(|(pos, _)| pos).collect()
+        } else {
+            design.employment_centers.iter().map(|c| (c.row as isize, c.col as isize)).collect()
+        };
+        let commute_times = CommuteTimes::new(&grid, &design.transit, &work_positions, |pos| walkable(&parcels, pos), conf);
+
+        let mut city = City {
             grid: grid,
             units: units,
             parcels: parcels,
             buildings: buildings,
             commercial: commercial,
+            work_positions: work_positions,
             neighborhoods: neighborhoods,
+            amenities: design.amenities.clone(),
+            transit: design.transit.clone(),
+            commute_times: commute_times,
             units_by_neighborhood: units_by_neighborhood,
             residential_parcels_by_neighborhood: residential_parcels_by_neighborhood,
-            neighborhood_trends: neighborhood_trends,
+            neighborhood_trends: neighborhood_trends(&neighborhood_trend_seeds),
+            neighborhood_trend_seeds: neighborhood_trend_seeds,
+            vacant: IndexedSet::new(),
+            vacant_by_neighborhood: Vec::new(),
+            map_version: 0,
+            events: Vec::new(),
+        };
+        city.index_vacancies();
+        city
+    }
+
+    // Add a new unit to the building at the given position,
+    // creating the building if necessary
+    pub fn add_unit(&mut self, pos: Position, area: f32, rent: f32, value: f32, year_built: usize, owner: (AgentType, usize)) -> UnitId {
+        let id = self.units.next_id();
+        let neighb_id = self.parcels.get(&pos).unwrap().neighborhood.unwrap();
+        let occupancy = self.occupancy_for(&pos, area);
+        self.units.push(Unit {
+            id: id,
+            pos: pos,
+            rent: rent,
+            occupancy: occupancy,
+            area: area,
+            value: value,
+            est_value: value,
+            condition: 1.0,
+            max_condition: 1.0,
+            retrofitted: false,
+            year_built: year_built,
+            maintenance_history: 0.,
+            tenants: BTreeSet::new(),
+            offers: Vec::new(),
+            months_vacant: 0,
+            lease: None,
+            fees: 0.,
+            recently_sold: false,
+            short_term: false,
+            dividend: 0.,
+            retired: false,
+            accepts_vouchers: true,
+            owner: owner
+        });
+        self.units_by_neighborhood[neighb_id].push(id);
+        self.refresh_vacancy(id);
+        if owner.0 == AgentType::Developer {
+            self.events.push(SimEvent::UnitBuilt {
+                unit: id,
+                developer: owner.1,
+            });
+        }
+        match self.buildings.get_mut(&pos) {
+            Some(building) => building.units.push(id),
+            None => self.buildings.insert(&pos, Building {
+                units: vec![id],
+                n_commercial: 0
+            })
+        }
+        id
+    }
+
+    // Split a vacant unit, carving a new unit of the given
+    // area out of it. Rent and value are divided by area,
+    // and the new unit is in the same condition
+    pub fn subdivide_unit(&mut self, unit_id: UnitId, area: f32) -> UnitId {
+        let unit = &self.units[unit_id];
+        let share = area / unit.area;
+        let (pos, year_built, owner) = (unit.pos, unit.year_built, unit.owner);
+        let (rent, value) = (unit.rent * share, unit.value * share);
+        let (condition, max_condition, maintenance_history) = (unit.condition, unit.max_condition, unit.maintenance_history);
+        let retrofitted = unit.retrofitted;
+        let occupancy = self.occupancy_for(&pos, unit.area - area);
+
+        let unit = &mut self.units[unit_id];
+        unit.area -= area;
+        unit.rent -= rent;
+        unit.value -= value;
+        unit.occupancy = min(unit.occupancy, occupancy);
+
+        let id = self.add_unit(pos, area, rent, value, year_built, owner);
+        let new_unit = &mut self.units[id];
+        new_unit.condition = condition;
+        new_unit.max_condition = max_condition;
+        new_unit.retrofitted = retrofitted;
+        new_unit.maintenance_history = maintenance_history;
+        self.refresh_vacancy(unit_id);
+        id
+    }
+
+    // Merge a vacant unit into another in the same building,
+    // which takes its area, rent, and value. The merged unit
+    // is roomier, housing as many as the larger of the two
+    pub fn merge_units(&mut self, unit_id: UnitId, into: UnitId) {
+        let unit = &mut self.units[unit_id];
+        let (pos, area, rent, value, occupancy) = (unit.pos, unit.area, unit.rent, unit.value, unit.occupancy);
+        unit.retired = true;
+        unit.occupancy = 0;
+        unit.offers.clear();
+
+        let target = &mut self.units[into];
+        target.area += area;
+        target.rent += rent;
+        target.value += value;
+        target.occupancy = max(target.occupancy, occupancy);
+
+        if let Some(building) = self.buildings.get_mut(&pos) {
+            building.units.retain(|&u_id| u_id != unit_id);
+        }
+        if let Some(neighb_id) = self.parcels.get(&pos).unwrap().neighborhood {
+            self.units_by_neighborhood[neighb_id].retain(|&u_id| u_id != unit_id);
+        }
+        self.refresh_vacancy(unit_id);
+        self.refresh_vacancy(into);
+    }
+
+    // Move a parcel into another neighborhood, taking its
+    // units along, e.g. when boundaries are redrawn.
+    // Returns the neighborhood it was in, if it moved
+    pub fn reassign_parcel(&mut self, pos: Position, neighb_id: usize, conf: &Config) -> Option<Option<usize>> {
+        let tax_rate = self.neighborhoods[neighb_id].tax_rate.unwrap_or(conf.property_tax_rate);
+        let parcel = self.parcels.get_mut(&pos)?;
+        let prev = parcel.neighborhood;
+        if prev == Some(neighb_id) {
+            return None;
+        }
+        parcel.neighborhood = Some(neighb_id);
+        parcel.tax_rate = tax_rate;
+        let residential = parcel.typ == ParcelType::Residential;
+
+        let unit_ids: Vec<UnitId> = self.buildings.get(&pos).map_or_else(Vec::new, |b| b.units.clone());
+        if let Some(prev_id) = prev {
+            self.units_by_neighborhood[prev_id].retain(|u_id| !unit_ids.contains(u_id));
+            self.residential_parcels_by_neighborhood[prev_id].retain(|&p| p != pos);
+            for &u_id in &unit_ids {
+                self.vacant_by_neighborhood[prev_id].remove(u_id);
+            }
+        }
+        for &u_id in &unit_ids {
+            self.refresh_vacancy(u_id);
+        }
+        self.units_by_neighborhood[neighb_id].extend(unit_ids);
+        if residential {
+            self.residential_parcels_by_neighborhood[neighb_id].push(pos);
+        }
+        Some(prev)
+    }
+
+    // Add rows to the bottom of the map and columns to its
+    // right, so existing positions stay where they are.
+    // The new positions are empty until parcels are added
+    pub fn expand(&mut self, rows: usize, cols: usize, conf: &Config) {
+        let dims = (self.grid.rows + rows, self.grid.cols + cols);
+        self.grid = HexGrid::new(dims.0, dims.1);
+        self.parcels.resize(dims);
+        self.buildings.resize(dims);
+        self.commercial.resize(dims);
+        self.restore_commute_times(conf);
+        self.map_version += 1;
+    }
+
+    pub fn add_neighborhood(&mut self, neighb: Neighborhood, rng: &mut StdRng) -> usize {
+        let seed = rng.gen();
+        self.neighborhoods.push(neighb);
+        self.neighborhood_trend_seeds.push(seed);
+        self.neighborhood_trends.push(OpenSimplex::new().set_seed(seed));
+        self.units_by_neighborhood.push(Vec::new());
+        self.residential_parcels_by_neighborhood.push(Vec::new());
+        self.vacant_by_neighborhood.push(IndexedSet::new());
+        self.neighborhoods.len() - 1
+    }
+
+    // Add an empty residential parcel
+    pub fn add_parcel(&mut self, pos: Position, neighb_id: usize, desirability: f32, conf: &Config) {
+        let amenities = amenity_score(&self.grid, &self.parcels, pos, &self.amenities, conf);
+        self.parcels.insert(&pos, Parcel {
+            pos: pos,
+            typ: ParcelType::Residential,
+            desirability: desirability + amenities,
+            neighborhood: Some(neighb_id),
+            tax_rate: self.neighborhoods[neighb_id].tax_rate.unwrap_or(conf.property_tax_rate),
+            amenities: amenities,
+            zoning: Zoning::default(),
+        });
+        self.residential_parcels_by_neighborhood[neighb_id].push(pos);
+    }
+
+    // Most units zoning allows on a parcel,
+    // and none where it's only for commercial use
+    pub fn max_units(&self, pos: &Position) -> usize {
+        let parcel = self.parcels.get(pos).unwrap();
+        if parcel.zoning.land_use == LandUse::Commercial {
+            return 0;
+        }
+        match (parcel.zoning.max_units, parcel.neighborhood) {
+            (Some(max_units), _) => max_units,
+            (None, Some(neighb_id)) => self.neighborhoods[neighb_id].max_units as usize,
+            (None, None) => 0,
+        }
+    }
+
+    // Units in use, leaving out retired ones
+    pub fn live_units<'a>(&'a self) -> impl Iterator<Item=&'a Unit> + 'a {
+        self.units.iter().filter(|u| !u.retired)
+    }
+
+    // Listed units with room for more tenants
+    pub fn vacant_units(&self) -> &[UnitId] {
+        self.vacant.as_slice()
+    }
+
+    // Listed units with room for more
+    // tenants in the given neighborhood
+    pub fn vacant_in(&self, neighb_id: usize) -> &[UnitId] {
+        self.vacant_by_neighborhood.get(neighb_id).map_or(&[], |vacant| vacant.as_slice())
+    }
+
+    pub fn has_vacancy(&self, unit_id: UnitId) -> bool {
+        self.vacant.contains(unit_id)
+    }
+
+    // Move a tenant out of wherever they're living,
+    // if anywhere, and into the given unit. Moves go through
+    // here and `move_out`, which keep the tenant's unit, the
+    // units' tenants and leases, and the vacancy index in step.
+    // Refuses the move, returning false, if the unit is full
+    pub fn move_tenant(&mut self, tenant: &mut Tenant, to: UnitId, time: usize, conf: &Config) -> bool {
+        if tenant.unit != Some(to) && self.units[to].vacancies() == 0 {
+            return false;
+        }
+        self.move_out(tenant, conf);
+        self.units[to].sign(&[tenant.id], time, conf);
+        self.refresh_vacancy(to);
+        tenant.unit = Some(to);
+        true
+    }
+
+    // Move a tenant out of wherever they're living, if
+    // anywhere, refunding what's left of their deposit
+    pub fn move_out(&mut self, tenant: &mut Tenant, conf: &Config) {
+        if let Some(from) = tenant.unit.take() {
+            tenant.savings += self.units[from].vacate(tenant.id, conf);
+            self.refresh_vacancy(from);
+        }
+    }
+
+    // List or delist a unit after anything that
+    // changes whether it's on the market, e.g.
+    // a sale or going short-term
+    pub fn refresh_vacancy(&mut self, unit_id: UnitId) {
+        let unit = &self.units[unit_id];
+        let listed = !unit.retired && unit.vacancies() > 0 && unit.for_rent();
+        let neighb_id = self.parcels.get(&unit.pos).and_then(|p| p.neighborhood);
+        if listed {
+            self.vacant.insert(unit_id);
+        } else {
+            self.vacant.remove(unit_id);
+        }
+        if let Some(vacant) = neighb_id.and_then(|neighb_id| self.vacant_by_neighborhood.get_mut(neighb_id)) {
+            if listed {
+                vacant.insert(unit_id);
+            } else {
+                vacant.remove(unit_id);
+            }
+        }
+    }
+
+    // Rebuild the vacancy index from scratch,
+    // e.g. once the units are all placed
+    pub fn index_vacancies(&mut self) {
+        self.vacant.clear();
+        self.vacant_by_neighborhood = vec![IndexedSet::new(); self.neighborhoods.len()];
+        for unit_id in self.units.ids() {
+            self.refresh_vacancy(unit_id);
         }
     }
 
+    // Occupancy of a new unit of the given area
+    fn occupancy_for(&self, pos: &Position, area: f32) -> usize {
+        let neighb_id = self.parcels.get(pos).unwrap().neighborhood.unwrap();
+        let neighb = &self.neighborhoods[neighb_id];
+        max(1, (area/neighb.sqm_per_occupant as f32).round() as usize)
+    }
+
+    // Recreate the neighborhood desirability trends,
+    // which aren't saved with the rest of the city
+    pub fn restore_trends(&mut self) {
+        self.neighborhood_trends = neighborhood_trends(&self.neighborhood_trend_seeds);
+    }
+
+    // Recompute travel times between work
+    // locations and homes, which also aren't saved
+    pub fn restore_commute_times(&mut self, conf: &Config) {
+        let parcels = &self.parcels;
+        self.commute_times = CommuteTimes::new(&self.grid, &self.transit, &self.work_positions, |pos| walkable(parcels, pos), conf);
+    }
+
     pub fn neighborhood_for_pos(&self, pos: &Position) -> Option<&Neighborhood> {
         let parcel = self.parcels.get(&pos).unwrap();
         match parcel.neighborhood {
@@ -313,21 +770,71 @@ impl City {
             None => None
         }
     }
+
+    // Share of a neighborhood's units
+    // that are rented short-term
+    pub fn short_term_share(&self, neighb_id: usize) -> f32 {
+        let unit_ids = &self.units_by_neighborhood[neighb_id];
+        if unit_ids.is_empty() {
+            return 0.;
+        }
+        let n_short_term = unit_ids.iter().filter(|&&u_id| self.units[u_id].short_term).count();
+        n_short_term as f32 / unit_ids.len() as f32
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Unit {
-    pub id: usize,
+    pub id: UnitId,
     pub rent: f32,
     pub occupancy: usize,
     pub condition: f32,
+
+    // Best condition upkeep can keep the unit in,
+    // above new (1) if it's been retrofitted
+    pub max_condition: f32,
+    #[serde(default)]
+    pub retrofitted: bool,
+    pub year_built: usize,
+    pub maintenance_history: f32,
     pub area: f32,
     pub value: f32,
-    pub tenants: FnvHashSet<usize>,
+
+    // What the unit would fetch now, as estimated
+    // each step from the market. `value` is its
+    // price at its last sale or appraisal
+    pub est_value: f32,
+
+    // Ordered, so sums over tenants come out
+    // the same in a run resumed from a checkpoint
+    pub tenants: BTreeSet<TenantId>,
     pub months_vacant: usize,
-    pub lease_month: usize,
+
+    // The tenants' lease, if occupied
+    pub lease: Option<Lease>,
+
+    // Penalties from tenants breaking their lease
+    // and deposits kept, owed to the owner
+    pub fees: f32,
     pub owner: (AgentType, usize),
     pub pos: Position,
     pub recently_sold: bool,
+
+    // Rented out short-term to visitors,
+    // and so off the long-term rental market
+    pub short_term: bool,
+
+    // For DOMA units, the monthly dividend
+    // each member living here can expect
+    pub dividend: f32,
+
+    // Merged into another unit. Retired units keep
+    // their id but are dropped from the building,
+    // neighborhood, and owner's units
+    pub retired: bool,
+
+    // Whether the owner takes housing vouchers
+    pub accepts_vouchers: bool,
     pub offers: Vec<(AgentType, usize, f32)> // landlord type, landlord id, offer amount
 }
 
@@ -337,7 +844,7 @@ impl Unit {
     }
 
     pub fn vacancies(&self) -> usize {
-        self.occupancy - self.tenants.len()
+        self.occupancy.saturating_sub(self.tenants.len())
     }
 
     pub fn rent_per_area(&self) -> f32 {
@@ -348,14 +855,260 @@ impl Unit {
         self.value/self.area
     }
 
+    // Rent less what DOMA pays back to
+    // its members living here as dividends
+    pub fn adjusted_rent(&self) -> f32 {
+        if self.is_doma() {
+            f32::max(0., self.rent - self.dividend * self.occupancy as f32)
+        } else {
+            self.rent
+        }
+    }
+
+    pub fn adjusted_rent_per_area(&self) -> f32 {
+        self.adjusted_rent()/self.area
+    }
+
     pub fn is_doma(&self) -> bool {
         self.owner.0 == AgentType::DOMA
     }
+
+    // Whether the owner collects rent, and so
+    // break fees and kept deposits
+    pub fn owner_collects(&self) -> bool {
+        matches!(self.owner.0, AgentType::Landlord | AgentType::DOMA | AgentType::Speculator)
+    }
+
+    // Whether vacancies in this unit are listed
+    // for long-term tenants. Speculators keep their
+    // units off the market, and short-term rentals
+    // are reserved for visitors
+    pub fn for_rent(&self) -> bool {
+        self.owner.0 != AgentType::Speculator && !self.short_term
+    }
+
+    // People living here over its capacity,
+    // if overcrowding is modeled
+    pub fn overcrowding(&self, conf: &Config) -> usize {
+        conf.overcrowding.as_ref().map_or(0, |overcrowding| overcrowding.excess(self, self.tenants.len()))
+    }
+
+    pub fn retrofitted(&self) -> bool {
+        self.retrofitted
+    }
+
+    // Multiple of the market rent the unit
+    // commands for being retrofitted, if it is
+    pub fn rent_premium(&self, conf: &Config) -> f32 {
+        conf.retrofit.as_ref().map_or(1., |retrofit| retrofit.premium(self))
+    }
+
+    pub fn age(&self, year: usize) -> usize {
+        year.saturating_sub(self.year_built)
+    }
+
+    // Maximum monthly deterioration, which speeds up
+    // as the building ages or is overcrowded and slows
+    // down with a history of good maintenance
+    pub fn deterioration(&self, year: usize, conf: &Config) -> f32 {
+        let age_factor = 1. + self.age(year) as f32 / conf.decay_age_scale;
+        let upkeep_factor = 1. / (1. + self.maintenance_history * conf.maintenance_history_effect);
+        let wear_factor = conf.overcrowding.as_ref().map_or(1., |overcrowding| {
+            1. + overcrowding.wear * self.overcrowding(conf) as f32
+        });
+        conf.base_decay_rate * age_factor * upkeep_factor * wear_factor
+    }
+
+    pub fn maintain(&mut self, maintenance: f32, decay: f32) {
+        self.condition = (self.condition - decay + maintenance).clamp(0., self.max_condition);
+
+        // Exponential moving average of maintenance
+        self.maintenance_history = 0.9 * self.maintenance_history + 0.1 * maintenance;
+    }
+
+    // Tenants moving in sign onto the
+    // lease, or a new one if it's vacant
+    fn sign(&mut self, members: &[TenantId], time: usize, conf: &Config) {
+        match &mut self.lease {
+            Some(lease) => lease.tenants.extend(members.iter().cloned()),
+            None => {
+                self.lease = Some(Lease::new(members.to_vec(), time, self.rent, conf));
+                self.months_vacant = 0;
+            }
+        }
+        self.tenants.extend(members.iter().cloned());
+    }
+
+    // Hold a tenant's security deposit
+    pub fn hold_deposit(&mut self, tenant: TenantId, amount: f32) {
+        if let Some(lease) = &mut self.lease {
+            lease.deposits.push(Deposit {
+                tenant: tenant,
+                amount: amount,
+                condition: self.condition,
+            });
+        }
+    }
+
+    // A tenant moving out comes off the lease,
+    // which ends once everyone's gone. Returns
+    // what's refunded of their deposit
+    fn vacate(&mut self, tenant: TenantId, conf: &Config) -> f32 {
+        self.tenants.remove(&tenant);
+        let mut refund = 0.;
+        if let Some(lease) = &mut self.lease {
+            lease.tenants.retain(|&t_id| t_id != tenant);
+            let (returned, kept) = lease.release(tenant, self.condition, conf);
+            if self.owner_collects() {
+                self.fees += kept;
+            }
+            refund = returned;
+        }
+        if self.vacant() {
+            self.lease = None;
+        }
+        refund
+    }
+
+    // Change the rent, including
+    // on any lease in place
+    pub fn set_rent(&mut self, rent: f32) {
+        self.rent = rent;
+        if let Some(lease) = &mut self.lease {
+            lease.rent = rent;
+        }
+    }
+
+    // Whether the lease, if any, is up at `time`
+    pub fn lease_up(&self, time: usize) -> bool {
+        self.lease.as_ref().is_none_or(|lease| lease.is_up(time))
+    }
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Building {
-    pub units: Vec<usize>,
+    pub units: Vec<UnitId>,
     pub n_commercial: usize
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::sim::Simulation;
+    use super::super::testing::fixture;
+
+    // Every unit is listed, citywide and in its
+    // neighborhood only, exactly when it has room and
+    // is for rent, and its tenants and lease agree
+    // with where the tenants think they live
+    fn assert_consistent(sim: &Simulation) {
+        let city = &sim.city;
+        for unit in city.units.iter() {
+            let listed = !unit.retired && unit.tenants.len() < unit.occupancy && unit.for_rent();
+            assert_eq!(city.has_vacancy(unit.id), listed, "unit {:?} listed", unit.id);
+            let neighb_id = city.parcels.get(&unit.pos).and_then(|p| p.neighborhood);
+            for n in 0..city.neighborhoods.len() {
+                let expected = listed && neighb_id == Some(n);
+                assert_eq!(city.vacant_in(n).contains(&unit.id), expected, "unit {:?} listed in neighborhood {}", unit.id, n);
+            }
+
+            let tenants: BTreeSet<TenantId> = sim.tenants.iter()
+                .filter(|t| t.unit == Some(unit.id))
+                .map(|t| t.id)
+                .collect();
+            assert_eq!(unit.tenants, tenants, "unit {:?} tenants", unit.id);
+            match &unit.lease {
+                Some(lease) => assert_eq!(lease.tenants.iter().cloned().collect::<BTreeSet<_>>(), tenants),
+                None => assert!(tenants.is_empty(), "unit {:?} has tenants but no lease", unit.id),
+            }
+        }
+        assert_eq!(city.vacant_units().len(), city.units.iter().filter(|u| city.has_vacancy(u.id)).count());
+    }
+
+    fn vacant_unit(sim: &Simulation) -> UnitId {
+        *sim.city.vacant_units().iter()
+            .find(|&&u_id| sim.city.units[u_id].vacant())
+            .expect("no vacant unit")
+    }
+
+    #[test]
+    fn index_after_build() {
+        let sim = fixture();
+        assert_consistent(&sim);
+    }
+
+    #[test]
+    fn move_tenant_and_move_out() {
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().find(|t| t.unit.is_some()).unwrap().id;
+        let from = sim.tenants[t_id].unit.unwrap();
+        let to = vacant_unit(&sim);
+
+        let tenant = &mut sim.tenants[t_id];
+        assert!(sim.city.move_tenant(tenant, to, sim.time, &sim.conf));
+        assert_eq!(sim.tenants[t_id].unit, Some(to));
+        assert!(!sim.city.units[from].tenants.contains(&t_id));
+        assert_consistent(&sim);
+
+        let tenant = &mut sim.tenants[t_id];
+        sim.city.move_out(tenant, &sim.conf);
+        assert_eq!(sim.tenants[t_id].unit, None);
+        assert!(sim.city.units[to].lease.is_none());
+        assert_consistent(&sim);
+    }
+
+    #[test]
+    fn move_into_full_unit() {
+        let mut sim = fixture();
+        let to = vacant_unit(&sim);
+        let occupancy = sim.city.units[to].occupancy;
+        let movers: Vec<TenantId> = sim.tenants.iter()
+            .filter(|t| t.unit != Some(to))
+            .take(occupancy + 1)
+            .map(|t| t.id)
+            .collect();
+        for &t_id in &movers[..occupancy] {
+            let tenant = &mut sim.tenants[t_id];
+            assert!(sim.city.move_tenant(tenant, to, sim.time, &sim.conf));
+        }
+        assert_eq!(sim.city.units[to].vacancies(), 0);
+
+        let t_id = movers[occupancy];
+        let from = sim.tenants[t_id].unit;
+        let tenant = &mut sim.tenants[t_id];
+        assert!(!sim.city.move_tenant(tenant, to, sim.time, &sim.conf));
+        assert_eq!(sim.tenants[t_id].unit, from);
+        assert_eq!(sim.city.units[to].tenants.len(), occupancy);
+        assert!(!sim.city.has_vacancy(to));
+        assert_consistent(&sim);
+    }
+
+    #[test]
+    fn subdivide_and_merge() {
+        let mut sim = fixture();
+        let u_id = vacant_unit(&sim);
+        let area = sim.city.units[u_id].area / 2.;
+        let new_id = sim.city.subdivide_unit(u_id, area);
+        assert!(sim.city.has_vacancy(new_id));
+        assert_consistent(&sim);
+
+        sim.city.merge_units(new_id, u_id);
+        assert!(!sim.city.has_vacancy(new_id));
+        assert!(sim.city.has_vacancy(u_id));
+        assert_consistent(&sim);
+    }
+
+    #[test]
+    fn reassign_parcel() {
+        let mut sim = fixture();
+        let u_id = vacant_unit(&sim);
+        let pos = sim.city.units[u_id].pos;
+        let prev = sim.city.parcels.get(&pos).unwrap().neighborhood.unwrap();
+        let next = (prev + 1) % sim.city.neighborhoods.len();
+        assert_eq!(sim.city.reassign_parcel(pos, next, &sim.conf), Some(Some(prev)));
+        assert!(sim.city.vacant_in(next).contains(&u_id));
+        assert!(!sim.city.vacant_in(prev).contains(&u_id));
+        assert_consistent(&sim);
+    }
+}
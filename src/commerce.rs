@@ -0,0 +1,117 @@
+use super::agent::Tenant;
+use super::arena::{Arena, TenantId};
+use super::city::City;
+use super::config::TimeStep;
+use super::grid::Position;
+use fnv::FnvHashMap;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct CommerceConfig {
+    // How far, in parcels, residents
+    // shop and storefronts draw desirability
+    pub radius: usize,
+
+    // Share of residents' monthly income spent
+    // locally, and the monthly revenue that keeps
+    // a storefront (a commercial floor) open
+    pub spending_share: f32,
+    pub storefront_revenue: f32,
+
+    // Share of the gap between open and supported
+    // storefronts closed each month, as shops
+    // open or fold
+    pub adjustment: f32,
+
+    // Desirability an open storefront adds nearby,
+    // and how much of that a vacant one takes away
+    pub desirability_effect: f32,
+    pub vacancy_drag: f32,
+}
+
+// Storefronts on a parcel's commercial floors
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Storefronts {
+    pub floors: usize,
+    pub open: f32,
+}
+
+impl Storefronts {
+    pub fn occupancy(&self) -> f32 {
+        self.open / self.floors as f32
+    }
+
+    // Pull on nearby desirability from
+    // open and vacant storefronts
+    fn draw(&self, conf: &CommerceConfig) -> f32 {
+        let vacant = self.floors as f32 - self.open;
+        conf.desirability_effect * (self.open - conf.vacancy_drag * vacant)
+    }
+}
+
+// Local businesses, which open and close with
+// the spending power of residents nearby. Every
+// storefront starts open, as the city is designed
+#[derive(Serialize, Deserialize)]
+pub struct Commerce {
+    pub storefronts: BTreeMap<Position, Storefronts>,
+}
+
+impl Commerce {
+    pub fn new(city: &City) -> Commerce {
+        Commerce {
+            storefronts: city.commercial.iter().map(|(pos, &floors)| {
+                (pos, Storefronts { floors: floors, open: floors as f32 })
+            }).collect(),
+        }
+    }
+
+    // Open or close storefronts toward what local
+    // spending supports, returning the change in
+    // desirability for each residential parcel
+    pub fn update(&mut self, city: &City, tenants: &Arena<TenantId, Tenant>, time_step: TimeStep, conf: &CommerceConfig) -> FnvHashMap<Position, f32> {
+        let mut income: FnvHashMap<Position, f32> = FnvHashMap::default();
+        for unit in city.units.iter().filter(|u| !u.vacant()) {
+            let total = unit.tenants.iter().fold(0., |acc, &t_id| acc + tenants[t_id].income);
+            *income.entry(unit.pos).or_insert(0.) += total;
+        }
+
+        let adjustment = time_step.chance(conf.adjustment);
+        let mut changes = FnvHashMap::default();
+        for (&pos, storefronts) in &mut self.storefronts {
+            let mut nearby = city.grid.radius(pos, conf.radius);
+            if !nearby.contains(&pos) {
+                nearby.push(pos);
+            }
+            nearby.sort();
+            let spending = nearby.iter()
+                .fold(0., |acc, p| acc + income.get(p).unwrap_or(&0.)) * conf.spending_share;
+            let supported = f32::min(storefronts.floors as f32, spending / conf.storefront_revenue);
+
+            let before = storefronts.draw(conf);
+            storefronts.open += (supported - storefronts.open) * adjustment;
+            let change = storefronts.draw(conf) - before;
+            for p in nearby {
+                *changes.entry(p).or_insert(0.) += change;
+            }
+        }
+        changes
+    }
+
+    // Open storefronts by position,
+    // each of which is a local job
+    pub fn jobs(&self) -> impl Iterator<Item=(Position, f32)> + '_ {
+        self.storefronts.iter().map(|(&pos, s)| (pos, s.open))
+    }
+
+    // Share of storefronts open, citywide
+    // or in the given neighborhood
+    pub fn occupancy(&self, city: &City, neighb_id: Option<usize>) -> f32 {
+        let (open, floors) = self.storefronts.iter()
+            .filter(|(pos, _)| neighb_id.is_none() || city.parcels.get(pos).unwrap().neighborhood == neighb_id)
+            .fold((0., 0), |acc, (_, s)| (acc.0 + s.open, acc.1 + s.floors));
+        if floors > 0 { open / floors as f32 } else { 1. }
+    }
+}
@@ -0,0 +1,176 @@
+#[cfg(feature = "plot")]
+use super::plot;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+#[cfg(feature = "plot")]
+use tracing::warn;
+
+// A run's history, from its `output.json`
+struct Run {
+    label: String,
+    history: Vec<Value>,
+}
+
+fn load_run(path: &str) -> Run {
+    let data = fs::read_to_string(path).expect("could not open run output");
+    let output: Value = serde_json::from_str(&data).expect("error while reading json");
+    let history = output["history"].as_array().expect("run output has no history").clone();
+
+    // Runs are named for their directory
+    let label = Path::new(path).parent()
+        .and_then(|dir| dir.file_name())
+        .map_or_else(|| path.to_string(), |name| name.to_string_lossy().to_string());
+    Run { label, history }
+}
+
+// A stat's value at each step, NaN
+// where it's missing or not a number
+fn series(history: &[Value], metric: &str) -> Vec<f32> {
+    history.iter().map(|step| step[metric].as_f64().map_or(f32::NAN, |v| v as f32)).collect()
+}
+
+fn mean(vals: &[f32]) -> f32 {
+    let vals: Vec<f32> = vals.iter().cloned().filter(|v| v.is_finite()).collect();
+    vals.iter().sum::<f32>() / vals.len() as f32
+}
+
+// Compares runs, e.g. of different policies, against the
+// first as the baseline, over the steps they all have
+pub fn run_comparison(paths: &[String]) {
+    let mut runs: Vec<Run> = paths.iter().map(|path| load_run(path)).collect();
+
+    // Runs from the same directory
+    // are told apart by their paths
+    for i in 0..runs.len() {
+        if runs.iter().filter(|r| r.label == runs[i].label).count() > 1 {
+            runs[i].label = paths[i].clone();
+        }
+    }
+    let steps = runs.iter().map(|r| r.history.len()).min().unwrap_or(0);
+    if steps == 0 {
+        panic!("runs to compare need at least one step");
+    }
+
+    // Numeric top-level stats every run has
+    let metrics: Vec<String> = match &runs[0].history[0] {
+        Value::Object(stats) => stats.iter()
+            .filter(|(_, v)| v.is_number())
+            .map(|(k, _)| k.clone())
+            .filter(|k| runs.iter().all(|r| r.history[0][k].is_number()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    info!("Comparing {:?} metrics over {:?} steps", metrics.len(), steps);
+
+    let now: DateTime<Utc> = Utc::now();
+    let dir = format!("runs/compare_{}", now.format("%Y.%m.%d.%H.%M.%S"));
+    fs::create_dir_all(&dir).unwrap();
+    let mut diffs = csv::Writer::from_path(format!("{}/diffs.csv", dir)).expect("Unable to write file");
+    diffs.write_record(["metric", "run", "step", "value", "diff"]).unwrap();
+    let mut summary = csv::Writer::from_path(format!("{}/summary.csv", dir)).expect("Unable to write file");
+    summary.write_record(["metric", "run", "final", "mean", "final_diff", "mean_diff", "final_pct_diff"]).unwrap();
+
+    let baseline = &runs[0];
+    let mut table = Map::new();
+    for metric in &metrics {
+        let base = series(&baseline.history[..steps], metric);
+        let (base_final, base_mean) = (base[steps - 1], mean(&base));
+        let mut by_run = Map::new();
+        for run in &runs {
+            let vals = series(&run.history[..steps], metric);
+            let diff: Vec<f32> = vals.iter().zip(base.iter()).map(|(v, b)| v - b).collect();
+            for (step, (v, d)) in vals.iter().zip(diff.iter()).enumerate() {
+                diffs.write_record([metric.clone(), run.label.clone(), step.to_string(), v.to_string(), d.to_string()]).unwrap();
+            }
+
+            let (fin, avg) = (vals[steps - 1], mean(&vals));
+            let pct = if base_final != 0. { Some((fin - base_final) / base_final.abs()) } else { None };
+            summary.write_record([
+                metric.clone(), run.label.clone(), fin.to_string(), avg.to_string(),
+                (fin - base_final).to_string(), (avg - base_mean).to_string(),
+                pct.map_or(String::new(), |p| p.to_string()),
+            ]).unwrap();
+            by_run.insert(run.label.clone(), json!({
+                "final": fin,
+                "mean": avg,
+                "final_diff": fin - base_final,
+                "mean_diff": avg - base_mean,
+                "final_pct_diff": pct,
+            }));
+        }
+        table.insert(metric.clone(), Value::Object(by_run));
+    }
+    diffs.flush().expect("Unable to write file");
+    summary.flush().expect("Unable to write file");
+
+    let report = json!({
+        "baseline": baseline.label,
+        "runs": runs.iter().map(|r| r.label.clone()).collect::<Vec<String>>(),
+        "steps": steps,
+        "metrics": table,
+    });
+    fs::write(format!("{}/summary.json", dir), report.to_string()).expect("Unable to write file");
+
+    #[cfg(feature = "plot")]
+    plot_comparison(&runs, &metrics, steps, &dir);
+
+    print_table(&runs, &table);
+    info!("Wrote comparison to {:?}", dir);
+}
+
+// Final values of each metric, and how far
+// each run ended up from the baseline
+fn print_table(runs: &[Run], table: &Map<String, Value>) {
+    let width = table.keys().map(|k| k.len()).max().unwrap_or(0);
+    print!("{:width$}", "", width = width);
+    for run in runs {
+        print!("  {:>24}", run.label);
+    }
+    println!();
+    for (metric, by_run) in table {
+        print!("{:width$}", metric, width = width);
+        for (i, run) in runs.iter().enumerate() {
+            let cell = &by_run[&run.label];
+            let fin = cell["final"].as_f64().unwrap_or(f64::NAN);
+            if i == 0 {
+                print!("  {:>24.4}", fin);
+            } else {
+                let diff = cell["final_diff"].as_f64().unwrap_or(f64::NAN);
+                print!("  {:>24}", format!("{:.4} ({:+.4})", fin, diff));
+            }
+        }
+        println!();
+    }
+}
+
+// A chart of each metric across runs, and
+// of each run's difference from the baseline
+#[cfg(feature = "plot")]
+fn plot_comparison(runs: &[Run], metrics: &[String], steps: usize, dir: &str) {
+    let plot_dir = format!("{}/plots", dir);
+    fs::create_dir_all(&plot_dir).unwrap();
+    for metric in metrics {
+        let vals: Vec<Vec<f32>> = runs.iter().map(|r| series(&r.history[..steps], metric)).collect();
+        let diffs: Vec<Vec<f32>> = vals[1..].iter()
+            .map(|v| v.iter().zip(vals[0].iter()).map(|(v, b)| v - b).collect())
+            .collect();
+        let named: Vec<(&str, &[f32])> = runs.iter().zip(vals.iter())
+            .map(|(r, v)| (r.label.as_str(), v.as_slice()))
+            .collect();
+        let named_diffs: Vec<(&str, &[f32])> = runs[1..].iter().zip(diffs.iter())
+            .map(|(r, d)| (r.label.as_str(), d.as_slice()))
+            .collect();
+        let charts = [
+            (format!("{}/{}.svg", plot_dir, metric), metric.clone(), named),
+            (format!("{}/{}_diff.svg", plot_dir, metric), format!("{} (vs {})", metric, runs[0].label), named_diffs),
+        ];
+        for (path, title, series) in &charts {
+            if let Err(err) = plot::line_chart(path, title, series) {
+                warn!("Couldn't plot {:?}: {}", path, err);
+            }
+        }
+    }
+}
@@ -1,10 +1,36 @@
+use super::agent::{Archetype, ArchetypeConfig};
+#[cfg(feature = "play")]
+use super::backend::SyncConfig;
+#[cfg(feature = "play")]
+use super::bots::BotConfig;
+#[cfg(feature = "play")]
+use super::replay::ReplayConfig;
+use super::city::Unit;
+use super::commerce::CommerceConfig;
+use super::drift::DriftConfig;
+use super::social::SocialConfig;
+use super::design::AmenityType;
+use super::economy::EconomyConfig;
+use super::export::ExportConfig;
+use super::lease::Renewal;
+use super::logging::LogConfig;
+use super::desirability::TenantModel;
+use super::market::{SearchConfig, TenantSelection};
+use super::progress::ProgressType;
+use super::retrofit::RetrofitConfig;
+use super::scenario::{ScheduledIntervention, ScheduledNarration};
+use super::union::UnionConfig;
+use fnv::FnvHashMap;
 use rand::Rng;
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
-use std::io::BufReader;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "UPPERCASE")]
 pub struct Config {
     pub design_id: String,
@@ -13,15 +39,72 @@ pub struct Config {
     pub doma_p_reserves: f32,
     pub doma_p_expenses: f32,
     pub doma_rent_income_limit: Option<f32>,
+    pub doma_round_target: f32,
+    pub doma_round_months: usize,
     pub desirability_stretch_factor: f64,
     pub base_appreciation: f32,
+
+    // Share of estimated value lost
+    // as a unit's condition goes to 0
+    #[serde(default)]
+    pub value_condition_discount: f32,
     pub sample_size: usize,
+    pub market_noise: f32,
     pub tenant_sample_size: usize,
+    pub tenant_model: TenantModel,
+    pub tenant_selection: TenantSelection,
+    pub application_rounds: usize,
     pub tenant_pool_size: usize,
+    pub player_timeout_secs: u64,
+    pub turn_steps: usize,
+    pub turn_timeout_secs: Option<u64>,
+
+    // What play sessions go through after burn-in.
+    // If empty, players take turns until it's reset
+    #[serde(default)]
+    pub session_phases: Vec<Phase>,
+
+    // Seconds running a turn's steps and syncing should
+    // take, beyond which the slowest phases are logged.
+    // If None, turns can take as long as they take
+    #[serde(default)]
+    pub turn_budget_secs: Option<f32>,
+
+    // Play sessions to host at once,
+    // each with its keys under `session:<id>:`
+    #[serde(default = "default_sessions")]
+    pub sessions: usize,
+    #[cfg(feature = "play")]
+    pub bots: BotConfig,
+    #[cfg(feature = "play")]
+    pub replay: ReplayConfig,
     pub trend_months: usize,
     pub rent_increase_rate: f32,
+    pub vacancy_discount: f32,
+    pub max_vacancy_discount: f32,
+    pub short_term_rent_multiple: f32,
+    pub short_term_demand: f32,
+    #[serde(default)]
+    pub neighborhood_short_term_demand: FnvHashMap<usize, f32>,
     pub moving_penalty: f32,
+
+    // How solo tenants and households
+    // look for places, if not by the above
+    #[serde(default)]
+    pub tenant_search: SearchConfig,
+    #[serde(default)]
+    pub household_search: SearchConfig,
     pub friend_limit: usize,
+
+    // Groups tenants are dealt into, by name and
+    // share, for landlords to screen on. None if empty
+    #[serde(default)]
+    pub tenant_groups: BTreeMap<String, f32>,
+
+    // Ties tenants hear of places through.
+    // If None, they only search at random
+    #[serde(default)]
+    pub social: Option<SocialConfig>,
     pub transmission_rate: f32,
     pub encounter_rate: f32,
     pub base_contribute_prob: f32,
@@ -29,6 +112,143 @@ pub struct Config {
     pub burn_in: usize,
     pub max_contagion_depth: usize,
     pub pop_p_occupancy: f32,
+    pub eviction_grace_months: usize,
+    pub eviction_penalty: f32,
+    pub policies: PolicyConfig,
+    pub economy: EconomyConfig,
+
+    // Only read in builds with play sessions
+    #[cfg(feature = "play")]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    pub metrics_addr: Option<String>,
+
+    // How to report progress through steps
+    #[serde(default)]
+    pub progress: ProgressType,
+
+    // Save debug runs' state every this many months,
+    // so they can be resumed, or 0 for never
+    #[serde(default)]
+    pub checkpoint_interval: usize,
+    #[serde(default)]
+    pub scenarios: Vec<ScheduledIntervention>,
+    #[serde(default)]
+    pub narration: Vec<ScheduledNarration>,
+
+    // Scenario pack in effect, as `<name>@<version>`
+    #[serde(default)]
+    pub scenario: Option<String>,
+    pub savings_rate: f32,
+    pub initial_savings_months: f32,
+    pub emergency_prob: f32,
+    pub emergency_cost: f32,
+    pub moving_cost: f32,
+    pub deposit_months: f32,
+
+    // Share of a deposit kept for each point
+    // of condition a unit loses over a tenancy
+    #[serde(default)]
+    pub deposit_deduction: f32,
+
+    // Length of new leases, 0 for month-to-month,
+    // what happens when they're up, and months of
+    // rent owed for breaking one early
+    #[serde(default = "default_lease_term_months")]
+    pub lease_term_months: usize,
+    #[serde(default)]
+    pub lease_renewal: Renewal,
+    #[serde(default)]
+    pub lease_break_penalty_months: f32,
+    pub landlord_starting_cash: f32,
+    pub landlord_archetypes: BTreeMap<Archetype, ArchetypeConfig>,
+    pub mortgage_interest_rate: f32,
+    pub mortgage_ltv: f32,
+    pub mortgage_term_months: usize,
+    pub foreclosure_discount: f32,
+    pub property_tax_rate: f32,
+    pub insurance_rate: f32,
+    pub maintenance_cost: f32,
+    pub max_unprofitable_months: usize,
+    pub developers: usize,
+    pub developer_starting_cash: f32,
+    pub construction_cost_per_area: f32,
+    pub construction_months: usize,
+    pub development_margin: f32,
+    pub land_cost_share: f32,
+    pub subdivide_vacancy_rate: f32,
+    pub merge_vacancy_rate: f32,
+    pub min_unit_area: f32,
+    pub conversion_cost_per_area: f32,
+    pub start_year: usize,
+
+    // How much time each step covers
+    #[serde(default)]
+    pub time_step: TimeStep,
+    pub max_building_age: usize,
+    pub base_decay_rate: f32,
+    pub decay_age_scale: f32,
+    pub maintenance_history_effect: f32,
+    pub target_condition: f32,
+    pub max_maintenance: f32,
+
+    // Units housing more than their area allows.
+    // If None, only `occupancy` limits tenants
+    #[serde(default)]
+    pub overcrowding: Option<OvercrowdingConfig>,
+
+    // Improvements owners can pay for up front.
+    // If None, units are only maintained
+    #[serde(default)]
+    pub retrofit: Option<RetrofitConfig>,
+    pub raise_prob: f32,
+    pub raise_mean: f32,
+    pub layoff_prob: f32,
+    pub job_change_prob: f32,
+    pub rehire_prob: f32,
+    pub unemployment_benefit: f32,
+    pub roommate_prob: f32,
+    pub max_household_size: usize,
+    pub household_income_change: f32,
+    pub in_migration_rate: f32,
+    pub out_migration_months: usize,
+    pub out_migration_prob: f32,
+    pub homeless_income_penalty: f32,
+    pub sheltered_income_penalty: f32,
+    pub speculators: usize,
+    pub speculator_starting_cash: f32,
+    pub speculation_horizon: usize,
+    pub speculator_markup: f32,
+    pub amenity_radius: f32,
+    pub amenity_weights: FnvHashMap<AmenityType, f32>,
+    pub walk_minutes: f32,
+    pub transit_minutes: f32,
+    pub transit_wait_minutes: f32,
+    pub gentrification_window: usize,
+    pub gentrification_rent_weight: f32,
+    pub gentrification_investment_weight: f32,
+    pub gentrification_income_weight: f32,
+    pub gentrification_desirability_effect: f32,
+
+    // Local businesses opening and closing.
+    // If None, storefronts stay as designed
+    #[serde(default)]
+    pub commerce: Option<CommerceConfig>,
+
+    // Parcels' desirability drifting apart.
+    // If None, parcels only change with
+    // their neighborhood and surroundings
+    #[serde(default)]
+    pub parcel_drift: Option<DriftConfig>,
+
+    // Tenants organizing by building.
+    // If None, tenants never unionize
+    #[serde(default)]
+    pub unions: Option<UnionConfig>,
+    pub displacement_months: usize,
 
     #[serde(default)]
     pub steps: usize,
@@ -39,31 +259,668 @@ pub struct Config {
     #[serde(default)]
     pub seed: u64,
 
+    // Where to report errors to, if anywhere
+    #[serde(default)]
     pub sentry_dsn: String,
 }
 
-pub fn load_config() -> Config {
-    let file = File::open("config.yaml").expect("could not open file");
-    let reader = BufReader::new(file);
-    let mut conf: Config = serde_yaml::from_reader(reader).expect("error while reading yaml");
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct PolicyConfig {
+    // Citywide cap on rent increases
+    // at lease renewal, e.g. 1.03
+    pub max_rent_increase: Option<f32>,
 
-    conf.steps = match env::var("STEPS") {
-        Ok(steps) => steps.parse().unwrap(),
-        Err(_) => 100,
-    };
+    // Per-neighborhood caps, keyed by design neighborhood id;
+    // these override the citywide cap
+    #[serde(default)]
+    pub neighborhood_max_rent_increase: FnvHashMap<usize, f32>,
 
-    conf.debug = match env::var("DEBUG") {
-        Ok(debug) => debug == "1",
-        Err(_) => false,
-    };
+    // Cap on the share of each neighborhood's
+    // units that may be rented short-term. If None, no cap
+    #[serde(default)]
+    pub max_short_term_share: Option<f32>,
+
+    // Annual tax on units left vacant for more than
+    // `VACANCY_TAX_MONTHS`, as a percent of their value.
+    // If None, no tax. Players can vote to change it
+    #[serde(default)]
+    pub vacancy_tax_rate: Option<f32>,
+    #[serde(default)]
+    pub vacancy_tax_months: usize,
+
+    // Housing voucher program. If None, no program
+    #[serde(default)]
+    pub vouchers: Option<VoucherConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct VoucherConfig {
+    // Most the program commits to
+    // paying out each month
+    pub budget: f32,
 
-    let mut rng = rand::thread_rng();
-    conf.seed = match env::var("SEED") {
-        Ok(seed) => seed.parse().unwrap(),
-        Err(_) => rng.gen(),
+    // Tenants paying more than this share of their
+    // income in rent are eligible, and voucher
+    // holders pay this share, up to the cap
+    pub rent_burden: f32,
+
+    // Most a voucher pays each month
+    pub max_subsidy: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct OvercrowdingConfig {
+    // Living space each person needs
+    pub sqm_per_person: f32,
+
+    // Share of a unit's desirability lost
+    // for each person over its capacity
+    pub desirability_penalty: f32,
+
+    // How much faster an overcrowded unit
+    // wears down, for each person over capacity
+    pub wear: f32,
+
+    // How much more likely residents of an overcrowded
+    // unit are to have an emergency expense, e.g. from
+    // illness, for each person over capacity
+    pub health_risk: f32,
+}
+
+impl OvercrowdingConfig {
+    // People a unit's area houses, however
+    // many its `occupancy` lets move in
+    pub fn capacity(&self, unit: &Unit) -> usize {
+        usize::max(1, (unit.area / self.sqm_per_person) as usize)
+    }
+
+    // People over capacity if the unit
+    // housed `n_tenants`
+    pub fn excess(&self, unit: &Unit, n_tenants: usize) -> usize {
+        n_tenants.saturating_sub(self.capacity(unit))
+    }
+}
+
+fn default_sessions() -> usize {
+    1
+}
+
+fn default_lease_term_months() -> usize {
+    12
+}
+
+impl PolicyConfig {
+    pub fn max_rent_increase(&self, neighb_id: usize) -> Option<f32> {
+        match self.neighborhood_max_rent_increase.get(&neighb_id) {
+            Some(&cap) => Some(cap),
+            None => self.max_rent_increase,
+        }
+    }
+
+    // Vacancy tax owed on a unit this step. Short-term
+    // rentals are in use, so they aren't taxed
+    pub fn vacancy_tax(&self, unit: &Unit, time_step: TimeStep) -> f32 {
+        match self.vacancy_tax_rate {
+            Some(rate) if unit.vacant() && !unit.short_term && unit.months_vacant > time_step.steps(self.vacancy_tax_months) => {
+                unit.value * rate / time_step.steps_per_year() as f32
+            }
+            _ => 0.
+        }
+    }
+
+    // Vacancy tax owed on a unit of the
+    // given value if held empty for `months`
+    pub fn vacancy_tax_over(&self, value: f32, months: usize) -> f32 {
+        match self.vacancy_tax_rate {
+            Some(rate) => value * rate / 12. * months.saturating_sub(self.vacancy_tax_months) as f32,
+            None => 0.
+        }
+    }
+}
+
+// How much time each step covers. Rents, incomes, and
+// costs are still set as monthly amounts, and durations
+// and chances in months, and converted to steps
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeStep {
+    Week,
+    #[default]
+    Month,
+    Quarter,
+}
+
+impl TimeStep {
+    pub fn steps_per_year(&self) -> usize {
+        match self {
+            TimeStep::Week => 52,
+            TimeStep::Month => 12,
+            TimeStep::Quarter => 4,
+        }
+    }
+
+    // Months in a step, for scaling monthly amounts
+    pub fn months(&self) -> f32 {
+        12. / self.steps_per_year() as f32
+    }
+
+    // Steps in a span of months, at least
+    // one step if the span is any time at all
+    pub fn steps(&self, months: usize) -> usize {
+        if months == 0 {
+            0
+        } else {
+            usize::max(1, (months as f32 / self.months()).round() as usize)
+        }
+    }
+
+    // Chance of something happening in a step,
+    // given the chance of it happening in a month
+    pub fn chance(&self, monthly: f32) -> f32 {
+        1. - (1. - monthly.clamp(0., 1.)).powf(self.months())
+    }
+
+    // Growth over a step, given monthly growth
+    pub fn growth(&self, monthly: f32) -> f32 {
+        (1. + monthly).max(0.).powf(self.months()) - 1.
+    }
+}
+
+// A stretch of a play session
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Phase {
+    // Turns for players, each
+    // running `TURN_STEPS` months
+    Act(usize),
+
+    // Months run without waiting for players
+    // in between turns
+    FastFw(usize),
+
+    // Months run after the session's finished
+    // and been scored, to show where things
+    // head once play ends. Only ever last
+    Epilogue(usize),
+}
+
+impl Config {
+    // Calendar year at the given step
+    pub fn year(&self, time: usize) -> usize {
+        self.start_year + time / self.time_step.steps_per_year()
+    }
+
+    // Steps in a span of months
+    pub fn steps(&self, months: usize) -> usize {
+        self.time_step.steps(months)
+    }
+
+    // Share of nights short-term rentals are booked
+    // in a neighborhood, keyed by design neighborhood id
+    pub fn short_term_demand(&self, neighb_id: usize) -> f32 {
+        *self.neighborhood_short_term_demand.get(&neighb_id).unwrap_or(&self.short_term_demand)
+    }
+
+    // A copy with parameters replaced, keyed by
+    // their config names, with `.` for nested ones,
+    // e.g. `SEED` or `POLICIES.MAX_RENT_INCREASE`
+    pub fn with_params(&self, params: &BTreeMap<String, Value>) -> Result<Config, String> {
+        let mut conf_val = serde_json::to_value(self).unwrap();
+        for (key, val) in params {
+            let mut target = &mut conf_val;
+            for part in key.split('.') {
+                target = target.get_mut(part).ok_or_else(|| format!("unknown parameter: {}", key))?;
+            }
+            *target = val.clone();
+        }
+        let conf = serde_json::from_value(conf_val).map_err(|err| format!("invalid parameter value: {}", err))?;
+        validate(&conf).map_err(|errors| {
+            errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("; ")
+        })?;
+        Ok(conf)
+    }
+}
+
+// Keys only read in builds with play sessions,
+// which headless builds can ignore
+#[cfg(feature = "play")]
+static PLAY_KEYS: [&str; 0] = [];
+#[cfg(not(feature = "play"))]
+static PLAY_KEYS: [&str; 3] = ["SYNC", "BOTS", "REPLAY"];
+
+// Problems with a config that would otherwise
+// surface as serde panics, or as runs that
+// quietly do something other than intended
+#[derive(Debug)]
+pub enum ConfigError {
+    // Unreadable YAML, a missing key, or a value
+    // of the wrong type, as serde describes it
+    Invalid(String),
+
+    // Likely misspelled, with the closest
+    // known key, if there's one near enough
+    UnknownKey { key: String, suggestion: Option<String> },
+
+    OutOfRange { key: &'static str, value: f64, expected: &'static str },
+    EmptyPhase { index: usize },
+    MisplacedEpilogue { index: usize },
+
+    // Months are numbered from 1,
+    // so month 0 never comes
+    BeforeStart { key: &'static str },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Invalid(err) => write!(f, "{}", err),
+            ConfigError::UnknownKey { key, suggestion: Some(suggestion) } =>
+                write!(f, "unknown key {}, did you mean {}?", key, suggestion),
+            ConfigError::UnknownKey { key, suggestion: None } =>
+                write!(f, "unknown key {}", key),
+            ConfigError::OutOfRange { key, value, expected } =>
+                write!(f, "{} is {}, expected {}", key, value, expected),
+            ConfigError::EmptyPhase { index } =>
+                write!(f, "SESSION_PHASES.{} runs for no time, expected at least 1", index),
+            ConfigError::MisplacedEpilogue { index } =>
+                write!(f, "SESSION_PHASES.{} is an EPILOGUE, expected only as the last phase", index),
+            ConfigError::BeforeStart { key } =>
+                write!(f, "{} has an entry at month 0, expected months from 1", key),
+        }
+    }
+}
+
+// Read a config, or everything wrong with it
+pub fn parse_config(data: &str) -> Result<Config, Vec<ConfigError>> {
+    // Keys serde passed over, which are
+    // in the YAML but not the config
+    let mut ignored = Vec::new();
+    if let Ok(raw) = serde_yaml::from_str::<serde_yaml::Value>(data) {
+        let _: Result<Config, _> = serde_ignored::deserialize(raw, |path| {
+            ignored.push(path.to_string().replace(".?", ""));
+        });
+    }
+
+    // Parsed again for errors that say where they are
+    let parsed = serde_yaml::from_str::<Config>(data);
+    let known = parsed.as_ref().ok().map(|conf| serde_json::to_value(conf).unwrap());
+    let mut errors: Vec<ConfigError> = ignored.into_iter()
+        .filter(|key| !PLAY_KEYS.contains(&key.as_str()))
+        .map(|key| ConfigError::UnknownKey {
+            suggestion: known.as_ref().and_then(|known| closest_key(&key, known)),
+            key: key,
+        })
+        .collect();
+    match parsed {
+        Ok(conf) if errors.is_empty() => Ok(conf),
+        Ok(_) => Err(errors),
+        Err(err) => {
+            errors.insert(0, ConfigError::Invalid(err.to_string()));
+            Err(errors)
+        }
+    }
+}
+
+// The known key most like an unknown one,
+// among those alongside it, if it's only
+// a letter or so off per four
+fn closest_key(key: &str, known: &Value) -> Option<String> {
+    let (parent, name) = match key.rsplit_once('.') {
+        Some((parent, name)) => (Some(parent), name),
+        None => (None, key),
     };
+    let mut siblings = known;
+    for part in parent.into_iter().flat_map(|parent| parent.split('.')) {
+        siblings = siblings.get(part)?;
+    }
+    siblings.as_object()?.keys()
+        .map(|sibling| (strsim::levenshtein(name, sibling), sibling))
+        .filter(|(dist, _)| *dist <= usize::max(1, name.len() / 4))
+        .min()
+        .map(|(_, sibling)| match parent {
+            Some(parent) => format!("{}.{}", parent, sibling),
+            None => sibling.clone(),
+        })
+}
+
+fn check_share(errors: &mut Vec<ConfigError>, key: &'static str, value: f32) {
+    if !(0. ..=1.).contains(&value) {
+        errors.push(ConfigError::OutOfRange { key: key, value: value as f64, expected: "between 0 and 1" });
+    }
+}
+
+fn check_nonzero(errors: &mut Vec<ConfigError>, key: &'static str, value: usize) {
+    if value == 0 {
+        errors.push(ConfigError::OutOfRange { key: key, value: 0., expected: "at least 1" });
+    }
+}
+
+// Values serde accepts but the
+// simulation can't sensibly run with
+pub fn validate(conf: &Config) -> Result<(), Vec<ConfigError>> {
+    let mut errors = Vec::new();
+    for (key, value) in [
+        ("POP_P_OCCUPANCY", conf.pop_p_occupancy),
+        ("DOMA_P_RENT_SHARE", conf.doma_p_rent_share),
+        ("DOMA_P_RESERVES", conf.doma_p_reserves),
+        ("DOMA_P_EXPENSES", conf.doma_p_expenses),
+        ("MORTGAGE_LTV", conf.mortgage_ltv),
+        ("LAND_COST_SHARE", conf.land_cost_share),
+        ("SUBDIVIDE_VACANCY_RATE", conf.subdivide_vacancy_rate),
+        ("MERGE_VACANCY_RATE", conf.merge_vacancy_rate),
+        ("SHORT_TERM_DEMAND", conf.short_term_demand),
+        ("SAVINGS_RATE", conf.savings_rate),
+        ("EMERGENCY_PROB", conf.emergency_prob),
+        ("RAISE_PROB", conf.raise_prob),
+        ("LAYOFF_PROB", conf.layoff_prob),
+        ("JOB_CHANGE_PROB", conf.job_change_prob),
+        ("REHIRE_PROB", conf.rehire_prob),
+        ("ROOMMATE_PROB", conf.roommate_prob),
+        ("IN_MIGRATION_RATE", conf.in_migration_rate),
+        ("OUT_MIGRATION_PROB", conf.out_migration_prob),
+        ("TRANSMISSION_RATE", conf.transmission_rate),
+        ("ENCOUNTER_RATE", conf.encounter_rate),
+        ("BASE_CONTRIBUTE_PROB", conf.base_contribute_prob),
+        ("BASE_CONTRIBUTE_PERCENT", conf.base_contribute_percent),
+    ] {
+        check_share(&mut errors, key, value);
+    }
+    for (key, value) in [
+        ("SAMPLE_SIZE", conf.sample_size),
+        ("TENANT_SAMPLE_SIZE", conf.tenant_sample_size),
+        ("TENANT_POOL_SIZE", conf.tenant_pool_size),
+        ("APPLICATION_ROUNDS", conf.application_rounds),
+        ("TURN_STEPS", conf.turn_steps),
+        ("SESSIONS", conf.sessions),
+        ("MORTGAGE_TERM_MONTHS", conf.mortgage_term_months),
+        ("MAX_HOUSEHOLD_SIZE", conf.max_household_size),
+        ("STEPS", conf.steps),
+        ("CONSTRUCTION_MONTHS", conf.construction_months),
+        ("FRIEND_LIMIT", conf.friend_limit),
+        ("MAX_BUILDING_AGE", conf.max_building_age),
+    ] {
+        check_nonzero(&mut errors, key, value);
+    }
+
+    // Play sessions burn in before players join
+    if cfg!(feature = "play") && !conf.debug && conf.burn_in >= conf.steps {
+        errors.push(ConfigError::OutOfRange { key: "BURN_IN", value: conf.burn_in as f64, expected: "less than STEPS" });
+    }
+
+    // Trends are fit to at least two points
+    if conf.steps(conf.trend_months) < 2 {
+        errors.push(ConfigError::OutOfRange { key: "TREND_MONTHS", value: conf.trend_months as f64, expected: "at least 2 steps' worth" });
+    }
 
-    println!("{:?}", conf);
+    // Buildings' ages are drawn back from the start year
+    if conf.max_building_age > conf.start_year {
+        errors.push(ConfigError::OutOfRange { key: "MAX_BUILDING_AGE", value: conf.max_building_age as f64, expected: "at most START_YEAR" });
+    }
 
+    if conf.initial_savings_months < 0. {
+        errors.push(ConfigError::OutOfRange { key: "INITIAL_SAVINGS_MONTHS", value: conf.initial_savings_months as f64, expected: "at least 0" });
+    }
+
+    // Raises are drawn from between 0 and twice the mean
+    if conf.raise_mean <= 0. {
+        errors.push(ConfigError::OutOfRange { key: "RAISE_MEAN", value: conf.raise_mean as f64, expected: "greater than 0" });
+    }
+
+    // Landlords don't cut rents at renewal
+    if conf.rent_increase_rate < 1. {
+        errors.push(ConfigError::OutOfRange { key: "RENT_INCREASE_RATE", value: conf.rent_increase_rate as f64, expected: "at least 1" });
+    }
+    for archetype in conf.landlord_archetypes.values() {
+        if archetype.rent_increase_multiple < 0. {
+            errors.push(ConfigError::OutOfRange { key: "LANDLORD_ARCHETYPES.RENT_INCREASE_MULTIPLE", value: archetype.rent_increase_multiple as f64, expected: "at least 0" });
+        }
+    }
+    if conf.turn_timeout_secs == Some(0) {
+        errors.push(ConfigError::OutOfRange { key: "TURN_TIMEOUT_SECS", value: 0., expected: "at least 1, or null" });
+    }
+    for (i, phase) in conf.session_phases.iter().enumerate() {
+        match phase {
+            Phase::Act(0) | Phase::FastFw(0) | Phase::Epilogue(0) => errors.push(ConfigError::EmptyPhase { index: i }),
+            Phase::Epilogue(_) if i + 1 < conf.session_phases.len() => errors.push(ConfigError::MisplacedEpilogue { index: i }),
+            _ => {},
+        }
+    }
+    if conf.scenarios.iter().any(|s| s.month == 0) {
+        errors.push(ConfigError::BeforeStart { key: "SCENARIOS" });
+    }
+    if conf.narration.iter().any(|n| n.month == 0) {
+        errors.push(ConfigError::BeforeStart { key: "NARRATION" });
+    }
+    #[cfg(feature = "play")]
+    if conf.bots.min_delay_ms > conf.bots.max_delay_ms {
+        errors.push(ConfigError::OutOfRange { key: "BOTS.MIN_DELAY_MS", value: conf.bots.min_delay_ms as f64, expected: "at most BOTS.MAX_DELAY_MS" });
+    }
+    #[cfg(feature = "play")]
+    if conf.replay.max_mb <= 0. {
+        errors.push(ConfigError::OutOfRange { key: "REPLAY.MAX_MB", value: conf.replay.max_mb as f64, expected: "greater than 0" });
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Print everything wrong with a config and exit.
+// Logging isn't set up until the config is read
+fn exit_invalid(path: &str, errors: &[ConfigError]) -> ! {
+    for err in errors {
+        eprintln!("Invalid config {}: {}", path, err);
+    }
+    std::process::exit(1);
+}
+
+pub fn validate_or_exit(path: &str, conf: &Config) {
+    if let Err(errors) = validate(conf) {
+        exit_invalid(path, &errors);
+    }
+}
+
+// A config file is layered with, in order, the optional
+// `<name>.local.yaml` alongside it, e.g. for a deployment's
+// or a developer's own settings, then env vars prefixed
+// with `DOMA_`, with `__` between nested keys,
+// e.g. `DOMA_POLICIES__MAX_RENT_INCREASE=1.05`
+static ENV_PREFIX: &str = "DOMA_";
+
+pub fn local_path(path: &str) -> PathBuf {
+    Path::new(path).with_extension("local.yaml")
+}
+
+fn read_layer(path: &Path) -> Result<serde_yaml::Value, ConfigError> {
+    let data = fs::read_to_string(path).map_err(|err| ConfigError::Invalid(format!("couldn't read {}: {}", path.display(), err)))?;
+    serde_yaml::from_str(&data).map_err(|err| ConfigError::Invalid(format!("{}: {}", path.display(), err)))
+}
+
+// Later layers' keys replace earlier ones',
+// sections are merged key by key
+fn merge(base: &mut serde_yaml::Value, layer: serde_yaml::Value) {
+    match (base, layer) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(layer)) => {
+            for (key, val) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, val),
+                    None => { base.insert(key, val); },
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+// Set a key, with `.` for nested ones,
+// adding sections as needed
+fn set_key(root: &mut serde_yaml::Value, key: &str, val: serde_yaml::Value) {
+    let mut target = root;
+    for part in key.split('.') {
+        if !target.is_mapping() {
+            *target = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+        }
+        let map = match target {
+            serde_yaml::Value::Mapping(map) => map,
+            _ => unreachable!(),
+        };
+        let part = serde_yaml::Value::String(part.to_string());
+        if !map.contains_key(&part) {
+            map.insert(part.clone(), serde_yaml::Value::Null);
+        }
+        target = map.get_mut(&part).unwrap();
+    }
+    *target = val;
+}
+
+// Env vars are read as YAML, e.g. `0.5` or `[csv]`,
+// or as a string if they aren't valid YAML
+fn env_value(val: String) -> serde_yaml::Value {
+    serde_yaml::from_str(&val).unwrap_or(serde_yaml::Value::String(val))
+}
+
+pub fn load_config(path: &str) -> Config {
+    let mut layered = read_layer(Path::new(path)).unwrap_or_else(|err| exit_invalid(path, &[err]));
+    let mut overlaid = false;
+    let local = local_path(path);
+    if local.exists() {
+        let layer = read_layer(&local).unwrap_or_else(|err| exit_invalid(path, &[err]));
+        merge(&mut layered, layer);
+        overlaid = true;
+    }
+
+    // `STEPS`, `SEED`, and `DEBUG` can
+    // also be set without the prefix
+    let mut overrides: Vec<(String, serde_yaml::Value)> = Vec::new();
+    for key in ["STEPS", "SEED"] {
+        if let Ok(val) = env::var(key) {
+            overrides.push((key.to_string(), env_value(val)));
+        }
+    }
+    if let Ok(debug) = env::var("DEBUG") {
+        overrides.push(("DEBUG".to_string(), serde_yaml::Value::Bool(debug == "1")));
+    }
+
+    // Other prefixed vars, e.g. a deployment's own
+    // `DOMA_DEPLOY_ENV`, are only taken if they
+    // name one of the config's sections or keys
+    let known = known_keys(&layered);
+    let mut from_env: Vec<(String, String)> = Vec::new();
+    for (var, val) in env::vars() {
+        if let Some(key) = var.strip_prefix(ENV_PREFIX) {
+            let key = key.replace("__", ".");
+            let top = key.split('.').next().unwrap_or_default();
+            if known.iter().any(|k| k == top) {
+                from_env.push((key.clone(), var));
+                overrides.push((key, env_value(val)));
+            } else {
+                eprintln!("Ignoring {}: {} isn't a config key", var, top);
+            }
+        }
+    }
+    for (key, val) in overrides {
+        set_key(&mut layered, &key, val);
+        overlaid = true;
+    }
+    let exit_invalid = |errors: &[ConfigError]| exit_invalid(path, &name_env_vars(errors, &from_env));
+
+    // Errors in the file alone can say where they are
+    let data = if overlaid {
+        serde_yaml::to_string(&layered).unwrap()
+    } else {
+        fs::read_to_string(path).unwrap()
+    };
+    let mut conf = parse_config(&data).unwrap_or_else(|errors| exit_invalid(&errors));
+    if layered.get("STEPS").is_none() {
+        conf.steps = 100;
+    }
+    if layered.get("SEED").is_none() {
+        conf.seed = rand::thread_rng().gen();
+    }
+
+    if let Err(errors) = validate(&conf) {
+        exit_invalid(&errors);
+    }
     conf
 }
+
+// Top-level keys a config can have: those in its
+// files, and those with defaults it can leave out
+fn known_keys(layered: &serde_yaml::Value) -> Vec<String> {
+    let mut keys: Vec<String> = PLAY_KEYS.iter().map(|key| key.to_string()).collect();
+    if let Some(map) = layered.as_mapping() {
+        keys.extend(map.iter().filter_map(|(key, _)| key.as_str()).map(String::from));
+    }
+    if let Ok(conf) = serde_yaml::from_value::<Config>(layered.clone()) {
+        if let Value::Object(map) = serde_json::to_value(conf).unwrap() {
+            keys.extend(map.keys().cloned());
+        }
+    }
+    keys
+}
+
+// Point errors in keys set by env vars at the vars,
+// since the config file doesn't have those values
+fn name_env_vars(errors: &[ConfigError], from_env: &[(String, String)]) -> Vec<ConfigError> {
+    errors.iter().map(|err| {
+        let msg = err.to_string();
+        let set_by = |key: &str| match err {
+            ConfigError::UnknownKey { key: bad, .. } => bad.starts_with(key),
+            ConfigError::OutOfRange { key: bad, .. } => bad.starts_with(key),
+            ConfigError::Invalid(_) => msg.contains(key),
+            _ => false,
+        };
+        match from_env.iter().find(|(key, _)| set_by(key)) {
+            Some((_, var)) => ConfigError::Invalid(format!("{} (set by {})", msg, var)),
+            None => ConfigError::Invalid(msg),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conf() -> Config {
+        load_config("config.yaml")
+    }
+
+    fn out_of_range(conf: &Config, expected_key: &str) -> bool {
+        validate(conf).unwrap_err().iter().any(|err| matches!(err, ConfigError::OutOfRange { key, .. } if *key == expected_key))
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(validate(&conf()).is_ok());
+    }
+
+    #[test]
+    fn raise_mean() {
+        let mut conf = conf();
+        conf.raise_mean = 0.;
+        assert!(out_of_range(&conf, "RAISE_MEAN"));
+    }
+
+    #[test]
+    fn initial_savings() {
+        let mut conf = conf();
+        conf.initial_savings_months = -1.;
+        assert!(out_of_range(&conf, "INITIAL_SAVINGS_MONTHS"));
+        conf.initial_savings_months = 0.;
+        assert!(validate(&conf).is_ok());
+    }
+
+    #[test]
+    fn building_age() {
+        let mut conf = conf();
+        conf.max_building_age = 0;
+        assert!(out_of_range(&conf, "MAX_BUILDING_AGE"));
+        conf.max_building_age = conf.start_year + 1;
+        assert!(out_of_range(&conf, "MAX_BUILDING_AGE"));
+    }
+}
@@ -0,0 +1,176 @@
+use super::config::Config;
+use super::events::LoggedEvent;
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use std::sync::Mutex;
+
+pub type DbResult<T> = rusqlite::Result<T>;
+
+// Stats are stored in long form, so
+// new stats don't need schema changes.
+// Stats broken down by group (e.g. neighborhoods)
+// go in `group_stats`, by group and member id
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    started TEXT NOT NULL,
+    finished TEXT,
+    months INTEGER,
+    dir TEXT,
+    design TEXT NOT NULL,
+    seed INTEGER NOT NULL,
+    params TEXT,
+    config TEXT NOT NULL,
+    init TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS stats (
+    run INTEGER NOT NULL REFERENCES runs(id),
+    month INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value REAL,
+    PRIMARY KEY (run, month, key)
+);
+CREATE TABLE IF NOT EXISTS group_stats (
+    run INTEGER NOT NULL REFERENCES runs(id),
+    month INTEGER NOT NULL,
+    grp TEXT NOT NULL,
+    member TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value REAL,
+    PRIMARY KEY (run, month, grp, member, key)
+);
+CREATE TABLE IF NOT EXISTS events (
+    run INTEGER NOT NULL REFERENCES runs(id),
+    month INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_run ON events (run, month);
+";
+
+// What a run was started with
+pub struct RunInfo<'a> {
+    // `debug`, `play`, or `sweep`
+    pub kind: &'a str,
+
+    // Where the run's output is saved, if anywhere
+    pub dir: Option<&'a str>,
+
+    // Sweep parameter values
+    pub params: Option<Value>,
+
+    pub conf: &'a Config,
+    pub init: &'a Value,
+}
+
+// A SQLite database that many runs are saved to,
+// so they can be compared with SQL queries.
+// Shared between sweep threads
+pub struct RunDb {
+    con: Mutex<Connection>,
+}
+
+impl RunDb {
+    pub fn open(path: &str) -> DbResult<RunDb> {
+        let con = Connection::open(path)?;
+
+        // WAL so that commits after every step stay cheap,
+        // and other processes can read while runs are going
+        con.pragma_update(None, "journal_mode", "WAL")?;
+        con.pragma_update(None, "synchronous", "NORMAL")?;
+        con.execute_batch(SCHEMA)?;
+        Ok(RunDb {
+            con: Mutex::new(con),
+        })
+    }
+
+    pub fn start_run(&self, info: &RunInfo) -> DbResult<i64> {
+        let con = self.con.lock().unwrap();
+        con.execute(
+            "INSERT INTO runs (kind, started, dir, design, seed, params, config, init)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                info.kind,
+                Utc::now().to_rfc3339(),
+                info.dir,
+                info.conf.design_id,
+                // SQLite integers are signed,
+                // so large seeds wrap around
+                info.conf.seed as i64,
+                info.params.as_ref().map(|p| p.to_string()),
+                serde_json::to_string(info.conf).unwrap(),
+                info.init.to_string(),
+            ])?;
+        Ok(con.last_insert_rowid())
+    }
+
+    // Pick a run back up from `month`, e.g. when it's resumed
+    // from a checkpoint, dropping what was recorded after it.
+    // False if the run, saved to `dir`, isn't in this database
+    pub fn resume_run(&self, run: i64, dir: &str, month: usize) -> DbResult<bool> {
+        let con = self.con.lock().unwrap();
+        let exists: bool = con.query_row(
+            "SELECT EXISTS(SELECT 1 FROM runs WHERE id = ?1 AND dir = ?2)",
+            params![run, dir], |row| row.get(0))?;
+        if !exists {
+            return Ok(false);
+        }
+        let tx = con.unchecked_transaction()?;
+        for table in &["stats", "group_stats", "events"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE run = ?1 AND month > ?2", table),
+                params![run, month as i64])?;
+        }
+        tx.execute("UPDATE runs SET finished = NULL, months = NULL WHERE id = ?1", params![run])?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    // Record a month's stats and the events since the last record.
+    // Only numeric stats are kept
+    pub fn record(&self, run: i64, month: usize, stats: &Value, events: &[LoggedEvent]) -> DbResult<()> {
+        let con = self.con.lock().unwrap();
+        let tx = con.unchecked_transaction()?;
+        {
+            let mut insert_stat = tx.prepare_cached(
+                "INSERT OR REPLACE INTO stats (run, month, key, value) VALUES (?1, ?2, ?3, ?4)")?;
+            let mut insert_group_stat = tx.prepare_cached(
+                "INSERT OR REPLACE INTO group_stats (run, month, grp, member, key, value) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            if let Some(stats) = stats.as_object() {
+                for (key, val) in stats {
+                    if let Some(v) = val.as_f64() {
+                        insert_stat.execute(params![run, month as i64, key, v])?;
+                    } else if let Some(members) = val.as_object() {
+                        for (member, member_stats) in members {
+                            for (member_key, v) in member_stats.as_object().into_iter().flatten() {
+                                if let Some(v) = v.as_f64() {
+                                    insert_group_stat.execute(params![run, month as i64, key, member, member_key, v])?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            let mut insert_event = tx.prepare_cached(
+                "INSERT INTO events (run, month, type, data) VALUES (?1, ?2, ?3, ?4)")?;
+            for event in events {
+                let data = serde_json::to_value(&event.event).unwrap();
+                insert_event.execute(params![run, event.month as i64, data["type"].as_str(), data.to_string()])?;
+            }
+        }
+        tx.commit()
+    }
+
+    // Runs that never finish, e.g. that crashed,
+    // are left without a `finished` time
+    pub fn finish_run(&self, run: i64, months: usize) -> DbResult<()> {
+        let con = self.con.lock().unwrap();
+        con.execute(
+            "UPDATE runs SET finished = ?1, months = ?2 WHERE id = ?3",
+            params![Utc::now().to_rfc3339(), months as i64, run])?;
+        Ok(())
+    }
+}
@@ -1,12 +1,40 @@
-use fnv::FnvHashMap;
-use redis::Commands;
+use fnv::{FnvHashMap, FnvHashSet};
+#[cfg(feature = "play")]
+use super::backend::SyncBackend;
+use super::city::{LandUse, ParcelType};
 use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use tracing::error;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Design {
     pub map: Map,
     pub neighborhoods: FnvHashMap<usize, Neighborhood>,
     pub city: CityConfig,
+
+    #[serde(default)]
+    pub amenities: Vec<Amenity>,
+
+    #[serde(default)]
+    pub transit: Vec<TransitLine>,
+
+    // If none, jobs are spread over
+    // commercial space instead
+    #[serde(default, rename = "employmentCenters")]
+    pub employment_centers: Vec<EmploymentCenter>,
+
+    // Boundary changes over the run. Neighborhoods
+    // that only exist after one still need to be
+    // declared, even without parcels at the start
+    #[serde(default)]
+    pub redistricting: Vec<Redistricting>,
+
+    // Parcels not in any zone can be built
+    // up to their neighborhood's maxUnits
+    #[serde(default)]
+    pub zoning: Vec<Zone>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -21,10 +49,94 @@ pub struct Neighborhood {
     pub max_area: u32,
     pub sqm_per_occupant: u32,
     pub p_commercial: f32,
-    pub color: String
+    pub color: String,
+
+    // Annual property tax rate;
+    // if not specified, the config default is used
+    #[serde(default)]
+    pub tax_rate: Option<f32>,
+
+    // Beds in homeless shelters
+    #[serde(default)]
+    pub shelter_capacity: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AmenityType {
+    Park,
+    Transit,
+    School,
 }
 
-#[derive(Deserialize, Debug)]
+// A point amenity that makes
+// nearby parcels more desirable
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Amenity {
+    #[serde(rename = "type")]
+    pub typ: AmenityType,
+    pub row: usize,
+    pub col: usize,
+}
+
+// A transit line running through
+// its stops in order, as (row, col)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransitLine {
+    pub name: String,
+    pub stops: Vec<(usize, usize)>,
+}
+
+// A place with jobs, e.g. a downtown or
+// an industrial park, with its own wages
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmploymentCenter {
+    pub name: String,
+    pub row: usize,
+    pub col: usize,
+
+    // Relative to other centers, as new
+    // hires are drawn in proportion to jobs
+    pub jobs: f32,
+
+    // Log-normal wage distribution for new hires;
+    // if not specified, the city's income distribution
+    #[serde(default)]
+    pub wage_mu: Option<f32>,
+    #[serde(default)]
+    pub wage_sigma: Option<f32>,
+
+    // Annual change in jobs, e.g. -0.05
+    // for a center losing 5% of its jobs a year
+    #[serde(default)]
+    pub growth: f32,
+}
+
+// Parcels, as (row, col), that move
+// into a neighborhood at a set month
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Redistricting {
+    pub month: usize,
+    pub neighborhood: usize,
+    pub parcels: Vec<(usize, usize)>,
+}
+
+// Zoning for a set of parcels, as (row, col),
+// which developers and landlords build within
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Zone {
+    pub parcels: Vec<(usize, usize)>,
+
+    #[serde(default)]
+    pub max_units: Option<usize>,
+
+    #[serde(default, rename = "use")]
+    pub land_use: LandUse,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CityConfig {
     pub name: String,
@@ -37,7 +149,7 @@ pub struct CityConfig {
     pub income_sigma: f32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Map {
     pub layout: Vec<Vec<Option<String>>>,
     pub offset: MapOffset,
@@ -49,12 +161,296 @@ pub struct MapOffset {
     pub col: bool,
 }
 
+// Problems with a design that would otherwise
+// surface as panics while creating the city.
+// Positions are (row, col)
+#[derive(Debug)]
+pub enum DesignError {
+    EmptyMap,
+
+    // Rows are expected to be as long as the first
+    OutOfBounds { row: usize, col: usize, cols: usize },
+
+    // Parcels are `<neighborhood id>|<parcel type>`
+    MalformedParcel { row: usize, col: usize, parcel: String },
+
+    // No residential parcels to put units on,
+    // or no units allowed on them
+    NoUnits { neighborhood: usize },
+    InvalidUnitRange { neighborhood: usize, min: u32, max: u32 },
+    InvalidAreaRange { neighborhood: usize, min: u32, max: u32 },
+    ZeroOccupantArea { neighborhood: usize },
+    InvalidCommercialShare { neighborhood: usize, p_commercial: f32 },
+
+    // Prices are needed to set rents
+    ZeroPrice,
+    ZeroPriceToRentRatio,
+
+    // Log-normal spreads can't be negative
+    InvalidIncomeSigma { sigma: f32 },
+    InvalidWageSigma { center: String, sigma: f32 },
+
+    // Tenants need somewhere to work, i.e. employment
+    // centers or buildings with commercial floors,
+    // which need more than 3 units
+    NoWorkPositions,
+
+    AmenityOutOfBounds { row: usize, col: usize },
+    TransitStopOutOfBounds { line: String, row: usize, col: usize },
+    EmploymentCenterOutOfBounds { center: String, row: usize, col: usize },
+
+    // Tenants commute to their work's parcel,
+    // so centers can't be on empty cells
+    EmploymentCenterOffParcel { center: String, row: usize, col: usize },
+    NoJobs { center: String },
+
+    UnknownRedistrictNeighborhood { month: usize, neighborhood: usize },
+    RedistrictParcelMissing { month: usize, row: usize, col: usize },
+    ZoneOutOfBounds { row: usize, col: usize },
+}
+
+impl fmt::Display for DesignError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DesignError::EmptyMap => write!(f, "map has no parcels"),
+            DesignError::OutOfBounds { row, col, cols } =>
+                write!(f, "parcel at ({}, {}) is outside the map, which is {} columns wide", row, col, cols),
+            DesignError::MalformedParcel { row, col, parcel } =>
+                write!(f, "parcel at ({}, {}) is {:?}, expected `<neighborhood id>|<parcel type>`", row, col, parcel),
+            DesignError::NoUnits { neighborhood } =>
+                write!(f, "neighborhood {} has no units", neighborhood),
+            DesignError::InvalidUnitRange { neighborhood, min, max } =>
+                write!(f, "neighborhood {} has minUnits {} and maxUnits {}, expected minUnits < maxUnits", neighborhood, min, max),
+            DesignError::InvalidAreaRange { neighborhood, min, max } =>
+                write!(f, "neighborhood {} has minArea {} and maxArea {}, expected 0 < minArea < maxArea", neighborhood, min, max),
+            DesignError::ZeroOccupantArea { neighborhood } =>
+                write!(f, "neighborhood {} has a sqmPerOccupant of 0", neighborhood),
+            DesignError::InvalidCommercialShare { neighborhood, p_commercial } =>
+                write!(f, "neighborhood {} has a pCommercial of {}, expected at least 0 and less than 1", neighborhood, p_commercial),
+            DesignError::ZeroPrice => write!(f, "city pricePerSqm must be greater than 0"),
+            DesignError::ZeroPriceToRentRatio => write!(f, "city priceToRentRatio must be greater than 0"),
+            DesignError::InvalidIncomeSigma { sigma } =>
+                write!(f, "city incomeSigma is {}, expected at least 0", sigma),
+            DesignError::InvalidWageSigma { center, sigma } =>
+                write!(f, "employment center {:?} has a wageSigma of {}, expected at least 0", center, sigma),
+            DesignError::NoWorkPositions =>
+                write!(f, "no commercial space for jobs; some neighborhood needs a pCommercial above 0 and a maxUnits above 4, or the design needs employmentCenters"),
+            DesignError::AmenityOutOfBounds { row, col } =>
+                write!(f, "amenity at ({}, {}) is outside the map", row, col),
+            DesignError::TransitStopOutOfBounds { line, row, col } =>
+                write!(f, "stop at ({}, {}) on transit line {:?} is outside the map", row, col, line),
+            DesignError::EmploymentCenterOutOfBounds { center, row, col } =>
+                write!(f, "employment center {:?} at ({}, {}) is outside the map", center, row, col),
+            DesignError::EmploymentCenterOffParcel { center, row, col } =>
+                write!(f, "employment center {:?} at ({}, {}) has no parcel, so no one can get to work there", center, row, col),
+            DesignError::NoJobs { center } =>
+                write!(f, "employment center {:?} must have more than 0 jobs", center),
+            DesignError::UnknownRedistrictNeighborhood { month, neighborhood } =>
+                write!(f, "redistricting in month {} moves parcels into neighborhood {}, which isn't in the design", month, neighborhood),
+            DesignError::RedistrictParcelMissing { month, row, col } =>
+                write!(f, "redistricting in month {} moves the parcel at ({}, {}), but there's no parcel there", month, row, col),
+            DesignError::ZoneOutOfBounds { row, col } =>
+                write!(f, "zoned parcel at ({}, {}) is outside the map", row, col),
+        }
+    }
+}
+
+impl std::error::Error for DesignError {}
+
+// Check a design for everything wrong with it at once
+pub fn validate(design: &Design) -> Result<(), Vec<DesignError>> {
+    let mut errors = Vec::new();
+    let layout = &design.map.layout;
+    let rows = layout.len();
+    let cols = layout.first().map_or(0, |row| row.len());
+    if rows == 0 || cols == 0 {
+        return Err(vec![DesignError::EmptyMap]);
+    }
+
+    // Neighborhoods with residential parcels
+    let mut residential = FnvHashSet::default();
+    for (r, row) in layout.iter().enumerate() {
+        for (c, cell) in row.iter().enumerate() {
+            let parcel = match cell {
+                Some(parcel) => parcel,
+                None => continue,
+            };
+            if c >= cols {
+                errors.push(DesignError::OutOfBounds { row: r, col: c, cols: cols });
+                continue;
+            }
+            let parts: Vec<&str> = parcel.split('|').collect();
+            let parsed = match parts.as_slice() {
+                [id, typ] => id.parse::<i32>().ok().zip(ParcelType::from_str(typ).ok()),
+                _ => None,
+            };
+            match parsed {
+                Some((id, ParcelType::Residential)) if id >= 0 => { residential.insert(id as usize); },
+                Some(_) => {},
+                None => errors.push(DesignError::MalformedParcel { row: r, col: c, parcel: parcel.clone() }),
+            }
+        }
+    }
+
+    // Neighborhoods can also get their
+    // residential parcels by redistricting
+    for change in &design.redistricting {
+        if !design.neighborhoods.contains_key(&change.neighborhood) {
+            errors.push(DesignError::UnknownRedistrictNeighborhood { month: change.month, neighborhood: change.neighborhood });
+        }
+        for &(r, c) in &change.parcels {
+            match layout.get(r).and_then(|row| row.get(c)) {
+                Some(Some(parcel)) => {
+                    let typ = parcel.split('|').nth(1).and_then(|typ| ParcelType::from_str(typ).ok());
+                    if typ == Some(ParcelType::Residential) {
+                        residential.insert(change.neighborhood);
+                    }
+                },
+                _ => errors.push(DesignError::RedistrictParcelMissing { month: change.month, row: r, col: c }),
+            }
+        }
+    }
+
+    let mut ids: Vec<&usize> = design.neighborhoods.keys().collect();
+    ids.sort();
+    let mut has_work = false;
+    for &id in ids {
+        let neighb = &design.neighborhoods[&id];
+        if !residential.contains(&id) || neighb.max_units == 0 {
+            errors.push(DesignError::NoUnits { neighborhood: id });
+        } else if neighb.min_units >= neighb.max_units {
+            errors.push(DesignError::InvalidUnitRange { neighborhood: id, min: neighb.min_units, max: neighb.max_units });
+        } else if neighb.max_units > 4 && neighb.p_commercial > 0. {
+            has_work = true;
+        }
+        if neighb.min_area == 0 || neighb.min_area >= neighb.max_area {
+            errors.push(DesignError::InvalidAreaRange { neighborhood: id, min: neighb.min_area, max: neighb.max_area });
+        }
+        if neighb.sqm_per_occupant == 0 {
+            errors.push(DesignError::ZeroOccupantArea { neighborhood: id });
+        }
+        if !(0. ..1.).contains(&neighb.p_commercial) {
+            errors.push(DesignError::InvalidCommercialShare { neighborhood: id, p_commercial: neighb.p_commercial });
+        }
+    }
+    if !has_work && design.employment_centers.is_empty() {
+        errors.push(DesignError::NoWorkPositions);
+    }
 
-pub fn load_design(design_id: &String) -> Design {
-    let client = redis::Client::open("redis://127.0.0.1/1").unwrap();
-    let con = client.get_connection().unwrap();
+    if design.city.price_per_sqm <= 0. {
+        errors.push(DesignError::ZeroPrice);
+    }
+    if design.city.price_to_rent_ratio <= 0. {
+        errors.push(DesignError::ZeroPriceToRentRatio);
+    }
+    if !(design.city.income_sigma >= 0. && design.city.income_sigma.is_finite()) {
+        errors.push(DesignError::InvalidIncomeSigma { sigma: design.city.income_sigma });
+    }
+
+    for amenity in &design.amenities {
+        if amenity.row >= rows || amenity.col >= cols {
+            errors.push(DesignError::AmenityOutOfBounds { row: amenity.row, col: amenity.col });
+        }
+    }
+    for line in &design.transit {
+        for &(r, c) in &line.stops {
+            if r >= rows || c >= cols {
+                errors.push(DesignError::TransitStopOutOfBounds { line: line.name.clone(), row: r, col: c });
+            }
+        }
+    }
+    for zone in &design.zoning {
+        for &(r, c) in &zone.parcels {
+            if r >= rows || c >= cols {
+                errors.push(DesignError::ZoneOutOfBounds { row: r, col: c });
+            }
+        }
+    }
+    for center in &design.employment_centers {
+        if center.row >= rows || center.col >= cols {
+            errors.push(DesignError::EmploymentCenterOutOfBounds { center: center.name.clone(), row: center.row, col: center.col });
+        } else if layout[center.row].get(center.col).is_none_or(|cell| cell.is_none()) {
+            errors.push(DesignError::EmploymentCenterOffParcel { center: center.name.clone(), row: center.row, col: center.col });
+        }
+        if center.jobs <= 0. {
+            errors.push(DesignError::NoJobs { center: center.name.clone() });
+        }
+        match center.wage_sigma {
+            Some(sigma) if !(sigma >= 0. && sigma.is_finite()) =>
+                errors.push(DesignError::InvalidWageSigma { center: center.name.clone(), sigma: sigma }),
+            _ => {}
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Print everything wrong with a design and exit
+pub fn validate_or_exit(design: &Design) {
+    if let Err(errors) = validate(design) {
+        for err in &errors {
+            error!("Invalid design: {}", err);
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "play")]
+pub fn load_design(design_id: &String, backend: &dyn SyncBackend) -> Design {
     let design_key = format!("design:{}", design_id);
-    let design_data: String = con.get(design_key).expect("no design for that id");
+    let design_data = backend.get(&design_key).unwrap().expect("no design for that id");
     let design: Design = serde_json::from_str(&design_data).expect("error while reading json");
     design
 }
+
+pub fn load_design_file(path: &str) -> Design {
+    let design_data = fs::read_to_string(path).expect("could not open design file");
+    let design: Design = serde_json::from_str(&design_data).expect("error while reading json");
+    design
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::design;
+
+    fn center(row: usize, col: usize) -> EmploymentCenter {
+        EmploymentCenter {
+            name: "Downtown".to_string(),
+            row: row,
+            col: col,
+            jobs: 100.,
+            wage_mu: None,
+            wage_sigma: None,
+            growth: 0.,
+        }
+    }
+
+    #[test]
+    fn generated_design_is_valid() {
+        assert!(validate(&design()).is_ok());
+    }
+
+    #[test]
+    fn employment_center_off_parcel() {
+        let mut design = design();
+        let (row, col) = design.map.layout.iter().enumerate()
+            .find_map(|(r, row)| row.iter().position(|cell| cell.is_some()).map(|c| (r, c)))
+            .unwrap();
+        design.employment_centers.push(center(row, col));
+        assert!(validate(&design).is_ok());
+
+        design.map.layout[row][col] = None;
+        let errors = validate(&design).unwrap_err();
+        assert!(errors.iter().any(|err| matches!(err, DesignError::EmploymentCenterOffParcel { row: r, col: c, .. } if *r == row && *c == col)));
+
+        let rows = design.map.layout.len();
+        design.employment_centers = vec![center(rows, 0)];
+        let errors = validate(&design).unwrap_err();
+        assert!(errors.iter().any(|err| matches!(err, DesignError::EmploymentCenterOutOfBounds { .. })));
+    }
+}
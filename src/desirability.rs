@@ -0,0 +1,114 @@
+use super::agent::Tenant;
+use super::city::{City, Unit};
+use serde::{Serialize, Deserialize};
+
+// How tenants score units they might live in.
+// A score of 0 means the unit is out of the question
+pub trait DesirabilityModel: Sync {
+    fn desirability(&self, tenant: &Tenant, unit: &Unit, city: &City) -> f32;
+}
+
+// What tenants consider about a unit,
+// for models to weigh against each other
+pub struct Factors {
+    // Square root of income to rent
+    pub ratio: f32,
+    pub spaciousness: f32,
+    pub amenities: f32,
+    pub condition: f32,
+    pub commute: f32,
+}
+
+impl Factors {
+    // None if the tenant can't afford the unit
+    pub fn new(tenant: &Tenant, unit: &Unit, city: &City) -> Option<Factors> {
+        let n_tenants = (unit.tenants.len() + 1) as f32;
+
+        // Adjust rent by last DOMA dividend
+        let adjusted_rent_per_tenant = tenant.adjusted_rent(unit);
+
+        // Tenants can spend at most 1/3 of income on rent
+        if tenant.income/3. < adjusted_rent_per_tenant {
+            return None;
+        }
+
+        let parcel = city.parcels.get(&unit.pos).unwrap();
+        Some(Factors {
+            ratio: (tenant.income / adjusted_rent_per_tenant).sqrt(),
+            spaciousness: f32::max(unit.area / n_tenants, 0.).powf(1. / 32.),
+            amenities: parcel.desirability,
+            condition: unit.condition,
+            commute: city.commute_times.ease(tenant.work, unit.pos),
+        })
+    }
+}
+
+// Affordability scales an even mix of the other factors
+pub struct Balanced;
+
+impl DesirabilityModel for Balanced {
+    fn desirability(&self, tenant: &Tenant, unit: &Unit, city: &City) -> f32 {
+        match Factors::new(tenant, unit, city) {
+            Some(f) => f.ratio * (f.spaciousness + f.amenities + f.condition + f.commute),
+            None => 0.
+        }
+    }
+}
+
+// Tenants care most about getting to work
+pub struct CommuteWeighted;
+
+impl DesirabilityModel for CommuteWeighted {
+    fn desirability(&self, tenant: &Tenant, unit: &Unit, city: &City) -> f32 {
+        match Factors::new(tenant, unit, city) {
+            Some(f) => f.ratio * (f.spaciousness + f.amenities + f.condition + 4. * f.commute),
+            None => 0.
+        }
+    }
+}
+
+// Tenants care most about paying less,
+// scaling directly with income to rent
+pub struct PriceSensitive;
+
+impl DesirabilityModel for PriceSensitive {
+    fn desirability(&self, tenant: &Tenant, unit: &Unit, city: &City) -> f32 {
+        match Factors::new(tenant, unit, city) {
+            Some(f) => f.ratio.powi(2) * (f.spaciousness + f.amenities + f.condition + f.commute),
+            None => 0.
+        }
+    }
+}
+
+// Tenants care most about the
+// neighborhood and the unit itself
+pub struct AmenityBased;
+
+impl DesirabilityModel for AmenityBased {
+    fn desirability(&self, tenant: &Tenant, unit: &Unit, city: &City) -> f32 {
+        match Factors::new(tenant, unit, city) {
+            Some(f) => f.ratio * (f.spaciousness + 3. * f.amenities + 2. * f.condition + f.commute),
+            None => 0.
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantModel {
+    Balanced,
+    CommuteWeighted,
+    PriceSensitive,
+    AmenityBased,
+}
+
+impl TenantModel {
+    pub fn model(&self) -> &'static dyn DesirabilityModel {
+        match self {
+            TenantModel::Balanced => &Balanced,
+            TenantModel::CommuteWeighted => &CommuteWeighted,
+            TenantModel::PriceSensitive => &PriceSensitive,
+            TenantModel::AmenityBased => &AmenityBased,
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::agent::Tenant;
+use super::arena::{Arena, TenantId};
+use super::city::{City, ParcelType};
+use super::grid::Position;
+use noise::{NoiseFn, OpenSimplex, Seedable};
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct DriftConfig {
+    // Parcels and months over which
+    // the drift is correlated
+    pub scale: f64,
+    pub months: f64,
+
+    // How far the noise moves desirability
+    pub strength: f32,
+
+    // Monthly pull on desirability from how full a
+    // parcel's units are, relative to the city, and
+    // how its residents' incomes compare to the city's
+    pub occupancy_feedback: f32,
+    pub income_feedback: f32,
+}
+
+// Parcel desirability drifting on its own, so hot and
+// cold spots emerge within and across neighborhoods.
+// Nearby parcels drift together, and parcels that fill
+// up with wealthier residents become more sought after
+#[derive(Serialize, Deserialize)]
+pub struct Drift {
+    seed: u32,
+
+    // Rebuilt from the seed when restoring a checkpoint
+    #[serde(skip)]
+    noise: OpenSimplex,
+}
+
+impl Drift {
+    pub fn new(seed: u32) -> Drift {
+        Drift {
+            seed: seed,
+            noise: OpenSimplex::new().set_seed(seed),
+        }
+    }
+
+    pub fn restore(&mut self) {
+        self.noise = OpenSimplex::new().set_seed(self.seed);
+    }
+
+    fn sample(&self, pos: Position, month: f32, conf: &DriftConfig) -> f32 {
+        self.noise.get([
+            pos.0 as f64 / conf.scale,
+            pos.1 as f64 / conf.scale,
+            month as f64 / conf.months,
+        ]) as f32
+    }
+
+    // Change in each residential parcel's desirability
+    // over a step of `months`, ending at `month`
+    pub fn update(&self, city: &City, tenants: &Arena<TenantId, Tenant>, month: f32, months: f32, conf: &DriftConfig) -> Vec<(Position, f32)> {
+        // Occupancy and incomes of each parcel's residents
+        let mut parcels = Vec::new();
+        let (mut city_residents, mut city_capacity, mut city_income) = (0, 0, 0.);
+        for parcel in city.parcels.values().filter(|p| p.typ == ParcelType::Residential) {
+            let (mut residents, mut capacity, mut income) = (0, 0, 0.);
+            if let Some(building) = city.buildings.get(&parcel.pos) {
+                for &u_id in &building.units {
+                    let unit = &city.units[u_id];
+                    residents += unit.tenants.len();
+                    capacity += unit.occupancy;
+                    income += unit.tenants.iter().fold(0., |acc, &t_id| acc + tenants[t_id].income);
+                }
+            }
+            city_residents += residents;
+            city_capacity += capacity;
+            city_income += income;
+            parcels.push((parcel.pos, residents, capacity, income));
+        }
+        let city_occupancy = city_residents as f32 / f32::max(1., city_capacity as f32);
+        let city_mean_income = city_income / f32::max(1., city_residents as f32);
+
+        parcels.into_iter().map(|(pos, residents, capacity, income)| {
+            let noise = self.sample(pos, month, conf) - self.sample(pos, month - months, conf);
+            let mut change = conf.strength * noise;
+            if capacity > 0 {
+                let occupancy = residents as f32 / capacity as f32;
+                change += months * conf.occupancy_feedback * (occupancy - city_occupancy);
+            }
+            if residents > 0 && city_mean_income > 0. {
+                let relative_income = (income / residents as f32) / city_mean_income - 1.;
+                change += months * conf.income_feedback * relative_income.clamp(-1., 1.);
+            }
+            (pos, change)
+        }).collect()
+    }
+}
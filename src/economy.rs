@@ -0,0 +1,92 @@
+use serde::{Serialize, Deserialize};
+use std::f32::consts::PI;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct EconomyConfig {
+    // Length of a full boom-bust cycle, in months.
+    // If 0, conditions don't cycle
+    pub cycle_months: usize,
+
+    // How far the annual mortgage interest rate
+    // swings above and below its base rate
+    pub interest_rate_amplitude: f32,
+
+    // Citywide monthly wage growth,
+    // and how far it swings over the cycle
+    pub income_growth: f32,
+    pub income_growth_amplitude: f32,
+
+    // How far migration swings over the cycle
+    // and over the year, as fractions of its base rate
+    pub migration_amplitude: f32,
+    pub seasonal_migration_amplitude: f32,
+
+    #[serde(default)]
+    pub shocks: Vec<Shock>,
+}
+
+// A temporary change in conditions,
+// e.g. a recession
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Shock {
+    pub start: usize,
+    pub months: usize,
+
+    // Added to the annual interest rate
+    // and monthly income growth
+    #[serde(default)]
+    pub interest_rate: f32,
+    #[serde(default)]
+    pub income_growth: f32,
+
+    // Added to the migration multiplier
+    #[serde(default)]
+    pub migration: f32,
+}
+
+// Economic conditions for a given month
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Conditions {
+    // Annual mortgage interest rate
+    pub interest_rate: f32,
+
+    // Monthly growth in wages
+    pub income_growth: f32,
+
+    // Multiplier on in- and out-migration
+    pub migration: f32,
+}
+
+impl Conditions {
+    // Conditions `months` into the run
+    pub fn at(months: f32, base_interest_rate: f32, conf: &EconomyConfig) -> Conditions {
+        let cycle = if conf.cycle_months > 0 {
+            (2. * PI * months / conf.cycle_months as f32).sin()
+        } else {
+            0.
+        };
+
+        // Most people move in the summer
+        let season = (2. * PI * (months.floor() % 12. - 3.) / 12.).sin();
+
+        let mut conditions = Conditions {
+            interest_rate: base_interest_rate + cycle * conf.interest_rate_amplitude,
+            income_growth: conf.income_growth + cycle * conf.income_growth_amplitude,
+            migration: 1.
+                + cycle * conf.migration_amplitude
+                + season * conf.seasonal_migration_amplitude,
+        };
+        for shock in &conf.shocks {
+            if months >= shock.start as f32 && months < (shock.start + shock.months) as f32 {
+                conditions.interest_rate += shock.interest_rate;
+                conditions.income_growth += shock.income_growth;
+                conditions.migration += shock.migration;
+            }
+        }
+        conditions.interest_rate = f32::max(0., conditions.interest_rate);
+        conditions.migration = f32::max(0., conditions.migration);
+        conditions
+    }
+}
@@ -0,0 +1,159 @@
+use super::city::City;
+use super::design::Design;
+use super::grid::Position;
+use fnv::FnvHashMap;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use rand_distr::LogNormal;
+use serde::{Serialize, Deserialize};
+
+// A place with jobs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobCenter {
+    pub name: String,
+    pub pos: Position,
+    pub jobs: f32,
+
+    // Log-normal (mu, sigma) of new hires'
+    // wages, if different from the city's
+    pub wages: Option<(f32, f32)>,
+
+    // Annual change in jobs
+    pub growth: f32,
+
+    // Local businesses in commercial space, whose
+    // jobs follow how many storefronts are open
+    #[serde(default)]
+    pub commercial: bool,
+}
+
+// Where tenants find work. Without employment centers
+// in the design, every parcel with commercial space is
+// a center, with as many jobs as commercial floors
+#[derive(Serialize, Deserialize)]
+pub struct Employment {
+    pub centers: Vec<JobCenter>,
+
+    // Rebuilt when restoring a checkpoint
+    #[serde(skip, default = "empty_dist")]
+    dist: WeightedIndex<f32>,
+
+    // Share of workers laid off this step at
+    // centers losing jobs, by center index
+    #[serde(skip)]
+    cuts: FnvHashMap<usize, f32>,
+}
+
+// Placeholder until it's rebuilt
+fn empty_dist() -> WeightedIndex<f32> {
+    WeightedIndex::new([1.]).unwrap()
+}
+
+impl Employment {
+    pub fn new(design: &Design, city: &City) -> Employment {
+        let centers = if design.employment_centers.is_empty() {
+            city.commercial.iter().map(|(pos, &floors)| JobCenter {
+                name: city.neighborhood_for_pos(&pos).map(|n| n.name.clone()).unwrap_or_default(),
+                pos: pos,
+                jobs: floors as f32,
+                wages: None,
+                growth: 0.,
+                commercial: true,
+            }).collect()
+        } else {
+            design.employment_centers.iter().map(|c| JobCenter {
+                name: c.name.clone(),
+                pos: (c.row as isize, c.col as isize),
+                jobs: c.jobs,
+                wages: c.wage_mu.map(|mu| (mu, c.wage_sigma.unwrap_or(design.city.income_sigma))),
+                growth: c.growth,
+                commercial: false,
+            }).collect()
+        };
+        let mut employment = Employment {
+            centers: centers,
+            dist: empty_dist(),
+            cuts: FnvHashMap::default(),
+        };
+        employment.restore();
+        employment
+    }
+
+    // Weight centers by their jobs
+    pub fn restore(&mut self) {
+        self.dist = WeightedIndex::new(self.centers.iter().map(|c| c.jobs)).unwrap();
+    }
+
+    // Where a job opening is, as
+    // the center's index and position
+    pub fn job(&self, rng: &mut StdRng) -> (usize, Position) {
+        let idx = self.dist.sample(rng);
+        (idx, self.centers[idx].pos)
+    }
+
+    // A job for a newcomer, and its wage, from the
+    // center's wages or otherwise the city's incomes.
+    // Designs are validated so wages are well-formed
+    pub fn hire(&self, incomes: &LogNormal<f32>, rng: &mut StdRng) -> (usize, Position, f32) {
+        let (idx, pos) = self.job(rng);
+        let wage = match self.centers[idx].wages {
+            Some((mu, sigma)) => LogNormal::new(mu, sigma).unwrap().sample(rng),
+            None => incomes.sample(rng),
+        };
+        (idx, pos, wage)
+    }
+
+    // Grow or shrink each center by a step's worth
+    // of its annual growth, always keeping a job.
+    // Shrinking centers cut as many workers as jobs
+    pub fn grow(&mut self, steps_per_year: usize) {
+        self.cuts.clear();
+        let mut changed = false;
+        for (idx, center) in self.centers.iter_mut().enumerate() {
+            if center.growth == 0. {
+                continue;
+            }
+            let rate = (1. + center.growth).max(0.).powf(1./steps_per_year as f32) - 1.;
+            center.jobs = f32::max(1., center.jobs * (1. + rate));
+            if rate < 0. {
+                self.cuts.insert(idx, -rate);
+            }
+            changed = true;
+        }
+        if changed {
+            self.restore();
+        }
+    }
+
+    // Staff commercial centers with a job per open
+    // storefront. Closing storefronts lay off their
+    // workers, on top of any cuts from growth
+    pub fn staff(&mut self, jobs: impl Iterator<Item=(Position, f32)>) {
+        let jobs: FnvHashMap<Position, f32> = jobs.collect();
+        let mut changed = false;
+        for (idx, center) in self.centers.iter_mut().enumerate().filter(|(_, c)| c.commercial) {
+            if let Some(&open) = jobs.get(&center.pos) {
+                let open = f32::max(1., open);
+                if open < center.jobs {
+                    *self.cuts.entry(idx).or_insert(0.) += 1. - open / center.jobs;
+                }
+                changed = changed || open != center.jobs;
+                center.jobs = open;
+            }
+        }
+        if changed {
+            self.restore();
+        }
+    }
+
+    // Chance of losing a job at the
+    // given center to cuts this step
+    pub fn cut(&self, center: usize) -> f32 {
+        *self.cuts.get(&center).unwrap_or(&0.)
+    }
+
+    pub fn jobs(&self) -> f32 {
+        self.centers.iter().map(|c| c.jobs).sum()
+    }
+}
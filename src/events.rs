@@ -0,0 +1,120 @@
+use super::agent::AgentType;
+use super::arena::{TenantId, UnitId};
+use super::grid::Position;
+use super::scenario::Intervention;
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Things that happen in the city, emitted as
+// the simulation steps, for the activity feed
+// and run output
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum SimEvent {
+    // A tenant moved into a unit, from
+    // the unit they had before, if any
+    MovedIn { tenant: TenantId, unit: UnitId, from: Option<UnitId> },
+
+    // A tenant moved out without a place to go,
+    // e.g. when their household broke up, or
+    // a speculator let their lease run out
+    MovedOut { tenant: TenantId, unit: UnitId },
+
+    Evicted { tenant: TenantId, unit: UnitId },
+
+    // A tenant moved out before their lease
+    // was up, paying the penalty for it
+    LeaseBroken { tenant: TenantId, unit: UnitId, penalty: f32 },
+
+    // A long-time resident was forced
+    // out of their neighborhood
+    Displaced { tenant: TenantId, neighborhood: usize },
+
+    Arrived { tenant: TenantId },
+    Departed { tenant: TenantId },
+
+    // Rent raised at lease renewal
+    RentRaised { unit: UnitId, from: f32, to: f32 },
+
+    // Owners are (agent type, agent id)
+    UnitSold { unit: UnitId, seller: (AgentType, usize), buyer: (AgentType, usize), price: f32 },
+
+    UnitBuilt { unit: UnitId, developer: usize },
+
+    // A landlord split a unit, carving out a new one,
+    // or merged a unit into another in the same building
+    UnitSubdivided { unit: UnitId, new_unit: UnitId, landlord: usize },
+    UnitsMerged { unit: UnitId, into: UnitId, landlord: usize },
+
+    Foreclosed { unit: UnitId, landlord: usize },
+
+    // An owner paid to retrofit a unit
+    Retrofitted { unit: UnitId, owner: (AgentType, usize), cost: f32 },
+    ListedShortTerm { unit: UnitId },
+
+    // Tenants of a building organized, and their union
+    // talked a raise down or struck against a landlord
+    UnionFormed { building: Position, members: usize },
+    RentNegotiated { unit: UnitId, from: f32, to: f32 },
+    StrikeStarted { building: Position, landlord: usize },
+    StrikeEnded { building: Position, landlord: usize },
+    DomaAcquired { unit: UnitId, price: f32 },
+    DomaRoundClosed { round: usize, raised: f32 },
+
+    // A scripted intervention from the config's scenarios
+    Intervention { intervention: Intervention },
+
+    // A message for players, scheduled
+    // in the config or sent by the operator
+    Narration { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedEvent {
+    pub month: usize,
+
+    #[serde(flatten)]
+    pub event: SimEvent,
+}
+
+// Events over a whole run
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EventLog {
+    pub events: Vec<LoggedEvent>,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog::default()
+    }
+
+    pub fn record(&mut self, month: usize, events: &[SimEvent]) {
+        self.events.extend(events.iter().map(|event| LoggedEvent {
+            month: month,
+            event: event.clone(),
+        }));
+    }
+
+    // Messages for players so far, oldest first
+    pub fn narration(&self) -> Vec<&LoggedEvent> {
+        self.events.iter().filter(|e| matches!(e.event, SimEvent::Narration { .. })).collect()
+    }
+
+    // The latest events, oldest first
+    pub fn recent(&self, n: usize) -> &[LoggedEvent] {
+        &self.events[self.events.len().saturating_sub(n)..]
+    }
+
+    // One JSON object per line, as runs
+    // can have a great many events
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for event in &self.events {
+            serde_json::to_writer(&mut file, event)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()
+    }
+}
@@ -0,0 +1,541 @@
+use super::arena::UnitId;
+use super::city::Unit;
+use super::sim::Simulation;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+
+    // Requires the `parquet` feature
+    Parquet,
+}
+
+// Requires the `plot` feature
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlotFormat {
+    Png,
+    Svg,
+}
+
+impl PlotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlotFormat::Png => "png",
+            PlotFormat::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ExportConfig {
+    pub formats: Vec<ExportFormat>,
+
+    // Chart debug runs in each of these
+    // formats, under `plots/`, or not if empty
+    #[serde(default)]
+    pub plots: Vec<PlotFormat>,
+
+    // Record tenant and unit panels every
+    // this many months, or 0 for never
+    pub panel_interval: usize,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            formats: vec![ExportFormat::Csv],
+            plots: Vec::new(),
+            panel_interval: 0,
+        }
+    }
+}
+
+// Values of a column, for a batch of rows
+pub enum Column {
+    Int(Vec<Option<i64>>),
+    Float(Vec<Option<f32>>),
+    Bool(Vec<bool>),
+    Str(Vec<String>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::Int(vals) => vals.len(),
+            Column::Float(vals) => vals.len(),
+            Column::Bool(vals) => vals.len(),
+            Column::Str(vals) => vals.len(),
+        }
+    }
+
+    // Missing values are left blank
+    fn cell(&self, row: usize) -> String {
+        match self {
+            Column::Int(vals) => vals[row].map_or_else(String::new, |v| v.to_string()),
+            Column::Float(vals) => vals[row].map_or_else(String::new, |v| v.to_string()),
+            Column::Bool(vals) => vals[row].to_string(),
+            Column::Str(vals) => vals[row].clone(),
+        }
+    }
+}
+
+// Rows stored by column. Every batch
+// written to a table has the same columns
+pub struct Batch {
+    pub names: Vec<String>,
+    pub columns: Vec<Column>,
+}
+
+impl Batch {
+    fn n_rows(&self) -> usize {
+        self.columns.first().map_or(0, |col| col.len())
+    }
+}
+
+trait TableWriter {
+    fn write(&mut self, batch: &Batch) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+
+    // Flush what's been written, returning where
+    // to resume from, if the format can be resumed
+    fn checkpoint(&mut self) -> io::Result<Option<u64>>;
+}
+
+struct CsvWriter {
+    writer: csv::Writer<File>,
+    wrote_header: bool,
+}
+
+impl CsvWriter {
+    fn new(path: &str) -> io::Result<CsvWriter> {
+        Ok(CsvWriter {
+            writer: csv::Writer::from_path(path)?,
+            wrote_header: false,
+        })
+    }
+
+    // Continue a file, dropping anything
+    // written past the given length
+    fn resume(path: &str, len: u64) -> io::Result<CsvWriter> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_len(len)?;
+        Ok(CsvWriter {
+            writer: csv::WriterBuilder::new().has_headers(false).from_writer(file),
+            wrote_header: len > 0,
+        })
+    }
+}
+
+impl TableWriter for CsvWriter {
+    fn write(&mut self, batch: &Batch) -> io::Result<()> {
+        if !self.wrote_header {
+            self.writer.write_record(&batch.names)?;
+            self.wrote_header = true;
+        }
+        for row in 0..batch.n_rows() {
+            self.writer.write_record(batch.columns.iter().map(|col| col.cell(row)))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn checkpoint(&mut self) -> io::Result<Option<u64>> {
+        self.writer.flush()?;
+        Ok(Some(self.writer.get_ref().metadata()?.len()))
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_writer {
+    use super::{Batch, Column, TableWriter};
+    use arrow_array::{ArrayRef, BooleanArray, Float32Array, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+    use std::fs::File;
+    use std::io;
+    use std::sync::Arc;
+
+    fn to_io(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+        io::Error::other(err)
+    }
+
+    // The schema is taken from the first batch
+    pub struct ParquetWriter {
+        path: String,
+        writer: Option<ArrowWriter<File>>,
+    }
+
+    impl ParquetWriter {
+        pub fn new(path: &str) -> ParquetWriter {
+            ParquetWriter {
+                path: path.to_string(),
+                writer: None,
+            }
+        }
+    }
+
+    impl TableWriter for ParquetWriter {
+        fn write(&mut self, batch: &Batch) -> io::Result<()> {
+            let arrays: Vec<ArrayRef> = batch.columns.iter().map(|col| -> ArrayRef {
+                match col {
+                    Column::Int(vals) => Arc::new(Int64Array::from(vals.clone())),
+                    Column::Float(vals) => Arc::new(Float32Array::from(vals.clone())),
+                    Column::Bool(vals) => Arc::new(BooleanArray::from(vals.clone())),
+                    Column::Str(vals) => Arc::new(StringArray::from(vals.clone())),
+                }
+            }).collect();
+            let fields: Vec<Field> = batch.names.iter().zip(&arrays)
+                .map(|(name, array)| {
+                    let nullable = !matches!(array.data_type(), DataType::Boolean | DataType::Utf8);
+                    Field::new(name, array.data_type().clone(), nullable)
+                })
+                .collect();
+            let record = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(to_io)?;
+
+            if self.writer.is_none() {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let file = File::create(&self.path)?;
+                self.writer = Some(ArrowWriter::try_new(file, record.schema(), Some(props)).map_err(to_io)?);
+            }
+            self.writer.as_mut().unwrap().write(&record).map_err(to_io)
+        }
+
+        // Parquet files aren't readable until closed
+        fn finish(self: Box<Self>) -> io::Result<()> {
+            if let Some(writer) = self.writer {
+                writer.close().map_err(to_io)?;
+            }
+            Ok(())
+        }
+
+        // Nor can they be continued
+        fn checkpoint(&mut self) -> io::Result<Option<u64>> {
+            Ok(None)
+        }
+    }
+}
+
+// A table written in each of the export formats
+struct Table {
+    writers: Vec<Box<dyn TableWriter>>,
+}
+
+impl Table {
+    fn new(dir: &str, name: &str, formats: &[ExportFormat]) -> io::Result<Table> {
+        Table::resume(dir, name, formats, None)
+    }
+
+    // Continue from a checkpoint's length,
+    // or start over if there isn't one
+    fn resume(dir: &str, name: &str, formats: &[ExportFormat], len: Option<u64>) -> io::Result<Table> {
+        let mut writers: Vec<Box<dyn TableWriter>> = Vec::new();
+        for format in formats {
+            match format {
+                ExportFormat::Csv => {
+                    let path = format!("{}/{}.csv", dir, name);
+                    match len {
+                        Some(len) => writers.push(Box::new(CsvWriter::resume(&path, len)?)),
+                        None => writers.push(Box::new(CsvWriter::new(&path)?)),
+                    }
+                }
+                #[cfg(feature = "parquet")]
+                ExportFormat::Parquet => {
+                    writers.push(Box::new(parquet_writer::ParquetWriter::new(&format!("{}/{}.parquet", dir, name))));
+                }
+                #[cfg(not(feature = "parquet"))]
+                ExportFormat::Parquet => {}
+            }
+        }
+        Ok(Table {
+            writers: writers,
+        })
+    }
+
+    fn write(&mut self, batch: &Batch) -> io::Result<()> {
+        if batch.n_rows() == 0 {
+            return Ok(());
+        }
+        for writer in &mut self.writers {
+            writer.write(batch)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        for writer in self.writers {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> io::Result<Option<u64>> {
+        let mut len = None;
+        for writer in &mut self.writers {
+            len = len.or(writer.checkpoint()?);
+        }
+        Ok(len)
+    }
+}
+
+// Metrics tables take their columns from the first
+// stats recorded; later keys not among them are dropped
+struct MetricsTable {
+    table: Table,
+    columns: Option<Vec<String>>,
+}
+
+impl MetricsTable {
+    fn write(&mut self, key_name: &str, rows: &[(usize, Option<String>, &Map<String, Value>)]) -> io::Result<()> {
+        let first = match rows.first() {
+            Some(row) => row.2,
+            None => return Ok(()),
+        };
+        let columns = self.columns.get_or_insert_with(|| {
+            first.iter().filter(|(_, v)| !v.is_object() && !v.is_array()).map(|(k, _)| k.clone()).collect()
+        });
+        let mut names = vec!["month".to_string()];
+        let mut cols = vec![Column::Int(rows.iter().map(|r| Some(r.0 as i64)).collect())];
+        if rows[0].1.is_some() {
+            names.push(key_name.to_string());
+            cols.push(Column::Str(rows.iter().map(|r| r.1.clone().unwrap_or_default()).collect()));
+        }
+        for name in columns.iter() {
+            names.push(name.clone());
+            cols.push(Column::Float(rows.iter().map(|r| r.2.get(name).and_then(|v| v.as_f64()).map(|v| v as f32)).collect()));
+        }
+        self.table.write(&Batch {
+            names: names,
+            columns: cols,
+        })
+    }
+}
+
+// Where an export's tables were at a checkpoint,
+// so a resumed run can pick up from there
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExportCheckpoint {
+    // CSV bytes written, by table
+    lengths: BTreeMap<String, u64>,
+
+    // Metrics tables' columns, by group,
+    // with "" for the citywide table
+    columns: BTreeMap<String, Vec<String>>,
+}
+
+// Writes run data as tables, so runs can be
+// loaded straight into pandas, R, etc:
+// - `metrics`: citywide stats, a row per record
+// - `metrics_<group>`: stats broken down by e.g.
+//    neighborhood, a row per member per record
+// - `tenants` and `units`: panel data, a row per
+//    tenant or unit every `PANEL_INTERVAL` months
+pub struct Exporter {
+    dir: String,
+    conf: ExportConfig,
+    metrics: MetricsTable,
+    groups: BTreeMap<String, MetricsTable>,
+    tenants: Table,
+    units: Table,
+}
+
+impl Exporter {
+    pub fn new(dir: &str, conf: &ExportConfig) -> io::Result<Exporter> {
+        #[cfg(not(feature = "parquet"))]
+        {
+            if conf.formats.contains(&ExportFormat::Parquet) {
+                warn!("Parquet export requires building with `--features parquet`, skipping");
+            }
+        }
+        Ok(Exporter {
+            dir: dir.to_string(),
+            conf: conf.clone(),
+            metrics: MetricsTable {
+                table: Table::new(dir, "metrics", &conf.formats)?,
+                columns: None,
+            },
+            groups: BTreeMap::new(),
+            tenants: Table::new(dir, "tenants", &conf.formats)?,
+            units: Table::new(dir, "units", &conf.formats)?,
+        })
+    }
+
+    // Continue an export from a checkpoint. CSV tables
+    // lose anything written since; Parquet tables
+    // can't be continued, so they start over
+    pub fn resume(dir: &str, conf: &ExportConfig, checkpoint: &ExportCheckpoint) -> io::Result<Exporter> {
+        if conf.formats.contains(&ExportFormat::Parquet) {
+            warn!("Parquet tables can't be resumed, they'll only have months from the checkpoint on");
+        }
+        let table = |name: &str| Table::resume(dir, name, &conf.formats, checkpoint.lengths.get(name).cloned());
+        let mut groups = BTreeMap::new();
+        for (group, columns) in &checkpoint.columns {
+            if group.is_empty() {
+                continue;
+            }
+            groups.insert(group.clone(), MetricsTable {
+                table: table(&format!("metrics_{}", group))?,
+                columns: Some(columns.clone()),
+            });
+        }
+        Ok(Exporter {
+            dir: dir.to_string(),
+            conf: conf.clone(),
+            metrics: MetricsTable {
+                table: table("metrics")?,
+                columns: checkpoint.columns.get("").cloned(),
+            },
+            groups: groups,
+            tenants: table("tenants")?,
+            units: table("units")?,
+        })
+    }
+
+    pub fn checkpoint(&mut self) -> io::Result<ExportCheckpoint> {
+        let mut checkpoint = ExportCheckpoint::default();
+        let mut tables: Vec<(String, &mut MetricsTable)> = vec![("".to_string(), &mut self.metrics)];
+        tables.extend(self.groups.iter_mut().map(|(group, table)| (group.clone(), table)));
+        for (group, metrics) in tables {
+            let name = if group.is_empty() { "metrics".to_string() } else { format!("metrics_{}", group) };
+            if let Some(len) = metrics.table.checkpoint()? {
+                checkpoint.lengths.insert(name, len);
+            }
+            if let Some(columns) = &metrics.columns {
+                checkpoint.columns.insert(group, columns.clone());
+            }
+        }
+        for (name, table) in [("tenants", &mut self.tenants), ("units", &mut self.units)] {
+            if let Some(len) = table.checkpoint()? {
+                checkpoint.lengths.insert(name.to_string(), len);
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn record(&mut self, sim: &Simulation, stats: &Value) -> io::Result<()> {
+        let month = sim.time;
+        if let Some(stats) = stats.as_object() {
+            self.metrics.write("", &[(month, None, stats)])?;
+
+            // Stats broken down by group
+            // are objects of objects
+            for (group, members) in stats {
+                let members = match members.as_object() {
+                    Some(members) if members.values().all(|m| m.is_object()) => members,
+                    _ => continue,
+                };
+                let rows: Vec<(usize, Option<String>, &Map<String, Value>)> = members.iter()
+                    .map(|(id, m)| (month, Some(id.clone()), m.as_object().unwrap()))
+                    .collect();
+                if !self.groups.contains_key(group) {
+                    let table = Table::new(&self.dir, &format!("metrics_{}", group), &self.conf.formats)?;
+                    self.groups.insert(group.clone(), MetricsTable {
+                        table: table,
+                        columns: None,
+                    });
+                }
+                self.groups.get_mut(group).unwrap().write("id", &rows)?;
+            }
+        }
+
+        if self.conf.panel_interval > 0 && month.is_multiple_of(self.conf.panel_interval) {
+            self.tenants.write(&tenant_panel(sim))?;
+            self.units.write(&unit_panel(sim))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.metrics.table.finish()?;
+        for (_, group) in self.groups {
+            group.table.finish()?;
+        }
+        self.tenants.finish()?;
+        self.units.finish()
+    }
+}
+
+fn neighborhood_id(sim: &Simulation, unit_id: UnitId) -> Option<i64> {
+    let unit = &sim.city.units[unit_id];
+    sim.city.parcels.get(&unit.pos).unwrap().neighborhood
+        .map(|neighb_id| sim.city.neighborhoods[neighb_id].id as i64)
+}
+
+fn tenant_panel(sim: &Simulation) -> Batch {
+    let tenants: Vec<_> = sim.tenants.iter().filter(|t| !t.departed).collect();
+    let month = sim.time as i64;
+    Batch {
+        names: ["month", "tenant", "unit", "neighborhood", "income", "employed", "savings",
+                "rent", "arrears", "evicted", "household", "tenure", "months_homeless", "shelter", "voucher", "player"]
+            .iter().map(|s| s.to_string()).collect(),
+        columns: vec![
+            Column::Int(tenants.iter().map(|_| Some(month)).collect()),
+            Column::Int(tenants.iter().map(|t| Some(t.id.0 as i64)).collect()),
+            Column::Int(tenants.iter().map(|t| t.unit.map(|u| u.0 as i64)).collect()),
+            Column::Int(tenants.iter().map(|t| t.unit.and_then(|u| neighborhood_id(sim, u))).collect()),
+            Column::Float(tenants.iter().map(|t| Some(t.income)).collect()),
+            Column::Bool(tenants.iter().map(|t| t.employed).collect()),
+            Column::Float(tenants.iter().map(|t| Some(t.savings)).collect()),
+
+            // Their share of the rent
+            Column::Float(tenants.iter().map(|t| t.unit.map(|u| {
+                let unit = &sim.city.units[u];
+                unit.rent / unit.occupancy as f32 * t.rent_weight
+            })).collect()),
+            Column::Int(tenants.iter().map(|t| Some(t.arrears as i64)).collect()),
+            Column::Bool(tenants.iter().map(|t| t.evicted).collect()),
+            Column::Int(tenants.iter().map(|t| t.household.map(|h| h as i64)).collect()),
+            Column::Int(tenants.iter().map(|t| Some(t.tenure as i64)).collect()),
+            Column::Int(tenants.iter().map(|t| Some(t.months_homeless as i64)).collect()),
+            Column::Int(tenants.iter().map(|t| t.shelter.map(|n| sim.city.neighborhoods[n].id as i64)).collect()),
+
+            // What their voucher pays toward the rent
+            Column::Float(tenants.iter().map(|t| t.voucher.and(t.unit).map(|u| {
+                let unit = &sim.city.units[u];
+                t.voucher_subsidy(unit, t.rent_share(unit))
+            })).collect()),
+            Column::Bool(tenants.iter().map(|t| t.player).collect()),
+        ],
+    }
+}
+
+fn unit_panel(sim: &Simulation) -> Batch {
+    let units: Vec<&Unit> = sim.city.live_units().collect();
+    let month = sim.time as i64;
+    Batch {
+        names: ["month", "unit", "neighborhood", "owner_type", "owner", "rent", "value", "area",
+                "condition", "occupancy", "tenants", "months_vacant", "short_term", "year_built"]
+            .iter().map(|s| s.to_string()).collect(),
+        columns: vec![
+            Column::Int(units.iter().map(|_| Some(month)).collect()),
+            Column::Int(units.iter().map(|u| Some(u.id.0 as i64)).collect()),
+            Column::Int(units.iter().map(|u| neighborhood_id(sim, u.id)).collect()),
+            Column::Str(units.iter().map(|u| u.owner.0.to_string()).collect()),
+            Column::Int(units.iter().map(|u| Some(u.owner.1 as i64)).collect()),
+            Column::Float(units.iter().map(|u| Some(u.rent)).collect()),
+            Column::Float(units.iter().map(|u| Some(u.value)).collect()),
+            Column::Float(units.iter().map(|u| Some(u.area)).collect()),
+            Column::Float(units.iter().map(|u| Some(u.condition)).collect()),
+            Column::Int(units.iter().map(|u| Some(u.occupancy as i64)).collect()),
+            Column::Int(units.iter().map(|u| Some(u.tenants.len() as i64)).collect()),
+            Column::Int(units.iter().map(|u| Some(u.months_vacant as i64)).collect()),
+            Column::Bool(units.iter().map(|u| u.short_term).collect()),
+            Column::Int(units.iter().map(|u| Some(u.year_built as i64)).collect()),
+        ],
+    }
+}
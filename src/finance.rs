@@ -0,0 +1,47 @@
+use super::arena::UnitId;
+use serde::{Serialize, Deserialize};
+
+// Fixed-rate, fully-amortizing mortgage
+// taken out against a single unit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mortgage {
+    pub unit: UnitId,
+    pub principal: f32,
+    pub rate: f32, // monthly
+    pub payment: f32,
+
+    // Whether or not the bank
+    // has foreclosed on the unit
+    pub foreclosed: bool,
+}
+
+impl Mortgage {
+    pub fn new(unit: UnitId, principal: f32, annual_rate: f32, term_months: usize) -> Mortgage {
+        let rate = annual_rate / 12.;
+        let payment = if rate == 0. {
+            principal / term_months as f32
+        } else {
+            principal * rate / (1. - (1. + rate).powi(-(term_months as i32)))
+        };
+
+        Mortgage {
+            unit: unit,
+            principal: principal,
+            rate: rate,
+            payment: payment,
+            foreclosed: false,
+        }
+    }
+
+    // Make this step's payments, covering
+    // `months`, returning the amount paid
+    pub fn pay(&mut self, months: f32) -> f32 {
+        if self.principal <= 0. {
+            return 0.;
+        }
+        let interest = self.principal * self.rate * months;
+        let payment = f32::min(self.payment * months, self.principal + interest);
+        self.principal -= payment - interest;
+        payment
+    }
+}
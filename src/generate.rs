@@ -0,0 +1,192 @@
+use super::design::{CityConfig, Design, Map, MapOffset, Neighborhood};
+use fnv::FnvHashMap;
+use noise::{NoiseFn, OpenSimplex, Seedable};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::BufReader;
+
+// How far (in parcels) neighborhood
+// boundaries wander, and how quickly
+static BOUNDARY_WARP: f64 = 2.;
+static BOUNDARY_SCALE: f64 = 0.15;
+static PARK_SCALE: f64 = 0.3;
+
+// Unit sizes, as in the default design
+static MIN_AREA: u32 = 30;
+static MAX_AREA: u32 = 120;
+static SQM_PER_OCCUPANT: u32 = 25;
+
+// The above, for runs to record
+pub fn constants() -> Value {
+    json!({
+        "boundary_warp": BOUNDARY_WARP,
+        "boundary_scale": BOUNDARY_SCALE,
+        "park_scale": PARK_SCALE,
+        "min_area": MIN_AREA,
+        "max_area": MAX_AREA,
+        "sqm_per_occupant": SQM_PER_OCCUPANT,
+    })
+}
+
+// High-level parameters for generating a city,
+// rather than designing one by hand
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CityParams {
+    pub rows: usize,
+    pub cols: usize,
+
+    // At least 2
+    pub neighborhoods: usize,
+
+    // Most units per building at the edge of the city;
+    // at the center it's `1 + density_gradient` times that
+    pub density: u32,
+    pub density_gradient: f32,
+
+    // How much more desirable, and so pricier,
+    // central neighborhoods are. At 0 neighborhoods
+    // vary at random; the higher, the more rents
+    // fall off with distance from the center
+    pub rent_gradient: f32,
+
+    // Share of floors in apartment buildings
+    // that are commercial space, i.e. jobs
+    pub commercial_ratio: f32,
+
+    #[serde(default = "default_park_ratio")]
+    pub park_ratio: f32,
+
+    // Seed for the layout, independent
+    // of the simulation's seed
+    #[serde(default)]
+    pub seed: u64,
+
+    // As in designs
+    pub city: CityConfig,
+}
+
+fn default_park_ratio() -> f32 {
+    0.08
+}
+
+pub fn load_params(path: &str) -> CityParams {
+    let file = File::open(path).expect("could not open city parameters file");
+    let reader = BufReader::new(file);
+    serde_yaml::from_reader(reader).expect("error while reading yaml")
+}
+
+// From 1 at the center of the map to 0 at its corners
+fn centrality(params: &CityParams, (r, c): (f64, f64)) -> f32 {
+    let (center_r, center_c) = ((params.rows - 1) as f64 / 2., (params.cols - 1) as f64 / 2.);
+    let max_dist = (center_r.powi(2) + center_c.powi(2)).sqrt().max(1.);
+    let dist = ((r - center_r).powi(2) + (c - center_c).powi(2)).sqrt();
+    (1. - dist / max_dist) as f32
+}
+
+// Neighborhoods are regions around randomly placed centers,
+// with their boundaries warped by noise so they aren't
+// straight lines. Parks are scattered in noisy clusters
+pub fn generate(params: &CityParams) -> Design {
+    // Neighborhood desirabilities are rescaled
+    // by their range, so a city needs at least two
+    assert!(params.neighborhoods >= 2, "generated cities need at least 2 neighborhoods");
+
+    let mut rng: StdRng = SeedableRng::seed_from_u64(params.seed);
+    let warp_r = OpenSimplex::new().set_seed(rng.gen());
+    let warp_c = OpenSimplex::new().set_seed(rng.gen());
+    let parks = OpenSimplex::new().set_seed(rng.gen());
+
+    let centers: Vec<(f64, f64)> = (0..params.neighborhoods)
+        .map(|_| (rng.gen_range(0., params.rows as f64), rng.gen_range(0., params.cols as f64)))
+        .collect();
+
+    // Only the densest share of the park
+    // noise becomes parks
+    let mut park_vals: Vec<f64> = Vec::with_capacity(params.rows * params.cols);
+    for r in 0..params.rows {
+        for c in 0..params.cols {
+            park_vals.push(parks.get([r as f64 * PARK_SCALE, c as f64 * PARK_SCALE]));
+        }
+    }
+    let mut sorted = park_vals.clone();
+    sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    let n_parks = (params.park_ratio * sorted.len() as f32) as usize;
+    let park_threshold = if n_parks > 0 { sorted[n_parks - 1] } else { f64::INFINITY };
+
+    let mut layout = Vec::with_capacity(params.rows);
+    let mut sizes = vec![0; params.neighborhoods];
+    for r in 0..params.rows {
+        let mut row = Vec::with_capacity(params.cols);
+        for c in 0..params.cols {
+            if park_vals[r * params.cols + c] >= park_threshold {
+                row.push(Some("-1|Park".to_string()));
+                continue;
+            }
+            let (x, y) = (r as f64 * BOUNDARY_SCALE, c as f64 * BOUNDARY_SCALE);
+            let pos = (
+                r as f64 + warp_r.get([x, y]) * BOUNDARY_WARP,
+                c as f64 + warp_c.get([x, y]) * BOUNDARY_WARP,
+            );
+            let neighb_id = centers.iter()
+                .map(|&(cr, cc)| (pos.0 - cr).powi(2) + (pos.1 - cc).powi(2))
+                .enumerate()
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                .unwrap().0;
+            sizes[neighb_id] += 1;
+            row.push(Some(format!("{}|Residential", neighb_id)));
+        }
+        layout.push(row);
+    }
+
+    // Neighborhoods crowded out by
+    // their neighbors are left out
+    let mut neighborhoods = FnvHashMap::default();
+    for (id, &center) in centers.iter().enumerate() {
+        if sizes[id] == 0 {
+            continue;
+        }
+        let central = centrality(params, center);
+        let max_units = (params.density as f32 * (1. + params.density_gradient * central)).round() as u32;
+        let max_units = u32::max(max_units, 2);
+        neighborhoods.insert(id, Neighborhood {
+            id: id as isize,
+            name: format!("N{}", id),
+            desirability: 1. + params.rent_gradient * central + rng.gen::<f32>(),
+            min_units: u32::max(1, max_units / 4),
+            max_units: max_units,
+            min_area: MIN_AREA,
+            max_area: MAX_AREA,
+            sqm_per_occupant: SQM_PER_OCCUPANT,
+            p_commercial: params.commercial_ratio,
+            color: "#fff".to_string(),
+            tax_rate: None,
+            shelter_capacity: 0,
+        });
+    }
+
+    // Only buildings of more than 3 units have
+    // commercial floors, and tenants need jobs
+    assert!(
+        neighborhoods.values().any(|n| n.max_units > 4) && params.commercial_ratio > 0.,
+        "generated city has no commercial space; increase the density or commercial ratio");
+
+    Design {
+        map: Map {
+            layout: layout,
+            offset: MapOffset {
+                row: false,
+                col: false,
+            },
+        },
+        neighborhoods: neighborhoods,
+        city: params.city.clone(),
+        amenities: Vec::new(),
+        transit: Vec::new(),
+        employment_centers: Vec::new(),
+        redistricting: Vec::new(),
+        zoning: Vec::new(),
+    }
+}
@@ -0,0 +1,94 @@
+use super::agent::Tenant;
+use super::arena::{Arena, TenantId};
+use super::city::City;
+use super::config::Config;
+use serde::{Serialize, Deserialize};
+
+// Per-neighborhood gentrification pressure,
+// from rising rents, landlords investing in
+// their units, and wealthier residents moving in
+#[derive(Serialize, Deserialize)]
+pub struct Gentrification {
+    // 0 is no pressure
+    pub index: Vec<f32>,
+
+    // Monthly observations for each neighborhood:
+    // (mean rent per area, mean maintenance, mean resident income)
+    history: Vec<Vec<(f32, f32, f32)>>,
+}
+
+// Relative change, bounded so that a factor
+// growing from almost nothing doesn't dominate
+fn growth(now: f32, then: f32) -> f32 {
+    if then > 0. {
+        (now / then - 1.).clamp(-1., 1.)
+    } else {
+        0.
+    }
+}
+
+impl Gentrification {
+    pub fn new(n_neighborhoods: usize) -> Gentrification {
+        Gentrification {
+            index: vec![0.; n_neighborhoods],
+            history: vec![Vec::new(); n_neighborhoods],
+        }
+    }
+
+    pub fn add_neighborhood(&mut self) {
+        self.index.push(0.);
+        self.history.push(Vec::new());
+    }
+
+    // Start a neighborhood's window over,
+    // e.g. once its boundaries change
+    pub fn reset(&mut self, neighb_id: usize) {
+        self.history[neighb_id].clear();
+        self.index[neighb_id] = 0.;
+    }
+
+    // Observe the neighborhoods and update their indices,
+    // returning how much each index changed
+    pub fn update(&mut self, city: &City, tenants: &Arena<TenantId, Tenant>, conf: &Config) -> Vec<f32> {
+        let mut changes = Vec::with_capacity(self.index.len());
+        for (neighb_id, unit_ids) in city.units_by_neighborhood.iter().enumerate() {
+            let (mut rent_per_area, mut maintenance, mut income, mut n_residents) = (0., 0., 0., 0);
+            for &u_id in unit_ids {
+                let unit = &city.units[u_id];
+                rent_per_area += unit.rent_per_area();
+                maintenance += unit.maintenance_history;
+                for &t_id in &unit.tenants {
+                    income += tenants[t_id].income;
+                    n_residents += 1;
+                }
+            }
+            let n_units = f32::max(1., unit_ids.len() as f32);
+            let obv = (
+                rent_per_area / n_units,
+                maintenance / n_units,
+                if n_residents > 0 { income / n_residents as f32 } else { 0. },
+            );
+
+            let history = &mut self.history[neighb_id];
+            history.push(obv);
+            if history.len() > conf.gentrification_window + 1 {
+                history.remove(0);
+            }
+
+            // Compare against the start of the window,
+            // once there's a full window to compare
+            if history.len() <= conf.gentrification_window {
+                changes.push(0.);
+                continue;
+            }
+            let then = history[0];
+            let pressure = conf.gentrification_rent_weight * growth(obv.0, then.0)
+                + conf.gentrification_investment_weight * growth(obv.1, then.1)
+                + conf.gentrification_income_weight * growth(obv.2, then.2);
+            let index = f32::max(0., pressure);
+            changes.push(index - self.index[neighb_id]);
+            self.index[neighb_id] = index;
+        }
+        changes
+    }
+}
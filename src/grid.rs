@@ -1,4 +1,7 @@
-use std::collections::HashSet;
+use super::arena::ParcelId;
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 
 pub type Position = (isize, isize);
 
@@ -20,6 +23,7 @@ const EVEN_ADJACENT_POSITIONS: [(isize, isize); 6] = [
     (1, 0),   // bottom right
 ];
 
+#[derive(Serialize, Deserialize)]
 pub struct HexGrid {
     pub rows: usize,
     pub cols: usize,
@@ -68,4 +72,106 @@ impl HexGrid {
     pub fn distance(&self, a: Position, b: Position) -> f32 {
         (((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)) as f32).sqrt()
     }
+
+    // Fewest steps between positions, going around
+    // nothing. Odd rows are shifted right, so
+    // offset positions are converted to cube ones
+    pub fn steps(&self, a: Position, b: Position) -> usize {
+        let cube = |(r, c): Position| {
+            let x = c - (r - (r & 1)) / 2;
+            (x, r, -x - r)
+        };
+        let (a, b) = (cube(a), cube(b));
+        *[(a.0 - b.0).abs(), (a.1 - b.1).abs(), (a.2 - b.2).abs()].iter().max().unwrap() as usize
+    }
+
+    pub fn index(&self, pos: Position) -> usize {
+        pos.0 as usize * self.cols + pos.1 as usize
+    }
+
+    pub fn parcel_id(&self, pos: Position) -> ParcelId {
+        ParcelId(self.index(pos))
+    }
+
+    // Steps from the nearest source to every position,
+    // in index order, moving only through passable
+    // positions and going no further than `max_steps`.
+    // None where a position can't be reached
+    pub fn distance_field<F>(&self, sources: &[Position], max_steps: Option<usize>, passable: F) -> Vec<Option<usize>>
+        where F: Fn(Position) -> bool {
+        let mut field = vec![None; self.rows * self.cols];
+        let mut queue = VecDeque::new();
+        for &pos in sources {
+            field[self.index(pos)] = Some(0);
+            queue.push_back(pos);
+        }
+        while let Some(pos) = queue.pop_front() {
+            let dist = field[self.index(pos)].unwrap();
+            if max_steps.is_some_and(|max| dist >= max) {
+                continue;
+            }
+            for adj in self.adjacent(pos) {
+                let i = self.index(adj);
+                if field[i].is_none() && passable(adj) {
+                    field[i] = Some(dist + 1);
+                    queue.push_back(adj);
+                }
+            }
+        }
+        field
+    }
+
+    // Steps to every position reachable from the specified
+    // one within `max_steps`, through passable positions.
+    // For small radii, where a whole field would be wasteful
+    pub fn reachable<F>(&self, pos: Position, max_steps: usize, passable: F) -> HashMap<Position, usize>
+        where F: Fn(Position) -> bool {
+        let mut dists = HashMap::new();
+        dists.insert(pos, 0);
+        let mut next = vec![pos];
+        for step in 1..=max_steps {
+            next = next.iter()
+                .flat_map(|&p| self.adjacent(p))
+                .filter(|&adj| passable(adj))
+                .filter(|adj| !dists.contains_key(adj))
+                .collect();
+            next.sort_unstable();
+            next.dedup();
+            for &adj in &next {
+                dists.insert(adj, step);
+            }
+        }
+        dists
+    }
+
+    // Shortest path between positions, including both,
+    // moving only through passable positions (A*)
+    pub fn path<F>(&self, from: Position, to: Position, passable: F) -> Option<Vec<Position>>
+        where F: Fn(Position) -> bool {
+        let mut came_from: Vec<Option<Position>> = vec![None; self.rows * self.cols];
+        let mut cost = vec![usize::MAX; self.rows * self.cols];
+        let mut frontier = BinaryHeap::new();
+        cost[self.index(from)] = 0;
+        frontier.push(Reverse((self.steps(from, to), from)));
+        while let Some(Reverse((_, pos))) = frontier.pop() {
+            if pos == to {
+                let mut path = vec![to];
+                while let Some(prev) = came_from[self.index(*path.last().unwrap())] {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let next_cost = cost[self.index(pos)] + 1;
+            for adj in self.adjacent(pos) {
+                let i = self.index(adj);
+                if next_cost < cost[i] && passable(adj) {
+                    cost[i] = next_cost;
+                    came_from[i] = Some(pos);
+                    frontier.push(Reverse((next_cost + self.steps(adj, to), adj)));
+                }
+            }
+        }
+        None
+    }
 }
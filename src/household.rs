@@ -0,0 +1,286 @@
+use super::agent::{Decision, Tenant};
+use super::arena::{Arena, TenantId};
+use super::city::{City, Unit};
+use super::config::Config;
+use super::events::SimEvent;
+use super::social::{Hearsay, SocialGraph};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+// A group of roommates who
+// search for and live in a unit together
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Household {
+    pub id: usize,
+    pub members: Vec<TenantId>,
+
+    // Member incomes when the household formed,
+    // to detect significant changes
+    pub incomes: Vec<f32>,
+}
+
+impl Household {
+    pub fn income(&self, tenants: &Arena<TenantId, Tenant>) -> f32 {
+        self.members.iter().fold(0., |acc, &t_id| acc + tenants[t_id].income)
+    }
+
+    // Split rent by share of household income
+    fn set_rent_weights(&self, tenants: &mut Arena<TenantId, Tenant>) {
+        let income = self.income(tenants);
+        let n = self.members.len() as f32;
+        for &t_id in &self.members {
+            let tenant = &mut tenants[t_id];
+            // Split evenly if no one has any income
+            tenant.rent_weight = if income > 0. { n * tenant.income / income } else { 1. };
+        }
+    }
+
+    // A unit is only desirable to the household
+    // if it's desirable to every member
+    pub fn desirability(&self, tenants: &Arena<TenantId, Tenant>, unit: &Unit, city: &City, conf: &Config) -> f32 {
+        let mut total = 0.;
+        for &t_id in &self.members {
+            let desirability = tenants[t_id].score(unit, city, conf);
+            if desirability == 0. {
+                return 0.;
+            }
+            total += desirability;
+        }
+        total / self.members.len() as f32
+    }
+
+    // Decide where to live; unlike tenants on their own,
+    // this also updates whether members are behind on rent
+    pub fn consider(
+        &self,
+        tenants: &mut Arena<TenantId, Tenant>,
+        city: &City,
+        month: usize,
+        hearsay: Option<&Hearsay>,
+        rng: &mut StdRng,
+        conf: &Config,
+    ) -> Decision {
+        let lead = &tenants[self.members[0]];
+        let together = lead.unit.is_some() && self.members.iter().all(|&t_id| tenants[t_id].unit == lead.unit);
+
+        // Members who aren't living together yet
+        // always look for a place, with no moving penalty
+        let mut reconsider = true;
+        let mut current_desirability = -1.;
+        let mut moving_penalty = 0.;
+        if together {
+            let unit = &city.units[lead.unit.unwrap()];
+            reconsider = unit.lease_up(month);
+            moving_penalty = conf.household_search.moving_penalty(conf);
+            current_desirability = self.desirability(tenants, unit, city, conf);
+            let affordable = current_desirability > 0.;
+            for &t_id in &self.members {
+                let tenant = &mut tenants[t_id];
+                if affordable {
+                    tenant.arrears = 0;
+                } else {
+                    tenant.arrears += 1;
+                }
+            }
+            reconsider = reconsider || !affordable;
+        }
+
+        // Households hear through their lead member's ties
+        let mut candidates = Vec::new();
+        let referrals = hearsay.map_or_else(Vec::new, |hearsay| hearsay.referrals.clone());
+        if reconsider {
+            let n_members = self.members.len();
+            let work = tenants[self.members[0]].work;
+            let sample = conf.household_search.sample(&referrals, work, city, rng, conf);
+            for u_id in sample {
+                let u = &city.units[u_id];
+                let can_afford_deposit = self.members.iter().all(|&t_id| {
+                    let tenant = &tenants[t_id];
+                    tenant.savings >= tenant.deposit(u, conf)
+                });
+                if u.vacancies() < n_members || !can_afford_deposit {
+                    continue;
+                }
+                let desirability = match (hearsay, &conf.social) {
+                    (Some(hearsay), Some(social)) => hearsay.weigh(self.desirability(tenants, u, city, conf), u, city, social),
+                    _ => self.desirability(tenants, u, city, conf)
+                };
+                if desirability > 0. {
+                    candidates.push((u_id, desirability));
+                }
+                if conf.household_search.satisfied(desirability) {
+                    break;
+                }
+            }
+            candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        }
+
+        Decision {
+            in_arrears: None,
+            current_desirability: current_desirability,
+            moving_penalty: moving_penalty,
+            candidates: candidates,
+            referrals: referrals,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Households {
+    pub households: BTreeMap<usize, Household>,
+    next_id: usize,
+
+    // Members of dissolved households
+    // who still need to find a place
+    #[serde(default)]
+    pub moving: Vec<TenantId>,
+}
+
+impl Default for Households {
+    fn default() -> Self {
+        Households::new()
+    }
+}
+
+impl Households {
+    pub fn new() -> Households {
+        Households {
+            households: BTreeMap::default(),
+            next_id: 0,
+            moving: Vec::new(),
+        }
+    }
+
+    pub fn form(&mut self, members: Vec<TenantId>, tenants: &mut Arena<TenantId, Tenant>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        for &t_id in &members {
+            tenants[t_id].household = Some(id);
+        }
+        let household = Household {
+            id: id,
+            incomes: members.iter().map(|&t_id| tenants[t_id].income).collect(),
+            members: members,
+        };
+        household.set_rent_weights(tenants);
+        self.households.insert(id, household);
+        id
+    }
+
+    pub fn join(&mut self, id: usize, tenant_id: TenantId, tenants: &mut Arena<TenantId, Tenant>) {
+        let household = self.households.get_mut(&id).unwrap();
+        household.members.push(tenant_id);
+        household.incomes.push(tenants[tenant_id].income);
+        household.set_rent_weights(tenants);
+        tenants[tenant_id].household = Some(id);
+    }
+
+    // Dissolve a household; everyone but the
+    // lead member moves out to find their own place
+    pub fn dissolve(&mut self, id: usize, tenants: &mut Arena<TenantId, Tenant>, city: &mut City, conf: &Config) {
+        let household = self.households.remove(&id).unwrap();
+        let lead = household.members[0];
+        for &t_id in &household.members {
+            let tenant = &mut tenants[t_id];
+            tenant.household = None;
+            tenant.rent_weight = 1.;
+            if t_id != lead {
+                self.moving.push(t_id);
+                if let Some(u_id) = tenant.unit {
+                    city.move_out(tenant, conf);
+                    city.events.push(SimEvent::MovedOut {
+                        tenant: t_id,
+                        unit: u_id,
+                    });
+                }
+            }
+        }
+    }
+
+    // Remove a single tenant from their household,
+    // e.g. when they're taken over by a player
+    pub fn leave(&mut self, tenant_id: TenantId, tenants: &mut Arena<TenantId, Tenant>, city: &mut City, conf: &Config) {
+        let id = match tenants[tenant_id].household {
+            Some(id) => id,
+            None => return,
+        };
+        let household = self.households.get_mut(&id).unwrap();
+        if household.members.len() <= 2 {
+            self.dissolve(id, tenants, city, conf);
+        } else {
+            let i = household.members.iter().position(|&t_id| t_id == tenant_id).unwrap();
+            household.members.remove(i);
+            household.incomes.remove(i);
+            household.set_rent_weights(tenants);
+        }
+        tenants[tenant_id].household = None;
+        tenants[tenant_id].rent_weight = 1.;
+    }
+
+    pub fn update(
+        &mut self,
+        tenants: &mut Arena<TenantId, Tenant>,
+        city: &mut City,
+        social_graph: &SocialGraph,
+        rng: &mut StdRng,
+        conf: &Config,
+    ) {
+        // Dissolve households where a member's
+        // income has changed significantly
+        let dissolved: Vec<usize> = self
+            .households
+            .values()
+            .filter(|h| {
+                h.members.iter().zip(h.incomes.iter()).any(|(&t_id, &income)| {
+                    let change = (tenants[t_id].income - income).abs();
+                    change > conf.household_income_change * income
+                })
+            })
+            .map(|h| h.id)
+            .collect();
+        for id in dissolved {
+            self.dissolve(id, tenants, city, conf);
+        }
+
+        // Tenants without a place or struggling
+        // with rent look for roommates among their friends
+        let mut seeking: Vec<TenantId> = tenants
+            .iter()
+            .filter(|t| !t.player && !t.departed && t.household.is_none() && (t.unit.is_none() || t.arrears > 0))
+            .map(|t| t.id)
+            .collect();
+        seeking.shuffle(rng);
+        for t_id in seeking {
+            if tenants[t_id].household.is_some() {
+                continue;
+            }
+            let roll: f32 = rng.gen();
+            if roll >= conf.roommate_prob {
+                continue;
+            }
+            for f_id in social_graph.friends(t_id) {
+                let friend = &tenants[f_id];
+                if f_id == t_id || friend.player || friend.departed {
+                    continue;
+                }
+                match friend.household {
+                    Some(h_id) => {
+                        if self.households[&h_id].members.len() < conf.max_household_size {
+                            self.join(h_id, t_id, tenants);
+                            break;
+                        }
+                    },
+                    None => {
+                        if friend.unit.is_none() || friend.arrears > 0 {
+                            self.form(vec![t_id, f_id], tenants);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
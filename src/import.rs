@@ -0,0 +1,333 @@
+use super::design::{CityConfig, Design, Map, MapOffset, Neighborhood};
+use fnv::FnvHashMap;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use tracing::info;
+
+// Unit sizes,
+// as in the default design
+static MIN_AREA: u32 = 30;
+static MAX_AREA: u32 = 120;
+static SQM_PER_OCCUPANT: u32 = 25;
+
+// Rows of hexes are closer together than columns
+static HEX_ROW_HEIGHT: f64 = 0.866;
+
+// Units per parcel when the data doesn't say
+static DEFAULT_MIN_UNITS: u32 = 2;
+static DEFAULT_MAX_UNITS: u32 = 12;
+
+// The above, for runs to record
+pub fn constants() -> Value {
+    json!({
+        "min_area": MIN_AREA,
+        "max_area": MAX_AREA,
+        "sqm_per_occupant": SQM_PER_OCCUPANT,
+        "hex_row_height": HEX_ROW_HEIGHT,
+        "default_min_units": DEFAULT_MIN_UNITS,
+        "default_max_units": DEFAULT_MAX_UNITS,
+    })
+}
+
+// How to read a GeoJSON file of parcel or
+// neighborhood polygons into a design.
+// Properties are read from each feature's
+// `properties`, as numbers or numeric strings
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportParams {
+    // GeoJSON file, with longitude/latitude
+    // coordinates, e.g. from city open data
+    pub path: String,
+
+    // Grid width, in parcels. The height
+    // follows from the shape of the area
+    pub cols: usize,
+
+    // Features with the same value for this property
+    // are one neighborhood. Otherwise each feature is
+    #[serde(default)]
+    pub neighborhood_property: Option<String>,
+
+    // Monthly rent per unit, which sets neighborhood
+    // desirability and the city's price per sqm
+    #[serde(default)]
+    pub rent_property: Option<String>,
+
+    // Number of housing units in the feature,
+    // which sets how dense its parcels are
+    #[serde(default)]
+    pub units_property: Option<String>,
+
+    // Features whose value for this property is
+    // `park` or `industrial` are parks or industry
+    #[serde(default)]
+    pub land_use_property: Option<String>,
+
+    #[serde(default = "default_p_commercial")]
+    pub p_commercial: f32,
+
+    // As in designs. The price per sqm is
+    // replaced if rents are given
+    pub city: CityConfig,
+}
+
+fn default_p_commercial() -> f32 {
+    0.2
+}
+
+pub fn load_params(path: &str) -> ImportParams {
+    let file = File::open(path).expect("could not open import parameters file");
+    let reader = BufReader::new(file);
+    serde_yaml::from_reader(reader).expect("error while reading yaml")
+}
+
+#[derive(PartialEq)]
+enum LandUse {
+    Residential,
+    Park,
+    Industrial,
+}
+
+// A ring is a closed list of (lon, lat) points
+type Ring = Vec<(f64, f64)>;
+
+struct Feature {
+    // Each polygon is an outer ring and its holes
+    polygons: Vec<Vec<Ring>>,
+    bounds: (f64, f64, f64, f64),
+    neighborhood: String,
+    land_use: LandUse,
+    rent: Option<f64>,
+    units: Option<f64>,
+}
+
+fn number(properties: &Value, key: &Option<String>) -> Option<f64> {
+    let val = &properties[key.as_ref()?];
+    match val {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn ring(coords: &Value) -> Ring {
+    coords.as_array().into_iter().flatten()
+        .filter_map(|pt| Some((pt[0].as_f64()?, pt[1].as_f64()?)))
+        .collect()
+}
+
+fn polygon(coords: &Value) -> Vec<Ring> {
+    coords.as_array().into_iter().flatten().map(ring).collect()
+}
+
+fn parse_feature(i: usize, feature: &Value, params: &ImportParams) -> Option<Feature> {
+    let geometry = &feature["geometry"];
+    let polygons = match geometry["type"].as_str()? {
+        "Polygon" => vec![polygon(&geometry["coordinates"])],
+        "MultiPolygon" => geometry["coordinates"].as_array()?.iter().map(polygon).collect(),
+        _ => return None,
+    };
+    let bounds = polygons.iter().flatten().flatten().fold(
+        (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        |(x0, y0, x1, y1), &(x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)));
+
+    let properties = &feature["properties"];
+    let land_use = match params.land_use_property.as_ref().and_then(|key| properties[key].as_str()) {
+        Some(s) if s.eq_ignore_ascii_case("park") => LandUse::Park,
+        Some(s) if s.eq_ignore_ascii_case("industrial") => LandUse::Industrial,
+        _ => LandUse::Residential,
+    };
+
+    // Only residential features need a neighborhood
+    let neighborhood = match (&params.neighborhood_property, &land_use) {
+        (_, LandUse::Park) | (_, LandUse::Industrial) => String::new(),
+        (Some(key), _) => match &properties[key] {
+            Value::String(s) => s.clone(),
+            Value::Null => return None,
+            val => val.to_string(),
+        },
+        (None, _) => i.to_string(),
+    };
+    Some(Feature {
+        polygons: polygons,
+        bounds: bounds,
+        neighborhood: neighborhood,
+        land_use: land_use,
+        rent: number(properties, &params.rent_property),
+        units: number(properties, &params.units_property),
+    })
+}
+
+// Even-odd rule
+fn in_ring(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+impl Feature {
+    fn contains(&self, pt: (f64, f64)) -> bool {
+        let (x0, y0, x1, y1) = self.bounds;
+        if pt.0 < x0 || pt.0 > x1 || pt.1 < y0 || pt.1 > y1 {
+            return false;
+        }
+        self.polygons.iter().any(|rings| {
+            match rings.split_first() {
+                Some((outer, holes)) => in_ring(outer, pt) && !holes.iter().any(|h| in_ring(h, pt)),
+                None => false,
+            }
+        })
+    }
+}
+
+// Each parcel takes on the feature under its center.
+// Parcels outside every feature are left empty
+pub fn import(params: &ImportParams) -> Design {
+    let data = fs::read_to_string(&params.path).expect("could not open GeoJSON file");
+    let geojson: Value = serde_json::from_str(&data).expect("error while reading GeoJSON");
+    let features: Vec<Feature> = geojson["features"].as_array().expect("GeoJSON has no features")
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| parse_feature(i, f, params))
+        .collect();
+    assert!(!features.is_empty(), "GeoJSON has no polygon features");
+
+    let (x0, y0, x1, y1) = features.iter().fold(
+        (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        |(a0, b0, a1, b1), f| (a0.min(f.bounds.0), b0.min(f.bounds.1), a1.max(f.bounds.2), b1.max(f.bounds.3)));
+
+    // Longitude degrees shrink away from the equator
+    let lon_scale = ((y0 + y1) / 2.).to_radians().cos();
+    let cell = (x1 - x0) * lon_scale / params.cols as f64;
+    let rows = ((y1 - y0) / (cell * HEX_ROW_HEIGHT)).ceil().max(1.) as usize;
+
+    // Neighborhood ids in order of first appearance
+    let mut neighb_ids: BTreeMap<String, usize> = BTreeMap::new();
+    for f in &features {
+        if f.land_use == LandUse::Residential && !neighb_ids.contains_key(&f.neighborhood) {
+            let id = neighb_ids.len();
+            neighb_ids.insert(f.neighborhood.clone(), id);
+        }
+    }
+
+    // Which features each neighborhood's parcels fall in,
+    // to spread the features' units over them
+    let mut parcel_features: Vec<Vec<usize>> = vec![Vec::new(); neighb_ids.len()];
+    let mut feature_parcels: Vec<usize> = vec![0; features.len()];
+    let mut layout = Vec::with_capacity(rows);
+    for r in 0..rows {
+        let mut row = Vec::with_capacity(params.cols);
+        for c in 0..params.cols {
+            // Odd rows are shifted half a parcel right
+            let shift = if r % 2 == 1 { 1. } else { 0.5 };
+            let pt = (
+                x0 + (c as f64 + shift) * cell / lon_scale,
+                y1 - (r as f64 + 0.5) * cell * HEX_ROW_HEIGHT,
+            );
+            let cell_str = features.iter().enumerate().find(|(_, f)| f.contains(pt)).map(|(i, f)| {
+                match f.land_use {
+                    LandUse::Park => "-1|Park".to_string(),
+                    LandUse::Industrial => "-1|Industrial".to_string(),
+                    LandUse::Residential => {
+                        let neighb_id = neighb_ids[&f.neighborhood];
+                        parcel_features[neighb_id].push(i);
+                        feature_parcels[i] += 1;
+                        format!("{}|Residential", neighb_id)
+                    }
+                }
+            });
+            row.push(cell_str);
+        }
+        layout.push(row);
+    }
+
+    let mut city = params.city.clone();
+    let mean_area = (MIN_AREA + MAX_AREA) as f32 / 2.;
+    let mut neighborhoods = FnvHashMap::default();
+    let mut rents = Vec::new();
+    for (name, &id) in &neighb_ids {
+        let parcels = &parcel_features[id];
+        if parcels.is_empty() {
+            continue;
+        }
+
+        // Mean rent over the neighborhood's parcels
+        let neighb_rents: Vec<f64> = parcels.iter().filter_map(|&i| features[i].rent).collect();
+        let rent = if neighb_rents.is_empty() {
+            None
+        } else {
+            Some(neighb_rents.iter().sum::<f64>() / neighb_rents.len() as f64)
+        };
+        rents.push((id, rent));
+
+        // Each feature's units are spread over
+        // its parcels. Unit counts per building are
+        // drawn from a range around the mean
+        let units: Vec<f64> = parcels.iter()
+            .filter_map(|&i| features[i].units.map(|u| u / feature_parcels[i] as f64))
+            .collect();
+        let (min_units, max_units) = if units.is_empty() {
+            (DEFAULT_MIN_UNITS, DEFAULT_MAX_UNITS)
+        } else {
+            let mean = units.iter().sum::<f64>() / units.len() as f64;
+            let min = u32::max(1, (mean / 2.).round() as u32);
+            (min, u32::max(min + 1, (mean * 1.5).round() as u32))
+        };
+
+        neighborhoods.insert(id, Neighborhood {
+            id: id as isize,
+            name: name.clone(),
+            desirability: 1.,
+            min_units: min_units,
+            max_units: max_units,
+            min_area: MIN_AREA,
+            max_area: MAX_AREA,
+            sqm_per_occupant: SQM_PER_OCCUPANT,
+            p_commercial: params.p_commercial,
+            color: "#fff".to_string(),
+            tax_rate: None,
+            shelter_capacity: 0,
+        });
+    }
+
+    // Neighborhood desirabilities are rescaled around 1,
+    // so rents are matched on average through the price per sqm.
+    // Neighborhoods without rents get the mean
+    let known: Vec<f64> = rents.iter().filter_map(|&(_, rent)| rent).collect();
+    if !known.is_empty() {
+        let mean_rent = known.iter().sum::<f64>() / known.len() as f64;
+        for (id, rent) in rents {
+            neighborhoods.get_mut(&id).unwrap().desirability = rent.unwrap_or(mean_rent) as f32;
+        }
+        city.price_per_sqm = mean_rent as f32 / mean_area * 12. * city.price_to_rent_ratio;
+    }
+
+    info!("Imported {:?} features onto a {}x{} grid", features.len(), rows, params.cols);
+    Design {
+        map: Map {
+            layout: layout,
+            offset: MapOffset {
+                row: false,
+                col: false,
+            },
+        },
+        neighborhoods: neighborhoods,
+        city: city,
+        amenities: Vec::new(),
+        transit: Vec::new(),
+        employment_centers: Vec::new(),
+        redistricting: Vec::new(),
+        zoning: Vec::new(),
+    }
+}
@@ -0,0 +1,108 @@
+use super::arena::TenantId;
+use super::config::Config;
+use serde::{Serialize, Deserialize};
+
+// What happens when a fixed-term lease is up
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Renewal {
+    // Another term of the same length
+    #[default]
+    Renew,
+
+    // Rolls over month to month
+    MonthToMonth,
+}
+
+// The terms tenants rent a unit on. Units carry their
+// lease through a sale, so new owners can only change
+// the rent or end it once it's up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lease {
+    // Step the lease was signed
+    pub start: usize,
+
+    // Length of a term in steps,
+    // or `None` for month-to-month
+    pub term: Option<usize>,
+
+    // Rent agreed for the current term
+    pub rent: f32,
+    pub renewal: Renewal,
+
+    // Tenants who've signed on,
+    // including roommates joining later
+    pub tenants: Vec<TenantId>,
+
+    // Security deposits held until
+    // each tenant moves out
+    pub deposits: Vec<Deposit>,
+}
+
+// A tenant's security deposit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Deposit {
+    pub tenant: TenantId,
+    pub amount: f32,
+
+    // Condition of the unit when it was paid
+    pub condition: f32,
+}
+
+impl Lease {
+    pub fn new(tenants: Vec<TenantId>, start: usize, rent: f32, conf: &Config) -> Lease {
+        Lease {
+            start: start,
+            term: match conf.lease_term_months {
+                0 => None,
+                months => Some(conf.steps(months)),
+            },
+            rent: rent,
+            renewal: conf.lease_renewal,
+            tenants: tenants,
+            deposits: Vec::new(),
+        }
+    }
+
+    // Returns a tenant's deposit as (refund, kept),
+    // keeping a share for however much the unit's
+    // condition has declined since they paid it
+    pub fn release(&mut self, tenant: TenantId, condition: f32, conf: &Config) -> (f32, f32) {
+        match self.deposits.iter().position(|d| d.tenant == tenant) {
+            Some(i) => {
+                let deposit = self.deposits.swap_remove(i);
+                let damage = f32::max(0., deposit.condition - condition);
+                let kept = deposit.amount * f32::min(1., damage * conf.deposit_deduction);
+                (deposit.amount - kept, kept)
+            }
+            None => (0., 0.)
+        }
+    }
+
+    // Steps between renewals at `time`:
+    // a term, or one step once month-to-month
+    pub fn period(&self, time: usize) -> usize {
+        match self.term {
+            Some(term) if self.renewal == Renewal::Renew || time <= self.start + term => term,
+            _ => 1,
+        }
+    }
+
+    // Whether the lease is up at `time`, when tenants
+    // can leave freely and the owner can change the
+    // rent or decline to renew
+    pub fn is_up(&self, time: usize) -> bool {
+        let elapsed = time.saturating_sub(self.start);
+        elapsed > 0 && elapsed.is_multiple_of(self.period(time))
+    }
+
+    // What a tenant paying `share` of the rent owes
+    // for moving out before the lease is up
+    pub fn break_penalty(&self, time: usize, share: f32, conf: &Config) -> f32 {
+        if self.is_up(time) || self.term.is_none() {
+            0.
+        } else {
+            share * conf.lease_break_penalty_months
+        }
+    }
+}
@@ -0,0 +1,78 @@
+#![recursion_limit = "512"]
+
+extern crate chrono;
+extern crate md5;
+extern crate noise;
+extern crate rand;
+#[cfg(feature = "play")]
+extern crate redis;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate petgraph;
+extern crate rand_distr;
+extern crate rayon;
+extern crate bincode;
+extern crate miniz_oxide;
+
+pub mod agent;
+pub mod analyze;
+pub mod arena;
+#[cfg(feature = "play")]
+pub mod backend;
+#[cfg(feature = "play")]
+pub mod bots;
+pub mod social;
+pub mod calibrate;
+pub mod city;
+pub mod commerce;
+pub mod compare;
+pub mod config;
+pub mod db;
+pub mod design;
+pub mod economy;
+pub mod employment;
+pub mod events;
+pub mod export;
+pub mod desirability;
+pub mod drift;
+pub mod finance;
+pub mod generate;
+pub mod gentrification;
+pub mod grid;
+pub mod household;
+pub mod import;
+pub mod lease;
+pub mod logging;
+pub mod market;
+#[cfg(feature = "play")]
+pub mod metrics;
+#[cfg(feature = "play")]
+pub mod play;
+#[cfg(feature = "play")]
+pub mod replay;
+pub mod retrofit;
+pub mod rng;
+pub mod scenario;
+pub mod screening;
+pub mod sim;
+pub mod stats;
+pub mod sweep;
+#[cfg(feature = "play")]
+pub mod sync;
+#[cfg(test)]
+mod testing;
+pub mod transit;
+pub mod union;
+pub mod policy;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod profile;
+pub mod provenance;
+pub mod progress;
+
+pub use agent::{Landlord, Tenant};
+pub use city::City;
+pub use config::Config;
+pub use design::Design;
+pub use sim::Simulation;
@@ -0,0 +1,54 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct LogConfig {
+    // Default level: error, warn, info, debug, or trace
+    pub level: String,
+
+    // Levels for particular modules, e.g.
+    // `play: debug`, overriding the default
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+
+    // Log JSON lines instead of text
+    #[serde(default)]
+    pub json: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            json: false,
+        }
+    }
+}
+
+impl LogConfig {
+    // Filter directives, e.g. `info,doma_sim::play=debug`.
+    // Only this crate's own logs are shown
+    fn directives(&self) -> String {
+        let mut directives = vec![format!("doma_sim={}", self.level)];
+        for (module, level) in &self.modules {
+            directives.push(format!("doma_sim::{}={}", module, level));
+        }
+        directives.join(",")
+    }
+}
+
+// Set up logging. The `RUST_LOG`
+// env var, if set, overrides the config
+pub fn init(conf: &LogConfig) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(conf.directives()));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if conf.json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
@@ -1,46 +1,142 @@
-extern crate chrono;
-extern crate md5;
-extern crate noise;
-extern crate pbr;
-extern crate rand;
-extern crate redis;
-extern crate serde;
+extern crate clap;
+extern crate doma_sim;
+#[cfg(feature = "play")]
 extern crate sentry;
-extern crate serde_json;
-extern crate serde_yaml;
-extern crate petgraph;
-extern crate rand_distr;
-
-mod agent;
-mod social;
-mod city;
-mod config;
-mod design;
-mod grid;
-mod play;
-mod sim;
-mod stats;
-mod sync;
-mod policy;
-use self::config::Config;
-use self::sim::Simulation;
-use self::play::{PlayManager, Control};
-use pbr::ProgressBar;
+
+use doma_sim::{analyze, calibrate, compare, config, design, generate, import, logging, progress, provenance, scenario, stats, sweep};
+#[cfg(feature = "play")]
+use doma_sim::{metrics, replay, sync};
+#[cfg(feature = "plot")]
+use doma_sim::plot;
+use doma_sim::config::Config;
+#[cfg(feature = "play")]
+use doma_sim::backend::{BackendType, Namespaced, SessionBackends, SyncBackend};
+use doma_sim::db::{RunDb, RunInfo};
+use doma_sim::design::Design;
+use doma_sim::events::EventLog;
+#[cfg(feature = "play")]
+use doma_sim::metrics::Metered;
+use doma_sim::export::{ExportCheckpoint, Exporter};
+use doma_sim::progress::ProgressSink;
+#[cfg(feature = "play")]
+use doma_sim::profile::Profiler;
+use doma_sim::sim::Simulation;
+#[cfg(feature = "play")]
+use doma_sim::play::{self, PlayManager, Control};
+use clap::Parser;
 use rand::rngs::StdRng;
 use rand::SeedableRng;
+use serde::{Serialize, Deserialize};
 use serde_json::{json, Value};
 use std::fs;
 use std::os::unix::fs::symlink;
 use std::path::Path;
-use chrono::{DateTime, Utc, Local};
+#[cfg(feature = "play")]
+use std::rc::Rc;
+#[cfg(feature = "play")]
+use std::sync::Arc;
+#[cfg(feature = "play")]
+use std::{thread, time};
+#[cfg(feature = "play")]
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+#[cfg(feature = "play")]
+use chrono::Local;
+#[cfg(feature = "play")]
+use md5::Digest;
+#[cfg(feature = "play")]
 use sentry::integrations::panic::register_panic_handler;
+use tracing::{debug, info, warn};
+#[cfg(feature = "play")]
+use tracing::info_span;
+
+#[derive(Parser, Debug)]
+#[command(about = "DOMA housing simulation")]
+struct Args {
+    /// Path to the config file
+    #[arg(long, default_value = "config.yaml")]
+    config: String,
+
+    /// Random seed, overriding the SEED env var
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Run once without players and save the run data
+    #[arg(long)]
+    debug: bool,
+
+    /// Steps to run in debug mode, overriding the STEPS env var
+    #[arg(long)]
+    steps: Option<usize>,
+
+    /// Design id, overriding the config's DESIGN_ID
+    #[arg(long)]
+    design: Option<String>,
+
+    /// Scenario pack to play, by name, from `scenarios/`
+    #[arg(long)]
+    scenario: Option<String>,
+
+    /// Generate the city from the given parameters file instead of a design
+    #[arg(long)]
+    generate: Option<String>,
+
+    /// Load the city from the given design JSON file
+    #[arg(long)]
+    design_file: Option<String>,
+
+    /// Import the city from GeoJSON, as set out in the given parameters file
+    #[arg(long)]
+    import: Option<String>,
+
+    /// Run a headless parameter sweep from the given file
+    #[arg(long)]
+    sweep: Option<String>,
+
+    /// Search for parameters matching the target metrics in the given file
+    #[arg(long)]
+    calibrate: Option<String>,
+
+    /// Report how sensitive outputs are to each parameter, as set out in the given file
+    #[arg(long)]
+    analyze: Option<String>,
+
+    /// Compare runs from their output.json files, the first as the baseline
+    #[arg(long, num_args = 2..)]
+    compare: Vec<String>,
+
+    /// Serve a recorded session from the given replay file, in builds with play sessions
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Playback speed for replays, as a multiple of real time
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f32,
 
-fn save_run_data(sim: &Simulation, history: &Vec<Value>, init: &Value, conf: &Config) {
+    /// Also save runs to the given SQLite database
+    #[arg(long)]
+    db: Option<String>,
+
+    /// Continue the debug run in the given directory from its last checkpoint
+    #[arg(long)]
+    resume: Option<String>,
+}
+
+// Where a run's data is saved. Concurrent
+// sessions each have their own
+fn run_dir(session: Option<&str>) -> String {
     let now: DateTime<Utc> = Utc::now();
-    let now_str = now.format("%Y.%m.%d.%H.%M.%S").to_string();
+    match session {
+        Some(session) => format!("runs/{}.session{}", now.format("%Y.%m.%d.%H.%M.%S"), session),
+        None => format!("runs/{}", now.format("%Y.%m.%d.%H.%M.%S")),
+    }
+}
+
+fn save_run_data(sim: &Simulation, history: &Vec<Value>, timings: &[Value], events: &EventLog, init: &Value, conf: &Config, dir: &str) {
     let results = json!({
         "init": init,
         "history": history,
+        "timings": timings,
         "meta": {
             "seed": conf.seed,
             "design": conf.design_id,
@@ -48,95 +144,588 @@ fn save_run_data(sim: &Simulation, history: &Vec<Value>, init: &Value, conf: &Co
             "units": sim.city.units.len(),
             "occupancy": sim.city.units.iter().fold(0, |acc, u| acc + u.occupancy),
             "neighborhoods": sim.design.neighborhoods,
+            "provenance": provenance::provenance(conf),
         }
     })
     .to_string();
 
-    let dir = format!("runs/{}", now_str);
-    let fname = format!("runs/{}/output.json", now_str);
+    let fname = format!("{}/output.json", dir);
 
-    let path = Path::new(&dir);
-    let run_path = Path::new(&now_str);
+    let path = Path::new(dir);
+    let run_path = path.file_name().unwrap();
     let latest_path = Path::new("runs/latest");
-    fs::create_dir(path).unwrap();
+    fs::create_dir_all(path).unwrap();
     fs::write(fname, results).expect("Unable to write file");
+    events.save(&format!("{}/events.jsonl", dir)).expect("Unable to write events");
+    // Concurrent sessions may race to update
+    // the link, in which case any of them will do
     if latest_path.exists() {
-        fs::remove_file(latest_path).unwrap();
+        let _ = fs::remove_file(latest_path);
+    }
+    if let Err(err) = symlink(run_path, latest_path) {
+        warn!("Couldn't link latest run: {}", err);
     }
-    symlink(run_path, latest_path).unwrap();
 
+    // The config as run, with any local config,
+    // env vars, and command line options applied.
+    // Written by way of JSON, which keeps floats
+    // as short as they were in the config
     let conf_path = Path::join(path, Path::new("config.yaml"));
-    fs::copy(Path::new("config.yaml"), conf_path).unwrap();
-    println!("Wrote output to {:?}", path);
+    let conf_yaml: serde_yaml::Value = serde_yaml::from_str(&serde_json::to_string(conf).unwrap()).unwrap();
+    fs::write(conf_path, serde_yaml::to_string(&conf_yaml).unwrap()).expect("Unable to write config");
+    info!("Wrote output to {:?}", path);
+}
+
+// Burn-in checkpoints are keyed by
+// the design and config they were run with
+#[cfg(feature = "play")]
+fn checkpoint_path(design: &Design, conf: &Config) -> String {
+    let key = json!({
+        "design": design,
+        "config": conf
+    }).to_string();
+    let hash = md5::Md5::digest(key.as_bytes());
+    format!("checkpoints/{:x}.bin", hash)
 }
 
 fn main() {
-    let conf = config::load_config();
-    let debug = conf.debug;
-    let steps = conf.steps;
-    let mut rng: StdRng = SeedableRng::seed_from_u64(conf.seed);
+    let args = Args::parse();
+    let mut conf = config::load_config(&args.config);
+    if let Some(name) = &args.scenario {
+        let pack = scenario::load_pack(name).expect("error while loading scenario");
+        conf = conf.with_params(&pack.params()).expect("error while applying scenario");
+    }
+    if let Some(seed) = args.seed {
+        conf.seed = seed;
+    }
+    if let Some(steps) = args.steps {
+        conf.steps = steps;
+    }
+    if let Some(design_id) = &args.design {
+        conf.design_id = design_id.clone();
+    }
+    conf.debug = conf.debug || args.debug;
+    config::validate_or_exit(&args.config, &conf);
+    logging::init(&conf.log);
+    debug!("{:?}", conf);
+
+    let db = args.db.as_ref().map(|path| RunDb::open(path).expect("Unable to open run database"));
 
+    // Headless parameter sweeps
+    if let Some(path) = &args.sweep {
+        let sweep = sweep::load_sweep(path);
+        sweep::run_sweep(&sweep, &conf, db.as_ref());
+        return;
+    }
+    if let Some(path) = &args.calibrate {
+        let cal = calibrate::load_calibration(path);
+        calibrate::run_calibration(&cal, &conf);
+        return;
+    }
+    if let Some(path) = &args.analyze {
+        let analysis = analyze::load_analysis(path);
+        analyze::run_analysis(&analysis, &conf);
+        return;
+    }
+    if !args.compare.is_empty() {
+        compare::run_comparison(&args.compare);
+        return;
+    }
+    if let Some(dir) = &args.resume {
+        let mut progress = progress::headless(conf.progress);
+        resume_run(dir, db.as_ref(), &mut *progress);
+        return;
+    }
+    #[cfg(feature = "play")]
+    serve(args, conf, db);
+
+    // Without play sessions, only debug runs are left
+    #[cfg(not(feature = "play"))]
+    {
+        let design = match built_design(&args) {
+            Some((_, design)) => design,
+            None => {
+                warn!("Headless builds need --generate, --import or --design-file");
+                std::process::exit(1);
+            }
+        };
+        design::validate_or_exit(&design);
+        let mut rng: StdRng = SeedableRng::seed_from_u64(conf.seed);
+        let mut progress = progress::headless(conf.progress);
+        let mut sim = Simulation::new(design, conf, &mut rng);
+        info!("{:?} tenants", sim.tenants.len());
+        debug_run(None, &mut sim, db.as_ref(), &mut *progress);
+    }
+}
+
+// A city built from the command line, if any,
+// along with the design id it's stored under
+fn built_design(args: &Args) -> Option<(&'static str, Design)> {
+    if let Some(path) = &args.generate {
+        Some(("generated", generate::generate(&generate::load_params(path))))
+    } else if let Some(path) = &args.import {
+        Some(("imported", import::import(&import::load_params(path))))
+    } else {
+        args.design_file.as_ref().map(|path| ("file", design::load_design_file(path)))
+    }
+}
+
+// Set up the sync backend and host sessions on it
+#[cfg(feature = "play")]
+fn serve(args: Args, mut conf: Config, db: Option<RunDb>) {
     let _guard = sentry::init(conf.sentry_dsn.clone());
     register_panic_handler();
+    play::handle_signals();
+
+    if let Some(addr) = &conf.metrics_addr {
+        metrics::serve(addr).expect("Unable to serve metrics");
+    }
+    let sessions = if conf.debug { 1 } else { conf.sessions };
+    let backends = SessionBackends::from_config(&conf.sync, sessions).unwrap();
+    let backend: Rc<dyn SyncBackend> = Rc::new(Metered::new(backends.connect().unwrap(), None));
+
+    // Generated, imported and file cities are stored
+    // like designs, so the frontend can load them too
+    if let Some((design_id, design)) = built_design(&args) {
+        conf.design_id = design_id.to_string();
+        backend.set(&format!("design:{}", design_id), serde_json::to_string(&design).unwrap().as_bytes()).unwrap();
+    }
+
+    // Serve a past session instead of running one
+    if let Some(path) = args.replay {
+        info!("Replaying {:?} at {}x...", path, args.replay_speed);
+        replay::play(&path, &*backend, args.replay_speed).unwrap();
+
+        // Clients can only reach the WebSocket
+        // backend while it's running
+        if conf.sync.backend == BackendType::Websocket {
+            info!("Serving the end of the replay until interrupted");
+            while !play::terminated() {
+                thread::sleep(time::Duration::from_millis(100));
+            }
+        }
+        return;
+    }
+
+    sync::set_meta(&*backend, &conf.sync).unwrap();
+    if sessions > 1 {
+        // Each session plays out on its own thread,
+        // seeded apart so they don't all play the same
+        info!("Hosting {:?} sessions", conf.sessions);
+        let backends = Arc::new(backends);
+        let db_path = &args.db;
+        let threads: Vec<_> = (0..conf.sessions).map(|i| {
+            let backends = backends.clone();
+            let mut conf = conf.clone();
+            conf.seed += i as u64;
+            let db_path = db_path.clone();
+            thread::spawn(move || {
+                let session = i.to_string();
+                let _span = info_span!("session", id = %session).entered();
+                let scoped = Rc::new(Namespaced::new(backends.connect().unwrap(), &session));
+                let backend: Rc<dyn SyncBackend> = Rc::new(Metered::new(scoped, Some(&session)));
+                let db = db_path.map(|path| RunDb::open(&path).expect("Unable to open run database"));
+                host(Some(&session), backend, conf, db.as_ref());
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    } else {
+        host(None, backend, conf, db.as_ref());
+    }
+}
+
+// Whether the run was interrupted. Only play
+// builds handle signals, so others never are
+fn interrupted() -> bool {
+    #[cfg(feature = "play")]
+    return play::terminated();
+    #[cfg(not(feature = "play"))]
+    return false;
+}
 
-    let mut play = PlayManager::new();
+// Step the sim, recording the step's
+// metrics in builds that serve them
+fn step_sim(sim: &mut Simulation, session: Option<&str>) {
+    #[cfg(feature = "play")]
+    let started = Instant::now();
+    sim.step();
+    #[cfg(feature = "play")]
+    metrics::metrics().record_step(metrics::session_label(session), sim, started.elapsed());
+}
+
+// Warn when a step or turn ran
+// over budget, and where the time went
+#[cfg(feature = "play")]
+fn check_budget(what: &str, profiler: &Profiler, budget_secs: Option<f32>) {
+    let secs = profiler.total().as_secs_f32();
+    if let Some(budget) = budget_secs.filter(|&budget| secs > budget) {
+        warn!("{} took {:.2}s, over its budget of {:.2}s: {}", what, secs, budget, profiler.summary());
+    }
+}
+
+// A debug run's progress, saved with
+// the simulation so it can be resumed
+#[derive(Serialize, Deserialize)]
+struct RunState {
+    init: Value,
+    history: Vec<Value>,
+    timings: Vec<Value>,
+    events: EventLog,
+    export: ExportCheckpoint,
+
+    // Row in the run database, if it's saved to one
+    #[serde(default)]
+    db_run: Option<i64>,
+}
+
+// Save the simulation, then the run's progress, so a
+// checkpoint is only complete once both are written.
+// Each replaces the last, so one cut short leaves
+// the previous checkpoint as it was
+fn save_run_checkpoint(dir: &str, sim: &Simulation, state: &mut RunState, exporter: &mut Exporter) {
+    state.export = exporter.checkpoint().unwrap();
+    let sim_path = format!("{}/checkpoint.bin", dir);
+    let state_path = format!("{}/checkpoint.json", dir);
+    let partial = format!("{}.partial", sim_path);
+    sim.save_checkpoint(&partial).expect("Unable to write checkpoint");
+    fs::rename(&partial, &sim_path).unwrap();
+    let partial = format!("{}.partial", state_path);
+    fs::write(&partial, serde_json::to_string(&json!({
+        "time": sim.time,
+        "state": state,
+    })).unwrap()).expect("Unable to write checkpoint");
+    fs::rename(&partial, &state_path).unwrap();
+}
+
+// Run once without players and save the run data
+fn debug_run(session: Option<&str>, sim: &mut Simulation, db: Option<&RunDb>, progress: &mut dyn ProgressSink) {
+    let dir = run_dir(session);
+    fs::create_dir_all(&dir).unwrap();
+    let exporter = Exporter::new(&dir, &sim.conf.export).unwrap();
+    let state = RunState {
+        init: stats::init_stats(sim),
+        history: Vec::with_capacity(sim.conf.steps),
+        timings: Vec::with_capacity(sim.conf.steps),
+        events: EventLog::new(),
+        export: ExportCheckpoint::default(),
+        db_run: None,
+    };
+    run_steps(&dir, sim, state, exporter, db, progress);
+}
+
+// Continue a debug run from its last checkpoint,
+// with the config it was started with
+fn resume_run(dir: &str, db: Option<&RunDb>, progress: &mut dyn ProgressSink) {
+    let dir = dir.trim_end_matches('/');
+    let mut sim = Simulation::from_checkpoint(&format!("{}/checkpoint.bin", dir)).expect("Unable to read checkpoint");
+    let saved: Value = serde_json::from_str(&fs::read_to_string(format!("{}/checkpoint.json", dir)).expect("Unable to read checkpoint"))
+        .expect("error while reading json");
+    if saved["time"].as_u64() != Some(sim.time as u64) {
+        panic!("Checkpoint in {} is incomplete", dir);
+    }
+    let state: RunState = serde_json::from_value(saved["state"].clone()).expect("error while reading checkpoint");
+    let exporter = Exporter::resume(dir, &sim.conf.export, &state.export).unwrap();
+    info!("Resuming {} from month {}", dir, sim.time);
+    run_steps(dir, &mut sim, state, exporter, db, progress);
+}
+
+fn run_steps(dir: &str, sim: &mut Simulation, mut state: RunState, mut exporter: Exporter, db: Option<&RunDb>, progress: &mut dyn ProgressSink) {
+    let steps = sim.conf.steps;
+    let db_run = db.map(|db| {
+        // A resumed run carries on in its own row,
+        // if it was saved to this database
+        if let Some(run) = state.db_run {
+            if db.resume_run(run, dir, sim.time).unwrap() {
+                return (db, run);
+            }
+        }
+        let run = db.start_run(&RunInfo {
+            kind: "debug",
+            dir: Some(dir),
+            params: None,
+            conf: &sim.conf,
+            init: &state.init,
+        }).unwrap();
+
+        // A resumed run's months so far
+        let mut month = sim.time - state.history.len();
+        let mut rest = &state.events.events[..];
+        for step_stats in &state.history {
+            month += 1;
+            let n = rest.iter().take_while(|e| e.month == month).count();
+            db.record(run, month, step_stats, &rest[..n]).unwrap();
+            rest = &rest[n..];
+        }
+        (db, run)
+    });
+    if let Some((_, run)) = db_run {
+        state.db_run = Some(run);
+    }
+    progress.start("debug", steps.saturating_sub(state.history.len()));
+    while state.history.len() < steps {
+        if interrupted() {
+            break;
+        }
+        step_sim(sim, None);
+        state.timings.push(sim.profiler.to_json());
+        state.events.record(sim.time, &sim.city.events);
+        let step_stats = stats::stats(sim);
+        exporter.record(sim, &step_stats).unwrap();
+        if let Some((db, run)) = db_run {
+            db.record(run, sim.time, &step_stats, state.events.recent(sim.city.events.len())).unwrap();
+        }
+        state.history.push(step_stats);
+        progress.inc(sim.time);
+
+        let interval = sim.conf.checkpoint_interval;
+        if interval > 0 && sim.time.is_multiple_of(interval) && state.history.len() < steps {
+            save_run_checkpoint(dir, sim, &mut state, &mut exporter);
+        }
+    }
+    progress.finish();
+
+    // Partial if interrupted, in which case
+    // it can be resumed from here
+    if interrupted() && sim.conf.checkpoint_interval > 0 {
+        save_run_checkpoint(dir, sim, &mut state, &mut exporter);
+    }
+    save_run_data(sim, &state.history, &state.timings, &state.events, &state.init, &sim.conf, dir);
+    exporter.finish().unwrap();
+    if !sim.conf.export.plots.is_empty() {
+        #[cfg(feature = "plot")]
+        plot::plot_run(dir, &state.history, &sim.conf.export.plots);
+        #[cfg(not(feature = "plot"))]
+        warn!("Plots require building with `--features plot`, skipping");
+    }
+    if let Some((db, run)) = db_run {
+        db.finish_run(run, sim.time).unwrap();
+    }
+
+    // Checkpoints are only for runs cut short
+    if state.history.len() >= steps {
+        let _ = fs::remove_file(format!("{}/checkpoint.bin", dir));
+        let _ = fs::remove_file(format!("{}/checkpoint.json", dir));
+    }
+}
+
+// Publish the session's outcomes
+// and keep them with the run
+#[cfg(feature = "play")]
+fn finish_session(play: &PlayManager, sim: &Simulation, events: &EventLog, start_stats: &Value, dir: &str) {
+    let summary = play.summarize(sim, events, start_stats);
+    if let Err(err) = play.record_scores(&summary) {
+        warn!("Couldn't record scores: {}", err);
+    }
+    if let Err(err) = play.set_finished(&summary) {
+        warn!("Couldn't publish results: {}", err);
+    }
+    fs::create_dir_all(dir).unwrap();
+    fs::write(format!("{}/summary.json", dir), summary.to_string()).expect("Unable to write summary");
+}
+
+// Host play sessions, one after another, until
+// terminated, or in debug mode, run once.
+// `session` is set if this is one of several
+#[cfg(feature = "play")]
+fn host(session: Option<&str>, backend: Rc<dyn SyncBackend>, mut conf: Config, db: Option<&RunDb>) {
+    let debug = conf.debug;
+    let mut rng: StdRng = SeedableRng::seed_from_u64(conf.seed);
+    let mut progress = progress::from_config(conf.progress, backend.clone());
+
+    // Play sessions are recorded for replays
+    let recorder = Rc::new(replay::Recorder::new(backend, &conf.replay));
+    let backend: Rc<dyn SyncBackend> = recorder.clone();
+    let mut play = PlayManager::new(backend.clone(), conf.sync.encoding);
+    if let Some(session) = session {
+        play = play.in_session(session);
+    }
+    let mut syncer = sync::Syncer::new(&conf.sync);
     loop {
         play.set_loading().unwrap();
 
+        // Apply changes an operator queued
+        // during the last session
+        let next_session = play.take_next_session().unwrap();
+        if !next_session.is_empty() {
+            conf = conf.with_params(&next_session).unwrap();
+            if next_session.contains_key("SEED") {
+                rng = SeedableRng::seed_from_u64(conf.seed);
+            }
+            info!("Configured session: {:?}", next_session);
+        }
+
         // Load and setup world
-        let design = design::load_design(&conf.design_id);
+        let design = design::load_design(&conf.design_id, &*backend);
+        design::validate_or_exit(&design);
         let mut sim = Simulation::new(design, conf.clone(), &mut rng);
-        println!("{:?} tenants", sim.tenants.len());
+        info!("{:?} tenants", sim.tenants.len());
         play.reset().unwrap();
 
         if debug {
-            let init_stats = stats::init_stats(&sim);
-            let mut history = Vec::with_capacity(steps);
-            let mut pb = ProgressBar::new(steps as u64);
-            for _ in 0..steps {
-                sim.step(&mut rng);
-                history.push(stats::stats(&sim));
-                pb.inc();
-            }
-            save_run_data(&sim, &history, &init_stats, &sim.conf);
+            debug_run(session, &mut sim, db, &mut *progress);
 
             // Run only once
             break;
 
         } else {
 
+            // Reuse the burn-in from an earlier session if possible
+            let checkpoint = checkpoint_path(&sim.design, &sim.conf);
+            match Simulation::from_checkpoint(&checkpoint) {
+                Ok(restored) => {
+                    info!("Restored burn-in from {:?}", checkpoint);
+                    sim = restored;
+                },
+                Err(_) => {
+                    info!("Burning in for {:?} months...", sim.conf.burn_in);
+                    progress.start("burn_in", sim.conf.burn_in);
+                    for _ in 0..sim.conf.burn_in {
+                        if play::terminated() {
+                            play.terminate().unwrap();
+                            return;
+                        }
+                        sim.step();
+                        progress.inc(sim.time);
+                    }
+                    progress.finish();
+                    // Saved under another name first so
+                    // concurrent sessions never read it half-written
+                    fs::create_dir_all("checkpoints").unwrap();
+                    let partial = format!("{}.{}.partial", checkpoint, session.unwrap_or("main"));
+                    sim.save_checkpoint(&partial).unwrap();
+                    fs::rename(&partial, &checkpoint).unwrap();
+                }
+            }
+
+            // Session history, and where each
+            // turn's time went, recorded at each sync
+            let init_stats = stats::init_stats(&sim);
+            let mut history = Vec::new();
+            let mut timings = Vec::new();
+            let mut events = EventLog::new();
+
+            let dir = run_dir(session);
+            fs::create_dir_all(&dir).unwrap();
+            if let Err(err) = recorder.start(&format!("{}/replay.bin", dir), &conf.design_id) {
+                warn!("Couldn't record replay: {}", err);
+            }
+            let mut exporter = Exporter::new(&dir, &sim.conf.export).unwrap();
+            let db_run = db.map(|db| (db, db.start_run(&RunInfo {
+                kind: "play",
+                dir: Some(&dir),
+                params: None,
+                conf: &sim.conf,
+                init: &init_stats,
+            }).unwrap()));
+
             // Setup tenants for players to choose
-            play.gen_player_tenant_pool(&sim.tenants, &sim.city, sim.conf.tenant_pool_size);
-            println!("Burning in for {:?} months...", sim.conf.burn_in);
-            for _ in 0..sim.conf.burn_in {
-                sim.step(&mut rng);
+            if let Err(err) = play.gen_player_tenant_pool(&sim.tenants, &sim.city, sim.conf.tenant_pool_size, &mut rng) {
+                warn!("Couldn't sync the tenant pool: {}", err);
             }
-            sync::sync(sim.time, &sim.city, &sim.design, stats::stats(&sim)).unwrap();
+            play.seat_bots(&mut sim, &mut rng);
+            play.set_schedule(&sim.conf.session_phases);
+            let mut finished = false;
+            let start_stats = stats::stats(&sim);
+            syncer.request_keyframe();
+            syncer.sync(&*backend, sim.time, &sim.city, &sim.tenants, &sim.design, start_stats.clone()).unwrap();
+            syncer.sync_events(&*backend, &events).unwrap();
+            syncer.sync_spectator(&*backend, &sim.landlords, &events).unwrap();
             play.set_ready().unwrap();
-            println!("Ready: Session {}", Local::now().to_rfc3339());
+            info!("Ready: Session {}", Local::now().to_rfc3339());
 
             loop {
                 // Blocks until a run command is received;
                 // will process other commands while waiting
-                let control = play.wait_for_control(&mut sim, &mut rng);
+                let control = play.wait_for_control(&mut sim, &mut events, &mut rng);
                 match control {
                     Control::Run(steps) => {
-                        println!("Running for {:?} steps...", steps);
-                        let mut pb = ProgressBar::new(steps as u64);
+                        info!("Running for {:?} steps...", steps);
+                        progress.start("turn", steps);
                         play.set_running().unwrap();
+                        let n_events = events.events.len();
+                        let mut turn = Profiler::default();
                         for step in 0..steps {
-                            sim.step(&mut rng);
+                            // Stops early if terminated or aborted
+                            if play.hold_between_steps(&sim.conf) {
+                                break;
+                            }
+                            step_sim(&mut sim, session);
+                            turn.merge(&sim.profiler);
+                            check_budget("Step", &sim.profiler, sim.conf.turn_budget_secs.map(|secs| secs / steps as f32));
+                            events.record(sim.time, &sim.city.events);
+                            if let Err(err) = play.apply_votes(&mut sim) {
+                                warn!("Couldn't apply votes: {}", err);
+                            }
                             play.sync_step(step, steps).unwrap();
-                            pb.inc();
+                            progress.inc(sim.time);
+                        }
+                        progress.finish();
+
+                        // Operator messages sent mid-run
+                        if let Err(err) = play.narrate(sim.time, &mut events) {
+                            warn!("Couldn't send narration: {}", err);
+                        }
+                        let step_stats = stats::stats(&sim);
+                        exporter.record(&sim, &step_stats).unwrap();
+                        if let Some((db, run)) = db_run {
+                            db.record(run, sim.time, &step_stats, &events.events[n_events..]).unwrap();
+                        }
+                        history.push(step_stats.clone());
+                        syncer.sync(&*backend, sim.time, &sim.city, &sim.tenants, &sim.design, step_stats).unwrap();
+                        turn.merge(syncer.timings());
+                        let started = Instant::now();
+                        syncer.sync_events(&*backend, &events).unwrap();
+                        syncer.sync_spectator(&*backend, &sim.landlords, &events).unwrap();
+                        if let Err(err) = play.sync_players(&sim.tenants, &sim.landlords, &sim.city, &sim.doma, &sim.unions, &sim.conf) {
+                            warn!("Couldn't sync players: {}", err);
                         }
-                        sync::sync(sim.time, &sim.city, &sim.design, stats::stats(&sim)).unwrap();
-                        play.sync_players(&sim.tenants, &sim.city, &sim.doma).unwrap();
+                        turn.add("sync_writes", started.elapsed());
+                        check_budget("Turn", &turn, sim.conf.turn_budget_secs);
+                        timings.push(turn.to_json());
                         play.set_ready().unwrap();
-                        println!("Finished running.");
+                        info!("Finished running.");
+                    },
+                    Control::Finish => {
+                        info!("Session finished.");
+                        finish_session(&play, &sim, &events, &start_stats, &dir);
+                        finished = true;
                     },
                     Control::Reset => {
-                        println!("Resetting...");
+                        info!("Resetting...");
+                        if !finished {
+                            finish_session(&play, &sim, &events, &start_stats, &dir);
+                        }
+                        recorder.stop();
+                        exporter.finish().unwrap();
+                        if let Some((db, run)) = db_run {
+                            db.finish_run(run, sim.time).unwrap();
+                        }
                         break;
+                    },
+                    Control::Resync => {
+                        syncer.request_keyframe();
+                        syncer.sync(&*backend, sim.time, &sim.city, &sim.tenants, &sim.design, stats::stats(&sim)).unwrap();
+                        syncer.sync_events(&*backend, &events).unwrap();
+                        syncer.sync_spectator(&*backend, &sim.landlords, &events).unwrap();
+                    },
+                    Control::Terminate => {
+                        // Flush the latest state, save
+                        // the session so far, and let
+                        // players know it's over
+                        syncer.request_keyframe();
+                        syncer.sync(&*backend, sim.time, &sim.city, &sim.tenants, &sim.design, stats::stats(&sim)).unwrap();
+                        save_run_data(&sim, &history, &timings, &events, &init_stats, &sim.conf, &dir);
+                        if !finished {
+                            finish_session(&play, &sim, &events, &start_stats, &dir);
+                        }
+                        exporter.finish().unwrap();
+                        if let Some((db, run)) = db_run {
+                            db.finish_run(run, sim.time).unwrap();
+                        }
+                        play.terminate().unwrap();
+                        return;
                     }
                 }
             }
@@ -0,0 +1,434 @@
+use super::agent::{Decision, Landlord, Tenant};
+use super::arena::{Arena, TenantId, UnitId};
+use super::city::City;
+use super::config::Config;
+use super::events::SimEvent;
+use super::grid::Position;
+use super::screening::{screen, Screened};
+use super::stats::percentile;
+use linreg::linear_regression;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use tracing::info_span;
+
+// How landlords choose among
+// applicants for a listed unit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantSelection {
+    // Prefer applicants with the most income
+    HighestIncome,
+
+    // Take applicants in the order their
+    // applications arrive, which is random
+    FirstCome,
+}
+
+// How tenants, or households, look for a place,
+// overriding `TENANT_SAMPLE_SIZE` and `MOVING_PENALTY`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct SearchConfig {
+    #[serde(default)]
+    pub sample_size: Option<usize>,
+    #[serde(default)]
+    pub moving_penalty: Option<f32>,
+
+    // Only look at units within this
+    // many parcels of work, if set
+    #[serde(default)]
+    pub radius: Option<usize>,
+
+    // Stop looking at the first unit at least
+    // this desirable, rather than looking
+    // through the whole sample, if set
+    #[serde(default)]
+    pub satisfice: Option<f32>,
+}
+
+impl SearchConfig {
+    pub fn moving_penalty(&self, conf: &Config) -> f32 {
+        self.moving_penalty.unwrap_or(conf.moving_penalty)
+    }
+
+    // Vacant units to look at, in random order, led by up
+    // to `SOCIAL.REFERRALS` heard of through ties, if any
+    pub fn sample(&self, referrals: &[UnitId], work: Position, city: &City, rng: &mut StdRng, conf: &Config) -> Vec<UnitId> {
+        let size = self.sample_size.unwrap_or(conf.tenant_sample_size);
+        let vacant_units = city.vacant_units();
+        let nearby = |u_id: &UnitId| match self.radius {
+            Some(radius) => city.grid.distance(city.units[*u_id].pos, work) <= radius as f32,
+            None => true
+        };
+        let mut sample: Vec<UnitId> = if referrals.is_empty() {
+            Vec::new()
+        } else {
+            let n_referrals = conf.social.as_ref().map_or(0, |social| social.referrals);
+            let referrals: Vec<UnitId> = referrals.iter().cloned().filter(nearby).collect();
+            referrals.choose_multiple(rng, usize::min(size, n_referrals)).cloned().collect()
+        };
+        let rest: Vec<UnitId> = match self.radius {
+            Some(_) => {
+                let nearby: Vec<UnitId> = vacant_units.iter().cloned().filter(nearby).collect();
+                nearby.choose_multiple(rng, size).cloned().collect()
+            }
+            None => vacant_units.choose_multiple(rng, size).cloned().collect()
+        };
+        let n_rest = size - sample.len();
+        let rest: Vec<UnitId> = rest.into_iter().filter(|u_id| !sample.contains(u_id)).take(n_rest).collect();
+        sample.extend(rest);
+        sample
+    }
+
+    // Whether a unit is good enough to stop looking
+    pub fn satisfied(&self, desirability: f32) -> bool {
+        self.satisfice.is_some_and(|satisfice| desirability >= satisfice)
+    }
+}
+
+// Rents per neighborhood, computed once a step
+// for landlords, developers, and speculators to
+// read, rather than each surveying the market
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MarketStats {
+    pub neighborhoods: Vec<NeighborhoodMarket>,
+
+    // Citywide estimated value per area,
+    // relative to the first estimate
+    pub price_index: f32,
+    base_value_per_area: f32,
+}
+
+// Rents are per area
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct NeighborhoodMarket {
+    // Percentiles of occupied units' rents
+    pub p25_rent: f32,
+    pub median_rent: f32,
+    pub p75_rent: f32,
+    pub p90_rent: f32,
+
+    // Mean rent of all units, including
+    // what vacant units are asking
+    pub mean_rent: f32,
+
+    pub occupied: usize,
+    pub vacancy_rate: f32,
+
+    // Change in median rent per month,
+    // over the last `TREND_MONTHS`
+    pub trend: f32,
+    history: Vec<f32>,
+}
+
+impl MarketStats {
+    pub fn update(&mut self, city: &City, trend_months: usize) {
+        self.neighborhoods.resize_with(city.units_by_neighborhood.len(), NeighborhoodMarket::default);
+        for (market, unit_ids) in self.neighborhoods.iter_mut().zip(&city.units_by_neighborhood) {
+            let mut rents: Vec<f32> = unit_ids.iter()
+                .map(|&u_id| &city.units[u_id])
+                .filter(|u| !u.vacant())
+                .map(|u| u.rent_per_area())
+                .collect();
+            market.p25_rent = percentile(&mut rents, 0.25);
+            market.median_rent = percentile(&mut rents, 0.5);
+            market.p75_rent = percentile(&mut rents, 0.75);
+            market.p90_rent = percentile(&mut rents, 0.9);
+
+            let total = unit_ids.iter().fold(0., |acc, &u_id| acc + city.units[u_id].rent_per_area());
+            market.mean_rent = total / f32::max(1., unit_ids.len() as f32);
+            market.occupied = rents.len();
+            market.vacancy_rate = 1. - rents.len() as f32 / f32::max(1., unit_ids.len() as f32);
+
+            market.history.push(market.median_rent);
+            if market.history.len() > trend_months {
+                market.history.remove(0);
+            }
+            let xs: Vec<f32> = (0..market.history.len()).map(|v| v as f32).collect();
+            market.trend = match linear_regression::<f32, f32, f32>(&xs, &market.history) {
+                Some((slope, _)) if market.history.len() > 1 => slope,
+                _ => 0.
+            };
+        }
+    }
+
+    // Forget a neighborhood's rent trend,
+    // e.g. once its boundaries change
+    pub fn reset(&mut self, neighb_id: usize) {
+        if let Some(market) = self.neighborhoods.get_mut(neighb_id) {
+            market.history.clear();
+            market.trend = 0.;
+        }
+    }
+
+    // Estimate every unit's value by capitalizing its rent,
+    // projected `horizon` steps out on its neighborhood's
+    // trend, and discounted for poor condition
+    pub fn estimate_values(&mut self, city: &mut City, price_to_rent_ratio: f32, horizon: usize, conf: &Config) {
+        let mut total_value = 0.;
+        let mut total_area = 0.;
+        for (market, unit_ids) in self.neighborhoods.iter().zip(&city.units_by_neighborhood) {
+            let growth = if market.median_rent > 0. {
+                f32::max(0., 1. + market.trend * horizon as f32 / market.median_rent)
+            } else {
+                1.
+            };
+            for &u_id in unit_ids {
+                let unit = &mut city.units[u_id];
+                let desirability = city.parcels.get(&unit.pos).unwrap().desirability;
+                let condition = 1. - conf.value_condition_discount * (1. - unit.condition);
+                unit.est_value = unit.rent * 12. * price_to_rent_ratio * desirability * growth * condition;
+                total_value += unit.est_value;
+                total_area += unit.area;
+            }
+        }
+
+        let value_per_area = total_value / f32::max(1., total_area);
+        if self.base_value_per_area == 0. {
+            self.base_value_per_area = value_per_area;
+        }
+        self.price_index = if self.base_value_per_area > 0. {
+            value_per_area / self.base_value_per_area
+        } else {
+            1.
+        };
+    }
+}
+
+// Agents' reads of the market are off by up to
+// this share, so they don't all act in lockstep
+pub fn noisy(value: f32, noise: f32, rng: &mut StdRng) -> f32 {
+    if noise > 0. {
+        value * rng.gen_range(1. - noise, 1. + noise)
+    } else {
+        value
+    }
+}
+
+// A tenant, or a household applying
+// together, looking for a place
+pub struct Applicant {
+    pub members: Vec<TenantId>,
+    pub income: f32,
+    pub decision: Decision,
+
+    // Next candidate to apply to
+    next: usize,
+}
+
+impl Applicant {
+    pub fn new(members: Vec<TenantId>, tenants: &Arena<TenantId, Tenant>, decision: Decision) -> Applicant {
+        Applicant {
+            income: members.iter().fold(0., |acc, &t_id| acc + tenants[t_id].income),
+            members: members,
+            decision: decision,
+            next: 0,
+        }
+    }
+
+    // The next unit worth applying to, if any.
+    // Candidates are best first, so once one isn't
+    // worth moving for none of the rest are
+    fn next_application(&mut self) -> Option<UnitId> {
+        let &(u_id, desirability) = self.decision.candidates.get(self.next)?;
+        self.next += 1;
+        if desirability - self.decision.moving_penalty > self.decision.current_desirability {
+            Some(u_id)
+        } else {
+            None
+        }
+    }
+}
+
+// Vacant units are listed at their current rent.
+// Each round, applicants who haven't found a place
+// apply to their next best listing, and landlords
+// choose among the applicants for each unit.
+// Everyone applies at once, so the outcome doesn't
+// depend on what order tenants are stepped in
+// Returns each application made, for auditing
+// which applicants landlords turned away
+pub fn clear(
+    applicants: &mut [Applicant],
+    tenants: &mut Arena<TenantId, Tenant>,
+    city: &mut City,
+    landlords: &[Landlord],
+    month: usize,
+    rng: &mut StdRng,
+    conf: &Config,
+) -> Vec<Screened> {
+    let _span = info_span!("market", applicants = applicants.len(), vacant = city.vacant_units().len()).entered();
+    let mut housed = vec![false; applicants.len()];
+    let mut screened = Vec::new();
+    for _ in 0..conf.application_rounds {
+        // Applicant indices for each unit,
+        // ordered by unit id for reproducibility
+        let mut applications: BTreeMap<UnitId, Vec<usize>> = BTreeMap::new();
+        for (i, applicant) in applicants.iter_mut().enumerate() {
+            if housed[i] {
+                continue;
+            }
+            if let Some(u_id) = applicant.next_application() {
+                applications.entry(u_id).or_default().push(i);
+            }
+        }
+        if applications.is_empty() {
+            break;
+        }
+
+        for (u_id, mut applied) in applications {
+            match conf.tenant_selection {
+                TenantSelection::HighestIncome => {
+                    applied.sort_by(|&a, &b| applicants[b].income.partial_cmp(&applicants[a].income).unwrap());
+                }
+                TenantSelection::FirstCome => applied.shuffle(rng),
+            }
+            for i in applied {
+                let applicant = &applicants[i];
+                if city.units[u_id].vacancies() < applicant.members.len() {
+                    continue;
+                }
+
+                // Turned away applicants try
+                // their next choice next round
+                let denied = screen(&applicant.members, &city.units[u_id], tenants, landlords, rng, conf);
+                screened.extend(applicant.members.iter().map(|&t_id| Screened {
+                    tenant: t_id,
+                    unit: u_id,
+                    denied: denied,
+                }));
+                if denied.is_some() {
+                    continue;
+                }
+                if !move_in(&applicant.members, u_id, tenants, city, month, conf) {
+                    continue;
+                }
+                if applicant.decision.referrals.contains(&u_id) {
+                    for &t_id in &applicant.members {
+                        tenants[t_id].referred = true;
+                    }
+                }
+                housed[i] = true;
+            }
+        }
+    }
+    screened
+}
+
+// Move tenants out of wherever they're living and
+// into the unit, if it has room for all of them.
+// Returns whether they moved in
+pub fn move_in(
+    members: &[TenantId],
+    unit_id: UnitId,
+    tenants: &mut Arena<TenantId, Tenant>,
+    city: &mut City,
+    month: usize,
+    conf: &Config,
+) -> bool {
+    let moving_within = members.iter().filter(|&&t_id| tenants[t_id].unit == Some(unit_id)).count();
+    if city.units[unit_id].vacancies() + moving_within < members.len() {
+        return false;
+    }
+
+    for &t_id in members {
+        let tenant = &mut tenants[t_id];
+        city.events.push(SimEvent::MovedIn {
+            tenant: t_id,
+            unit: unit_id,
+            from: tenant.unit,
+        });
+        if let Some(u_id) = tenant.unit {
+            // Leaving before the lease is up costs
+            // the tenant, as much as they have
+            let unit = &city.units[u_id];
+            let penalty = match &unit.lease {
+                Some(lease) => f32::min(tenant.savings, lease.break_penalty(month, tenant.rent_share(unit), conf)),
+                None => 0.
+            };
+            if penalty > 0. {
+                tenant.savings -= penalty;
+                city.events.push(SimEvent::LeaseBroken {
+                    tenant: t_id,
+                    unit: u_id,
+                    penalty: penalty,
+                });
+            }
+
+            let unit = &mut city.units[u_id];
+            if unit.owner_collects() {
+                unit.fees += penalty;
+            }
+        }
+
+        // Into a new lease if the unit
+        // was vacant, otherwise the current one
+        city.move_tenant(tenant, unit_id, month, conf);
+        tenant.referred = false;
+        tenant.arrears = 0;
+        tenant.savings = f32::max(0., tenant.savings - conf.moving_cost);
+    }
+
+    let unit = &mut city.units[unit_id];
+
+    // Deposits come out of savings,
+    // which were checked beforehand
+    for &t_id in members {
+        let tenant = &mut tenants[t_id];
+        let deposit = f32::min(tenant.savings, tenant.deposit(unit, conf));
+        tenant.savings -= deposit;
+        unit.hold_deposit(t_id, deposit);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::fixture;
+
+    fn vacant_unit(city: &City) -> UnitId {
+        *city.vacant_units().iter()
+            .find(|&&u_id| city.units[u_id].vacant())
+            .expect("no vacant unit")
+    }
+
+    // Leaving mid-lease costs the tenant,
+    // and the owner collects it
+    #[test]
+    fn move_in_breaks_lease() {
+        let mut sim = fixture();
+        let from = vacant_unit(&sim.city);
+        let t_id = sim.tenants.iter().find(|t| t.unit.is_none()).unwrap().id;
+        sim.tenants[t_id].savings = 1e6;
+        assert!(move_in(&[t_id], from, &mut sim.tenants, &mut sim.city, sim.time, &sim.conf));
+
+        let to = vacant_unit(&sim.city);
+        let fees = sim.city.units[from].fees;
+        assert!(move_in(&[t_id], to, &mut sim.tenants, &mut sim.city, sim.time + 1, &sim.conf));
+        let penalty = sim.city.events.iter().find_map(|event| match event {
+            SimEvent::LeaseBroken { tenant, unit, penalty } if *tenant == t_id && *unit == from => Some(*penalty),
+            _ => None,
+        }).expect("lease not broken");
+        assert!(penalty > 0.);
+        if sim.city.units[from].owner_collects() {
+            assert_eq!(sim.city.units[from].fees, fees + penalty);
+        }
+    }
+
+    // Households only move in together
+    #[test]
+    fn move_in_needs_room_for_everyone() {
+        let mut sim = fixture();
+        let u_id = vacant_unit(&sim.city);
+        let occupancy = sim.city.units[u_id].occupancy;
+        let members: Vec<TenantId> = sim.tenants.iter().take(occupancy + 1).map(|t| t.id).collect();
+        let homes: Vec<Option<UnitId>> = members.iter().map(|&t_id| sim.tenants[t_id].unit).collect();
+        assert!(!move_in(&members, u_id, &mut sim.tenants, &mut sim.city, sim.time, &sim.conf));
+        assert_eq!(members.iter().map(|&t_id| sim.tenants[t_id].unit).collect::<Vec<_>>(), homes);
+        assert!(sim.city.units[u_id].vacant());
+    }
+}
@@ -0,0 +1,231 @@
+use super::backend::{SyncBackend, SyncResult};
+use super::sim::Simulation;
+use prometheus::{Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use prometheus::exponential_buckets;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+// For monitoring a live deployment. Metrics
+// are always kept, but only served if
+// `METRICS_ADDR` is set. Each is labeled
+// with the session it's for, `main` unless
+// several are hosted at once
+pub struct Metrics {
+    registry: Registry,
+    step_seconds: HistogramVec,
+
+    // Time spent in each phase of a step
+    phase_seconds: HistogramVec,
+    steps: IntCounterVec,
+    housed: IntGaugeVec,
+    homeless: IntGaugeVec,
+    players: IntGaugeVec,
+    players_ready: IntGaugeVec,
+
+    // Latency of each backend operation,
+    // e.g. round trips to Redis
+    backend_seconds: HistogramVec,
+
+    // Size of what's written to the backend,
+    // by the kind of key, e.g. `state` or `player`
+    payload_bytes: HistogramVec,
+
+    // When the main loop last made progress,
+    // whether stepping or waiting on players.
+    // If this falls behind, the loop has stalled
+    last_tick: GaugeVec,
+}
+
+// The label for a session's metrics
+pub fn session_label(session: Option<&str>) -> &str {
+    session.unwrap_or("main")
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::new_custom(Some("doma".to_string()), None).unwrap(),
+            step_seconds: HistogramVec::new(
+                HistogramOpts::new("step_seconds", "Time taken to simulate a month")
+                    .buckets(exponential_buckets(0.01, 2., 12).unwrap()),
+                &["session"]).unwrap(),
+            phase_seconds: HistogramVec::new(
+                HistogramOpts::new("phase_seconds", "Time taken by each phase of a month")
+                    .buckets(exponential_buckets(0.001, 2., 14).unwrap()),
+                &["session", "phase"]).unwrap(),
+            steps: IntCounterVec::new(Opts::new("steps_total", "Months simulated"), &["session"]).unwrap(),
+            housed: IntGaugeVec::new(Opts::new("tenants_housed", "Tenants with a home"), &["session"]).unwrap(),
+            homeless: IntGaugeVec::new(Opts::new("tenants_homeless", "Tenants without a home"), &["session"]).unwrap(),
+            players: IntGaugeVec::new(Opts::new("players_connected", "Players in the session"), &["session"]).unwrap(),
+            players_ready: IntGaugeVec::new(Opts::new("players_ready", "Players who have ended their turn"), &["session"]).unwrap(),
+            backend_seconds: HistogramVec::new(
+                HistogramOpts::new("backend_seconds", "Latency of backend operations")
+                    .buckets(exponential_buckets(0.0001, 2., 14).unwrap()),
+                &["session", "op"]).unwrap(),
+            payload_bytes: HistogramVec::new(
+                HistogramOpts::new("payload_bytes", "Size of payloads written to the backend")
+                    .buckets(exponential_buckets(64., 4., 10).unwrap()),
+                &["session", "key"]).unwrap(),
+            last_tick: GaugeVec::new(Opts::new("last_tick_timestamp_seconds", "When the main loop last made progress"), &["session"]).unwrap(),
+        };
+        metrics.registry.register(Box::new(metrics.step_seconds.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.phase_seconds.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.steps.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.housed.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.homeless.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.players.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.players_ready.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.backend_seconds.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.payload_bytes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.last_tick.clone())).unwrap();
+        metrics
+    }
+
+    pub fn record_step(&self, session: &str, sim: &Simulation, duration: Duration) {
+        self.step_seconds.with_label_values(&[session]).observe(duration.as_secs_f64());
+        for (phase, duration) in sim.profiler.phases() {
+            self.phase_seconds.with_label_values(&[session, phase]).observe(duration.as_secs_f64());
+        }
+        self.steps.with_label_values(&[session]).inc();
+        let tenants = sim.tenants.iter().filter(|t| !t.departed);
+        let (housed, homeless): (Vec<_>, Vec<_>) = tenants.partition(|t| t.unit.is_some());
+        self.housed.with_label_values(&[session]).set(housed.len() as i64);
+        self.homeless.with_label_values(&[session]).set(homeless.len() as i64);
+        self.tick(session);
+    }
+
+    pub fn record_players(&self, session: &str, players: usize, ready: usize) {
+        self.players.with_label_values(&[session]).set(players as i64);
+        self.players_ready.with_label_values(&[session]).set(ready as i64);
+    }
+
+    pub fn tick(&self, session: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.last_tick.with_label_values(&[session]).set(now.as_secs_f64());
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf).unwrap();
+        buf
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+// Serve metrics for Prometheus to scrape at `/metrics`
+pub fn serve(addr: &str) -> std::io::Result<()> {
+    // Bind here so that address
+    // errors surface immediately
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let listener = runtime.block_on(TcpListener::bind(addr))?;
+    info!("Serving metrics on {:?}", addr);
+    thread::spawn(move || {
+        runtime.block_on(accept(listener));
+    });
+    Ok(())
+}
+
+async fn accept(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream));
+            }
+            Err(err) => warn!("Failed to accept metrics client: {:?}", err),
+        }
+    }
+}
+
+// Only the request line matters, so
+// this doesn't bother parsing the rest
+async fn respond(mut stream: TcpStream) {
+    let mut buf = [0; 1024];
+    let n = match stream.read(&mut buf).await {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics().encode()),
+        _ => ("404 Not Found", Vec::new()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, TextEncoder::new().format_type(), body.len());
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(&body).await;
+}
+
+// Times every operation on the backend
+// it wraps, and measures what's written
+pub struct Metered {
+    backend: Rc<dyn SyncBackend>,
+    session: String,
+}
+
+impl Metered {
+    pub fn new(backend: Rc<dyn SyncBackend>, session: Option<&str>) -> Metered {
+        Metered {
+            backend: backend,
+            session: session_label(session).to_string(),
+        }
+    }
+
+    fn time<T, F>(&self, op: &str, f: F) -> T where F: FnOnce() -> T {
+        let start = Instant::now();
+        let result = f();
+        metrics().backend_seconds.with_label_values(&[&self.session, op]).observe(start.elapsed().as_secs_f64());
+        result
+    }
+
+    // Keys are labeled by their prefix, e.g. all
+    // `player:<token>:<id>` keys as `player`
+    fn measure(&self, key: &str, value: &[u8]) {
+        let kind = key.split(':').next().unwrap_or(key);
+        metrics().payload_bytes.with_label_values(&[&self.session, kind]).observe(value.len() as f64);
+    }
+}
+
+impl SyncBackend for Metered {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        self.time("get", || self.backend.get(key))
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.measure(key, value);
+        self.time("set", || self.backend.set(key, value))
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.time("del", || self.backend.del(key))
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.measure(key, value);
+        self.time("lpush", || self.backend.lpush(key, value))
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        self.time("lpop", || self.backend.lpop(key))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.measure(key, value);
+        self.time("hset", || self.backend.hset(key, field, value))
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        self.time("hgetall", || self.backend.hgetall(key))
+    }
+}
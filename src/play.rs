@@ -1,71 +1,489 @@
-use serde::Deserialize;
-use redis::{Commands, Connection};
+use serde::{Serialize, Deserialize};
 use strum_macros::{Display};
-use super::agent::{Tenant, DOMA};
+use super::agent::{AgentType, Landlord, Tenant, DOMA};
+use super::arena::{Arena, TenantId, UnitId};
+use super::bots::Bot;
+use super::backend::{SyncBackend, SyncConfig, SyncResult};
+use super::sync::Encoding;
 use super::policy::Policy;
 use super::sim::Simulation;
 use super::city::{City, Unit};
+use super::config::{Config, Phase};
+use super::design::{self, Design};
+use super::events::{EventLog, SimEvent};
+use super::market;
+use super::metrics::{metrics, session_label};
+use super::scenario;
+use super::stats;
+use super::union::Unions;
+use rand::distributions::Alphanumeric;
 use rand::seq::SliceRandom;
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use rand::Rng;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use std::{thread, time};
+use tracing::{debug, info, info_span, warn};
 
 static COMMAND_INTERVAL_MS: u64 = 500;
 
+// DOMA members vote on strategy, and players
+// on policy, quarterly, however long steps are
+static VOTE_INTERVAL_MONTHS: usize = 3;
+
+// Players register with a key of their own,
+// so it can't be too easy to guess, and are
+// issued their token under it
+static MIN_TOKEN_LENGTH: usize = 16;
+static TOKEN_LENGTH: usize = 32;
+
+// Commands each kind of player can send,
+// so the frontend knows what to offer
+static TENANT_ACTIONS: &[&str] = &["MoveTenant", "Organize", "DOMAAdd", "DOMAPledge", "DOMAPreach", "DOMAVote", "VacancyTaxVote", "EndTurn", "ReleaseTenant"];
+static LANDLORD_ACTIONS: &[&str] = &["SetRent", "Maintain", "SellUnit", "VacancyTaxVote", "EndTurn", "ReleaseLandlord"];
+
+// Best scores listed per kind of player
+static TOP_SCORES: usize = 10;
+
+// Set on SIGINT or SIGTERM, so the
+// session can be wound down between steps
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+// A second signal exits immediately
+pub fn handle_signals() {
+    ctrlc::set_handler(|| {
+        if TERMINATED.swap(true, Ordering::SeqCst) {
+            process::exit(1);
+        }
+        info!("Shutting down...");
+    }).expect("could not set signal handler");
+}
+
+pub fn terminated() -> bool {
+    TERMINATED.load(Ordering::SeqCst)
+}
+
 #[derive(Display, Debug)]
 pub enum Status {
     Loading,
     Ready,
     Running,
+    Paused,
+    Finished,
+    Terminated,
 }
 
 #[derive(Display, PartialEq, Debug, Deserialize)]
-enum Command {
-    SelectTenant(String, usize),    // player_id, tenant_id
+pub(crate) enum Command {
+    SelectTenant(String, TenantId), // player_id, tenant_id
     ReleaseTenant(String),          // player_id
     ReleaseTenants,                 //
-    MoveTenant(String, usize),      // player_id, unit_id
+    MoveTenant(String, UnitId),     // player_id, unit_id
+    Organize(String),               // player_id
+    SelectLandlord(String),         // player_id
+    ReleaseLandlord(String),        // player_id
+    SetRent(String, UnitId, f32),   // player_id, unit_id, rent
+    Maintain(String, UnitId, f32),  // player_id, unit_id, budget
+    SellUnit(String, UnitId),       // player_id, unit_id
     DOMAAdd(String, f32),           // player_id, amount
+    DOMAPledge(String, f32),        // player_id, share of monthly savings
     DOMAPreach(String, f32, bool),  // player_id, amount, trigger
     DOMAConfigure(f32, f32, f32),   // p_dividend, p_rent_share, rent_income_limit
+    DOMAVote(String, Option<usize>, f32, f32), // player_id, neighborhood_id, rent_discount, p_dividend
+    VacancyTaxVote(String, Option<f32>), // player_id, annual rate
     RentFreeze(usize),              // months
     MarketTax(usize),               // months
     Run(usize),                     // steps
     Reset,                          //
+    Resync,                         //
+    NextSession(BTreeMap<String, Value>), // config params
+    Heartbeat(String),              // player_id
+    EndTurn(String),                // player_id
+    SetHandle(String, String),      // player_id, handle
+    Rejoin(String, String),         // player_id, token
+}
+
+// Commands for a player are sent with their token,
+// as `{"token": <token>, "command": <command>}`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Envelope {
+    Signed { token: String, command: Command },
+    Bare(Command),
+}
+
+impl Command {
+    // Commands that steer the whole session,
+    // which only the operator can send
+    fn for_operator(&self) -> bool {
+        matches!(self,
+            Command::ReleaseTenants | Command::DOMAConfigure(..) |
+            Command::RentFreeze(_) | Command::MarketTax(_) |
+            Command::Run(_) | Command::Reset | Command::NextSession(_))
+    }
+
+    // The player the command is for, if any
+    fn player_id(&self) -> Option<&str> {
+        match self {
+            Command::SelectTenant(p_id, _) | Command::ReleaseTenant(p_id) |
+            Command::MoveTenant(p_id, _) | Command::Organize(p_id) |
+            Command::SelectLandlord(p_id) |
+            Command::ReleaseLandlord(p_id) | Command::SetRent(p_id, _, _) |
+            Command::Maintain(p_id, _, _) | Command::SellUnit(p_id, _) |
+            Command::DOMAAdd(p_id, _) | Command::DOMAPledge(p_id, _) |
+            Command::DOMAPreach(p_id, _, _) |
+            Command::DOMAVote(p_id, _, _, _) | Command::VacancyTaxVote(p_id, _) |
+            Command::Heartbeat(p_id) | Command::SetHandle(p_id, _) |
+            Command::EndTurn(p_id) | Command::Rejoin(p_id, _) => Some(p_id),
+            _ => None,
+        }
+    }
+}
+
+// What a player controls, which they
+// can take back with its rejoin token
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+enum Seat {
+    Tenant(TenantId),
+    Landlord(usize),
+}
+
+// Commands for whoever is running the session,
+// on their own channel so that players can't
+// send them and a stuck player can't block them
+#[derive(Display, PartialEq, Debug, Deserialize)]
+enum AdminCommand {
+    Pause,                          // hold between steps
+    Resume,                         // undo Pause or FastForward
+    Advance(usize),                 // steps, without waiting for players
+    FastForward(usize),             // steps at a time, until Resume
+    Abort,                          // end the session and start a new one
+    Narrate(String),                // message for players
+    Scenario(String),               // pack name, for the next session
+}
+
+// Admin commands are signed with the operator's token,
+// as `{"token": <token>, "command": <command>}`
+#[derive(Deserialize)]
+struct AdminEnvelope {
+    token: String,
+    command: AdminCommand,
+}
+
+// A player's standing across sessions,
+// under the handle they play as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Score {
+    pub handle: String,
+    pub role: String,
+    pub best: f32,
+    pub total: f32,
+    pub sessions: usize,
+}
+
+// A player's vote on DOMA's
+// strategy for the next quarter
+#[derive(Debug, Serialize, Deserialize)]
+struct Vote {
+    neighborhood: Option<usize>,
+    rent_discount: f32,
+    p_dividend: f32,
+}
+
+impl Vote {
+    // Shares outside 0-1 aren't counted
+    fn valid(&self) -> bool {
+        (0. ..=1.).contains(&self.rent_discount) && (0. ..=1.).contains(&self.p_dividend)
+    }
+}
+
+// A player's vote on the vacancy tax
+// rate, where None is for no tax
+#[derive(Debug, Serialize, Deserialize)]
+struct PolicyVote {
+    vacancy_tax_rate: Option<f32>,
+}
+
+impl PolicyVote {
+    // Rates outside 0-1 aren't counted
+    fn valid(&self) -> bool {
+        self.vacancy_tax_rate.is_none_or(|rate| (0. ..=1.).contains(&rate))
+    }
+}
+
+// Median of the values, averaging the
+// middle two if there's an even number
+fn median(vals: &mut [f32]) -> f32 {
+    vals.sort_by(|a, b| a.total_cmp(b));
+    let mid = vals.len() / 2;
+    if vals.len().is_multiple_of(2) {
+        (vals[mid - 1] + vals[mid]) / 2.
+    } else {
+        vals[mid]
+    }
+}
+
+// A seat as a player took it, to compare
+// against at the end of the session
+#[derive(Debug, Clone, Copy)]
+struct Start {
+    month: usize,
+    rent_burden: Option<f32>,
+    cash: f32,
+    n_units: usize,
+}
+
+// Share of income going to rent, as in the
+// stats, or None if the tenant isn't housed
+fn rent_burden(tenant: &Tenant, city: &City) -> Option<f32> {
+    let unit = &city.units[tenant.unit?];
+    if tenant.income > 0. {
+        Some(unit.rent / unit.occupancy as f32 / tenant.income)
+    } else {
+        None
+    }
+}
+
+// Where a session is in its phases
+struct Schedule {
+    // Phases still to come, the first being the current
+    // one, with the turns or months left in it
+    phases: VecDeque<Phase>,
+
+    // Whether players take turns until reset,
+    // rather than going through phases
+    open_ended: bool,
+
+    // Whether the session's been told it's over
+    finished: bool,
+}
+
+impl Schedule {
+    fn new(phases: &[Phase]) -> Schedule {
+        Schedule {
+            phases: phases.iter().cloned().collect(),
+            open_ended: phases.is_empty(),
+            finished: false,
+        }
+    }
+
+    fn current(&self) -> Option<Phase> {
+        self.phases.front().cloned()
+    }
+
+    // Months to run next without waiting for
+    // players, if the session is in such a phase
+    fn unattended(&mut self) -> Option<usize> {
+        match self.current()? {
+            Phase::FastFw(months) => {
+                self.phases.pop_front();
+                Some(months)
+            },
+
+            // The epilogue only runs once the
+            // session's finished and been scored
+            Phase::Epilogue(months) if self.finished => {
+                self.phases.pop_front();
+                Some(months)
+            },
+            Phase::Epilogue(_) | Phase::Act(_) => None,
+        }
+    }
+
+    fn end_turn(&mut self) {
+        if let Some(Phase::Act(turns)) = self.phases.front_mut() {
+            if *turns > 1 {
+                *turns -= 1;
+            } else {
+                self.phases.pop_front();
+            }
+        }
+    }
+
+    // Whether every phase but the epilogue has
+    // run, the first time it's asked once they have
+    fn finish(&mut self) -> bool {
+        let played = self.phases.iter().all(|p| matches!(p, Phase::Epilogue(_)));
+        if self.open_ended || self.finished || !played {
+            return false;
+        }
+        self.finished = true;
+        true
+    }
 }
 
 pub enum Control {
     Run(usize),
-    Reset
+    Reset,
+    Resync,
+    Terminate,
+
+    // The session went through all its phases
+    Finish,
 }
 
 pub struct PlayManager {
-    con: Connection,
-    players: HashMap<String, usize>
+    backend: Rc<dyn SyncBackend>,
+    encoding: Encoding,
+    players: HashMap<String, TenantId>,
+
+    // Players controlling landlords,
+    // by landlord id
+    landlord_players: HashMap<String, usize>,
+
+    // Tallies of the last applied votes
+    last_vote: Value,
+    last_policy_vote: Value,
+
+    // Tallies of the votes so far, kept
+    // to show players if the votes can't
+    // be read back, e.g. while Redis is down
+    vote_tally: Value,
+    policy_tally: Value,
+
+    // Config changes for the next session,
+    // e.g. a different design or seed
+    next_session: BTreeMap<String, Value>,
+
+    paused: bool,
+
+    // Steps to run whenever the session would
+    // otherwise wait for players
+    fast_forward: Option<usize>,
+
+    // Control decided mid-run, e.g. an abort,
+    // to act on once the run stops
+    pending: Option<Control>,
+
+    // Paused when the operator advanced the
+    // session, so to pause again after
+    repause: bool,
+
+    // When players were last heard from
+    last_seen: HashMap<String, Instant>,
+
+    // Players aren't expected to send heartbeats
+    // while steps run, so timeouts count from
+    // when the session started waiting for them
+    waiting_since: Instant,
+
+    // Players' tokens, and the seats
+    // each token can rejoin to
+    player_tokens: HashMap<String, String>,
+    tokens: HashMap<String, Vec<Seat>>,
+
+    // Seats as they were first taken this session
+    starts: HashMap<Seat, Start>,
+    schedule: Schedule,
+
+    // Messages from the operator,
+    // not yet in the event log
+    narration: Vec<String>,
+
+    // Names players chose to be listed under on
+    // the leaderboard, kept across sessions.
+    // Otherwise they're listed by player id
+    handles: HashMap<String, String>,
+
+    // Players who have ended their turn,
+    // and when the turn started
+    turn_ended: HashSet<String>,
+    turn_started: Instant,
+    turn_timeout: Option<time::Duration>,
+
+    // Bots, and their own randomness,
+    // so they don't change how the city plays out
+    bots: Vec<Bot>,
+    bot_rng: StdRng,
+
+    // Id of this session, if it's one of several,
+    // to list it in the `sessions` lobby
+    session: Option<String>,
 }
 
 impl PlayManager {
-    pub fn new() -> PlayManager {
-        let client = redis::Client::open("redis://127.0.0.1/1").unwrap();
-        let con = client.get_connection().unwrap();
-
+    pub fn new(backend: Rc<dyn SyncBackend>, encoding: Encoding) -> PlayManager {
         PlayManager {
-            con: con,
-            players: HashMap::new()
+            backend: backend,
+            encoding: encoding,
+            players: HashMap::new(),
+            landlord_players: HashMap::new(),
+            last_vote: Value::Null,
+            last_policy_vote: Value::Null,
+            vote_tally: Value::Null,
+            policy_tally: Value::Null,
+            next_session: BTreeMap::new(),
+            paused: false,
+            fast_forward: None,
+            pending: None,
+            repause: false,
+            last_seen: HashMap::new(),
+            waiting_since: Instant::now(),
+            player_tokens: HashMap::new(),
+            tokens: HashMap::new(),
+            starts: HashMap::new(),
+            schedule: Schedule::new(&[]),
+            narration: Vec::new(),
+            handles: HashMap::new(),
+            turn_ended: HashSet::new(),
+            turn_started: Instant::now(),
+            turn_timeout: None,
+            bots: Vec::new(),
+            bot_rng: SeedableRng::seed_from_u64(0),
+            session: None,
         }
     }
 
-    pub fn gen_player_tenant_pool(&self, tenants: &Vec<Tenant>, city: &City, size: usize) {
-        let mut rng = rand::thread_rng();
-        let tenants = tenants.choose_multiple(&mut rng, size);
-        let _: () = self.con.del("tenants").unwrap();
+    pub fn in_session(mut self, session: &str) -> PlayManager {
+        self.session = Some(session.to_string());
+        self
+    }
+
+    // Config changes queued with `NextSession`,
+    // clearing them
+    pub fn take_next_session(&mut self) -> SyncResult<BTreeMap<String, Value>> {
+        self.backend.del("next_session")?;
+        Ok(std::mem::take(&mut self.next_session))
+    }
+
+    // Changes are checked when they're queued, so
+    // that a bad design or value is rejected rather
+    // than stopping the next session from loading
+    fn queue_next_session(&mut self, params: BTreeMap<String, Value>, conf: &Config) -> Result<(), String> {
+        let mut next_session = self.next_session.clone();
+        next_session.extend(params);
+        let next_conf = conf.with_params(&next_session)?;
+        if next_session.contains_key("DESIGN_ID") {
+            let design_key = format!("design:{}", next_conf.design_id);
+            let design_data = self.backend.get(&design_key)
+                .map_err(|err| err.to_string())?
+                .ok_or_else(|| format!("no design {:?}", next_conf.design_id))?;
+            let design: Design = serde_json::from_str(&design_data).map_err(|err| err.to_string())?;
+            if let Err(errors) = design::validate(&design) {
+                return Err(errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("; "));
+            }
+        }
+        self.backend.set("next_session", serde_json::to_string(&next_session).unwrap().as_bytes())
+            .map_err(|err| err.to_string())?;
+        self.next_session = next_session;
+        Ok(())
+    }
+
+    pub fn gen_player_tenant_pool(&self, tenants: &Arena<TenantId, Tenant>, city: &City, size: usize, rng: &mut StdRng) -> SyncResult<()> {
+        let residents: Vec<&Tenant> = tenants.iter().filter(|t| !t.departed).collect();
+        let tenants = residents.choose_multiple(rng, size);
+        self.backend.del("tenants")?;
 
         // Move tenants into vacant units if necessary
         let vacant_units: Vec<&Unit> = city
-            .units
+            .vacant_units()
             .iter()
-            .filter(|u| u.vacancies() > 0)
+            .map(|&u_id| &city.units[u_id])
             .collect();
 
         for t in tenants {
@@ -98,9 +516,10 @@ impl PlayManager {
                 None => None
             };
 
-            let _: () = self.con.lpush("tenants", json!({
+            self.backend.lpush("tenants", &self.encoding.encode(&json!({
                 "id": t.id,
                 "income": t.income,
+                "savings": t.savings,
                 "work": {
                     "pos": t.work,
                     "neighborhood": work_neighborhood
@@ -110,11 +529,26 @@ impl PlayManager {
                     "id": t.unit,
                     "neighborhood": unit_neighborhood
                 }
-            }).to_string()).unwrap();
+            })))?;
         }
+        Ok(())
     }
 
-    pub fn sync_players(&self, tenants: &Vec<Tenant>, city: &City, doma: &DOMA) -> redis::RedisResult<()> {
+    pub fn sync_players(&mut self, tenants: &Arena<TenantId, Tenant>, landlords: &[Landlord], city: &City, doma: &DOMA, unions: &Unions, conf: &Config) -> SyncResult<()> {
+        match self.tally_votes() {
+            Ok((_, tally)) => self.vote_tally = tally,
+            Err(err) => warn!("Couldn't tally votes, showing the last tally: {}", err),
+        }
+        match self.tally_policy_votes() {
+            Ok((_, tally)) => self.policy_tally = tally,
+            Err(err) => warn!("Couldn't tally policy votes, showing the last tally: {}", err),
+        }
+        let tally = &self.vote_tally;
+        let policy_vote = json!({
+            "tally": self.policy_tally,
+            "result": self.last_policy_vote,
+            "vacancy_tax_rate": conf.policies.vacancy_tax_rate
+        });
         for (player_id, &t_id) in &self.players {
             let tenant = &tenants[t_id];
             let mut adjusted_rent = None;
@@ -127,13 +561,12 @@ impl PlayManager {
             };
             let desirability = match unit {
                 Some(unit) => {
-                    let parcel = &city.parcels.get(&unit.pos).unwrap();
                     adjusted_rent = Some(tenant.adjusted_rent(&unit));
                     unit_neighborhood = match city.neighborhood_for_pos(&unit.pos) {
                         Some(neighb) => Some(&neighb.name),
                         None => None
                     };
-                    tenant.desirability(unit, parcel)
+                    tenant.desirability(unit, city, conf)
                 },
                 None => -1.
             };
@@ -143,15 +576,45 @@ impl PlayManager {
                 None => None
             };
 
-            let key = format!("player:{}:tenant", player_id);
-            self.con.set(key, json!({
+            let key = match self.private_key(player_id, "tenant") {
+                Some(key) => key,
+                None => continue,
+            };
+            self.backend.set(&key, &self.encoding.encode(&json!({
                 "id": t_id,
+                "actions": TENANT_ACTIONS,
                 "income": tenant.income,
+                "savings": tenant.savings,
+                "employed": tenant.employed,
+                "union": unit.and_then(|unit| unions.unions.get(&unit.pos)).map(|union| json!({
+                    "formed": union.formed,
+                    "members": union.members.len(),
+                    "member": union.members.contains(&t_id),
+                    "bargaining": conf.unions.as_ref().is_some_and(|c| union.bargains(c)),
+                    "striking": union.strike.is_some()
+                })),
                 "shares": match doma.shares.get(&t_id) {
                     None => 0.,
                     Some(s) => *s
                 },
                 "dividend": tenant.last_dividend,
+                "pledge": tenant.pledge,
+                "contributed": doma.contributions.get(&t_id).cloned().unwrap_or(0.),
+                "doma_round": {
+                    "number": doma.round.number,
+                    "raised": doma.round.raised,
+                    "target": doma.round.target,
+                    "months_left": doma.round.months_left
+                },
+                "doma_vote": {
+                    "tally": tally,
+                    "result": self.last_vote
+                },
+                "policy_vote": policy_vote,
+                "household": tenant.household,
+                "months_homeless": tenant.months_homeless,
+                "shelter": tenant.shelter.map(|n| &city.neighborhoods[n].name),
+                "voucher": tenant.voucher,
                 "rent": adjusted_rent,
                 "work": {
                     "pos": tenant.work,
@@ -170,164 +633,1399 @@ impl PlayManager {
                     },
                     None => Value::Null
                 }
-            }).to_string())?
+            })))?
+        }
+
+        // Monthly reports for landlord players
+        for (player_id, &l_id) in &self.landlord_players {
+            let landlord = &landlords[l_id];
+            let units: Vec<Value> = landlord.units.iter().map(|&u_id| {
+                let unit = &city.units[u_id];
+                json!({
+                    "id": u_id,
+                    "rent": unit.rent,
+                    "asking_rent": landlord.player_rents.get(&u_id),
+                    "tenants": unit.tenants.len(),
+                    "occupancy": unit.occupancy,
+                    "months_vacant": unit.months_vacant,
+                    "condition": unit.condition,
+                    "maintenance": landlord.unit_maintenance.get(&u_id),
+                    "net_income": landlord.net_income.get(&u_id),
+                    "for_sale": landlord.for_sale.contains(&u_id),
+                    "best_offer": unit.offers.iter().map(|&(_, _, amount)| amount).fold(None, |best: Option<f32>, amount| {
+                        Some(best.map_or(amount, |b| f32::max(b, amount)))
+                    }),
+                    "pos": unit.pos,
+                    "neighborhood": city.neighborhood_for_pos(&unit.pos).map(|neighb| &neighb.name)
+                })
+            }).collect();
+
+            let key = match self.private_key(player_id, "landlord") {
+                Some(key) => key,
+                None => continue,
+            };
+            self.backend.set(&key, &self.encoding.encode(&json!({
+                "id": l_id,
+                "actions": LANDLORD_ACTIONS,
+                "cash": landlord.cash,
+                "debt": landlord.mortgages.iter().fold(0., |acc, m| acc + m.principal),
+                "income": landlord.net_income.values().sum::<f32>(),
+                "taxes": landlord.taxes_paid,
+                "vacancy_tax": landlord.vacancy_tax_paid,
+                "policy_vote": policy_vote,
+                "n_vacant": landlord.units.iter().filter(|&&u_id| city.units[u_id].vacant()).count(),
+                "units": units
+            })))?
         }
         Ok(())
     }
 
-    pub fn sync_step(&self, step: usize, steps: usize) -> redis::RedisResult<()> {
-        self.con.set("step", step)?;
-        self.con.set("step", step as f32/steps as f32)
+    // A player's own state is kept under their token,
+    // so only they can find it
+    fn private_key(&self, p_id: &str, kind: &str) -> Option<String> {
+        self.player_tokens.get(p_id).map(|token| format!("player:{}:{}", token, kind))
     }
 
-    fn set_status(&self, state: Status) -> redis::RedisResult<()> {
-        self.con.set("status", state.to_string().to_lowercase())?;
+    fn del_private_keys(&self, p_id: &str) -> SyncResult<()> {
+        for kind in &["tenant", "landlord"] {
+            if let Some(key) = self.private_key(p_id, kind) {
+                self.backend.del(&key)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn set_ready(&self) -> redis::RedisResult<()> {
+    pub fn sync_step(&self, step: usize, steps: usize) -> SyncResult<()> {
+        self.backend.set("step", step.to_string().as_bytes())?;
+        self.backend.set("step", (step as f32/steps as f32).to_string().as_bytes())
+    }
+
+    fn set_status(&self, state: Status) -> SyncResult<()> {
+        let status = state.to_string().to_lowercase();
+        self.backend.set("status", status.as_bytes())?;
+        if let Some(session) = &self.session {
+            let entry = json!({
+                "status": status,
+                "players": self.players.len() + self.landlord_players.len(),
+            });
+            self.backend.hset("sessions", session, entry.to_string().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn set_ready(&self) -> SyncResult<()> {
         self.set_status(Status::Ready)
     }
 
-    pub fn set_running(&self) -> redis::RedisResult<()> {
+    pub fn set_running(&self) -> SyncResult<()> {
         self.set_status(Status::Running)
     }
 
-    pub fn set_loading(&self) -> redis::RedisResult<()> {
+    pub fn set_loading(&self) -> SyncResult<()> {
         self.set_status(Status::Loading)
     }
 
-    pub fn reset(&mut self) -> redis::RedisResult<()> {
+    // Publish the session's outcomes for the
+    // endgame screen, with the leaderboard
+    pub fn set_finished(&self, summary: &Value) -> SyncResult<()> {
+        let mut results = summary.clone();
+        results["leaderboard"] = json!({
+            "tenant": self.top_scores("tenant", TOP_SCORES)?,
+            "landlord": self.top_scores("landlord", TOP_SCORES)?
+        });
+        self.backend.set("results", &self.encoding.encode(&results))?;
+        self.set_status(Status::Finished)
+    }
+
+    // Add players' scores from a session's
+    // summary to their standings
+    pub fn record_scores(&self, summary: &Value) -> SyncResult<()> {
+        let mut scores = self.scores()?;
+        for (p_id, player) in summary["players"].as_object().into_iter().flatten() {
+            let (role, score) = match (player["type"].as_str(), player["score"].as_f64()) {
+                (Some(role), Some(score)) => (role, score as f32),
+                _ => continue,
+            };
+            let handle = self.handles.get(p_id).unwrap_or(p_id);
+            let field = format!("{}:{}", role, handle);
+            let entry = scores.entry(field.clone()).or_insert_with(|| Score {
+                handle: handle.clone(),
+                role: role.to_string(),
+                best: score,
+                total: 0.,
+                sessions: 0,
+            });
+            entry.best = f32::max(entry.best, score);
+            entry.total += score;
+            entry.sessions += 1;
+            self.backend.hset("leaderboard", &field, serde_json::to_string(entry).unwrap().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // The best scores for a kind
+    // of player, across sessions
+    pub fn top_scores(&self, role: &str, n: usize) -> SyncResult<Vec<Score>> {
+        let mut top: Vec<Score> = self.scores()?.into_values()
+            .filter(|score| score.role == role)
+            .collect();
+        top.sort_by(|a, b| b.best.total_cmp(&a.best));
+        top.truncate(n);
+        Ok(top)
+    }
+
+    fn scores(&self) -> SyncResult<HashMap<String, Score>> {
+        let scores_raw: HashMap<String, String> = self.backend.hgetall("leaderboard")?;
+        Ok(scores_raw.into_iter()
+            .filter_map(|(field, score)| Some((field, serde_json::from_str(&score).ok()?)))
+            .collect())
+    }
+
+    // How the session turned out, citywide since `start`
+    // (the stats as the session began) and for each player
+    // since they took their seat
+    pub fn summarize(&self, sim: &Simulation, events: &EventLog, start: &Value) -> Value {
+        let end = stats::stats(sim);
+        let change = |start: &Value, end: &Value, key: &str| json!({
+            "start": start[key],
+            "end": end[key]
+        });
+        let count = |since: usize, is_match: &dyn Fn(&SimEvent) -> bool| {
+            events.events.iter().filter(|e| e.month >= since && is_match(&e.event)).count()
+        };
+
+        // Neighborhoods where rent burdens
+        // didn't rise over the session
+        let stabilized: Vec<&str> = sim.city.neighborhoods.iter().enumerate()
+            .filter(|(id, _)| {
+                let key = id.to_string();
+                let ratio = |stats: &Value| stats["neighborhoods"][&key]["median_rent_income_ratio"].as_f64();
+                match (ratio(start), ratio(&end)) {
+                    (Some(start), Some(end)) => end <= start,
+                    _ => false,
+                }
+            })
+            .map(|(_, neighb)| neighb.name.as_str())
+            .collect();
+
+        let mut players = Map::new();
+        for (p_id, &t_id) in &self.players {
+            let start = match self.starts.get(&Seat::Tenant(t_id)) {
+                Some(start) => start,
+                None => continue,
+            };
+            let tenant = &sim.tenants[t_id];
+            let shares = sim.doma.shares.get(&t_id).cloned().unwrap_or(0.);
+            players.insert(p_id.clone(), json!({
+                "type": "tenant",
+                "id": t_id,
+                "months": sim.time - start.month,
+                "rent_burden": {
+                    "start": start.rent_burden,
+                    "end": rent_burden(tenant, &sim.city)
+                },
+                "savings": {
+                    "start": start.cash,
+                    "end": tenant.savings
+                },
+                "times_displaced": count(start.month, &|e| matches!(e, SimEvent::Displaced { tenant, .. } if *tenant == t_id)),
+                "times_evicted": count(start.month, &|e| matches!(e, SimEvent::Evicted { tenant, .. } if *tenant == t_id)),
+                "doma_shares": shares,
+                "doma_contributed": sim.doma.contributions.get(&t_id).cloned().unwrap_or(0.),
+                "score": tenant.savings - start.cash + shares
+            }));
+        }
+        for (p_id, &l_id) in &self.landlord_players {
+            let start = match self.starts.get(&Seat::Landlord(l_id)) {
+                Some(start) => start,
+                None => continue,
+            };
+            let landlord = &sim.landlords[l_id];
+            players.insert(p_id.clone(), json!({
+                "type": "landlord",
+                "id": l_id,
+                "months": sim.time - start.month,
+                "units": {
+                    "start": start.n_units,
+                    "end": landlord.units.len()
+                },
+                "cash": {
+                    "start": start.cash,
+                    "end": landlord.cash
+                },
+                "evictions": count(start.month, &|e| match e {
+                    SimEvent::Evicted { unit, .. } => sim.city.units[*unit].owner == (AgentType::Landlord, l_id),
+                    _ => false,
+                }),
+                "score": landlord.cash - start.cash
+            }));
+        }
+
+        json!({
+            "time": sim.time,
+            "city": {
+                "median_rent_income_ratio": change(start, &end, "median_rent_income_ratio"),
+                "p_rent_burdened": change(start, &end, "p_rent_burdened"),
+                "doma_units": change(start, &end, "doma_units"),
+                "doma_members": change(start, &end, "doma_members"),
+                "displaced": count(0, &|e| matches!(e, SimEvent::Displaced { .. })),
+                "evicted": count(0, &|e| matches!(e, SimEvent::Evicted { .. })),
+                "neighborhoods_stabilized": stabilized
+            },
+            "players": players
+        })
+    }
+
+    // Remove players' state so the frontend
+    // doesn't wait on a session that's gone
+    pub fn terminate(&mut self) -> SyncResult<()> {
+        for player_id in self.player_tokens.keys() {
+            self.del_private_keys(player_id)?;
+        }
+        self.players.clear();
+        self.landlord_players.clear();
+        self.last_seen.clear();
+        self.player_tokens.clear();
+        self.tokens.clear();
+        self.backend.del("tenants")?;
+        self.backend.del("doma_votes")?;
+        self.backend.del("policy_votes")?;
+        self.backend.del("cmds")?;
+        self.backend.del("admin_cmds")?;
+        self.backend.del("turn")?;
+        self.set_status(Status::Terminated)
+    }
+
+    // Count up DOMA votes. The target neighborhood
+    // is decided by plurality, and rent discount and
+    // dividend split by the median vote. Votes stand
+    // until changed, but only count while the player
+    // is still playing.
+    // Returns the winning vote, if any, and the tally
+    fn tally_votes(&self) -> SyncResult<(Option<Vote>, Value)> {
+        let votes_raw: HashMap<String, String> = self.backend.hgetall("doma_votes")?;
+        let votes: Vec<Vote> = votes_raw
+            .iter()
+            .filter(|(p_id, _)| self.players.contains_key(*p_id))
+            .filter_map(|(_, v)| serde_json::from_str::<Vote>(v).ok())
+            .filter(|v| v.valid())
+            .collect();
+        if votes.is_empty() {
+            return Ok((None, json!({ "n_votes": 0 })));
+        }
+
+        let mut neighborhoods: BTreeMap<Option<usize>, usize> = BTreeMap::new();
+        for v in &votes {
+            *neighborhoods.entry(v.neighborhood).or_insert(0) += 1;
+        }
+        let (neighborhood, _) = neighborhoods.iter().fold((None, 0), |acc, (&neighb, &count)| {
+            if count > acc.1 { (neighb, count) } else { acc }
+        });
+
+        let rent_discount = median(&mut votes.iter().map(|v| v.rent_discount).collect::<Vec<_>>());
+        let p_dividend = median(&mut votes.iter().map(|v| v.p_dividend).collect::<Vec<_>>());
+
+        let tally = json!({
+            "n_votes": votes.len(),
+            "neighborhoods": neighborhoods.iter().map(|(neighb, count)| {
+                json!({ "id": neighb, "votes": count })
+            }).collect::<Vec<Value>>(),
+            "winner": {
+                "neighborhood": neighborhood,
+                "rent_discount": rent_discount,
+                "p_dividend": p_dividend
+            }
+        });
+        let winner = Vote {
+            neighborhood: neighborhood,
+            rent_discount: rent_discount,
+            p_dividend: p_dividend,
+        };
+        Ok((Some(winner), tally))
+    }
+
+    // Count up policy votes. The vacancy tax is
+    // set by the median vote, counting votes for
+    // no tax as zero, and only counting players,
+    // tenants or landlords, who are still playing.
+    // Returns the winning vote, if any, and the tally
+    fn tally_policy_votes(&self) -> SyncResult<(Option<PolicyVote>, Value)> {
+        let votes_raw: HashMap<String, String> = self.backend.hgetall("policy_votes")?;
+        let players = self.players();
+        let mut rates: Vec<f32> = votes_raw
+            .iter()
+            .filter(|(p_id, _)| players.contains(p_id))
+            .filter_map(|(_, v)| serde_json::from_str::<PolicyVote>(v).ok())
+            .filter(|v| v.valid())
+            .map(|v| v.vacancy_tax_rate.unwrap_or(0.))
+            .collect();
+        if rates.is_empty() {
+            return Ok((None, json!({ "n_votes": 0 })));
+        }
+        let median = median(&mut rates);
+        let winner = PolicyVote {
+            vacancy_tax_rate: if median > 0. { Some(median) } else { None },
+        };
+        let tally = json!({
+            "n_votes": rates.len(),
+            "n_for_tax": rates.iter().filter(|&&r| r > 0.).count(),
+            "winner": winner
+        });
+        Ok((Some(winner), tally))
+    }
+
+    // At the end of each quarter apply
+    // the winning votes and start new ones
+    pub fn apply_votes(&mut self, sim: &mut Simulation) -> SyncResult<()> {
+        if !sim.time.is_multiple_of(sim.conf.steps(VOTE_INTERVAL_MONTHS)) {
+            return Ok(());
+        }
+        let (winner, tally) = self.tally_votes()?;
+        if let Some(vote) = winner {
+            debug!("Applying DOMA vote {:?}", vote);
+            let doma = &mut sim.doma;
+            // Votes are checked when cast, but
+            // boundaries can be redrawn since
+            match vote.neighborhood {
+                Some(id) => match sim.city.neighborhoods.iter().position(|n| n.id as usize == id) {
+                    Some(idx) => doma.target_neighborhood = Some(idx),
+                    None => warn!("DOMA vote for unknown neighborhood {:?}, keeping the current target", id),
+                },
+                None => doma.target_neighborhood = None,
+            }
+            doma.rent_discount = vote.rent_discount.clamp(0., 1.);
+            let p_dividend = vote.p_dividend.clamp(0., 1. - doma.p_expenses);
+            doma.p_reserves = 1.0 - p_dividend - doma.p_expenses;
+            self.last_vote = tally;
+        }
+
+        let (winner, tally) = self.tally_policy_votes()?;
+        if let Some(vote) = winner {
+            debug!("Applying policy vote {:?}", vote);
+            sim.conf.policies.vacancy_tax_rate = vote.vacancy_tax_rate;
+            self.last_policy_vote = tally;
+        }
+        self.backend.del("policy_votes")
+    }
+
+    pub fn reset(&mut self) -> SyncResult<()> {
         self.players.clear();
-        self.con.del("game_step")?;
-        self.con.del("cmds")
+        self.landlord_players.clear();
+        self.last_vote = Value::Null;
+        self.last_policy_vote = Value::Null;
+        self.vote_tally = Value::Null;
+        self.policy_tally = Value::Null;
+        self.paused = false;
+        self.fast_forward = None;
+        self.pending = None;
+        self.last_seen.clear();
+        self.player_tokens.clear();
+        self.tokens.clear();
+        self.starts.clear();
+        self.schedule = Schedule::new(&[]);
+        self.turn_ended.clear();
+        self.bots.clear();
+        self.backend.del("turn")?;
+        self.backend.del("doma_votes")?;
+        self.backend.del("policy_votes")?;
+        self.backend.del("game_step")?;
+        self.backend.del("cmds")
+    }
+
+    // Log operator messages sent since last
+    // time, and send players every message so far
+    pub fn narrate(&mut self, month: usize, events: &mut EventLog) -> SyncResult<()> {
+        if self.narration.is_empty() {
+            return Ok(());
+        }
+        let narration: Vec<SimEvent> = self.narration.drain(..)
+            .map(|message| SimEvent::Narration { message: message })
+            .collect();
+        events.record(month, &narration);
+        self.backend.set("narration", &self.encoding.encode(&events.narration()))
+    }
+
+    // Go through these phases this session
+    pub fn set_schedule(&mut self, phases: &[Phase]) {
+        self.schedule = Schedule::new(phases);
     }
 
-    pub fn wait_for_control(&mut self, sim: &mut Simulation, rng: &mut StdRng) -> Control {
+    pub fn wait_for_control(&mut self, sim: &mut Simulation, events: &mut EventLog, rng: &mut StdRng) -> Control {
+        let _span = info_span!("play", month = sim.time).entered();
         let ms = time::Duration::from_millis(COMMAND_INTERVAL_MS);
+        let timeout = time::Duration::from_secs(sim.conf.player_timeout_secs);
+        self.waiting_since = Instant::now();
+        if self.repause {
+            self.repause = false;
+            self.paused = true;
+            if let Err(err) = self.set_status(Status::Paused) {
+                warn!("Couldn't set status: {}", err);
+            }
+        }
+        self.start_turn(sim.conf.turn_timeout_secs.map(time::Duration::from_secs));
+        for bot in &mut self.bots {
+            bot.plan(sim, &sim.conf.bots, &mut self.bot_rng);
+        }
+        let mut was_paused = false;
+        if let Some(months) = self.schedule.unattended() {
+            return Control::Run(sim.conf.steps(months));
+        }
+        if self.schedule.finish() {
+            return Control::Finish;
+        }
         loop {
-            let control = self.process_commands(sim, rng);
-            match control {
-                Some(ctrl) => return ctrl,
-                None => {
-                    thread::sleep(ms);
-                    continue
+            if terminated() {
+                return Control::Terminate;
+            }
+            metrics().tick(session_label(self.session.as_deref()));
+            metrics().record_players(session_label(self.session.as_deref()), self.players().len(), self.turn_ended.len());
+            if let Err(err) = self.release_idle_players(sim, timeout) {
+                warn!("Couldn't release idle players: {}", err);
+            }
+            if let Some(ctrl) = self.pending.take() {
+                return ctrl;
+            }
+            if let Some(ctrl) = self.process_admin_commands(false, &sim.conf) {
+                return ctrl;
+            }
+            if let Err(err) = self.narrate(sim.time, events) {
+                warn!("Couldn't send narration: {}", err);
+            }
+
+            // Player commands, and the turn timer,
+            // wait while paused
+            if self.paused {
+                was_paused = true;
+            } else {
+                if was_paused {
+                    was_paused = false;
+                    self.restart_turn_timer();
                 }
+                if let Some(ctrl) = self.process_commands(sim, rng) {
+                    return ctrl;
+                }
+                if let Some(steps) = self.fast_forward {
+                    return Control::Run(steps);
+                }
+                // Once the session's over, it only
+                // waits to be reset
+                if !self.schedule.finished {
+                    self.run_bots(sim, rng);
+                    if self.turn_over() {
+                        self.schedule.end_turn();
+                        return Control::Run(sim.conf.turn_steps);
+                    }
+                }
+            }
+            thread::sleep(ms);
+        }
+    }
+
+    // Bots join as tenants who
+    // aren't already players
+    pub fn seat_bots(&mut self, sim: &mut Simulation, rng: &mut StdRng) {
+        self.bot_rng = SeedableRng::seed_from_u64(sim.conf.seed);
+        let candidates: Vec<TenantId> = sim.tenants.iter()
+            .filter(|t| !t.departed && !t.player)
+            .map(|t| t.id)
+            .collect();
+        let chosen: Vec<TenantId> = candidates.choose_multiple(&mut self.bot_rng, sim.conf.bots.n).cloned().collect();
+        for (i, t_id) in chosen.into_iter().enumerate() {
+            let mut bot = Bot::new(i, t_id);
+            let cmd = Command::SelectTenant(bot.id.clone(), t_id);
+            self.handle_command(cmd, Some(bot.token.clone()), sim, rng);
+
+            // Bots go on with the token they're issued
+            if let Some(token) = self.player_tokens.get(&bot.id) {
+                bot.token = token.clone();
+                self.bots.push(bot);
             }
         }
     }
 
+    fn run_bots(&mut self, sim: &mut Simulation, rng: &mut StdRng) {
+        let mut due = Vec::new();
+        for bot in &mut self.bots {
+            for cmd in bot.due() {
+                due.push((bot.token.clone(), cmd));
+            }
+        }
+        for (token, cmd) in due {
+            // Bots choose where to move when they plan,
+            // so another may have taken the unit since
+            if let Command::MoveTenant(p_id, u_id) = &cmd {
+                if !sim.city.has_vacancy(*u_id) {
+                    debug!("Bot {:?} skipped moving into taken unit {:?}", p_id, u_id);
+                    continue;
+                }
+            }
+            self.handle_command(cmd, Some(token), sim, rng);
+        }
+    }
+
+    fn players(&self) -> HashSet<&String> {
+        self.players.keys().chain(self.landlord_players.keys()).collect()
+    }
+
+    fn start_turn(&mut self, timeout: Option<time::Duration>) {
+        self.turn_ended.clear();
+        self.turn_timeout = timeout;
+        self.restart_turn_timer();
+        debug!("Turn started, waiting on {:?}", self.players());
+    }
+
+    fn restart_turn_timer(&mut self) {
+        self.turn_started = Instant::now();
+        if let Err(err) = self.sync_turn() {
+            warn!("Couldn't sync turn: {}", err);
+        }
+    }
+
+    // Which players have ended their turn, and how long
+    // the rest have left, for the frontend's timer
+    fn sync_turn(&self) -> SyncResult<()> {
+        let remaining = self.turn_timeout.map(|timeout| timeout.saturating_sub(self.turn_started.elapsed()).as_secs_f32().ceil() as u64);
+        self.backend.set("turn", serde_json::to_string(&json!({
+            "ended": self.turn_ended,
+            "remaining_secs": remaining,
+            "phase": self.schedule.current(),
+        })).unwrap().as_bytes())
+    }
+
+    // The turn is over once every player has ended
+    // theirs. When time's up, it's ended for those
+    // who haven't, without doing anything for them
+    fn turn_over(&mut self) -> bool {
+        let players = self.players();
+        if players.is_empty() {
+            return false;
+        }
+        let waiting: Vec<String> = players.into_iter()
+            .filter(|p_id| !self.turn_ended.contains(*p_id))
+            .cloned()
+            .collect();
+        if let Some(timeout) = self.turn_timeout {
+            if self.turn_started.elapsed() > timeout {
+                for p_id in waiting {
+                    info!("Turn timed out for {:?}", p_id);
+                    self.turn_ended.insert(p_id);
+                }
+                return true;
+            }
+        }
+        waiting.is_empty()
+    }
+
+    // Called between steps of a run. Holds while
+    // the session is paused, and returns true if
+    // the run should stop early
+    pub fn hold_between_steps(&mut self, conf: &Config) -> bool {
+        let ms = time::Duration::from_millis(COMMAND_INTERVAL_MS);
+        loop {
+            if terminated() {
+                return true;
+            }
+            metrics().tick(session_label(self.session.as_deref()));
+            if let Some(ctrl) = self.process_admin_commands(true, conf) {
+                self.pending = Some(ctrl);
+                return true;
+            }
+            if !self.paused {
+                return false;
+            }
+            thread::sleep(ms);
+        }
+    }
+
+    fn process_admin_commands(&mut self, running: bool, conf: &Config) -> Option<Control> {
+        loop {
+            let cmd_raw = match self.backend.lpop("admin_cmds") {
+                Ok(cmd_raw) => cmd_raw?,
+                Err(err) => {
+                    warn!("Couldn't read admin commands: {}", err);
+                    return None;
+                }
+            };
+            let cmd = match serde_json::from_str::<AdminEnvelope>(&cmd_raw) {
+                Ok(envelope) => match authorize_operator(Some(&envelope.token), &conf.sync) {
+                    Ok(()) => envelope.command,
+                    Err(err) => {
+                        warn!("Rejected admin {}: {}", envelope.command, err);
+                        continue;
+                    }
+                },
+                Err(err) => {
+                    warn!("Invalid admin command {:?}: {}", cmd_raw, err);
+                    continue;
+                }
+            };
+            info!("Admin: {}", cmd);
+            let status = match cmd {
+                AdminCommand::Pause => {
+                    self.paused = true;
+                    Status::Paused
+                },
+                AdminCommand::Resume => {
+                    self.paused = false;
+                    self.fast_forward = None;
+                    if running { Status::Running } else { Status::Ready }
+                },
+                AdminCommand::Advance(steps) => {
+                    if running {
+                        info!("Already running");
+                        continue;
+                    }
+                    self.repause = self.paused;
+                    self.paused = false;
+                    return Some(Control::Run(steps));
+                },
+                AdminCommand::FastForward(steps) => {
+                    self.paused = false;
+                    self.fast_forward = Some(steps);
+                    if running { Status::Running } else { Status::Ready }
+                },
+                AdminCommand::Abort => return Some(Control::Reset),
+                AdminCommand::Narrate(message) => {
+                    self.narration.push(message);
+                    continue;
+                },
+                AdminCommand::Scenario(name) => {
+                    let queued = scenario::load_pack(&name)
+                        .and_then(|pack| self.queue_next_session(pack.params(), conf));
+                    if let Err(err) = queued {
+                        warn!("Couldn't queue scenario {:?}: {}", name, err);
+                    }
+                    continue;
+                },
+            };
+            if let Err(err) = self.set_status(status) {
+                warn!("Couldn't set status: {}", err);
+            }
+        }
+    }
+
+    // Players who haven't been heard from in a while
+    // are released, keeping their tokens' seats to rejoin
+    fn release_idle_players(&mut self, sim: &mut Simulation, timeout: time::Duration) -> SyncResult<()> {
+        let waiting_since = self.waiting_since;
+        let idle: Vec<String> = self.last_seen.iter()
+            .filter(|(_, &seen)| seen.max(waiting_since).elapsed() > timeout)
+            .map(|(p_id, _)| p_id.clone())
+            .collect();
+        for p_id in idle {
+            info!("Player timed out: {:?}", p_id);
+            self.del_private_keys(&p_id)?;
+            self.last_seen.remove(&p_id);
+            self.player_tokens.remove(&p_id);
+            if let Some(t_id) = self.players.remove(&p_id) {
+                sim.tenants[t_id].player = false;
+            }
+            if let Some(l_id) = self.landlord_players.remove(&p_id) {
+                sim.landlords[l_id].release_player();
+            }
+        }
+        Ok(())
+    }
+
+    // Commands for a player must bear their token,
+    // and commands for the session the operator's.
+    // Joining is sent with the player's registration key,
+    // and rejoining carries its own token
+    fn authorize(&self, cmd: &Command, token: Option<&str>, conf: &SyncConfig) -> Result<(), &'static str> {
+        if cmd.for_operator() {
+            return authorize_operator(token, conf);
+        }
+
+        // Players resync when they miss a patch
+        if let Command::Resync = cmd {
+            let token = token.ok_or("no token")?;
+            return match self.player_tokens.values().any(|t| t == token) {
+                true => Ok(()),
+                false => authorize_operator(Some(token), conf),
+            };
+        }
+        let p_id = cmd.player_id().ok_or("not a player")?;
+        if let Command::Rejoin(..) = cmd {
+            return Ok(());
+        }
+        let token = token.ok_or("no token")?;
+        match (self.player_tokens.get(p_id), cmd) {
+            (Some(t), _) if t == token => Ok(()),
+            (Some(_), _) => Err("wrong token"),
+            (None, Command::SelectTenant(..)) | (None, Command::SelectLandlord(..)) => {
+                if token.len() >= MIN_TOKEN_LENGTH {
+                    Ok(())
+                } else {
+                    Err("token too short")
+                }
+            },
+            (None, _) => Err("not a player"),
+        }
+    }
+
+    // New players are issued a token, published under
+    // the key they registered with so only they find it.
+    // Tokens don't come from the seeded rng,
+    // which anyone with the config could replay
+    fn issue_token(&self, p_id: &str, key: &str) -> SyncResult<String> {
+        if let Some(token) = self.player_tokens.get(p_id) {
+            return Ok(token.clone());
+        }
+        let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(TOKEN_LENGTH).collect();
+        self.backend.set(&format!("player:{}:token", key), token.as_bytes())?;
+        Ok(token)
+    }
+
+    // A new player takes a seat, so earlier
+    // players' tokens no longer rejoin to it
+    fn bind_seat(&mut self, p_id: &str, token: &str, seat: Seat) {
+        for seats in self.tokens.values_mut() {
+            seats.retain(|s| *s != seat);
+        }
+        self.tokens.entry(token.to_string()).or_default().push(seat);
+        self.player_tokens.insert(p_id.to_string(), token.to_string());
+        self.last_seen.insert(p_id.to_string(), Instant::now());
+    }
+
+    // A player leaving for good gives up their seat
+    fn unbind_seat(&mut self, p_id: &str, seat: Seat) -> SyncResult<()> {
+        for seats in self.tokens.values_mut() {
+            seats.retain(|s| *s != seat);
+        }
+        if !self.players.contains_key(p_id) && !self.landlord_players.contains_key(p_id) {
+            self.del_private_keys(p_id)?;
+            self.player_tokens.remove(p_id);
+            self.last_seen.remove(p_id);
+        }
+        Ok(())
+    }
+
+    // Anyone holding the token's seats has the same
+    // token, so is the same player on an earlier connection
+    fn rejoin(&mut self, p_id: String, token: &str, sim: &mut Simulation) -> bool {
+        let seats = match self.tokens.get(token) {
+            Some(seats) if !seats.is_empty() => seats.clone(),
+            _ => return false,
+        };
+        for &seat in &seats {
+            let earlier: Vec<String> = match seat {
+                Seat::Tenant(t_id) => self.players.iter().filter(|(_, &t)| t == t_id).map(|(p, _)| p.clone()).collect(),
+                Seat::Landlord(l_id) => self.landlord_players.iter().filter(|(_, &l)| l == l_id).map(|(p, _)| p.clone()).collect(),
+            };
+            for p in earlier {
+                match seat {
+                    Seat::Tenant(_) => { self.players.remove(&p); }
+                    Seat::Landlord(_) => { self.landlord_players.remove(&p); }
+                }
+                // Their state is under the same token, so is kept
+                if !self.players.contains_key(&p) && !self.landlord_players.contains_key(&p) {
+                    self.player_tokens.remove(&p);
+                    self.last_seen.remove(&p);
+                }
+            }
+            match seat {
+                Seat::Tenant(t_id) => {
+                    sim.households.leave(t_id, &mut sim.tenants, &mut sim.city, &sim.conf);
+                    let tenant = &mut sim.tenants[t_id];
+                    tenant.player = true;
+
+                    // They may have left the city
+                    // while the AI had them
+                    tenant.departed = false;
+                    tenant.months_homeless = 0;
+                    self.players.insert(p_id.clone(), t_id);
+                },
+                Seat::Landlord(l_id) => {
+                    sim.landlords[l_id].player = true;
+                    self.landlord_players.insert(p_id.clone(), l_id);
+                }
+            }
+        }
+        self.player_tokens.insert(p_id.clone(), token.to_string());
+        self.last_seen.insert(p_id, Instant::now());
+        true
+    }
+
+    // The landlord a player controls,
+    // if they own the given unit
+    fn player_landlord<'a>(&self, player_id: &str, unit_id: UnitId, sim: &'a mut Simulation) -> Option<&'a mut Landlord> {
+        let &l_id = self.landlord_players.get(player_id)?;
+        let landlord = &mut sim.landlords[l_id];
+        if landlord.units.contains(&unit_id) {
+            Some(landlord)
+        } else {
+            None
+        }
+    }
+
     pub fn process_commands(&mut self, sim: &mut Simulation, rng: &mut StdRng) -> Option<Control> {
         let mut control = None;
         loop {
-            let cmd_raw: Option<String> = self.con.lpop("cmds").unwrap();
+            let cmd_raw = match self.backend.lpop("cmds") {
+                Ok(cmd_raw) => cmd_raw,
+                Err(err) => {
+                    warn!("Couldn't read commands: {}", err);
+                    None
+                }
+            };
             match cmd_raw {
                 None => break,
-                Some(cmd) => {
-                    match serde_json::from_str(&cmd).unwrap() {
-                        Command::SelectTenant(p_id, t_id) => {
-                            println!("Player joined: {:?}", p_id);
-                            self.players.insert(p_id, t_id);
-                            let tenant = &mut sim.tenants[t_id];
-                            tenant.player = true;
-
-                            // Evict from existing unit, if any
-                            match tenant.unit {
-                                Some(_u_id) => {
-                                    let unit = &mut sim.city.units[_u_id];
-                                    unit.tenants.remove(&t_id);
-                                    tenant.unit = None;
-                                },
-                                None => {}
-                            }
+                Some(cmd_raw) => {
+                    let (signed_token, cmd) = match serde_json::from_str(&cmd_raw) {
+                        Ok(Envelope::Signed { token, command }) => (Some(token), command),
+                        Ok(Envelope::Bare(command)) => (None, command),
+                        Err(err) => {
+                            warn!("Invalid command {:?}: {}", cmd_raw, err);
+                            continue;
+                        }
+                    };
+                    if let Some(ctrl) = self.handle_command(cmd, signed_token, sim, rng) {
+                        control = Some(ctrl);
+                    }
+                }
+            }
+        }
+        control
+    }
 
-                            // Reset tenant DOMA shares
-                            sim.doma.shares.insert(t_id, 0.);
-                        },
-                        Command::ReleaseTenant(p_id) => {
-                            println!("Player left: {:?}", p_id);
-                            match self.players.remove(&p_id) {
-                                Some(t_id) => {
-                                    sim.tenants[t_id].player = false;
-                                },
-                                None => {}
-                            }
-                        },
-                        Command::ReleaseTenants => {
-                            for t in &mut sim.tenants {
-                                t.player = false;
-                            }
-                        },
-                        Command::MoveTenant(p_id, u_id) => {
-                            println!("Player {:?} moving to: {:?}", p_id, u_id);
-                            match self.players.get(&p_id) {
-                                Some(&t_id) => {
-                                    let tenant = &mut sim.tenants[t_id];
-                                    match tenant.unit {
-                                        Some(_u_id) => {
-                                            let unit = &mut sim.city.units[_u_id];
-                                            unit.tenants.remove(&t_id);
-                                        },
-                                        None => {}
-                                    }
-                                    let unit = &mut sim.city.units[u_id];
-                                    unit.tenants.insert(t_id);
-                                    tenant.unit = Some(u_id);
-                                },
-                                None => {}
-                            }
-                        },
-                        Command::DOMAAdd(p_id, amount) => {
-                            println!("Player {:?} adding {:?} to DOMA", p_id, amount);
-                            match self.players.get(&p_id) {
-                                Some(&t_id) => {
-                                    sim.doma.add_funds(t_id, amount);
-                                },
-                                None => {}
-                            }
-                        },
-                        Command::DOMAPreach(p_id, amount, trigger) => {
-                            println!("Player {:?} preaching {:?}", p_id, amount);
-                            match self.players.get(&p_id) {
-                                Some(&tenant_id) => {
-                                    sim.conf.encounter_rate = f32::min(sim.conf.encounter_rate + amount, 0.75);
-                                    sim.conf.base_contribute_prob = f32::min(sim.conf.base_contribute_prob + amount, 0.75);
-                                    sim.conf.base_contribute_percent = f32::min(sim.conf.base_contribute_percent + amount, 0.20);
-                                    if trigger {
-                                        let infected = sim.social_graph.contagion(tenant_id, sim.conf.encounter_rate, sim.conf.transmission_rate, sim.conf.max_contagion_depth, rng);
-                                        for t_id in infected {
-                                            let t = &sim.tenants[t_id];
-                                            sim.doma.add_funds(t_id, sim.conf.base_contribute_percent * t.income);
-                                        }
-                                    }
-                                },
-                                None => {}
+    fn handle_command(&mut self, cmd: Command, signed_token: Option<String>, sim: &mut Simulation, rng: &mut StdRng) -> Option<Control> {
+        if let Err(err) = self.authorize(&cmd, signed_token.as_deref(), &sim.conf.sync) {
+            warn!("Rejected {} for {:?}: {}", cmd, cmd.player_id(), err);
+            return None;
+        }
+        debug!("Received {} from {:?}", cmd, cmd.player_id());
+        let signed_token = signed_token.unwrap_or_default();
+        let mut control = None;
+        match cmd {
+            Command::SelectTenant(p_id, t_id) => {
+                // Tenants someone else is playing can't be taken
+                let claimed = match sim.tenants.get(t_id) {
+                    Some(tenant) => self.players.iter().any(|(p, &t)| t == t_id && *p != p_id)
+                        || (tenant.player && self.players.get(&p_id) != Some(&t_id)),
+                    None => true,
+                };
+                if claimed {
+                    warn!("Rejected selecting tenant {:?} from {:?}", t_id, p_id);
+                    return None;
+                }
+                let token = match self.issue_token(&p_id, &signed_token) {
+                    Ok(token) => token,
+                    Err(err) => {
+                        warn!("Couldn't issue token: {}", err);
+                        return None;
+                    }
+                };
+                info!("Player joined: {:?}", p_id);
+                self.bind_seat(&p_id, &token, Seat::Tenant(t_id));
+                self.starts.entry(Seat::Tenant(t_id)).or_insert(Start {
+                    month: sim.time,
+                    rent_burden: rent_burden(&sim.tenants[t_id], &sim.city),
+                    cash: sim.tenants[t_id].savings,
+                    n_units: 0,
+                });
+                self.players.insert(p_id, t_id);
+                sim.households.leave(t_id, &mut sim.tenants, &mut sim.city, &sim.conf);
+                let tenant = &mut sim.tenants[t_id];
+                tenant.player = true;
+
+                // Pooled tenants may have left the
+                // city since; players bring them back
+                tenant.departed = false;
+                tenant.months_homeless = 0;
+
+                // Evict from existing unit, if any
+                sim.city.move_out(tenant, &sim.conf);
+
+                // Reset tenant DOMA shares
+                sim.doma.shares.insert(t_id, 0.);
+                sim.doma.contributions.remove(&t_id);
+                sim.tenants[t_id].pledge = 0.;
+            },
+            Command::ReleaseTenant(p_id) => {
+                info!("Player left: {:?}", p_id);
+                match self.players.remove(&p_id) {
+                    Some(t_id) => {
+                        sim.tenants[t_id].player = false;
+                        if let Err(err) = self.unbind_seat(&p_id, Seat::Tenant(t_id)) {
+                            warn!("Couldn't release player: {}", err);
+                        }
+                    },
+                    None => {}
+                }
+            },
+            Command::ReleaseTenants => {
+                for t in &mut sim.tenants {
+                    t.player = false;
+                }
+            },
+            Command::MoveTenant(p_id, u_id) => {
+                debug!("Player {:?} moving to: {:?}", p_id, u_id);
+                match self.players.get(&p_id) {
+                    Some(_) if sim.city.units.get(u_id).is_none() => {
+                        warn!("Rejected move into unknown unit {:?} from {:?}", u_id, p_id);
+                    },
+                    // The player may not have seen
+                    // the unit was merged away yet
+                    Some(_) if sim.city.units[u_id].retired => {
+                        warn!("Rejected move into merged unit {:?} from {:?}", u_id, p_id);
+                    },
+                    // Moving as anyone else does, so it costs
+                    // the same and shows up in the event log
+                    Some(&t_id) => {
+                        if !market::move_in(&[t_id], u_id, &mut sim.tenants, &mut sim.city, sim.time, &sim.conf) {
+                            warn!("Rejected move into full unit {:?} from {:?}", u_id, p_id);
+                        }
+                    },
+                    None => {}
+                }
+            },
+            Command::Organize(p_id) => {
+                // Organizing takes the player's whole turn
+                let t_id = match self.players.get(&p_id) {
+                    Some(&t_id) if !self.turn_ended.contains(&p_id) => t_id,
+                    _ => {
+                        warn!("Rejected organizing from {:?}", p_id);
+                        return None;
+                    }
+                };
+                if let Some(unions) = &sim.conf.unions {
+                    let n = sim.unions.organize(t_id, &sim.tenants, &mut sim.city, sim.time, rng, unions);
+                    debug!("Player {:?} recruited {:?} neighbors", p_id, n);
+                }
+                self.last_seen.insert(p_id.clone(), Instant::now());
+                self.turn_ended.insert(p_id);
+                if let Err(err) = self.sync_turn() {
+                    warn!("Couldn't sync turn: {}", err);
+                }
+            },
+            Command::SelectLandlord(p_id) => {
+                // Assign the smallest portfolio
+                // not already controlled by a player
+                let l_id = sim.landlords
+                    .iter()
+                    .filter(|l| !l.player && !l.units.is_empty())
+                    .min_by_key(|l| l.units.len())
+                    .map(|l| l.id);
+                if let Some(l_id) = l_id {
+                    let token = match self.issue_token(&p_id, &signed_token) {
+                        Ok(token) => token,
+                        Err(err) => {
+                            warn!("Couldn't issue token: {}", err);
+                            return None;
+                        }
+                    };
+                    info!("Player joined as landlord {:?}: {:?}", l_id, p_id);
+
+                    // Players control one landlord at a
+                    // time, so give back the one they had
+                    if let Some(&prev) = self.landlord_players.get(&p_id) {
+                        sim.landlords[prev].release_player();
+                        for seats in self.tokens.values_mut() {
+                            seats.retain(|s| *s != Seat::Landlord(prev));
+                        }
+                    }
+                    let landlord = &mut sim.landlords[l_id];
+                    landlord.player = true;
+                    self.bind_seat(&p_id, &token, Seat::Landlord(l_id));
+                    self.starts.entry(Seat::Landlord(l_id)).or_insert(Start {
+                        month: sim.time,
+                        rent_burden: None,
+                        cash: landlord.cash,
+                        n_units: landlord.units.len(),
+                    });
+                    self.landlord_players.insert(p_id, l_id);
+                }
+            },
+            Command::ReleaseLandlord(p_id) => {
+                info!("Landlord player left: {:?}", p_id);
+                if let Some(l_id) = self.landlord_players.remove(&p_id) {
+                    sim.landlords[l_id].release_player();
+                    if let Err(err) = self.unbind_seat(&p_id, Seat::Landlord(l_id)) {
+                        warn!("Couldn't release player: {}", err);
+                    }
+                }
+            },
+            Command::SetRent(p_id, u_id, rent) => {
+                debug!("Player {:?} setting rent for {:?}: {:?}", p_id, u_id, rent);
+                if let Some(landlord) = self.player_landlord(&p_id, u_id, sim) {
+                    landlord.player_rents.insert(u_id, f32::max(0., rent));
+                }
+            },
+            Command::Maintain(p_id, u_id, budget) => {
+                debug!("Player {:?} setting maintenance for {:?}: {:?}", p_id, u_id, budget);
+                if let Some(landlord) = self.player_landlord(&p_id, u_id, sim) {
+                    landlord.player_maintenance.insert(u_id, f32::max(0., budget));
+                }
+            },
+            Command::SellUnit(p_id, u_id) => {
+                debug!("Player {:?} selling {:?}", p_id, u_id);
+                if let Some(landlord) = self.player_landlord(&p_id, u_id, sim) {
+                    if !landlord.for_sale.contains(&u_id) {
+                        landlord.for_sale.push(u_id);
+                    }
+                }
+            },
+            Command::DOMAAdd(p_id, amount) => {
+                debug!("Player {:?} adding {:?} to DOMA", p_id, amount);
+                match self.players.get(&p_id) {
+                    Some(&t_id) => {
+                        sim.doma.add_funds(t_id, amount);
+                    },
+                    None => {}
+                }
+            },
+            Command::DOMAPledge(p_id, share) => {
+                debug!("Player {:?} pledging {:?} of savings to DOMA", p_id, share);
+                if let Some(&t_id) = self.players.get(&p_id) {
+                    sim.tenants[t_id].pledge = share.clamp(0., 1.);
+                }
+            },
+            Command::DOMAPreach(p_id, amount, trigger) => {
+                debug!("Player {:?} preaching {:?}", p_id, amount);
+                match self.players.get(&p_id) {
+                    Some(&tenant_id) => {
+                        sim.conf.encounter_rate = f32::min(sim.conf.encounter_rate + amount, 0.75);
+                        sim.conf.base_contribute_prob = f32::min(sim.conf.base_contribute_prob + amount, 0.75);
+                        sim.conf.base_contribute_percent = f32::min(sim.conf.base_contribute_percent + amount, 0.20);
+                        if trigger {
+                            let infected = sim.social_graph.contagion(tenant_id, sim.conf.encounter_rate, sim.conf.transmission_rate, sim.conf.max_contagion_depth, rng);
+                            for t_id in infected {
+                                let t = &sim.tenants[t_id];
+                                sim.doma.add_funds(t_id, sim.conf.base_contribute_percent * t.income);
                             }
-                        },
-                        Command::DOMAConfigure(p_dividend, p_rent_share, rent_income_limit) => {
-                            println!("Configuring DOMA {:?}, {:?}, {:?}", p_dividend, p_rent_share, rent_income_limit);
-                            sim.doma.p_reserves = 1.0 - p_dividend - sim.doma.p_expenses;
-                            sim.doma.p_rent_share = p_rent_share;
-                            sim.doma.rent_income_limit = Some(rent_income_limit);
-                        },
-                        Command::RentFreeze(months) => {
-                            println!("Rent Freeze for {:?} months", months);
-                            sim.policies.push((Policy::RentFreeze, months));
-                        },
-                        Command::MarketTax(months) => {
-                            println!("Market Tax for {:?} months", months);
-                            sim.policies.push((Policy::MarketTax, months));
-                        },
-                        Command::Run(n) => {
-                            control = Some(Control::Run(n));
-                        },
-                        Command::Reset => {
-                            control = Some(Control::Reset);
                         }
+                    },
+                    None => {}
+                }
+            },
+            Command::DOMAConfigure(p_dividend, p_rent_share, rent_income_limit) => {
+                debug!("Configuring DOMA {:?}, {:?}, {:?}", p_dividend, p_rent_share, rent_income_limit);
+                sim.doma.p_reserves = 1.0 - p_dividend - sim.doma.p_expenses;
+                sim.doma.p_rent_share = p_rent_share;
+                sim.doma.rent_income_limit = Some(rent_income_limit);
+            },
+            Command::DOMAVote(p_id, neighborhood, rent_discount, p_dividend) => {
+                debug!("Player {:?} voting {:?}, {:?}, {:?}", p_id, neighborhood, rent_discount, p_dividend);
+                if self.players.contains_key(&p_id) {
+                    let vote = Vote {
+                        neighborhood: neighborhood,
+                        rent_discount: rent_discount,
+                        p_dividend: p_dividend,
+                    };
+                    if !vote.valid() {
+                        warn!("Rejected out-of-range vote {:?} from {:?}", vote, p_id);
+                        return None;
+                    }
+                    if let Some(id) = vote.neighborhood {
+                        if !sim.city.neighborhoods.iter().any(|n| n.id as usize == id) {
+                            warn!("Rejected vote for unknown neighborhood {:?} from {:?}", id, p_id);
+                            return None;
+                        }
+                    }
+                    if let Err(err) = self.backend.hset("doma_votes", &p_id, serde_json::to_string(&vote).unwrap().as_bytes()) {
+                        warn!("Couldn't record vote from {:?}: {}", p_id, err);
+                    }
+                }
+            },
+            Command::VacancyTaxVote(p_id, rate) => {
+                debug!("Player {:?} voting for vacancy tax {:?}", p_id, rate);
+                if self.players.contains_key(&p_id) || self.landlord_players.contains_key(&p_id) {
+                    let vote = PolicyVote {
+                        vacancy_tax_rate: rate,
+                    };
+                    if !vote.valid() {
+                        warn!("Rejected out-of-range vote {:?} from {:?}", vote, p_id);
+                        return None;
+                    }
+                    if let Err(err) = self.backend.hset("policy_votes", &p_id, serde_json::to_string(&vote).unwrap().as_bytes()) {
+                        warn!("Couldn't record vote from {:?}: {}", p_id, err);
+                    }
+                }
+            },
+            Command::RentFreeze(months) => {
+                info!("Rent Freeze for {:?} months", months);
+                sim.policies.push((Policy::RentFreeze, months));
+            },
+            Command::MarketTax(months) => {
+                info!("Market Tax for {:?} months", months);
+                sim.policies.push((Policy::MarketTax, months));
+            },
+            Command::Run(n) => {
+                control = Some(Control::Run(n));
+            },
+            Command::Reset => {
+                control = Some(Control::Reset);
+            },
+            Command::Resync => {
+                control = Some(Control::Resync);
+            },
+            Command::EndTurn(p_id) => {
+                if self.players.contains_key(&p_id) || self.landlord_players.contains_key(&p_id) {
+                    debug!("Player {:?} ended their turn", p_id);
+                    self.last_seen.insert(p_id.clone(), Instant::now());
+                    self.turn_ended.insert(p_id);
+                    if let Err(err) = self.sync_turn() {
+                        warn!("Couldn't sync turn: {}", err);
                     }
                 }
+            },
+            Command::SetHandle(p_id, handle) => {
+                // Handles are leaderboard names,
+                // so one can't be taken from another player
+                if self.handles.iter().any(|(other, h)| *h == handle && *other != p_id) {
+                    warn!("Rejected handle {:?} for {:?}, already in use", handle, p_id);
+                    return None;
+                }
+                debug!("Player {:?} playing as {:?}", p_id, handle);
+                self.handles.insert(p_id, handle);
+            },
+            Command::Heartbeat(p_id) => {
+                if self.players.contains_key(&p_id) || self.landlord_players.contains_key(&p_id) {
+                    self.last_seen.insert(p_id, Instant::now());
+                }
+            },
+            Command::Rejoin(p_id, token) => {
+                if self.rejoin(p_id.clone(), &token, sim) {
+                    info!("Player rejoined: {:?}", p_id);
+                } else {
+                    info!("Player {:?} couldn't rejoin: unknown token", p_id);
+                }
+            },
+            Command::NextSession(params) => {
+                info!("Next session: {:?}", params);
+                if let Err(err) = self.queue_next_session(params, &sim.conf) {
+                    warn!("Couldn't configure next session: {}", err);
+                }
             }
         }
         control
     }
 }
+
+fn authorize_operator(token: Option<&str>, conf: &SyncConfig) -> Result<(), &'static str> {
+    match (&conf.operator_token, token) {
+        (None, _) => Err("no operator token configured"),
+        (Some(op), Some(token)) if op == token => Ok(()),
+        (Some(_), Some(_)) => Err("wrong token"),
+        (Some(_), None) => Err("no token"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::TimeStep;
+    use super::super::testing::{fixture, MemoryBackend};
+
+    static ALICE_KEY: &str = "alice-registration-key";
+    static MALLORY_KEY: &str = "mallory-registration-key";
+
+    fn manager() -> (Rc<MemoryBackend>, PlayManager) {
+        let backend = Rc::new(MemoryBackend::default());
+        let manager = PlayManager::new(backend.clone(), Encoding::Json);
+        (backend, manager)
+    }
+
+    fn rng() -> StdRng {
+        SeedableRng::seed_from_u64(0)
+    }
+
+    #[test]
+    fn select_tenant_issues_token() {
+        let (backend, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+
+        let token = manager.player_tokens.get("alice").cloned().expect("no token issued");
+        assert_ne!(token, ALICE_KEY);
+        assert_eq!(backend.get(&format!("player:{}:token", ALICE_KEY)).unwrap(), Some(token.clone()));
+        assert!(manager.authorize(&Command::EndTurn("alice".to_string()), Some(&token), &sim.conf.sync).is_ok());
+        assert!(manager.authorize(&Command::EndTurn("alice".to_string()), Some(ALICE_KEY), &sim.conf.sync).is_err());
+    }
+
+    #[test]
+    fn select_claimed_tenant() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+
+        manager.handle_command(Command::SelectTenant("mallory".to_string(), t_id), Some(MALLORY_KEY.to_string()), &mut sim, &mut rng());
+        assert_eq!(manager.players.get("alice"), Some(&t_id));
+        assert!(!manager.players.contains_key("mallory"));
+        assert!(!manager.player_tokens.contains_key("mallory"));
+        assert_eq!(manager.tokens[&token], vec![Seat::Tenant(t_id)]);
+    }
+
+    fn vacant_unit(sim: &Simulation) -> UnitId {
+        *sim.city.vacant_units().iter()
+            .find(|&&u_id| sim.city.units[u_id].vacant())
+            .expect("no vacant unit")
+    }
+
+    // Players move like anyone else, so their
+    // moves are logged and cost them the same
+    #[test]
+    fn player_move_is_logged() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+        sim.tenants[t_id].savings = 1e6;
+        sim.city.events.clear();
+
+        let u_id = vacant_unit(&sim);
+        manager.handle_command(Command::MoveTenant("alice".to_string(), u_id), Some(token), &mut sim, &mut rng());
+        assert_eq!(sim.tenants[t_id].unit, Some(u_id));
+        assert!(sim.city.events.iter().any(|event| match event {
+            SimEvent::MovedIn { tenant, unit, from: None } => *tenant == t_id && *unit == u_id,
+            _ => false,
+        }));
+        let deposit: f32 = sim.city.units[u_id].lease.as_ref().unwrap().deposits.iter().map(|d| d.amount).sum();
+        assert_eq!(sim.tenants[t_id].savings, 1e6 - sim.conf.moving_cost - deposit);
+    }
+
+    #[test]
+    fn player_move_breaks_lease() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+        sim.tenants[t_id].savings = 1e6;
+
+        let from = vacant_unit(&sim);
+        manager.handle_command(Command::MoveTenant("alice".to_string(), from), Some(token.clone()), &mut sim, &mut rng());
+        let to = vacant_unit(&sim);
+        manager.handle_command(Command::MoveTenant("alice".to_string(), to), Some(token), &mut sim, &mut rng());
+        assert_eq!(sim.tenants[t_id].unit, Some(to));
+        assert!(sim.city.events.iter().any(|event| match event {
+            SimEvent::LeaseBroken { tenant, unit, penalty } => *tenant == t_id && *unit == from && *penalty > 0.,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn player_move_into_full_or_unknown_unit() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+
+        let full = sim.city.units.iter()
+            .find(|u| !u.retired && u.vacancies() == 0)
+            .expect("no full unit")
+            .id;
+        manager.handle_command(Command::MoveTenant("alice".to_string(), full), Some(token.clone()), &mut sim, &mut rng());
+        assert_eq!(sim.tenants[t_id].unit, None);
+        assert!(!sim.city.units[full].tenants.contains(&t_id));
+
+        let unknown = UnitId(sim.city.units.len());
+        manager.handle_command(Command::MoveTenant("alice".to_string(), unknown), Some(token), &mut sim, &mut rng());
+        assert_eq!(sim.tenants[t_id].unit, None);
+    }
+
+    // Players are shown the last tally
+    // while votes can't be read back
+    #[test]
+    fn sync_players_while_disconnected() {
+        let (backend, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+        manager.handle_command(Command::DOMAVote("alice".to_string(), None, 0.1, 0.5), Some(token.clone()), &mut sim, &mut rng());
+        manager.handle_command(Command::VacancyTaxVote("alice".to_string(), Some(0.1)), Some(token.clone()), &mut sim, &mut rng());
+        manager.sync_players(&sim.tenants, &sim.landlords, &sim.city, &sim.doma, &sim.unions, &sim.conf).unwrap();
+
+        backend.down.set(true);
+        manager.sync_players(&sim.tenants, &sim.landlords, &sim.city, &sim.doma, &sim.unions, &sim.conf).unwrap();
+        backend.down.set(false);
+        let payload: Value = serde_json::from_str(&backend.get(&format!("player:{}:tenant", token)).unwrap().unwrap()).unwrap();
+        assert_eq!(payload["doma_vote"]["tally"]["n_votes"], 1);
+        assert_eq!(payload["policy_vote"]["tally"]["n_votes"], 1);
+    }
+
+    // Votes from players who have since
+    // left aren't counted
+    #[test]
+    fn policy_votes_of_departed_players() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let mut tenants = sim.tenants.iter().map(|t| t.id);
+        let (alice, mallory) = (tenants.next().unwrap(), tenants.next().unwrap());
+        manager.handle_command(Command::SelectTenant("alice".to_string(), alice), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        manager.handle_command(Command::SelectTenant("mallory".to_string(), mallory), Some(MALLORY_KEY.to_string()), &mut sim, &mut rng());
+        let alice_token = manager.player_tokens["alice"].clone();
+        let mallory_token = manager.player_tokens["mallory"].clone();
+        manager.handle_command(Command::VacancyTaxVote("alice".to_string(), Some(0.1)), Some(alice_token), &mut sim, &mut rng());
+        manager.handle_command(Command::VacancyTaxVote("mallory".to_string(), Some(0.5)), Some(mallory_token.clone()), &mut sim, &mut rng());
+        assert_eq!(manager.tally_policy_votes().unwrap().1["n_votes"], 2);
+
+        manager.handle_command(Command::ReleaseTenant("mallory".to_string()), Some(mallory_token), &mut sim, &mut rng());
+        let (winner, tally) = manager.tally_policy_votes().unwrap();
+        assert_eq!(tally["n_votes"], 1);
+        assert_eq!(winner.unwrap().vacancy_tax_rate, Some(0.1));
+    }
+
+    #[test]
+    fn median_of_even_count() {
+        assert_eq!(median(&mut [3., 1., 2., 4.]), 2.5);
+        assert_eq!(median(&mut [3., 1., 2.]), 2.);
+    }
+
+    // Votes are applied quarterly,
+    // however many steps that is
+    #[test]
+    fn vote_interval() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        let t_id = sim.tenants.iter().next().unwrap().id;
+        manager.handle_command(Command::SelectTenant("alice".to_string(), t_id), Some(ALICE_KEY.to_string()), &mut sim, &mut rng());
+        let token = manager.player_tokens["alice"].clone();
+
+        sim.conf.time_step = TimeStep::Week;
+        for (time, applied) in [(3, false), (13, true)] {
+            manager.handle_command(Command::VacancyTaxVote("alice".to_string(), Some(0.1)), Some(token.clone()), &mut sim, &mut rng());
+            sim.conf.policies.vacancy_tax_rate = None;
+            sim.time = time;
+            manager.apply_votes(&mut sim).unwrap();
+            assert_eq!(sim.conf.policies.vacancy_tax_rate.is_some(), applied, "at step {}", time);
+        }
+    }
+
+    // Bots that chose the same last vacancy
+    // don't overfill it when they get to moving
+    #[test]
+    fn bots_share_last_vacancy() {
+        let (_, mut manager) = manager();
+        let mut sim = fixture();
+        sim.conf.bots.n = 2;
+        manager.seat_bots(&mut sim, &mut rng());
+        assert_eq!(manager.bots.len(), 2);
+
+        let u_id = vacant_unit(&sim);
+        let occupancy = sim.city.units[u_id].occupancy;
+        let bot_tenants: Vec<TenantId> = manager.bots.iter().map(|bot| bot.tenant).collect();
+        let fillers: Vec<TenantId> = sim.tenants.iter()
+            .filter(|t| !bot_tenants.contains(&t.id))
+            .take(occupancy - 1)
+            .map(|t| t.id)
+            .collect();
+        for t_id in fillers {
+            let tenant = &mut sim.tenants[t_id];
+            assert!(sim.city.move_tenant(tenant, u_id, sim.time, &sim.conf));
+        }
+
+        for bot in &mut manager.bots {
+            bot.actions.push_back((Instant::now(), Command::MoveTenant(bot.id.clone(), u_id)));
+        }
+        manager.run_bots(&mut sim, &mut rng());
+        assert_eq!(sim.city.units[u_id].tenants.len(), occupancy);
+        assert_eq!(bot_tenants.iter().filter(|&&t_id| sim.tenants[t_id].unit == Some(u_id)).count(), 1);
+    }
+}
@@ -0,0 +1,101 @@
+use super::export::PlotFormat;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+const SIZE: (u32, u32) = (800, 480);
+
+// Line chart of each named series over steps,
+// as an SVG or, for any other extension, a PNG
+pub fn line_chart(path: &str, title: &str, series: &[(&str, &[f32])]) -> Result<(), Box<dyn Error>> {
+    if Path::new(path).extension().map_or(false, |ext| ext == "svg") {
+        draw(SVGBackend::new(path, SIZE).into_drawing_area(), title, series)
+    } else {
+        draw(BitMapBackend::new(path, SIZE).into_drawing_area(), title, series)
+    }
+}
+
+fn draw<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, title: &str, series: &[(&str, &[f32])]) -> Result<(), Box<dyn Error>>
+    where DB::ErrorType: 'static {
+    root.fill(&WHITE)?;
+
+    // Flat series get some room
+    // above and below anyway
+    let steps = series.iter().map(|(_, vals)| vals.len()).max().unwrap_or(0).max(2);
+    let (lo, hi) = series.iter().flat_map(|(_, vals)| vals.iter())
+        .filter(|v| v.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    let (lo, hi) = if lo > hi { (0., 1.) } else if lo == hi { (lo - 1., hi + 1.) } else { (lo, hi) };
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 20))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0..steps - 1, lo..hi)?;
+    chart.configure_mesh().x_desc("step").draw()?;
+
+    for (i, (name, vals)) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart.draw_series(LineSeries::new(
+            vals.iter().enumerate().filter(|(_, v)| v.is_finite()).map(|(t, &v)| (t, v)),
+            color.stroke_width(2)))?
+            .label(*name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2)));
+    }
+    chart.configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+// A stat's value at each step, NaN
+// where it's missing or not a number
+fn series(history: &[Value], pointer: &str) -> Vec<f32> {
+    history.iter()
+        .map(|step| step.pointer(pointer).and_then(|v| v.as_f64()).map_or(f32::NAN, |v| v as f32))
+        .collect()
+}
+
+// Charts of a run's history, for a quick look
+// without loading its data elsewhere first
+pub fn plot_run(dir: &str, history: &[Value], formats: &[PlotFormat]) {
+    let plot_dir = format!("{}/plots", dir);
+    fs::create_dir_all(&plot_dir).unwrap();
+
+    // Neighborhoods are keyed by id
+    let mut neighborhoods: Vec<String> = history.first()
+        .and_then(|step| step["neighborhoods"].as_object())
+        .map_or_else(Vec::new, |n| n.keys().cloned().collect());
+    neighborhoods.sort_by_key(|id| id.parse::<usize>().unwrap_or(usize::MAX));
+    let rents: Vec<(String, Vec<f32>)> = neighborhoods.iter()
+        .map(|id| (format!("neighborhood {}", id), series(history, &format!("/neighborhoods/{}/mean_rent_per_area", id))))
+        .collect();
+
+    let charts = vec![
+        ("rent_per_area", "mean rent per area", rents),
+        ("homelessness", "homelessness", vec![
+            ("percent_homeless".to_string(), series(history, "/percent_homeless")),
+        ]),
+        ("doma_share", "DOMA share", vec![
+            ("units".to_string(), series(history, "/p_units_doma")),
+            ("members".to_string(), series(history, "/doma_members_p")),
+        ]),
+    ];
+    for (name, title, lines) in &charts {
+        let named: Vec<(&str, &[f32])> = lines.iter().map(|(k, v)| (k.as_str(), v.as_slice())).collect();
+        for format in formats {
+            let path = format!("{}/{}.{}", plot_dir, name, format.extension());
+            if let Err(err) = line_chart(&path, title, &named) {
+                warn!("Couldn't plot {:?}: {}", path, err);
+            }
+        }
+    }
+    info!("Wrote plots to {:?}", plot_dir);
+}
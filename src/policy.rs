@@ -1,4 +1,6 @@
-#[derive(PartialEq, Debug)]
+use serde::{Serialize, Deserialize};
+
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum Policy {
     RentFreeze,
     MarketTax
@@ -0,0 +1,72 @@
+use serde_json::{Map, Value};
+use std::time::{Duration, Instant};
+
+// Wall time spent in each phase of a step, for
+// diagnosing slow steps and laggy sessions.
+// Phases are timed as laps, each from the end
+// of the previous one
+#[derive(Default, Debug)]
+pub struct Profiler {
+    lap_start: Option<Instant>,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Profiler {
+    // Start timing afresh, forgetting earlier phases
+    pub fn start(&mut self) {
+        self.phases.clear();
+        self.lap_start = Some(Instant::now());
+    }
+
+    // Time since the last lap counts towards the phase
+    pub fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        if let Some(start) = self.lap_start {
+            self.add(phase, now - start);
+        }
+        self.lap_start = Some(now);
+    }
+
+    // Phases timed more than once add up
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    // Add another profiler's phases,
+    // e.g. each step's over a turn
+    pub fn merge(&mut self, other: &Profiler) {
+        for &(phase, duration) in &other.phases {
+            self.add(phase, duration);
+        }
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, d)| *d).sum()
+    }
+
+    // The slowest phases first
+    pub fn summary(&self) -> String {
+        let mut phases = self.phases.clone();
+        phases.sort_by_key(|&(_, d)| std::cmp::Reverse(d));
+        phases.iter()
+            .map(|(phase, d)| format!("{} {:.3}s", phase, d.as_secs_f64()))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    // Seconds in each phase, and in all
+    pub fn to_json(&self) -> Value {
+        let mut timings: Map<String, Value> = self.phases.iter()
+            .map(|(phase, d)| (phase.to_string(), d.as_secs_f64().into()))
+            .collect();
+        timings.insert("total".to_string(), self.total().as_secs_f64().into());
+        Value::Object(timings)
+    }
+}
@@ -0,0 +1,178 @@
+#[cfg(feature = "play")]
+use super::backend::SyncBackend;
+use pbr::ProgressBar;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use std::io::Stdout;
+#[cfg(feature = "play")]
+use std::rc::Rc;
+use std::time::Instant;
+use tracing::warn;
+
+// Where to report progress through steps
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressType {
+    // A progress bar, for running by hand
+    #[default]
+    Terminal,
+
+    // JSON lines on stdout, for supervisors
+    Json,
+
+    // The `progress` key of the sync backend,
+    // in builds with play sessions
+    Backend,
+
+    None,
+}
+
+// Reports progress through a stretch of steps,
+// e.g. a debug run, burn-in, or a play turn
+pub trait ProgressSink {
+    fn start(&mut self, phase: &str, steps: usize);
+
+    // A step finished, leaving the simulation at `month`
+    fn inc(&mut self, month: usize);
+
+    fn finish(&mut self);
+}
+
+#[cfg(feature = "play")]
+pub fn from_config(typ: ProgressType, backend: Rc<dyn SyncBackend>) -> Box<dyn ProgressSink> {
+    match typ {
+        ProgressType::Backend => Box::new(JsonProgress::new(BackendKey { backend: backend })),
+        typ => headless(typ),
+    }
+}
+
+// For runs without a sync backend,
+// which fall back to a progress bar
+pub fn headless(typ: ProgressType) -> Box<dyn ProgressSink> {
+    match typ {
+        ProgressType::Terminal => Box::new(TerminalProgress { bar: None }),
+        ProgressType::Json => Box::new(JsonProgress::new(StdoutLines)),
+        ProgressType::Backend => {
+            warn!("No sync backend to report progress to");
+            Box::new(TerminalProgress { bar: None })
+        }
+        ProgressType::None => Box::new(NoProgress),
+    }
+}
+
+struct TerminalProgress {
+    bar: Option<ProgressBar<Stdout>>,
+}
+
+impl ProgressSink for TerminalProgress {
+    fn start(&mut self, _phase: &str, steps: usize) {
+        self.bar = Some(ProgressBar::new(steps as u64));
+    }
+
+    fn inc(&mut self, _month: usize) {
+        if let Some(bar) = &mut self.bar {
+            bar.inc();
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut bar) = self.bar.take() {
+            bar.finish();
+        }
+    }
+}
+
+// Where JSON progress records go
+trait RecordWriter {
+    fn write(&self, record: &str);
+}
+
+struct StdoutLines;
+
+impl RecordWriter for StdoutLines {
+    fn write(&self, record: &str) {
+        println!("{}", record);
+    }
+}
+
+// Only the latest record is kept
+#[cfg(feature = "play")]
+struct BackendKey {
+    backend: Rc<dyn SyncBackend>,
+}
+
+#[cfg(feature = "play")]
+impl RecordWriter for BackendKey {
+    fn write(&self, record: &str) {
+        if let Err(err) = self.backend.set("progress", record.as_bytes()) {
+            warn!("Couldn't report progress: {}", err);
+        }
+    }
+}
+
+// Reports each event as a JSON object, e.g.
+// `{"type": "progress", "event": "step", "phase": "run",
+// "step": 3, "steps": 12, "month": 15, "elapsed_secs": 0.4}`
+struct JsonProgress<W> {
+    writer: W,
+    phase: String,
+    step: usize,
+    steps: usize,
+    month: Option<usize>,
+    started: Instant,
+}
+
+impl<W: RecordWriter> JsonProgress<W> {
+    fn new(writer: W) -> JsonProgress<W> {
+        JsonProgress {
+            writer: writer,
+            phase: String::new(),
+            step: 0,
+            steps: 0,
+            month: None,
+            started: Instant::now(),
+        }
+    }
+
+    fn record(&self, event: &str) {
+        let record = json!({
+            "type": "progress",
+            "event": event,
+            "phase": self.phase,
+            "step": self.step,
+            "steps": self.steps,
+            "month": self.month,
+            "elapsed_secs": self.started.elapsed().as_secs_f32(),
+        });
+        self.writer.write(&record.to_string());
+    }
+}
+
+impl<W: RecordWriter> ProgressSink for JsonProgress<W> {
+    fn start(&mut self, phase: &str, steps: usize) {
+        self.phase = phase.to_string();
+        self.step = 0;
+        self.steps = steps;
+        self.month = None;
+        self.started = Instant::now();
+        self.record("start");
+    }
+
+    fn inc(&mut self, month: usize) {
+        self.step += 1;
+        self.month = Some(month);
+        self.record("step");
+    }
+
+    fn finish(&mut self) {
+        self.record("finish");
+    }
+}
+
+struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn start(&mut self, _phase: &str, _steps: usize) {}
+    fn inc(&mut self, _month: usize) {}
+    fn finish(&mut self) {}
+}
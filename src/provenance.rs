@@ -0,0 +1,39 @@
+use super::config::Config;
+use super::{generate, import};
+use serde_json::{json, Value};
+
+// Features the binary was built with
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "play") {
+        features.push("play");
+    }
+    if cfg!(feature = "parquet") {
+        features.push("parquet");
+    }
+    if cfg!(feature = "plot") {
+        features.push("plot");
+    }
+    features
+}
+
+// What's needed to reproduce a run beyond its
+// config file: the code it ran with, the config
+// with every default and override applied, values
+// worked out from it, and values fixed in the code
+pub fn provenance(conf: &Config) -> Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+        "features": features(),
+        "config": conf,
+        "derived": {
+            "months_per_step": conf.time_step.months(),
+            "steps_per_year": conf.time_step.steps_per_year(),
+        },
+        "constants": {
+            "generate": generate::constants(),
+            "import": import::constants(),
+        },
+    })
+}
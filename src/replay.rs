@@ -0,0 +1,231 @@
+use super::backend::{SyncBackend, SyncError, SyncResult, Write};
+use super::play;
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write as IoWrite};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// Recorded commands are replayed onto this list,
+// rather than `cmds`, so they're only shown
+static REPLAY_COMMANDS_KEY: &str = "replay:cmds";
+
+static COMPRESSION_LEVEL: u8 = 6;
+static WAIT_INTERVAL_MS: u64 = 100;
+
+// Whether and how play sessions are recorded
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ReplayConfig {
+    pub record: bool,
+
+    // Frames are written out in batches of this many
+    pub flush_frames: usize,
+
+    // Recording stops once the file is this big, in MB
+    pub max_mb: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Event {
+    Write(Write),
+    Command(String),
+}
+
+// Milliseconds since the recording started
+#[derive(Serialize, Deserialize, Debug)]
+struct Frame {
+    ms: u64,
+    event: Event,
+}
+
+struct Recording {
+    file: BufWriter<File>,
+    start: Instant,
+    unflushed: usize,
+    written: usize,
+}
+
+impl Recording {
+    // Frames are compressed separately and
+    // prefixed with their length, so a recording
+    // can be read up to wherever it was cut off.
+    // Returns false once it's reached its size cap
+    fn record(&mut self, event: Event, conf: &ReplayConfig) -> SyncResult<bool> {
+        let frame = Frame {
+            ms: self.start.elapsed().as_millis() as u64,
+            event: event,
+        };
+        let compressed = compress_to_vec(&bincode::serialize(&frame)?, COMPRESSION_LEVEL);
+        let size = 4 + compressed.len();
+        if (self.written + size) as f32 > conf.max_mb * 1e6 {
+            self.file.flush()?;
+            return Ok(false);
+        }
+        self.file.write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.file.write_all(&compressed)?;
+        self.written += size;
+        self.unflushed += 1;
+        if self.unflushed >= conf.flush_frames {
+            self.file.flush()?;
+            self.unflushed = 0;
+        }
+        Ok(true)
+    }
+}
+
+// Records a session as it's synced: every write to
+// the backend and every player command read from it
+pub struct Recorder {
+    backend: Rc<dyn SyncBackend>,
+    recording: RefCell<Option<Recording>>,
+    conf: ReplayConfig,
+}
+
+impl Recorder {
+    pub fn new(backend: Rc<dyn SyncBackend>, conf: &ReplayConfig) -> Recorder {
+        Recorder {
+            backend: backend,
+            recording: RefCell::new(None),
+            conf: conf.clone(),
+        }
+    }
+
+    // Start recording to a new file, if recording's on.
+    // The design and payload encoding are recorded first
+    // so that the replay can be served on its own
+    pub fn start(&self, path: &str, design_id: &str) -> SyncResult<()> {
+        if !self.conf.record {
+            return Ok(());
+        }
+        *self.recording.borrow_mut() = Some(Recording {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+            unflushed: 0,
+            written: 0,
+        });
+        for key in &["meta".to_string(), format!("design:{}", design_id)] {
+            if let Some(value) = self.backend.get(key)? {
+                self.record(Event::Write(Write::Set(key.to_string(), value.into_bytes())));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(mut rec) = self.recording.borrow_mut().take() {
+            if let Err(err) = rec.file.flush() {
+                warn!("Couldn't finish recording replay: {}", err);
+            }
+        }
+    }
+
+    // A failed recording shouldn't end the session
+    fn record(&self, event: Event) {
+        let mut recording = self.recording.borrow_mut();
+        if let Some(rec) = recording.as_mut() {
+            match rec.record(event, &self.conf) {
+                Ok(true) => {},
+                Ok(false) => {
+                    warn!("Stopped recording replay at {} MB", self.conf.max_mb);
+                    *recording = None;
+                },
+                Err(err) => {
+                    warn!("Stopped recording replay: {}", err);
+                    *recording = None;
+                }
+            }
+        }
+    }
+}
+
+impl SyncBackend for Recorder {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        self.backend.get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.record(Event::Write(Write::Set(key.to_string(), value.to_vec())));
+        self.backend.set(key, value)
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.record(Event::Write(Write::Del(key.to_string())));
+        self.backend.del(key)
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.record(Event::Write(Write::Lpush(key.to_string(), value.to_vec())));
+        self.backend.lpush(key, value)
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        let value = self.backend.lpop(key)?;
+        if key == "cmds" {
+            if let Some(cmd) = &value {
+                self.record(Event::Command(cmd.clone()));
+            }
+        }
+        Ok(value)
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.record(Event::Write(Write::Hset(key.to_string(), field.to_string(), value.to_vec())));
+        self.backend.hset(key, field, value)
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        self.backend.hgetall(key)
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> SyncResult<Option<Frame>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {},
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let mut compressed = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut compressed)?;
+    let data = decompress_to_vec(&compressed)
+        .map_err(|err| SyncError::Replay(Box::new(bincode::ErrorKind::Custom(format!("corrupt frame: {:?}", err)))))?;
+    Ok(Some(bincode::deserialize(&data)?))
+}
+
+// Serve a recorded session through the backend,
+// with the original timing sped up by `speed`
+pub fn play(path: &str, backend: &dyn SyncBackend, speed: f32) -> SyncResult<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    backend.del(REPLAY_COMMANDS_KEY)?;
+
+    let start = Instant::now();
+    let mut n_frames = 0;
+    while let Some(frame) = read_frame(&mut reader)? {
+        // Sessions can sit idle for a while, so
+        // wait in slices to stay responsive to signals
+        let due = Duration::from_secs_f32(frame.ms as f32 / 1000. / speed);
+        while let Some(wait) = due.checked_sub(start.elapsed()) {
+            if play::terminated() {
+                break;
+            }
+            thread::sleep(wait.min(Duration::from_millis(WAIT_INTERVAL_MS)));
+        }
+        if play::terminated() {
+            break;
+        }
+        match &frame.event {
+            Event::Write(write) => write.apply_to(backend)?,
+            Event::Command(cmd) => backend.lpush(REPLAY_COMMANDS_KEY, cmd.as_bytes())?,
+        }
+        n_frames += 1;
+    }
+    info!("Replayed {} frames", n_frames);
+    Ok(())
+}
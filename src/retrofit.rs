@@ -0,0 +1,62 @@
+use super::city::Unit;
+use serde::{Serialize, Deserialize};
+
+// Capital improvements, e.g. insulation or new heating,
+// paid for up front rather than month to month like
+// maintenance. A retrofitted unit can be kept in better
+// condition than new, and commands a rent premium
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct RetrofitConfig {
+    pub cost_per_area: f32,
+
+    // How far above new condition
+    // a retrofitted unit can be kept
+    pub condition_gain: f32,
+
+    // Share more rent a retrofitted unit commands
+    pub rent_premium: f32,
+
+    // Owners retrofit only if the premium would
+    // pay back the cost within this many months
+    pub payback_months: usize,
+}
+
+impl RetrofitConfig {
+    pub fn cost(&self, unit: &Unit) -> f32 {
+        unit.area * self.cost_per_area
+    }
+
+    // Months for the premium to pay back the cost,
+    // given the monthly rent the unit would fetch
+    // as it is. None if it wouldn't in time
+    pub fn payback(&self, unit: &Unit, rent: f32) -> Option<f32> {
+        let premium = rent * self.rent_premium;
+        if unit.retrofitted() || premium <= 0. {
+            return None;
+        }
+        let months = self.cost(unit) / premium;
+        if months <= self.payback_months as f32 {
+            Some(months)
+        } else {
+            None
+        }
+    }
+
+    // Multiple of the market rent a unit commands
+    pub fn premium(&self, unit: &Unit) -> f32 {
+        if unit.retrofitted() {
+            1. + self.rent_premium
+        } else {
+            1.
+        }
+    }
+
+    // Retrofitting also brings the unit
+    // up to its new best condition
+    pub fn apply(&self, unit: &mut Unit) {
+        unit.retrofitted = true;
+        unit.max_condition = 1. + self.condition_gain;
+        unit.condition = unit.max_condition;
+    }
+}
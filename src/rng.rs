@@ -0,0 +1,55 @@
+use super::agent::AgentType;
+use super::arena::{Arena, TenantId};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Mix bits so nearby inputs
+// give unrelated outputs (SplitMix64)
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// An agent's RNG stream, derived from the master seed
+// and the agent's type and id. An agent's draws don't
+// depend on how many other agents there are
+// or what order they step in
+pub fn agent_rng(seed: u64, typ: AgentType, id: usize) -> StdRng {
+    let typ_seed = mix(seed ^ mix(typ as u64 + 1));
+    StdRng::seed_from_u64(mix(typ_seed ^ id as u64))
+}
+
+// The seed for a single step, so that every stream
+// can be re-derived from the master seed and time alone
+// and no RNG state needs to be carried between steps
+pub fn step_seed(seed: u64, time: usize) -> u64 {
+    mix(seed ^ mix(time as u64))
+}
+
+pub struct RngStreams {
+    // For city-wide randomness,
+    // e.g. shuffling and contagion
+    pub world: StdRng,
+
+    pub tenants: Arena<TenantId, StdRng>,
+    pub landlords: Vec<StdRng>,
+    pub developers: Vec<StdRng>,
+    pub speculators: Vec<StdRng>,
+    pub doma: StdRng,
+}
+
+impl RngStreams {
+    pub fn new(seed: u64, n_tenants: usize, n_landlords: usize, n_developers: usize, n_speculators: usize) -> RngStreams {
+        let streams = |typ, n| -> Vec<StdRng> { (0..n).map(|id| agent_rng(seed, typ, id)).collect() };
+        RngStreams {
+            world: StdRng::seed_from_u64(mix(seed)),
+            tenants: streams(AgentType::Tenant, n_tenants).into(),
+            landlords: streams(AgentType::Landlord, n_landlords),
+            developers: streams(AgentType::Developer, n_developers),
+            speculators: streams(AgentType::Speculator, n_speculators),
+            doma: agent_rng(seed, AgentType::DOMA, 0),
+        }
+    }
+}
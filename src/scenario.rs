@@ -0,0 +1,398 @@
+use super::agent::AgentType;
+use super::city::LandUse;
+use super::design::Neighborhood;
+use super::grid::Position;
+use super::policy::Policy;
+use super::sim::Simulation;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use tracing::{info, warn};
+
+// Where scenario packs are kept,
+// as `<name>.yaml` files
+static PACKS_DIR: &str = "scenarios";
+
+// Something that happens at a set month,
+// scripted in the config
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ScheduledIntervention {
+    pub month: usize,
+    pub intervention: Intervention,
+}
+
+// A message for players at a set month,
+// to tell the story of what's happening
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ScheduledNarration {
+    pub month: usize,
+    pub message: String,
+}
+
+// A whole session's setup under one name, e.g. a
+// city in crisis: the design, parameters, shocks,
+// and the story told alongside. Packs are versioned,
+// so runs can say which revision they played
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ScenarioPack {
+    #[serde(skip)]
+    pub name: String,
+    pub version: usize,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub design_id: Option<String>,
+
+    // Config changes, keyed as for `NextSession`,
+    // with `.` for nested ones
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+
+    // These replace the config's own
+    #[serde(default)]
+    pub scenarios: Vec<ScheduledIntervention>,
+    #[serde(default)]
+    pub narration: Vec<ScheduledNarration>,
+}
+
+impl ScenarioPack {
+    // The pack as config changes
+    pub fn params(&self) -> BTreeMap<String, Value> {
+        let mut params = self.params.clone();
+        if let Some(design_id) = &self.design_id {
+            params.insert("DESIGN_ID".to_string(), json!(design_id));
+        }
+        params.insert("SCENARIOS".to_string(), serde_json::to_value(&self.scenarios).unwrap());
+        params.insert("NARRATION".to_string(), serde_json::to_value(&self.narration).unwrap());
+        params.insert("SCENARIO".to_string(), json!(format!("{}@{}", self.name, self.version)));
+        params
+    }
+}
+
+pub fn load_pack(name: &str) -> Result<ScenarioPack, String> {
+    if name.is_empty() || name.contains(['/', '\\', '.']) {
+        return Err(format!("invalid scenario name: {:?}", name));
+    }
+    let path = format!("{}/{}.yaml", PACKS_DIR, name);
+    let data = fs::read_to_string(&path).map_err(|err| format!("couldn't read {}: {}", path, err))?;
+    let mut pack: ScenarioPack = serde_yaml::from_str(&data).map_err(|err| format!("invalid {}: {}", path, err))?;
+    pack.name = name.to_string();
+    Ok(pack)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Intervention {
+    // Freeze rents, as players can vote to do
+    #[serde(rename_all = "UPPERCASE")]
+    RentFreeze { months: usize },
+
+    #[serde(rename_all = "UPPERCASE")]
+    MarketTax { months: usize },
+
+    // Build units in a neighborhood, by design
+    // neighborhood id. Public units are owned by DOMA,
+    // others are sold by developers
+    #[serde(rename_all = "UPPERCASE")]
+    BuildUnits {
+        neighborhood: usize,
+        units: usize,
+        #[serde(default)]
+        public: bool,
+    },
+
+    // Change in all wages, e.g. -0.1
+    #[serde(rename_all = "UPPERCASE")]
+    IncomeShock { change: f32 },
+
+    // Grow the map by ROWS at the bottom and COLS at
+    // the right, split into NEIGHBORHOODS new ones
+    #[serde(rename_all = "UPPERCASE")]
+    Expand {
+        #[serde(default)]
+        rows: usize,
+        #[serde(default)]
+        cols: usize,
+        neighborhoods: usize,
+    },
+
+    // Move parcels, as (row, col), into a neighborhood,
+    // by design neighborhood id, e.g. a rezoning
+    #[serde(rename_all = "UPPERCASE")]
+    Redistrict {
+        neighborhood: usize,
+        parcels: Vec<Position>,
+    },
+
+    // Change the zoning of parcels, as (row, col), and of
+    // all parcels in the NEIGHBORHOOD, by design id, if
+    // given, e.g. to upzone. What's unset stays as it was
+    #[serde(rename_all = "UPPERCASE")]
+    Rezone {
+        #[serde(default)]
+        neighborhood: Option<usize>,
+        #[serde(default)]
+        parcels: Vec<Position>,
+        #[serde(default)]
+        max_units: Option<usize>,
+        #[serde(default, rename = "USE")]
+        land_use: Option<LandUse>,
+    },
+}
+
+impl Intervention {
+    pub fn apply(&self, sim: &mut Simulation, rng: &mut StdRng) {
+        match self {
+            Intervention::RentFreeze { months } => {
+                sim.policies.push((Policy::RentFreeze, *months));
+            }
+            Intervention::MarketTax { months } => {
+                sim.policies.push((Policy::MarketTax, *months));
+            }
+            Intervention::BuildUnits { neighborhood, units, public } => {
+                match sim.city.neighborhoods.iter().position(|n| n.id as usize == *neighborhood) {
+                    Some(neighb_id) => build_units(sim, neighb_id, *units, *public, rng),
+                    None => warn!("No neighborhood {:?} to build units in", neighborhood),
+                }
+            }
+            Intervention::Redistrict { neighborhood, parcels } => {
+                match sim.city.neighborhoods.iter().position(|n| n.id as usize == *neighborhood) {
+                    Some(neighb_id) => redistrict(sim, neighb_id, parcels),
+                    None => warn!("No neighborhood {:?} to move parcels into", neighborhood),
+                }
+            }
+            Intervention::Rezone { neighborhood, parcels, max_units, land_use } => {
+                let mut parcels = parcels.clone();
+                if let Some(neighborhood) = neighborhood {
+                    match sim.city.neighborhoods.iter().position(|n| n.id as usize == *neighborhood) {
+                        Some(neighb_id) => parcels.extend(sim.city.parcels.values()
+                            .filter(|p| p.neighborhood == Some(neighb_id))
+                            .map(|p| p.pos)),
+                        None => warn!("No neighborhood {:?} to rezone", neighborhood),
+                    }
+                }
+                rezone(sim, &parcels, *max_units, *land_use);
+            }
+            Intervention::Expand { rows, cols, neighborhoods } => {
+                expand(sim, *rows, *cols, *neighborhoods, rng);
+            }
+            Intervention::IncomeShock { change } => {
+                for tenant in &mut sim.tenants {
+                    tenant.wage *= 1. + *change;
+                    tenant.income *= 1. + *change;
+                }
+                sim.wage_index *= 1. + *change;
+            }
+        }
+    }
+}
+
+// Units are spread over the neighborhood's
+// least dense parcels, regardless of zoning,
+// and rented at the neighborhood's going rate
+fn build_units(sim: &mut Simulation, neighb_id: usize, n_units: usize, public: bool, rng: &mut StdRng) {
+    let city = &mut sim.city;
+    let mut parcels: Vec<(Position, usize)> = city.residential_parcels_by_neighborhood[neighb_id]
+        .iter()
+        .map(|pos| (*pos, city.buildings.get(pos).map_or(0, |b| b.units.len())))
+        .collect();
+    if parcels.is_empty() {
+        warn!("No residential parcels to build units on");
+        return;
+    }
+
+    let existing = &city.units_by_neighborhood[neighb_id];
+    let rent_per_area = if existing.is_empty() {
+        0.
+    } else {
+        existing.iter().map(|&u| city.units[u].rent_per_area()).sum::<f32>() / existing.len() as f32
+    };
+    let (min_area, max_area) = {
+        let neighb = &city.neighborhoods[neighb_id];
+        (neighb.min_area, neighb.max_area)
+    };
+    let price_to_rent_ratio = sim.design.city.price_to_rent_ratio;
+    let year = sim.conf.year(sim.time);
+
+    // Without developers, all units are public
+    let n_developers = sim.developers.len();
+    for i in 0..n_units {
+        let parcel = parcels.iter_mut().min_by_key(|(_, n)| *n).unwrap();
+        parcel.1 += 1;
+        let pos = parcel.0;
+
+        let area = rng.gen_range(min_area, max_area) as f32;
+        let rent = rent_per_area * area;
+        let value = rent * 12. * price_to_rent_ratio * city.parcels.get(&pos).unwrap().desirability;
+        if public || n_developers == 0 {
+            let id = city.add_unit(pos, area, rent, value, year, (AgentType::DOMA, 0));
+            sim.doma.units.push(id);
+        } else {
+            let developer = &mut sim.developers[i % n_developers];
+            let id = city.add_unit(pos, area, rent, value, year, (AgentType::Developer, developer.id));
+            developer.units.push(id);
+            developer.cost_basis.insert(id, area * sim.conf.construction_cost_per_area);
+        }
+    }
+}
+
+// Parcels keep their units, which now count towards
+// their new neighborhood. Rent histories of the
+// neighborhoods that changed no longer describe
+// them, so agents and stats start them over
+fn redistrict(sim: &mut Simulation, neighb_id: usize, parcels: &[Position]) {
+    let (rows, cols) = (sim.city.grid.rows as isize, sim.city.grid.cols as isize);
+    let mut changed = vec![neighb_id];
+    for &pos in parcels {
+        if pos.0 < 0 || pos.1 < 0 || pos.0 >= rows || pos.1 >= cols {
+            warn!("No parcel at {:?} to move", pos);
+            continue;
+        }
+        match sim.city.reassign_parcel(pos, neighb_id, &sim.conf) {
+            Some(Some(prev)) if !changed.contains(&prev) => changed.push(prev),
+            _ => {}
+        }
+    }
+
+    for &n in &changed {
+        for landlord in &mut sim.landlords {
+            landlord.rent_obvs.insert(n, Vec::new());
+        }
+        for developer in &mut sim.developers {
+            developer.rent_obvs.insert(n, Vec::new());
+        }
+        for speculator in &mut sim.speculators {
+            speculator.rent_obvs.insert(n, Vec::new());
+        }
+        sim.market.reset(n);
+        sim.gentrification.reset(n);
+    }
+}
+
+fn rezone(sim: &mut Simulation, parcels: &[Position], max_units: Option<usize>, land_use: Option<LandUse>) {
+    let (rows, cols) = (sim.city.grid.rows as isize, sim.city.grid.cols as isize);
+    for pos in parcels {
+        let in_bounds = pos.0 >= 0 && pos.1 >= 0 && pos.0 < rows && pos.1 < cols;
+        let parcel = match if in_bounds { sim.city.parcels.get_mut(pos) } else { None } {
+            Some(parcel) => parcel,
+            None => {
+                warn!("No parcel at {:?} to rezone", pos);
+                continue;
+            }
+        };
+        if max_units.is_some() {
+            parcel.zoning.max_units = max_units;
+        }
+        if let Some(land_use) = land_use {
+            parcel.zoning.land_use = land_use;
+        }
+    }
+}
+
+// Sprawl at the edge of the city. New neighborhoods are
+// regions around random centers on the new land, built up
+// like the city's sparsest, least desirable neighborhood,
+// and their units are sold by developers, or if there
+// are none, start out with landlords, or with DOMA
+fn expand(sim: &mut Simulation, rows: usize, cols: usize, n_neighborhoods: usize, rng: &mut StdRng) {
+    let (prev_rows, prev_cols) = (sim.city.grid.rows, sim.city.grid.cols);
+    if (rows == 0 && cols == 0) || n_neighborhoods == 0 {
+        warn!("Nothing to expand the map by");
+        return;
+    }
+    let template = match sim.city.neighborhoods.iter().filter(|n| n.max_units > 0).min_by_key(|n| n.max_units) {
+        Some(template) => template.clone(),
+        None => {
+            warn!("No residential neighborhood to build new ones like");
+            return;
+        }
+    };
+    sim.city.expand(rows, cols, &sim.conf);
+    let new_positions: Vec<Position> = (0..sim.city.grid.rows)
+        .flat_map(|r| (0..sim.city.grid.cols).map(move |c| (r, c)))
+        .filter(|&(r, c)| r >= prev_rows || c >= prev_cols)
+        .map(|(r, c)| (r as isize, c as isize))
+        .collect();
+
+    let desirability = sim.city.neighborhoods.iter().map(|n| n.desirability).fold(f32::INFINITY, f32::min);
+    let first_id = sim.design.neighborhoods.keys().max().map_or(0, |id| id + 1);
+    let centers: Vec<Position> = new_positions.choose_multiple(rng, n_neighborhoods).cloned().collect();
+    let mut neighb_ids = Vec::with_capacity(centers.len());
+    for design_id in (first_id..).take(centers.len()) {
+        let neighb = Neighborhood {
+            id: design_id as isize,
+            name: format!("N{}", design_id),
+            desirability: desirability,
+            shelter_capacity: 0,
+            ..template.clone()
+        };
+        sim.design.neighborhoods.insert(design_id, neighb.clone());
+        let neighb_id = sim.city.add_neighborhood(neighb, rng);
+        for landlord in &mut sim.landlords {
+            landlord.rent_obvs.insert(neighb_id, Vec::new());
+            landlord.trend_ests.insert(neighb_id, 0.);
+            landlord.invest_ests.insert(neighb_id, 0.);
+        }
+        for developer in &mut sim.developers {
+            developer.rent_obvs.insert(neighb_id, Vec::new());
+        }
+        for speculator in &mut sim.speculators {
+            speculator.rent_obvs.insert(neighb_id, Vec::new());
+            speculator.trend_ests.insert(neighb_id, 1.);
+        }
+        sim.gentrification.add_neighborhood();
+        neighb_ids.push((neighb_id, design_id));
+    }
+
+    // The design's map grows too,
+    // as it's what the frontend draws
+    let layout = &mut sim.design.map.layout;
+    for row in layout.iter_mut() {
+        row.resize(sim.city.grid.cols, None);
+    }
+    layout.resize(sim.city.grid.rows, vec![None; sim.city.grid.cols]);
+
+    let price_per_sqm = sim.design.city.price_per_sqm;
+    let price_to_rent_ratio = sim.design.city.price_to_rent_ratio;
+    let year = sim.conf.year(sim.time);
+    let n_developers = sim.developers.len();
+    let mut n_units = 0;
+    for pos in new_positions {
+        let nearest = centers.iter()
+            .map(|&center| sim.city.grid.distance(pos, center))
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap().0;
+        let (neighb_id, design_id) = neighb_ids[nearest];
+        sim.design.map.layout[pos.0 as usize][pos.1 as usize] = Some(format!("{}|Residential", design_id));
+        sim.city.add_parcel(pos, neighb_id, desirability, &sim.conf);
+
+        let parcel_desirability = sim.city.parcels.get(&pos).unwrap().desirability;
+        for _ in 0..rng.gen_range(template.min_units, template.max_units) {
+            let area = rng.gen_range(template.min_area, template.max_area) as f32;
+            let rent = price_per_sqm * area * desirability / price_to_rent_ratio / 12.;
+            let value = price_to_rent_ratio * rent * 12. * parcel_desirability;
+            if n_developers > 0 {
+                let developer = &mut sim.developers[n_units % n_developers];
+                let id = sim.city.add_unit(pos, area, rent, value, year, (AgentType::Developer, developer.id));
+                developer.units.push(id);
+                developer.cost_basis.insert(id, area * sim.conf.construction_cost_per_area);
+            } else if let Some(landlord) = sim.landlords.choose_mut(rng) {
+                let id = sim.city.add_unit(pos, area, rent, value, year, (AgentType::Landlord, landlord.id));
+                landlord.units.push(id);
+            } else {
+                let id = sim.city.add_unit(pos, area, rent, value, year, (AgentType::DOMA, 0));
+                sim.doma.units.push(id);
+            }
+            n_units += 1;
+        }
+    }
+    info!("Expanded the map to {}x{}, with {:?} new units", sim.city.grid.rows, sim.city.grid.cols, n_units);
+}
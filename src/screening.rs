@@ -0,0 +1,159 @@
+use super::agent::{AgentType, Landlord, Tenant};
+use super::arena::{Arena, TenantId, UnitId};
+use super::city::Unit;
+use super::config::Config;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use strum_macros::Display;
+
+// Rules a landlord archetype screens applicants by.
+// By default, anyone who applies is considered
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ScreeningConfig {
+    // Applicants must make at least this
+    // many times their share of the rent
+    #[serde(default)]
+    pub income_multiple: Option<f32>,
+
+    // Turn away applicants with an eviction record
+    #[serde(default)]
+    pub reject_evicted: bool,
+
+    // Turn away applicants holding housing vouchers
+    #[serde(default)]
+    pub refuse_vouchers: bool,
+
+    // Chance of turning away an applicant
+    // for being in each of `TENANT_GROUPS`
+    #[serde(default)]
+    pub group_denial: BTreeMap<String, f32>,
+}
+
+#[derive(Display, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DenialReason {
+    Income,
+    Eviction,
+    Voucher,
+    Group,
+}
+
+// A tenant's application to a unit this step,
+// and why it was denied, if it was
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Screened {
+    pub tenant: TenantId,
+    pub unit: UnitId,
+    pub denied: Option<DenialReason>,
+}
+
+// Groups are dealt out by id rather than sampled,
+// so the proportions hold for newcomers too and
+// don't use the RNG. `None` if there are no groups
+pub fn assign_group(id: TenantId, conf: &Config) -> Option<usize> {
+    if conf.tenant_groups.is_empty() {
+        return None;
+    }
+    let total: f32 = conf.tenant_groups.values().sum();
+    let p = (id.0 as f64 * 0.618_033_988_7).fract() as f32 * total;
+    let mut cumulative = 0.;
+    for (i, share) in conf.tenant_groups.values().enumerate() {
+        cumulative += share;
+        if p < cumulative {
+            return Some(i);
+        }
+    }
+    Some(conf.tenant_groups.len() - 1)
+}
+
+pub fn group_name(group: usize, conf: &Config) -> &str {
+    conf.tenant_groups.keys().nth(group).unwrap()
+}
+
+// Whether the unit's owner turns away applicants, and why.
+// Only landlords screen, by their archetype's rules
+pub fn screen(members: &[TenantId], unit: &Unit, tenants: &Arena<TenantId, Tenant>, landlords: &[Landlord], rng: &mut StdRng, conf: &Config) -> Option<DenialReason> {
+    if unit.owner.0 != AgentType::Landlord {
+        return None;
+    }
+    let archetype = &landlords[unit.owner.1].archetype;
+    let rules = match conf.landlord_archetypes.get(archetype) {
+        Some(archetype) => &archetype.screening,
+        None => return None,
+    };
+
+    let applicants = members.iter().map(|&t_id| &tenants[t_id]);
+    if rules.refuse_vouchers && applicants.clone().any(|t| t.voucher.is_some()) {
+        return Some(DenialReason::Voucher);
+    }
+    if rules.reject_evicted && applicants.clone().any(|t| t.evicted) {
+        return Some(DenialReason::Eviction);
+    }
+    if let Some(multiple) = rules.income_multiple {
+        let income = applicants.clone().fold(0., |acc, t| acc + t.income);
+        let rent = unit.rent * members.len() as f32 / unit.occupancy as f32;
+        if income < multiple * rent {
+            return Some(DenialReason::Income);
+        }
+    }
+    for t in applicants {
+        let p = t.group
+            .and_then(|group| rules.group_denial.get(group_name(group, conf)))
+            .cloned()
+            .unwrap_or(0.);
+        if p > 0. && rng.gen::<f32>() < p {
+            return Some(DenialReason::Group);
+        }
+    }
+    None
+}
+
+// Applications and denials by who applied: each
+// tenant group, voucher holders, and those with an
+// eviction record, for comparing denial rates
+pub fn audit(screened: &[Screened], tenants: &Arena<TenantId, Tenant>, conf: &Config) -> Map<String, Value> {
+    let mut counts: BTreeMap<String, (usize, BTreeMap<DenialReason, usize>)> = BTreeMap::new();
+    for s in screened {
+        let tenant = &tenants[s.tenant];
+        let mut keys = vec!["all".to_string()];
+        if let Some(group) = tenant.group {
+            keys.push(group_name(group, conf).to_string());
+        }
+        if tenant.voucher.is_some() {
+            keys.push("voucher_holders".to_string());
+        }
+        if tenant.evicted {
+            keys.push("evicted".to_string());
+        }
+        for key in keys {
+            let entry = counts.entry(key).or_default();
+            entry.0 += 1;
+            if let Some(reason) = s.denied {
+                *entry.1.entry(reason).or_default() += 1;
+            }
+        }
+    }
+
+    // Relative to the denial rate for everyone
+    let rate = |(applications, denials): &(usize, BTreeMap<DenialReason, usize>)| {
+        denials.values().sum::<usize>() as f32 / f32::max(1., *applications as f32)
+    };
+    let overall = counts.get("all").map_or(0., rate);
+    counts.iter().map(|(key, count)| {
+        let mut stats = json!({
+            "applications": count.0,
+            "denials": count.1.values().sum::<usize>(),
+            "denial_rate": rate(count),
+            "relative_denial_rate": if overall > 0. { rate(count) / overall } else { 0. },
+        });
+        for reason in &[DenialReason::Income, DenialReason::Eviction, DenialReason::Voucher, DenialReason::Group] {
+            stats[format!("denied_{}", reason)] = json!(count.1.get(reason).unwrap_or(&0));
+        }
+        (key.clone(), stats)
+    }).collect()
+}
@@ -1,81 +1,176 @@
-use super::agent::{AgentType, Landlord, Tenant, DOMA};
-use super::city::{City, Unit};
-use super::social::{SocialGraph};
+use super::arena::{Arena, TenantId, UnitId};
+use super::agent::{assign_archetypes, AgentType, Decision, FundingRound, Landlord, Tenant, Developer, DevelopmentMarket, Speculator, Voucher, DOMA};
+use super::city::{City, ParcelType, Unit};
+use super::social::{n_friends, Hearsay, SocialGraph};
+use super::household::Households;
+use super::market::{self, Applicant, MarketStats};
 use super::config::Config;
 use super::policy::Policy;
 use super::design::Design;
+use super::economy::Conditions;
+use super::employment::Employment;
+use super::events::SimEvent;
+use super::grid::Position;
+use super::gentrification::Gentrification;
+use super::drift::Drift;
+use super::profile::Profiler;
+use super::commerce::Commerce;
+use super::rng::{step_seed, RngStreams};
+use super::scenario::Intervention;
+use super::screening::{assign_group, Screened};
+use super::union::Unions;
 use noise::NoiseFn;
-use rand::distributions::WeightedIndex;
-use rand_distr::{LogNormal, Distribution};
+use rand_distr::LogNormal;
 use rand::prelude::*;
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use tracing::{info, info_span};
 
+#[derive(Serialize, Deserialize)]
 pub struct Simulation {
     pub time: usize,
     pub city: City,
     pub doma: DOMA,
     pub conf: Config,
-    pub tenants: Vec<Tenant>,
+    pub tenants: Arena<TenantId, Tenant>,
     pub landlords: Vec<Landlord>,
+    pub developers: Vec<Developer>,
+    pub speculators: Vec<Speculator>,
     pub policies: Vec<(Policy, usize)>,
     pub social_graph: SocialGraph,
+    pub households: Households,
+    pub unions: Unions,
+
+    // Workplace and neighborhood ties, which
+    // tenants hear of places to live through
+    pub ties: SocialGraph,
+
+    pub employment: Employment,
     pub design: Design,
-    transfers: Vec<(AgentType, usize, usize, f32)>,
+
+    // Unit sales made this step:
+    // (buyer type, buyer id, unit id, price)
+    pub transfers: Vec<(AgentType, usize, UnitId, f32)>,
+
+    // Evictions made this step:
+    // (unit id, tenant id)
+    pub evictions: Vec<(UnitId, TenantId)>,
+
+    // Leases speculators let run out this step,
+    // to hold the units vacant: (unit id, tenant id)
+    #[serde(default)]
+    pub lease_endings: Vec<(UnitId, TenantId)>,
+
+    // Applications made this step, and
+    // whether landlords turned them away
+    pub screened: Vec<Screened>,
+
+    // Tenants forced out this step of a neighborhood
+    // they'd lived in for a while: (tenant id, neighborhood id)
+    pub displacements: Vec<(TenantId, usize)>,
+    pub gentrification: Gentrification,
+    pub drift: Drift,
+    pub commerce: Commerce,
+
+    // Economic conditions this step, and
+    // citywide wage growth since the start
+    pub economy: Conditions,
+    pub wage_index: f32,
+
+    // Tenants who arrived in or
+    // left the city this step
+    pub arrivals: Vec<TenantId>,
+    pub departures: Vec<TenantId>,
+
+    // Rents per neighborhood, as of
+    // the start of this step
+    pub market: MarketStats,
+
+    // Scripted interventions applied this step
+    #[serde(skip)]
+    pub interventions: Vec<Intervention>,
+
+    // Time spent in each phase of this step
+    #[serde(skip)]
+    pub profiler: Profiler,
 
     // For random iteration over populations
     landlord_order: Vec<usize>,
-    tenant_order: Vec<usize>,
+    tenant_order: Vec<TenantId>,
 }
 
 impl Simulation {
+    // Save the full world state, e.g. after burn-in,
+    // so it can be reused instead of recomputed.
+    // RNG streams are derived from the seed and time,
+    // so a restored simulation makes the same random draws.
+    // State that's iterated over is kept in ordered maps,
+    // so it's visited in the same order after restoring
+    pub fn save_checkpoint(&self, path: &str) -> bincode::Result<()> {
+        let file = File::create(path)?;
+        bincode::serialize_into(BufWriter::new(file), self)
+    }
+
+    pub fn from_checkpoint(path: &str) -> bincode::Result<Simulation> {
+        let file = File::open(path)?;
+        let mut sim: Simulation = bincode::deserialize_from(BufReader::new(file))?;
+        sim.city.restore_trends();
+        sim.city.restore_commute_times(&sim.conf);
+        sim.employment.restore();
+        sim.drift.restore();
+        Ok(sim)
+    }
+
     pub fn new(design: Design, config: Config, mut rng: &mut StdRng) -> Simulation {
         // Generate city from provided design
-        println!("Creating city...");
-        let mut city = City::new(&design, &mut rng);
+        info!("Creating city...");
+        let mut city = City::new(&design, &config, &mut rng);
 
         // Create landlords
-        let mut landlords: Vec<Landlord> = (0..design.city.landlords)
-            .map(|i| Landlord::new(i as usize, design.neighborhoods.len()))
+        let archetypes = assign_archetypes(design.city.landlords as usize, &config.landlord_archetypes);
+        let mut landlords: Vec<Landlord> = archetypes.into_iter().enumerate()
+            .map(|(i, archetype)| Landlord::new(i, archetype, design.neighborhoods.len(), config.landlord_starting_cash))
+            .collect();
+
+        // Create developers
+        let developers: Vec<Developer> = (0..config.developers)
+            .map(|i| Developer::new(i, design.neighborhoods.len(), config.developer_starting_cash))
+            .collect();
+
+        // Create speculators
+        let speculators: Vec<Speculator> = (0..config.speculators)
+            .map(|i| Speculator::new(i, design.neighborhoods.len(), config.speculator_starting_cash))
             .collect();
 
         // Create tenants
-        println!("Creating tenants...");
+        info!("Creating tenants...");
         let income_dist = LogNormal::new(design.city.income_mu, design.city.income_sigma).unwrap();
-        let mut commercial = Vec::new();
-        let mut commercial_weights = Vec::new();
-        for (pos, n) in city.commercial.iter() {
-            commercial.push(pos);
-            commercial_weights.push(n);
-        }
-        let work_dist = WeightedIndex::new(commercial_weights).unwrap();
-        let vacancies: Vec<usize> = city.units.iter().map(|u| u.id).collect();
+        let employment = Employment::new(&design, &city);
+        let vacancies: Vec<UnitId> = city.units.ids().collect();
         let occupancy = city.units.iter().fold(0, |acc, u| acc + u.occupancy);
         let population_size = (config.pop_p_occupancy * occupancy as f32).round() as usize;
-        let mut tenants: Vec<Tenant> = (0..population_size)
+        let mut tenants: Arena<TenantId, Tenant> = (0..population_size)
             .map(|i| {
-                let tenant_id = i as usize;
-                let income = income_dist.sample(&mut rng);
-                let work_pos = commercial[work_dist.sample(&mut rng)];
-
-                let mut tenant = Tenant {
-                    id: tenant_id,
-                    unit: None,
-                    units: Vec::new(),
-                    income: income,
-                    work: work_pos,
-                    last_dividend: 0.,
-                    player: false,
-                };
+                let tenant_id = TenantId(i);
+                let (center, work_pos, income) = employment.hire(&income_dist, &mut rng);
+                let savings = initial_savings(income, &config, &mut rng);
+
+                let mut tenant = Tenant::new(tenant_id, income, savings, work_pos);
+                tenant.center = center;
+                tenant.group = assign_group(tenant_id, &config);
 
-                let lease_month = rng.gen_range(0, 11) as usize;
-                let (best_id, best_desirability) = vacancies.iter().fold((0, 0.), |acc, &u_id| {
+                let lease_start = rng.gen_range(0, config.time_step.steps_per_year() - 1);
+                let (best_id, best_desirability) = vacancies.iter().fold((UnitId(0), 0.), |acc, &u_id| {
                     let u = &city.units[u_id];
-                    let p = &city.parcels.get(&u.pos).unwrap();
-                    if u.vacancies() <= 0 {
+                    if u.vacancies() == 0 {
                         acc
                     } else {
-                        let desirability = tenant.desirability(u, p);
+                        let desirability = tenant.desirability(u, &city, &config);
                         if desirability > acc.1 {
                             (u_id, desirability)
                         } else {
@@ -83,22 +178,27 @@ impl Simulation {
                         }
                     }
                 });
-                tenant.unit = if best_desirability > 0. {
-                    let u = &mut city.units[best_id];
-                    u.tenants.insert(tenant_id);
-                    u.lease_month = lease_month;
-                    Some(best_id)
-                } else {
-                    None
-                };
+                if best_desirability > 0. {
+                    // They paid a deposit when they moved in,
+                    // so it's held like anyone else's
+                    city.move_tenant(&mut tenant, best_id, lease_start, &config);
+                    let unit = &mut city.units[best_id];
+                    let deposit = f32::min(tenant.savings, tenant.deposit(unit, &config));
+                    tenant.savings -= deposit;
+                    unit.hold_deposit(tenant_id, deposit);
+                }
 
                 tenant
             })
             .collect();
 
         // Create social network
-        println!("Creating social network...");
+        info!("Creating social network...");
         let social_graph = SocialGraph::new(tenants.len(), config.friend_limit, &mut rng);
+        let ties = match &config.social {
+            Some(social) => SocialGraph::local_ties(&tenants, &city, social, &mut rng),
+            None => SocialGraph::from(vec![Vec::new(); tenants.len()]),
+        };
 
         // Distribute ownership of units
         for (_, b) in city.buildings.iter() {
@@ -111,14 +211,14 @@ impl Simulation {
                         landlord.units.push(u.id);
                         (AgentType::Landlord, landlord.id)
                     } else if roll < 0.9 {
-                        let unit_tenants: Vec<usize> = u.tenants.iter().cloned().collect();
+                        let unit_tenants: Vec<TenantId> = u.tenants.iter().cloned().collect();
                         let t_id = *unit_tenants.choose(&mut rng).unwrap();
                         tenants[t_id].units.push(u.id);
-                        (AgentType::Tenant, t_id)
+                        (AgentType::Tenant, t_id.0)
                     } else {
-                        let tenant = tenants.choose_mut(&mut rng).unwrap();
+                        let tenant = tenants.as_mut_slice().choose_mut(&mut rng).unwrap();
                         tenant.units.push(u.id);
-                        (AgentType::Tenant, tenant.id)
+                        (AgentType::Tenant, tenant.id.0)
                     }
                 } else {
                     if roll < 0.75 {
@@ -126,13 +226,14 @@ impl Simulation {
                         landlord.units.push(u.id);
                         (AgentType::Landlord, landlord.id)
                     } else {
-                        let tenant = tenants.choose_mut(&mut rng).unwrap();
+                        let tenant = tenants.as_mut_slice().choose_mut(&mut rng).unwrap();
                         tenant.units.push(u.id);
-                        (AgentType::Tenant, tenant.id)
+                        (AgentType::Tenant, tenant.id.0)
                     }
                 };
             }
         }
+        city.index_vacancies();
 
         let doma = DOMA::new(
             config.doma_starting_funds,
@@ -140,28 +241,101 @@ impl Simulation {
             config.doma_p_reserves,
             config.doma_p_expenses,
             config.doma_rent_income_limit,
+            FundingRound::new(1, config.doma_round_target, config.steps(config.doma_round_months)),
         );
 
+        let gentrification = Gentrification::new(city.neighborhoods.len());
+        let commerce = Commerce::new(&city);
+        let economy = Conditions::at(0., config.mortgage_interest_rate, &config.economy);
+        let drift = Drift::new(rng.gen());
         let landlord_order = (0..landlords.len()).collect();
-        let tenant_order = (0..tenants.len()).collect();
+        let tenant_order = tenants.ids().collect();
 
         Simulation {
             time: 0,
             city: city,
             conf: config,
             landlords: landlords,
+            developers: developers,
+            speculators: speculators,
             tenants: tenants,
             doma: doma,
             design: design,
             policies: Vec::new(),
             social_graph: social_graph,
+            households: Households::new(),
+            unions: Unions::new(),
+            ties: ties,
+            employment: employment,
             landlord_order: landlord_order,
             tenant_order: tenant_order,
-            transfers: Vec::new()
+            transfers: Vec::new(),
+            evictions: Vec::new(),
+            lease_endings: Vec::new(),
+            screened: Vec::new(),
+            displacements: Vec::new(),
+            gentrification: gentrification,
+            drift: drift,
+            commerce: commerce,
+            economy: economy,
+            wage_index: 1.,
+            arrivals: Vec::new(),
+            departures: Vec::new(),
+            market: MarketStats::default(),
+            interventions: Vec::new(),
+            profiler: Profiler::default(),
         }
     }
 
-    pub fn step(&mut self, mut rng: &mut StdRng) {
+    pub fn step(&mut self) {
+        let _span = info_span!("step", month = self.time).entered();
+
+        // Each agent gets its own RNG stream for this step,
+        // derived from the seed and time, so runs are reproducible
+        // and can be resumed from a checkpoint
+        let mut streams = RngStreams::new(
+            step_seed(self.conf.seed, self.time),
+            self.tenants.len(),
+            self.landlords.len(),
+            self.developers.len(),
+            self.speculators.len(),
+        );
+        let mut rng = &mut streams.world;
+        let prev_units: Vec<Option<UnitId>> = self.tenants.iter().map(|t| t.unit).collect();
+        self.economy = Conditions::at(self.time as f32 * self.conf.time_step.months(), self.conf.mortgage_interest_rate, &self.conf.economy);
+
+        self.city.events.clear();
+        self.profiler.start();
+
+        // Scripted interventions come first,
+        // so agents respond to them this step.
+        // Months are numbered as in the output,
+        // where this step's results are month `time + 1`,
+        // the design's redistricting included
+        let month = self.time + 1;
+        self.interventions = self.conf.scenarios.iter()
+            .filter(|s| s.month == month)
+            .map(|s| s.intervention.clone())
+            .chain(self.design.redistricting.iter()
+                .filter(|r| r.month == month)
+                .map(|r| Intervention::Redistrict {
+                    neighborhood: r.neighborhood,
+                    parcels: r.parcels.iter().map(|&(row, col)| (row as isize, col as isize)).collect(),
+                }))
+            .collect();
+        for intervention in self.interventions.clone() {
+            intervention.apply(self, rng);
+            self.city.events.push(SimEvent::Intervention {
+                intervention: intervention,
+            });
+        }
+        for narration in self.conf.narration.iter().filter(|n| n.month == month) {
+            self.city.events.push(SimEvent::Narration {
+                message: narration.message.clone(),
+            });
+        }
+        self.profiler.lap("interventions");
+
         let mut rent_freeze = false;
         let mut market_tax = false;
         for (p, _) in &self.policies {
@@ -171,6 +345,7 @@ impl Simulation {
             }
         }
 
+        self.transfers.clear();
         for tenant in &mut self.tenants {
             self.transfers.extend(
                 tenant.check_purchase_offers(&mut self.city, self.design.city.price_to_rent_ratio),
@@ -178,57 +353,205 @@ impl Simulation {
         }
         for landlord in &mut self.landlords {
             self.transfers.extend(
-                landlord
-                    .check_purchase_offers(&mut self.city, self.design.city.price_to_rent_ratio),
+                landlord.check_purchase_offers(
+                    &mut self.city,
+                    self.design.city.price_to_rent_ratio,
+                    self.conf.steps(self.conf.max_unprofitable_months),
+                ),
             );
         }
-        for (landlord_typ, landlord_id, unit_id, amount) in self.transfers.drain(..) {
+        for developer in &mut self.developers {
+            self.transfers.extend(developer.check_purchase_offers(&mut self.city));
+        }
+        for speculator in &mut self.speculators {
+            self.transfers.extend(speculator.check_purchase_offers(&mut self.city, self.conf.speculator_markup));
+        }
+        for &(landlord_typ, landlord_id, unit_id, amount) in &self.transfers {
+            // New owners decide for
+            // themselves whether to rent short-term
+            self.city.units[unit_id].short_term = false;
+            self.city.refresh_vacancy(unit_id);
             match landlord_typ {
                 AgentType::Landlord => {
                     let landlord = &mut self.landlords[landlord_id];
                     landlord.units.push(unit_id);
+                    landlord.finance_purchase(unit_id, amount, self.economy.interest_rate, &self.conf);
                 }
                 AgentType::DOMA => {
-                    self.doma.units.push(unit_id);
-                    self.doma.funds -= amount;
+                    let unit = &mut self.city.units[unit_id];
+                    self.doma.acquire(unit, amount, self.design.city.price_to_rent_ratio);
+                    self.city.events.push(SimEvent::DomaAcquired {
+                        unit: unit_id,
+                        price: amount,
+                    });
+                }
+                AgentType::Speculator => {
+                    self.speculators[landlord_id].acquire(unit_id, amount);
                 }
                 _ => {}
             }
         }
+        self.profiler.lap("sales");
 
+        // Landlords, developers, and speculators
+        // all read the market as it is now
+        self.market.update(&self.city, self.conf.steps(self.conf.trend_months));
+        self.market.estimate_values(&mut self.city, self.design.city.price_to_rent_ratio, self.conf.steps(self.conf.trend_months), &self.conf);
+        self.profiler.lap("market");
+        let landlords_span = info_span!("landlords").entered();
         self.landlord_order.shuffle(&mut rng);
         for &landlord_id in &self.landlord_order {
             self.landlords[landlord_id].step(
                 &mut self.city,
                 self.time,
-                self.design.city.price_to_rent_ratio,
                 rent_freeze,
                 market_tax,
-                &mut rng,
+                &self.market,
+                &mut streams.landlords[landlord_id],
                 &self.conf,
             );
         }
 
-        let mut vacant_units: Vec<usize> = self
-            .city
-            .units
-            .iter()
-            .filter(|u| u.vacancies() > 0)
-            .map(|u| u.id)
-            .collect();
+        let held: Vec<Position> = self.developers.iter().flat_map(|d| d.parcels.iter().cloned()).collect();
+        let development = DevelopmentMarket {
+            stats: &self.market,
+            price_to_rent_ratio: self.design.city.price_to_rent_ratio,
+            held: &held,
+        };
+        for (developer, rng) in self.developers.iter_mut().zip(streams.developers.iter_mut()) {
+            developer.step(
+                &mut self.city,
+                self.time,
+                &development,
+                rng,
+                &self.conf,
+            );
+        }
 
-        self.tenant_order.shuffle(&mut rng);
-        for &tenant_id in &self.tenant_order {
+        for (speculator, rng) in self.speculators.iter_mut().zip(streams.speculators.iter_mut()) {
+            speculator.step(
+                &mut self.city,
+                &self.market,
+                rng,
+                &self.conf,
+            );
+        }
+        landlords_span.exit();
+        self.profiler.lap("landlords");
+
+        // Unions respond to the rent raised this step
+        self.unions.update(&mut self.city, &mut self.landlords, &mut self.tenants, self.time, &self.conf);
+
+        self.evictions.clear();
+        for landlord in &self.landlords {
+            self.evictions.extend(
+                landlord.file_evictions(&self.city, &self.tenants, self.conf.steps(self.conf.eviction_grace_months)),
+            );
+        }
+
+        // Union members have longer to catch up on rent
+        if let Some(union_conf) = &self.conf.unions {
+            let grace = self.conf.steps(self.conf.eviction_grace_months + union_conf.eviction_protection_months);
+            let (tenants, city, unions) = (&self.tenants, &self.city, &self.unions);
+            self.evictions.retain(|&(u_id, t_id)| {
+                tenants[t_id].arrears > grace || !unions.protects(city.units[u_id].pos, t_id, union_conf)
+            });
+        }
+
+        for &(unit_id, tenant_id) in &self.evictions {
             let tenant = &mut self.tenants[tenant_id];
-            if !tenant.player {
-                tenant.step(
-                    &mut self.city,
-                    self.time,
-                    &mut vacant_units,
-                    &mut rng,
-                    &self.conf,
-                );
+            self.city.move_out(tenant, &self.conf);
+            tenant.arrears = 0;
+            tenant.evicted = true;
+            self.city.events.push(SimEvent::Evicted {
+                tenant: tenant_id,
+                unit: unit_id,
+            });
+        }
+
+        // Speculators' tenants move out when their lease is up
+        self.lease_endings.clear();
+        for speculator in &self.speculators {
+            self.lease_endings.extend(speculator.end_leases(&self.city, self.time));
+        }
+        for &(unit_id, tenant_id) in &self.lease_endings {
+            let tenant = &mut self.tenants[tenant_id];
+            self.city.move_out(tenant, &self.conf);
+            self.city.events.push(SimEvent::MovedOut {
+                tenant: tenant_id,
+                unit: unit_id,
+            });
+        }
+
+        self.households.update(&mut self.tenants, &mut self.city, &self.social_graph, &mut rng, &self.conf);
+
+        // What tenants hear from their ties, if any
+        let hearsay: Arena<TenantId, Option<Hearsay>> = match &self.conf.social {
+            Some(_) => self.tenants.par_iter()
+                .map(|t| if t.departed { None } else { Some(self.ties.hearsay(t.id, &self.tenants, &self.city)) })
+                .collect::<Vec<_>>().into(),
+            None => Arena::new(),
+        };
+
+        // Households use their lead member's stream
+        let mut household_ids: Vec<usize> = self.households.households.keys().cloned().collect();
+        household_ids.sort();
+        let mut applicants = Vec::new();
+        for h_id in household_ids {
+            let household = &self.households.households[&h_id];
+            let lead = household.members[0];
+            let decision = household.consider(
+                &mut self.tenants,
+                &self.city,
+                self.time,
+                hearsay.get(lead).and_then(|h| h.as_ref()),
+                &mut streams.tenants[lead],
+                &self.conf,
+            );
+            applicants.push(Applicant::new(household.members.clone(), &self.tenants, decision));
+        }
+
+        self.tenant_order.shuffle(&mut rng);
+
+        // Tenants decide in parallel, each with their own RNG
+        // stream so results are reproducible.
+        // Tenants in households move with their household
+        let city = &self.city;
+        let time = self.time;
+        let conf = &self.conf;
+        let hearsay = &hearsay;
+        let decisions: Arena<TenantId, Option<Decision>> = self
+            .tenants
+            .par_iter()
+            .zip(streams.tenants.par_iter_mut())
+            .map(|(tenant, rng)| {
+                if tenant.player || tenant.departed || tenant.household.is_some() {
+                    return None;
+                }
+                Some(tenant.consider(city, time, hearsay.get(tenant.id).and_then(|h| h.as_ref()), rng, conf))
+            })
+            .collect::<Vec<_>>().into();
+
+        for (tenant_id, decision) in decisions.ids().zip(decisions.into_vec()) {
+            if let Some(decision) = decision {
+                self.tenants[tenant_id].update_arrears(&decision);
+                applicants.push(Applicant::new(vec![tenant_id], &self.tenants, decision));
+            }
+        }
 
+        // Tenants whose household just dissolved
+        // are first in line to find a new place
+        let moving = std::mem::take(&mut self.households.moving);
+        applicants.sort_by_key(|a| !a.members.iter().any(|t_id| moving.contains(t_id)));
+
+        // Then apply for listed units, with
+        // landlords choosing among applicants
+        self.screened = market::clear(&mut applicants, &mut self.tenants, &mut self.city, &self.landlords, self.time, rng, &self.conf);
+        self.profiler.lap("tenants");
+
+        for &tenant_id in &self.tenant_order {
+            let tenant = &self.tenants[tenant_id];
+            if !tenant.player && !tenant.departed {
                 // Word-of-mouth/contagion
                 let roll: f32 = rng.gen();
                 if roll < self.conf.base_contribute_prob {
@@ -236,13 +559,47 @@ impl Simulation {
                     let infected = self.social_graph.contagion(tenant_id, self.conf.encounter_rate, self.conf.transmission_rate, self.conf.max_contagion_depth, &mut rng);
                     for t_id in infected {
                         let t = &self.tenants[t_id];
+                        if t.departed {
+                            continue;
+                        }
                         self.doma.add_funds(t_id, self.conf.base_contribute_percent * t.income);
                     }
                 }
             }
         }
 
-        if self.time % 12 == 0 {
+        // Jobs shift between centers, and
+        // those shrinking lay off workers
+        self.employment.grow(self.conf.time_step.steps_per_year());
+
+        // Local businesses open and close with
+        // nearby spending, changing what it's like
+        // to live around them and who they employ
+        if let Some(commerce) = &self.conf.commerce {
+            let changes = self.commerce.update(&self.city, &self.tenants, self.conf.time_step, commerce);
+            for (pos, change) in changes {
+                if let Some(parcel) = self.city.parcels.get_mut(&pos) {
+                    if parcel.typ == ParcelType::Residential {
+                        parcel.desirability = f32::max(0., parcel.desirability + change);
+                    }
+                }
+            }
+            self.employment.staff(self.commerce.jobs());
+        }
+        for (tenant, rng) in self.tenants.iter_mut().zip(streams.tenants.iter_mut()) {
+            if tenant.departed {
+                continue;
+            }
+            tenant.update_income(&self.employment, self.economy.income_growth, rng, &self.conf);
+            let pledged = tenant.update_savings(&self.city, rng, &self.conf);
+            if pledged > 0. {
+                self.doma.add_funds(tenant.id, pledged);
+            }
+        }
+        self.wage_index *= 1. + self.conf.time_step.growth(self.economy.income_growth);
+        self.issue_vouchers();
+
+        if self.time.is_multiple_of(self.conf.time_step.steps_per_year()) {
             // Appraise
             for unit_ids in &self.city.units_by_neighborhood {
                 let units: Vec<&Unit> = unit_ids
@@ -270,7 +627,7 @@ impl Simulation {
             }
         }
 
-        self.doma.step(&mut self.city, &mut self.tenants, &mut rng);
+        self.doma.step(&mut self.city, &mut self.tenants, self.conf.year(self.time), &mut streams.doma, &self.conf);
 
         // Desirability changes, random walk
         for (neighb_id, parcel_ids) in self.city.residential_parcels_by_neighborhood.iter().enumerate() {
@@ -291,6 +648,29 @@ impl Simulation {
             }
         }
 
+        if let Some(drift) = &self.conf.parcel_drift {
+            let months = self.conf.time_step.months();
+            let month = (self.time + 1) as f32 * months;
+            for (pos, change) in self.drift.update(&self.city, &self.tenants, month, months, drift) {
+                let parcel = self.city.parcels.get_mut(&pos).unwrap();
+                parcel.desirability = f32::max(0., parcel.desirability + change);
+            }
+        }
+
+        // Gentrification pressure feeds back
+        // into neighborhood desirability
+        let changes = self.gentrification.update(&self.city, &self.tenants, &self.conf);
+        for (neighb_id, parcel_ids) in self.city.residential_parcels_by_neighborhood.iter().enumerate() {
+            let change = changes[neighb_id] * self.conf.gentrification_desirability_effect;
+            for p in parcel_ids {
+                let parcel = self.city.parcels.get_mut(p).unwrap();
+                parcel.desirability = f32::max(0., parcel.desirability + change);
+            }
+        }
+        self.track_displacement(&prev_units);
+        self.shelter();
+        self.migrate(rng);
+
         // Tick policies
         self.policies = self.policies.drain(..).filter_map(|(p, duration)| {
             let d = duration - 1;
@@ -301,6 +681,232 @@ impl Simulation {
             }
         }).collect();
 
+        self.profiler.lap("city");
         self.time += 1;
     }
+
+    // Tenants are displaced if they had to leave a neighborhood
+    // they'd lived in for a while, either by eviction or because
+    // they could no longer afford their place
+    fn track_displacement(&mut self, prev_units: &[Option<UnitId>]) {
+        self.displacements.clear();
+        let neighborhood = |city: &City, unit: Option<UnitId>| {
+            unit.and_then(|u_id| city.parcels.get(&city.units[u_id].pos).unwrap().neighborhood)
+        };
+        for (tenant, &prev_unit) in self.tenants.iter_mut().zip(prev_units) {
+            let prev = neighborhood(&self.city, prev_unit);
+            let now = neighborhood(&self.city, tenant.unit);
+            if now.is_some() && now == prev {
+                tenant.tenure += 1;
+                continue;
+            }
+            if let (Some(neighb_id), Some(u_id)) = (prev, prev_unit) {
+                let evicted = self.evictions.iter().any(|&(_, t_id)| t_id == tenant.id);
+                let unaffordable = tenant.desirability(&self.city.units[u_id], &self.city, &self.conf) == 0.;
+                if tenant.tenure >= self.conf.steps(self.conf.displacement_months) && (evicted || unaffordable) {
+                    self.displacements.push((tenant.id, neighb_id));
+                    self.city.events.push(SimEvent::Displaced {
+                        tenant: tenant.id,
+                        neighborhood: neighb_id,
+                    });
+                }
+            }
+            tenant.tenure = 0;
+        }
+    }
+
+    // Each month, vouchers are renewed for holders still
+    // rent-burdened, then issued to the most burdened tenants,
+    // the homeless first, for as long as the program's budget
+    // can cover what they'd pay out at current rents. Homeless
+    // holders are counted at the most a voucher pays, and
+    // tenants lose theirs when they leave or the budget's spent
+    fn issue_vouchers(&mut self) {
+        let program = match &self.conf.policies.vouchers {
+            Some(program) => program.clone(),
+            None => return
+        };
+        let voucher = Voucher {
+            rent_burden: program.rent_burden,
+            max_subsidy: program.max_subsidy,
+        };
+
+        let mut eligible = Vec::new();
+        for tenant in &mut self.tenants {
+            let held = tenant.voucher.take().is_some();
+            if tenant.departed {
+                continue;
+            }
+            let (subsidy, burden) = match tenant.unit {
+                Some(u_id) => {
+                    let rent = tenant.rent_share(&self.city.units[u_id]);
+                    (voucher.subsidy(rent, tenant.income), rent / f32::max(1., tenant.income))
+                },
+                None => (program.max_subsidy, f32::INFINITY),
+            };
+            if burden > program.rent_burden {
+                eligible.push((tenant.id, held, burden, subsidy));
+            }
+        }
+
+        let mut committed = 0.;
+        eligible.sort_by(|(_, held_a, a, _), (_, held_b, b, _)| held_b.cmp(held_a).then(b.total_cmp(a)));
+        for (t_id, _, _, subsidy) in eligible {
+            if committed + subsidy <= program.budget {
+                committed += subsidy;
+                self.tenants[t_id].voucher = Some(voucher);
+            }
+        }
+    }
+
+    // Homeless tenants take up free shelter beds, near
+    // their work if there's room there, those homeless
+    // longest first. Tenants who find a home, or leave
+    // the city, give up their bed
+    fn shelter(&mut self) {
+        let mut free: Vec<usize> = self.city.neighborhoods.iter().map(|n| n.shelter_capacity).collect();
+        for tenant in &mut self.tenants {
+            if tenant.unit.is_some() || tenant.departed {
+                tenant.shelter = None;
+            }
+            if let Some(neighb_id) = tenant.shelter {
+                free[neighb_id] = free[neighb_id].saturating_sub(1);
+            }
+        }
+
+        let mut unsheltered: Vec<TenantId> = self.tenants.iter()
+            .filter(|t| t.unit.is_none() && t.shelter.is_none() && !t.departed)
+            .map(|t| t.id)
+            .collect();
+        unsheltered.sort_by_key(|&t_id| Reverse(self.tenants[t_id].months_homeless));
+        for t_id in unsheltered {
+            let tenant = &mut self.tenants[t_id];
+            let near_work = self.city.parcels.get(&tenant.work).unwrap().neighborhood;
+            let neighb_id = match near_work.filter(|&n| free[n] > 0).or_else(|| free.iter().position(|&n| n > 0)) {
+                Some(neighb_id) => neighb_id,
+                None => break
+            };
+            free[neighb_id] -= 1;
+            tenant.shelter = Some(neighb_id);
+        }
+    }
+
+    // Tenants who've been homeless long enough may give up
+    // and leave the city, and newcomers arrive, with how
+    // many of each following the economy
+    fn migrate(&mut self, rng: &mut StdRng) {
+        self.departures.clear();
+        let leave_prob = self.conf.time_step.chance(f32::min(1., self.conf.out_migration_prob * self.economy.migration));
+        for tenant in &mut self.tenants {
+            if tenant.departed {
+                continue;
+            }
+            if tenant.unit.is_some() {
+                tenant.months_homeless = 0;
+                continue;
+            }
+            tenant.months_homeless += 1;
+
+            // Households stick together, and
+            // those with a shelter bed stay
+            if tenant.player || tenant.household.is_some() || tenant.shelter.is_some()
+                || tenant.months_homeless < self.conf.steps(self.conf.out_migration_months) {
+                continue;
+            }
+            let roll: f32 = rng.gen();
+            if roll < leave_prob {
+                tenant.departed = true;
+                self.social_graph.remove(tenant.id);
+                self.ties.remove(tenant.id);
+                self.departures.push(tenant.id);
+                self.city.events.push(SimEvent::Departed { tenant: tenant.id });
+            }
+        }
+
+        // Round randomly so that
+        // fractional arrivals add up over time
+        self.arrivals.clear();
+        let population = self.tenants.iter().filter(|t| !t.departed).count();
+        let expected = population as f32 * self.conf.in_migration_rate * self.economy.migration * self.conf.time_step.months();
+        let roll: f32 = rng.gen();
+        let n_arrivals = expected.floor() as usize + if roll < expected.fract() { 1 } else { 0 };
+
+        // Newcomers get new ids so that existing
+        // references to tenants stay valid
+        let income_dist = LogNormal::new(self.design.city.income_mu, self.design.city.income_sigma).unwrap();
+        let n_friends: Vec<usize> = (0..n_arrivals).map(|_| n_friends(self.conf.friend_limit, rng)).collect();
+        for id in self.social_graph.add_nodes(&n_friends, &self.tenants, rng) {
+            let (center, work, wage) = self.employment.hire(&income_dist, rng);
+            let income = wage * self.wage_index;
+            let savings = initial_savings(income, &self.conf, rng);
+            let mut tenant = Tenant::new(id, income, savings, work);
+            tenant.center = center;
+            tenant.group = assign_group(id, &self.conf);
+            self.ties.add_nodes(&[0], &self.tenants, rng);
+            if let Some(social) = &self.conf.social {
+                self.ties.tie_newcomer(&tenant, &self.tenants, social, rng);
+            }
+            self.tenants.push(tenant);
+            self.tenant_order.push(id);
+            self.arrivals.push(id);
+            self.city.events.push(SimEvent::Arrived { tenant: id });
+        }
+    }
+}
+
+// Tenants start with up to `INITIAL_SAVINGS_MONTHS`
+// of their income in savings, or none if it's 0
+fn initial_savings(income: f32, conf: &Config, rng: &mut StdRng) -> f32 {
+    if conf.initial_savings_months > 0. {
+        income * rng.gen_range(0., conf.initial_savings_months)
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::stats;
+    use super::super::testing::{self, fixture};
+    use serde_json::Value;
+    use std::fs;
+
+    static STEPS: usize = 6;
+    static CHECKPOINT_AT: usize = 3;
+
+    fn run(sim: &mut Simulation, steps: usize) -> Vec<Value> {
+        (0..steps).map(|_| {
+            sim.step();
+            stats::stats(sim)
+        }).collect()
+    }
+
+    // A run resumed from a checkpoint
+    // goes on as if it were never stopped
+    #[test]
+    fn resume_from_checkpoint() {
+        let expected = run(&mut fixture(), STEPS);
+
+        let mut sim = fixture();
+        let mut history = run(&mut sim, CHECKPOINT_AT);
+        let path = std::env::temp_dir().join(format!("doma_sim_test_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        sim.save_checkpoint(path).unwrap();
+        drop(sim);
+
+        let mut sim = Simulation::from_checkpoint(path).unwrap();
+        let _ = fs::remove_file(path);
+        history.extend(run(&mut sim, STEPS - CHECKPOINT_AT));
+        assert_eq!(history, expected);
+    }
+
+    #[test]
+    fn no_initial_savings() {
+        let mut conf = testing::config();
+        conf.initial_savings_months = 0.;
+        let mut sim = testing::simulation(conf);
+        assert!(sim.tenants.iter().all(|t| t.savings == 0.));
+        run(&mut sim, 1);
+    }
 }
@@ -1,36 +1,200 @@
-use fnv::FnvHashSet;
-use petgraph::csr::Csr;
+use super::agent::Tenant;
+use super::arena::{Arena, TenantId, UnitId};
+use super::city::{City, Unit};
+use super::grid::Position;
+use fnv::{FnvHashMap, FnvHashSet};
 use rand::prelude::*;
 use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
 
+// Ties among tenants who work or live near each
+// other, which they hear about places to live through
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct SocialConfig {
+    // Ties each tenant forms with others working
+    // at the same place, and with others living
+    // in the same neighborhood
+    pub workplace_ties: usize,
+    pub neighbor_ties: usize,
+
+    // Vacancies heard of through ties a tenant
+    // looks at, out of their search's sample
+    pub referrals: usize,
+
+    // How much more desirable a neighborhood is
+    // for the share of a tenant's ties living there
+    pub tie_preference: f32,
+}
+
+// What a tenant hears from their
+// ties about where to live
+pub struct Hearsay {
+    // Vacant units in buildings where ties live
+    pub referrals: Vec<UnitId>,
+
+    // Share of ties living in each neighborhood
+    pub neighborhoods: Vec<f32>,
+}
+
+impl Hearsay {
+    // A unit's desirability, raised
+    // where more of the tenant's ties live
+    pub fn weigh(&self, desirability: f32, unit: &Unit, city: &City, conf: &SocialConfig) -> f32 {
+        match city.parcels.get(&unit.pos).unwrap().neighborhood {
+            Some(neighb_id) => desirability * (1. + conf.tie_preference * self.neighborhoods[neighb_id]),
+            None => desirability
+        }
+    }
+}
+
+// Saved as each tenant's list of friends
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Vec<TenantId>>", into = "Vec<Vec<TenantId>>")]
 pub struct SocialGraph {
-    graph: Csr<usize, ()>,
+    // Each tenant's friends, by tenant id.
+    // Kept as plain lists rather than a compressed
+    // graph so newcomers can be added cheaply
+    friends: Arena<TenantId, Vec<TenantId>>,
+}
+
+impl From<SocialGraph> for Vec<Vec<TenantId>> {
+    fn from(social_graph: SocialGraph) -> Vec<Vec<TenantId>> {
+        social_graph.friends.into_vec()
+    }
+}
+
+impl From<Vec<Vec<TenantId>>> for SocialGraph {
+    fn from(friends: Vec<Vec<TenantId>>) -> SocialGraph {
+        SocialGraph {
+            friends: friends.into()
+        }
+    }
 }
 
 impl SocialGraph {
     pub fn new(n: usize, friend_limit: usize, mut rng: &mut StdRng) -> SocialGraph {
-        let graph = Csr::<usize, ()>::with_nodes(n);
-        let mut social_graph = SocialGraph {
-            graph: graph
-        };
-        for i in 0..n {
-            let n_friends = rng.gen_range(0, friend_limit) as usize;
-            social_graph.add_random_friends(i, n_friends, &mut rng);
+        let mut social_graph = SocialGraph::from(vec![Vec::new(); n]);
+        for id in (0..n).map(TenantId) {
+            let n_friends = n_friends(friend_limit, rng);
+            social_graph.add_random_friends(id, n_friends, |_| false, &mut rng);
         }
 
         social_graph
     }
 
-    pub fn add_random_friends(&mut self, id: usize, n: usize, rng: &mut StdRng) {
+    // Friendships go both ways, and never
+    // to those who've left the city
+    pub fn add_random_friends<F>(&mut self, id: TenantId, n: usize, departed: F, rng: &mut StdRng) where F: Fn(TenantId) -> bool {
         // There may be some redundancy here,
         // which we accept for simplicity
         for _ in 0..n {
-            let friend = rng.gen_range(0, self.graph.node_count());
-            self.graph.add_edge(id as u32, friend as u32, ());
+            let friend = TenantId(rng.gen_range(0, self.friends.len()));
+            if !departed(friend) {
+                self.tie(id, friend);
+            }
+        }
+    }
+
+    // Add newcomers with the given numbers
+    // of random friends, returning their ids
+    pub fn add_nodes(&mut self, n_friends: &[usize], tenants: &Arena<TenantId, Tenant>, rng: &mut StdRng) -> Vec<TenantId> {
+        let ids: Vec<TenantId> = n_friends.iter().map(|_| self.friends.push(Vec::new())).collect();
+        for (&id, &n) in ids.iter().zip(n_friends) {
+            self.add_random_friends(id, n, |f_id| tenants.get(f_id).is_some_and(|t| t.departed), rng);
+        }
+        ids
+    }
+
+    // A tenant leaving the city drops out
+    // of their friends' lists, and they out of theirs
+    pub fn remove(&mut self, id: TenantId) {
+        for f_id in std::mem::take(&mut self.friends[id]) {
+            self.friends[f_id].retain(|&f| f != id);
         }
     }
 
-    pub fn contagion(&self, start_id: usize, p: f32, t: f32, max_depth: usize, rng: &mut StdRng) -> FnvHashSet<usize> {
+    pub fn friends<'a>(&'a self, id: TenantId) -> impl Iterator<Item=TenantId> + 'a {
+        self.friends[id].iter().cloned()
+    }
+
+    pub fn degree(&self, id: TenantId) -> usize {
+        self.friends[id].len()
+    }
+
+    pub fn tie(&mut self, a: TenantId, b: TenantId) {
+        if a != b && !self.friends[a].contains(&b) {
+            self.friends[a].push(b);
+            self.friends[b].push(a);
+        }
+    }
+
+    // Tie a tenant to up to `n` others in a group
+    fn tie_within(&mut self, id: TenantId, group: &[TenantId], n: usize, rng: &mut StdRng) {
+        for &other in group.choose_multiple(rng, n) {
+            self.tie(id, other);
+        }
+    }
+
+    // Ties among tenants who work at the
+    // same place or live in the same neighborhood
+    pub fn local_ties(tenants: &Arena<TenantId, Tenant>, city: &City, conf: &SocialConfig, rng: &mut StdRng) -> SocialGraph {
+        let mut graph = SocialGraph::from(vec![Vec::new(); tenants.len()]);
+        let mut coworkers: FnvHashMap<Position, Vec<TenantId>> = FnvHashMap::default();
+        let mut neighbors = vec![Vec::new(); city.neighborhoods.len()];
+        for tenant in tenants.iter().filter(|t| !t.departed) {
+            coworkers.entry(tenant.work).or_default().push(tenant.id);
+            if let Some(neighb_id) = home_neighborhood(tenant, city) {
+                neighbors[neighb_id].push(tenant.id);
+            }
+        }
+        for tenant in tenants.iter().filter(|t| !t.departed) {
+            graph.tie_within(tenant.id, &coworkers[&tenant.work], conf.workplace_ties, rng);
+            if let Some(neighb_id) = home_neighborhood(tenant, city) {
+                graph.tie_within(tenant.id, &neighbors[neighb_id], conf.neighbor_ties, rng);
+            }
+        }
+        graph
+    }
+
+    // Newcomers only know people from work at first,
+    // so are tied to coworkers among existing tenants
+    pub fn tie_newcomer(&mut self, newcomer: &Tenant, tenants: &Arena<TenantId, Tenant>, conf: &SocialConfig, rng: &mut StdRng) {
+        let coworkers: Vec<TenantId> = tenants.iter()
+            .filter(|t| !t.departed && t.work == newcomer.work)
+            .map(|t| t.id)
+            .collect();
+        self.tie_within(newcomer.id, &coworkers, conf.workplace_ties, rng);
+    }
+
+    // What a tenant's ties know about vacancies
+    // where they live, and where they live
+    pub fn hearsay(&self, id: TenantId, tenants: &Arena<TenantId, Tenant>, city: &City) -> Hearsay {
+        let mut referrals = Vec::new();
+        let mut neighborhoods = vec![0.; city.neighborhoods.len()];
+        let n_ties = self.friends[id].len() as f32;
+        for &f_id in &self.friends[id] {
+            let unit = match tenants[f_id].unit {
+                Some(u_id) => &city.units[u_id],
+                None => continue,
+            };
+            if let Some(neighb_id) = city.parcels.get(&unit.pos).unwrap().neighborhood {
+                neighborhoods[neighb_id] += 1. / n_ties;
+            }
+            for &u_id in &city.buildings.get(&unit.pos).unwrap().units {
+                if city.has_vacancy(u_id) && !referrals.contains(&u_id) {
+                    referrals.push(u_id);
+                }
+            }
+        }
+        Hearsay {
+            referrals: referrals,
+            neighborhoods: neighborhoods,
+        }
+    }
+
+    pub fn contagion(&self, start_id: TenantId, p: f32, t: f32, max_depth: usize, rng: &mut StdRng) -> FnvHashSet<TenantId> {
         let mut depth = 0;
         let mut nodes = FnvHashSet::default();
         let mut next = FnvHashSet::default();
@@ -40,10 +204,7 @@ impl SocialGraph {
         while fringe.len() > 0 {
             next.clear();
             for id in fringe.drain() {
-                let neighbs = self.graph.neighbors_slice(id as u32);
-                for n in neighbs {
-                    let n = *n as usize;
-
+                for &n in &self.friends[id] {
                     // Don't revisit nodes
                     if !nodes.contains(&n) {
                         // Do the two encounter each other?
@@ -68,3 +229,12 @@ impl SocialGraph {
         nodes
     }
 }
+
+// How many friends a tenant makes, fewer than `limit`
+pub fn n_friends(limit: usize, rng: &mut StdRng) -> usize {
+    rng.gen_range(0, usize::max(1, limit))
+}
+
+fn home_neighborhood(tenant: &Tenant, city: &City) -> Option<usize> {
+    tenant.unit.and_then(|u_id| city.parcels.get(&city.units[u_id].pos).unwrap().neighborhood)
+}
@@ -1,10 +1,41 @@
-use super::agent::AgentType;
+use super::agent::{AgentType, Archetype, Landlord, Tenant};
+use super::city::Unit;
+use super::screening;
 use super::sim::Simulation;
-use serde_json::{json, Value};
-use std::collections::{HashMap, HashSet};
+use fnv::FnvHashMap;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Value at the given percentile (0-1),
+// using the nearest rank. Sorts `vals`
+pub(crate) fn percentile(vals: &mut [f32], p: f32) -> f32 {
+    if vals.is_empty() {
+        return 0.;
+    }
+    vals.sort_by(|a, b| a.total_cmp(b));
+    let idx = ((vals.len() - 1) as f32 * p).round() as usize;
+    vals[idx]
+}
+
+// Gini coefficient, 0 for perfect
+// equality up to 1 for maximal inequality
+fn gini(vals: &mut [f32]) -> f32 {
+    let total: f32 = vals.iter().sum();
+    if vals.is_empty() || total == 0. {
+        return 0.;
+    }
+    vals.sort_by(|a, b| a.total_cmp(b));
+    let n = vals.len() as f32;
+    let weighted = vals
+        .iter()
+        .enumerate()
+        .fold(0., |acc, (i, v)| acc + (i as f32 + 1.) * v);
+    (2. * weighted) / (n * total) - (n + 1.) / n
+}
 
 pub fn init_stats(sim: &Simulation) -> Value {
     let incomes: Vec<f32> = sim.tenants.iter().map(|t| t.income).collect();
+    let savings: Vec<f32> = sim.tenants.iter().map(|t| t.savings).collect();
     let values: Vec<f32> = sim.city.units.iter().map(|u| u.value).collect();
     let rents: Vec<f32> = sim.city.units.iter().map(|u| u.rent).collect();
     let areas: Vec<f32> = sim.city.units.iter().map(|u| u.area).collect();
@@ -12,6 +43,7 @@ pub fn init_stats(sim: &Simulation) -> Value {
     let rents_per_occupancy: Vec<f32> = sim.city.units.iter().map(|u| u.rent/u.occupancy as f32).collect();
     json!({
         "incomes": incomes,
+        "savings": savings,
         "values": values,
         "rents": rents,
         "rents_per_occupancy": rents_per_occupancy,
@@ -21,9 +53,11 @@ pub fn init_stats(sim: &Simulation) -> Value {
 }
 
 pub fn stats(sim: &Simulation) -> Value {
-    let n_units = sim.city.units.len() as f32;
+    let n_units = sim.city.live_units().count() as f32;
     let mut n_housed = 0.;
+    let mut n_overcrowded = 0;
     let mut n_vacant = 0.;
+    let mut n_short_term = 0;
     let mut n_parcels = 0.;
     let mut n_affordable = 0.;
     let mut mean_rent = 0.;
@@ -40,9 +74,100 @@ pub fn stats(sim: &Simulation) -> Value {
     let mut min_value = 1. / 0.;
     let mut mean_desirability = 0.;
     let mut unique_landlords = HashSet::new();
+    // Hashed the same way every run, so
+    // the index sums up in the same order
+    let mut units_by_owner = FnvHashMap::default();
     let mut landlord_data = HashMap::new();
     let mut doma_data = (0., 0.);
-    let mean_income = sim.tenants.iter().fold(0., |acc, t| acc + t.income)/sim.tenants.len() as f32;
+
+    // Tenants who've left the city aren't counted
+    let residents: Vec<&Tenant> = sim.tenants.iter().filter(|t| !t.departed).collect();
+    let population = residents.len();
+    let mean_income = residents.iter().fold(0., |acc, t| acc + t.income)/population as f32;
+    let mut incomes: Vec<f32> = residents.iter().map(|t| t.income).collect();
+    let n_unemployed = residents.iter().filter(|t| !t.employed).count();
+
+    // Commutes of those with a home and a job,
+    // and jobs and workers at each of the design's
+    // employment centers, if it has any
+    let commutes: Vec<f32> = residents.iter()
+        .filter(|t| t.employed)
+        .filter_map(|t| t.unit.map(|u_id| sim.city.commute_times.get(t.work, sim.city.units[u_id].pos)))
+        .collect();
+    let mean_commute_minutes = if commutes.is_empty() { 0. } else {
+        commutes.iter().sum::<f32>() / commutes.len() as f32
+    };
+    let mut employment_centers = BTreeMap::new();
+    if !sim.design.employment_centers.is_empty() {
+        for center in &sim.employment.centers {
+            let workers = residents.iter().filter(|t| t.employed && t.work == center.pos).count();
+            employment_centers.insert(center.name.clone(), json!({
+                "jobs": center.jobs,
+                "workers": workers,
+            }));
+        }
+    }
+    let mut savings: Vec<f32> = residents.iter().map(|t| t.savings).collect();
+    let mean_savings = savings.iter().sum::<f32>()/population as f32;
+    let mut rent_income_ratios = Vec::new();
+    let mut n_rent_burdened = 0;
+    let mut n_severely_rent_burdened = 0;
+
+    let mut evictions_by_neighborhood = HashMap::new();
+    for &(unit_id, _) in &sim.evictions {
+        let unit = &sim.city.units[unit_id];
+        if let Some(neighb_id) = sim.city.parcels.get(&unit.pos).unwrap().neighborhood {
+            *evictions_by_neighborhood.entry(neighb_id).or_insert(0) += 1;
+        }
+    }
+
+    let mut displacements_by_neighborhood = HashMap::new();
+    for &(_, neighb_id) in &sim.displacements {
+        *displacements_by_neighborhood.entry(neighb_id).or_insert(0) += 1;
+    }
+
+    // Point-in-time homeless counts, and how
+    // long those currently homeless have been
+    let homeless: Vec<&&Tenant> = residents.iter().filter(|t| t.unit.is_none()).collect();
+    let n_sheltered = homeless.iter().filter(|t| t.shelter.is_some()).count();
+    let mean_months_homeless = if homeless.is_empty() { 0. } else {
+        homeless.iter().fold(0, |acc, t| acc + t.months_homeless) as f32 / homeless.len() as f32
+    };
+    let shelter_capacity = sim.city.neighborhoods.iter().fold(0, |acc, n| acc + n.shelter_capacity);
+    let mut sheltered_by_neighborhood = HashMap::new();
+    for t in &homeless {
+        if let Some(neighb_id) = t.shelter {
+            *sheltered_by_neighborhood.entry(neighb_id).or_insert(0) += 1;
+        }
+    }
+
+    // Voucher program cost, and holders it keeps
+    // from being rent-burdened, i.e. paying over the
+    // program's share of their income, who otherwise would be
+    let voucher_holders: Vec<&&Tenant> = residents.iter().filter(|t| t.voucher.is_some()).collect();
+    let mut voucher_cost = 0.;
+    let mut n_vouchers_unused = 0;
+    let mut n_displacement_prevented = 0;
+    for t in &voucher_holders {
+        if let Some(u_id) = t.unit {
+            let unit = &sim.city.units[u_id];
+            let rent = t.rent_share(unit);
+            let subsidy = t.voucher_subsidy(unit, rent);
+            if !unit.accepts_vouchers {
+                n_vouchers_unused += 1;
+            }
+            let burdened = t.voucher.map_or(0., |v| v.rent_burden * t.income);
+            if rent > burdened && rent - subsidy <= burdened {
+                n_displacement_prevented += 1;
+            }
+            voucher_cost += subsidy;
+        }
+    }
+    let voucher_cost_per_prevented = if n_displacement_prevented > 0 {
+        voucher_cost/n_displacement_prevented as f32
+    } else {
+        0.
+    };
 
     let mut neighborhood_stats = HashMap::new();
     for (neighb_id, unit_ids) in sim.city.units_by_neighborhood.iter().enumerate() {
@@ -52,14 +177,18 @@ pub fn stats(sim: &Simulation) -> Value {
 
         let mut nei_n_doma = 0;
         let mut nei_n_vacant = 0.;
+        let mut nei_n_short_term = 0;
         let mut nei_n_tenants = 0;
+        let mut nei_n_overcrowded = 0;
         let mut nei_mean_rent = 0.;
         let mut nei_mean_rent_per_area = 0.;
         let mut nei_mean_rent_per_tenant = 0.;
         let mut nei_mean_adjusted_rent_per_area = 0.;
         let mut nei_mean_value_per_area = 0.;
+        let mut nei_mean_est_value_per_area = 0.;
         let mut nei_mean_months_vacant = 0.;
         let mut nei_mean_rent_income_ratio = 0.;
+        let mut nei_rent_income_ratios = Vec::new();
 
         for &unit_id in unit_ids {
             let unit = &sim.city.units[unit_id];
@@ -69,6 +198,7 @@ pub fn stats(sim: &Simulation) -> Value {
             nei_mean_rent_per_area += unit.rent_per_area();
             nei_mean_months_vacant += unit.months_vacant as f32;
             nei_mean_value_per_area += value / unit.area;
+            nei_mean_est_value_per_area += unit.est_value / unit.area;
             mean_value += value;
             mean_condition += unit.condition;
             mean_price_to_rent_ratio += if unit.rent == 0. {
@@ -80,7 +210,11 @@ pub fn stats(sim: &Simulation) -> Value {
                 min_value = value;
             }
 
-            if unit.vacant() {
+            // Short-term rentals are off the housing
+            // market rather than vacant
+            if unit.short_term {
+                nei_n_short_term += 1;
+            } else if unit.vacant() {
                 nei_n_vacant += 1.;
             }
 
@@ -91,6 +225,7 @@ pub fn stats(sim: &Simulation) -> Value {
                 let tenant = &sim.tenants[t_id];
                 rent_discount += tenant.last_dividend;
                 nei_mean_rent_income_ratio += rent_per_tenant / tenant.income;
+                nei_rent_income_ratios.push(rent_per_tenant / tenant.income);
                 nei_mean_rent_per_tenant += rent_per_tenant;
                 if (rent_per_tenant-tenant.last_dividend) / tenant.income <= 0.3 {
                     n_affordable += 1.;
@@ -100,8 +235,12 @@ pub fn stats(sim: &Simulation) -> Value {
             nei_mean_adjusted_rent_per_area += mean_adj_rent_per_area;
             n_housed += unit.tenants.len() as f32;
             nei_n_tenants += unit.tenants.len();
+            if unit.overcrowding(&sim.conf) > 0 {
+                nei_n_overcrowded += unit.tenants.len();
+            }
 
             unique_landlords.insert(unit.owner);
+            *units_by_owner.entry(unit.owner).or_insert(0) += 1;
             match unit.owner.0 {
                 AgentType::Landlord => {
                     let data = landlord_data.entry(unit.owner.1).or_insert((0., 0.));
@@ -124,6 +263,13 @@ pub fn stats(sim: &Simulation) -> Value {
             .iter()
             .fold(0., |acc, pos| acc + sim.city.parcels.get(&pos).unwrap().desirability);
 
+        // How much further units
+        // could be built under zoning
+        let nei_zoned_capacity = parcels.iter().fold(0, |acc, pos| {
+            let n_units = sim.city.buildings.get(pos).map_or(0, |b| b.units.len());
+            acc + sim.city.max_units(pos).saturating_sub(n_units)
+        });
+
         neighborhood_stats.insert(
             neighb_id,
             json!({
@@ -133,16 +279,37 @@ pub fn stats(sim: &Simulation) -> Value {
                 "mean_rent_per_area": nei_mean_rent_per_area/nei_n_units,
                 "mean_adjusted_rent_per_area": nei_mean_adjusted_rent_per_area/nei_n_units,
                 "mean_value_per_area": nei_mean_value_per_area/nei_n_units,
+                "mean_est_value_per_area": nei_mean_est_value_per_area/nei_n_units,
                 "mean_months_vacant": nei_mean_months_vacant/nei_n_units,
                 "mean_rent_income_ratio": if nei_n_tenants > 0 {
                     nei_mean_rent_income_ratio/nei_n_tenants as f32
                 } else { 0. },
+                "median_rent_income_ratio": percentile(&mut nei_rent_income_ratios, 0.5),
                 "mean_desirability": nei_mean_desirability/parcels.len() as f32,
-                "doma_units": nei_n_doma
+                "doma_units": nei_n_doma,
+                "evictions": evictions_by_neighborhood.get(&neighb_id).unwrap_or(&0),
+                "displaced": displacements_by_neighborhood.get(&neighb_id).unwrap_or(&0),
+                "sheltered": sheltered_by_neighborhood.get(&neighb_id).unwrap_or(&0),
+                "shelter_capacity": sim.city.neighborhoods[neighb_id].shelter_capacity,
+                "gentrification_index": sim.gentrification.index[neighb_id],
+                "commercial_occupancy": sim.commerce.occupancy(&sim.city, Some(neighb_id)),
+                "units": unit_ids.len(),
+                "zoned_capacity": nei_zoned_capacity,
+                "short_term_units": nei_n_short_term,
+                "p_overcrowded": if nei_n_tenants > 0 {
+                    nei_n_overcrowded as f32/nei_n_tenants as f32
+                } else { 0. },
+                "p_units_short_term": nei_n_short_term as f32/nei_n_units,
+
+                // Units with room for more tenants,
+                // including partly occupied ones
+                "listings": sim.city.vacant_in(neighb_id).len()
             }),
         );
 
         n_vacant += nei_n_vacant;
+        n_overcrowded += nei_n_overcrowded;
+        n_short_term += nei_n_short_term;
         mean_rent += nei_mean_rent;
         mean_rent_per_tenant += nei_mean_rent_per_tenant;
         mean_rent_per_area += nei_mean_rent_per_area;
@@ -151,8 +318,22 @@ pub fn stats(sim: &Simulation) -> Value {
         mean_months_vacant += nei_mean_months_vacant;
         mean_rent_income_ratio += nei_mean_rent_income_ratio;
         mean_desirability += nei_mean_desirability;
+
+        // Rent burdened tenants pay more than 30% of income
+        // in rent, severely rent burdened more than 50%
+        n_rent_burdened += nei_rent_income_ratios.iter().filter(|&&r| r > 0.3).count();
+        n_severely_rent_burdened += nei_rent_income_ratios.iter().filter(|&&r| r > 0.5).count();
+        rent_income_ratios.extend(nei_rent_income_ratios);
     }
 
+    // How far apart hot and cold parcels are
+    let desirabilities: Vec<f32> = sim.city.residential_parcels_by_neighborhood.iter().flatten()
+        .map(|pos| sim.city.parcels.get(pos).unwrap().desirability)
+        .collect();
+    let desirability_mean = desirabilities.iter().sum::<f32>() / f32::max(1., desirabilities.len() as f32);
+    let desirability_sd = (desirabilities.iter().map(|d| (d - desirability_mean).powi(2)).sum::<f32>()
+        / f32::max(1., desirabilities.len() as f32)).sqrt();
+
     let mut landlord_stats = HashMap::new();
     for landlord in &sim.landlords {
         let data = landlord_data.entry(landlord.id).or_insert((0., 0.));
@@ -163,7 +344,9 @@ pub fn stats(sim: &Simulation) -> Value {
                 "n_units": l_n_units,
                 "p_units": l_n_units/n_units,
                 "mean_condition": data.0/l_n_units,
-                "mean_adjusted_rent_per_area": data.1/l_n_units
+                "mean_adjusted_rent_per_area": data.1/l_n_units,
+                "cash": landlord.cash,
+                "debt": landlord.mortgages.iter().fold(0., |acc, m| acc + m.principal)
             }),
         );
     }
@@ -180,38 +363,270 @@ pub fn stats(sim: &Simulation) -> Value {
         }),
     );
 
+    // Concentration of ownership,
+    // as a Herfindahl-Hirschman index over all owners
+    let ownership_hhi = units_by_owner
+        .values()
+        .fold(0., |acc, &n| acc + (n as f32/n_units).powi(2));
+
+    // Outcomes by how many ties tenants
+    // have to hear of places through
+    let mut by_ties: BTreeMap<&str, Vec<&Tenant>> = BTreeMap::new();
+    for tenant in sim.tenants.iter().filter(|t| !t.departed) {
+        let bucket = match sim.ties.degree(tenant.id) {
+            0 => "0",
+            1..=5 => "1-5",
+            6..=11 => "6-11",
+            _ => "12+",
+        };
+        by_ties.entry(bucket).or_default().push(tenant);
+    }
+    let tie_stats: Map<String, Value> = by_ties.iter().map(|(bucket, tenants)| {
+        let housed: Vec<&&Tenant> = tenants.iter().filter(|t| t.unit.is_some()).collect();
+        let n_housed = f32::max(1., housed.len() as f32);
+        let rent_income_ratio = housed.iter().fold(0., |acc, t| {
+            let unit = &sim.city.units[t.unit.unwrap()];
+            acc + unit.rent / unit.occupancy as f32 / t.income
+        });
+        (bucket.to_string(), json!({
+            "n_tenants": tenants.len(),
+            "percent_homeless": 1. - housed.len() as f32/tenants.len() as f32,
+            "mean_rent_income_ratio": rent_income_ratio/n_housed,
+            "p_referred": housed.iter().filter(|t| t.referred).count() as f32/n_housed,
+        }))
+    }).collect();
+    let n_referred = sim.tenants.iter().filter(|t| !t.departed && t.unit.is_some() && t.referred).count();
+
+    // Outcomes by kind of landlord
+    let mut by_archetype: BTreeMap<Archetype, Vec<&Landlord>> = BTreeMap::new();
+    for landlord in &sim.landlords {
+        by_archetype.entry(landlord.archetype).or_default().push(landlord);
+    }
+    let archetype_stats: Map<String, Value> = by_archetype.iter().map(|(archetype, landlords)| {
+        let units: Vec<&Unit> = landlords.iter()
+            .flat_map(|l| l.units.iter().map(|&u_id| &sim.city.units[u_id]))
+            .collect();
+
+        // Archetypes without units report 0s
+        let n_units = f32::max(1., units.len() as f32);
+        let n_evictions = sim.evictions.iter().filter(|&&(u_id, _)| {
+            match sim.city.units[u_id].owner {
+                (AgentType::Landlord, l_id) => sim.landlords[l_id].archetype == *archetype,
+                _ => false
+            }
+        }).count();
+        (archetype.to_string(), json!({
+            "n_landlords": landlords.len(),
+            "n_units": units.len(),
+            "p_vacant": units.iter().filter(|u| u.vacant()).count() as f32 / n_units,
+            "mean_condition": units.iter().fold(0., |acc, u| acc + u.condition) / n_units,
+            "mean_rent_per_area": units.iter().fold(0., |acc, u| acc + u.rent_per_area()) / n_units,
+            "net_income": landlords.iter().fold(0., |acc, l| acc + l.net_income.values().sum::<f32>()),
+            "n_evictions": n_evictions,
+        }))
+    }).collect();
+
+    // How far below list rent DOMA units come
+    // once dividends are paid back to members, and
+    // how DOMA rents compare to landlords'.
+    // Null if there are no units to compare
+    let doma_units = sim.doma.units.iter().map(|&u_id| &sim.city.units[u_id]);
+    let (doma_mean_rent_discount, doma_rent_per_area) = if n_doma_units > 0. {
+        (
+            Some(doma_units.clone().fold(0., |acc, u| acc + 1. - u.adjusted_rent() / f32::max(1., u.rent)) / n_doma_units),
+            Some(doma_units.fold(0., |acc, u| acc + u.adjusted_rent_per_area()) / n_doma_units),
+        )
+    } else {
+        (None, None)
+    };
+    let (landlord_rent_per_area, n_rented) = sim.city.live_units()
+        .filter(|u| u.owner.0 == AgentType::Landlord)
+        .fold((0., 0), |acc, u| (acc.0 + u.rent_per_area(), acc.1 + 1));
+    let doma_rent_vs_market_rent = doma_rent_per_area.filter(|_| landlord_rent_per_area > 0.)
+        .map(|doma_rent_per_area| doma_rent_per_area / (landlord_rent_per_area / n_rented as f32));
+
+    let n_sales = sim.transfers.len();
+    let mean_sale_price = if n_sales > 0 {
+        sim.transfers.iter().fold(0., |acc, &(_, _, _, amount)| acc + amount)/n_sales as f32
+    } else {
+        0.
+    };
+
+    // Landlord finances
+    let property_tax_revenue = sim.landlords.iter().fold(0., |acc, l| acc + l.taxes_paid);
+
+    // Vacancy tax, and units vacant long enough to owe it
+    let vacancy_tax_revenue = sim.landlords.iter().fold(0., |acc, l| acc + l.vacancy_tax_paid)
+        + sim.speculators.iter().fold(0., |acc, s| acc + s.vacancy_tax_paid);
+    let n_vacancy_taxable = sim.landlords.iter().flat_map(|l| &l.units)
+        .chain(sim.speculators.iter().flat_map(|s| &s.units))
+        .filter(|&&u_id| sim.conf.policies.vacancy_tax(&sim.city.units[u_id], sim.conf.time_step) > 0.)
+        .count();
+    let (total_net_income, n_landlord_units) = sim.landlords.iter().fold((0., 0), |acc, l| {
+        (acc.0 + l.net_income.values().sum::<f32>(), acc.1 + l.net_income.len())
+    });
+    let mean_landlord_net_income = if n_landlord_units > 0 {
+        total_net_income/n_landlord_units as f32
+    } else {
+        0.
+    };
+    let n_units_unprofitable = sim.landlords.iter().fold(0, |acc, l| {
+        acc + l.unprofitable_units(sim.conf.steps(sim.conf.max_unprofitable_months)).len()
+    });
+
+    // Vacancy from speculators holding
+    // units off the rental market
+    let n_speculator_units = sim.speculators.iter().fold(0, |acc, s| acc + s.units.len());
+    let n_speculative_vacant = sim.speculators.iter().fold(0, |acc, s| {
+        acc + s.units.iter().filter(|&&u_id| sim.city.units[u_id].vacant()).count()
+    });
+    let p_vacancy_speculative = if n_vacant > 0. {
+        n_speculative_vacant as f32/n_vacant
+    } else {
+        0.
+    };
+    let mut rents_per_area: Vec<f32> = sim.city.live_units().map(|u| u.rent_per_area()).collect();
+
     json!({
-        "population": sim.tenants.len(),
-        "percent_homeless": 1. - n_housed/sim.tenants.len() as f32,
+        "population": population,
+        "percent_homeless": 1. - n_housed/population as f32,
+
+        // Share of those housed living in overcrowded units
+        "p_overcrowded": if n_housed > 0. { n_overcrowded as f32/n_housed } else { 0. },
         "percent_vacant": n_vacant/n_units,
+        "n_short_term": n_short_term,
+        "p_units_short_term": n_short_term as f32/n_units,
+        "listings": sim.city.vacant_units().len(),
         "percent_affordable": n_affordable/n_housed,
         "n_units": n_units,
         "p_units": 1.,
         "mean_income": mean_income,
+        "median_income": percentile(&mut incomes, 0.5),
+        "income_gini": gini(&mut incomes),
+        "n_homeless": population - n_housed as usize,
+        "n_sheltered": n_sheltered,
+        "n_unsheltered": homeless.len() - n_sheltered,
+        "mean_months_homeless": mean_months_homeless,
+        "shelter_capacity": shelter_capacity,
+        "p_shelter_occupied": if shelter_capacity > 0 { n_sheltered as f32/shelter_capacity as f32 } else { 0. },
+        "p_housed_referred": if n_housed > 0. { n_referred as f32/n_housed } else { 0. },
+        "n_arrivals": sim.arrivals.len(),
+        "n_departures": sim.departures.len(),
+        "interventions": sim.interventions,
+        "median_rent_income_ratio": percentile(&mut rent_income_ratios, 0.5),
+        "p_rent_burdened": if n_housed > 0. { n_rent_burdened as f32/n_housed } else { 0. },
+        "p_severely_rent_burdened": if n_housed > 0. { n_severely_rent_burdened as f32/n_housed } else { 0. },
+        "unemployment_rate": n_unemployed as f32/population as f32,
+        "n_jobs": sim.employment.jobs(),
+        "mean_commute_minutes": mean_commute_minutes,
+        "employment_centers": employment_centers,
+        "interest_rate": sim.economy.interest_rate,
+        "income_growth": sim.economy.income_growth,
+        "migration_rate": sim.economy.migration,
+        "mean_savings": mean_savings,
+        "p10_savings": percentile(&mut savings, 0.1),
+        "median_savings": percentile(&mut savings, 0.5),
+        "p90_savings": percentile(&mut savings, 0.9),
         "mean_rent": mean_rent/n_units,
         "mean_rent_per_tenant": mean_rent_per_tenant/n_housed,
         "mean_rent_per_area": mean_rent_per_area/n_units,
+        "median_rent_per_area": percentile(&mut rents_per_area, 0.5),
         "mean_adjusted_rent_per_area": mean_adjusted_rent_per_area/n_units,
         "mean_months_vacant": mean_months_vacant/n_units,
         "mean_value_per_area": mean_value_per_area/n_units,
         "mean_value": mean_value/n_units,
+        "price_index": sim.market.price_index,
+        "commercial_occupancy": sim.commerce.occupancy(&sim.city, None),
         "min_value": min_value,
         "mean_condition": mean_condition/n_units,
         "mean_price_to_rent_ratio": mean_price_to_rent_ratio/n_units,
         "mean_rent_income_ratio": if n_housed > 0. { mean_rent_income_ratio/n_housed } else { 0. },
         "mean_offers": mean_offers/n_units,
         "unique_landlords": unique_landlords.len(),
+        "ownership_hhi": ownership_hhi,
+        "n_sales": n_sales,
+        "n_evictions": sim.evictions.len(),
+        "n_speculator_lease_endings": sim.lease_endings.len(),
+        "n_displaced": sim.displacements.len(),
+        "n_foreclosures": sim.landlords.iter().fold(0, |acc, l| acc + l.n_foreclosures),
+        "mortgage_debt": sim.landlords.iter().fold(0., |acc, l| {
+            acc + l.mortgages.iter().fold(0., |acc, m| acc + m.principal)
+        }),
+        "property_tax_revenue": property_tax_revenue,
+        "vacancy_tax_rate": sim.conf.policies.vacancy_tax_rate,
+        "vacancy_tax_revenue": vacancy_tax_revenue,
+        "n_vacancy_taxable": n_vacancy_taxable,
+        "voucher_budget": sim.conf.policies.vouchers.as_ref().map(|v| v.budget),
+        "voucher_cost": voucher_cost,
+        "n_vouchers": voucher_holders.len(),
+        "n_vouchers_unused": n_vouchers_unused,
+        "n_displacement_prevented": n_displacement_prevented,
+        "voucher_cost_per_prevented": voucher_cost_per_prevented,
+        "deposits_held": sim.city.live_units().filter_map(|u| u.lease.as_ref()).fold(0., |acc, l| {
+            acc + l.deposits.iter().fold(0., |acc, d| acc + d.amount)
+        }),
+        "mean_landlord_net_income_per_unit": mean_landlord_net_income,
+        "n_units_unprofitable": n_units_unprofitable,
+        "n_units_subdivided": sim.landlords.iter().fold(0, |acc, l| acc + l.n_subdivided),
+        "n_units_merged": sim.landlords.iter().fold(0, |acc, l| acc + l.n_merged),
+        "n_units_retrofitted": sim.landlords.iter().fold(sim.doma.n_retrofitted, |acc, l| acc + l.n_retrofitted),
+        "p_units_retrofitted": sim.city.live_units().filter(|u| u.retrofitted()).count() as f32/n_units,
+        "n_units_built": sim.developers.iter().fold(0, |acc, d| acc + d.n_built),
+        "n_construction_projects": sim.developers.iter().fold(0, |acc, d| acc + d.projects.len()),
+        "n_developer_units": sim.developers.iter().fold(0, |acc, d| acc + d.units.len()),
+        "n_speculator_units": n_speculator_units,
+        "p_units_speculator": n_speculator_units as f32/n_units,
+        "n_speculative_vacant": n_speculative_vacant,
+        "p_vacancy_speculative": p_vacancy_speculative,
+        "n_households": sim.households.households.len(),
+        "mean_household_size": if sim.households.households.is_empty() { 0. } else {
+            sim.households.households.values().fold(0, |acc, h| acc + h.members.len()) as f32
+                / sim.households.households.len() as f32
+        },
+        "n_unions": sim.unions.unions.len(),
+        "n_union_members": sim.unions.unions.values().fold(0, |acc, u| acc + u.members.len()),
+        "n_strikes": sim.unions.n_striking(),
+        "n_rent_negotiated": sim.unions.n_negotiated,
+        "strike_rent_withheld": sim.unions.withheld,
+        "n_rent_capped": sim.landlords.iter().fold(0, |acc, l| acc + l.n_rent_capped),
+        "mean_sale_price": mean_sale_price,
         "doma_members": sim.doma.shares.len(),
-        "doma_members_p": sim.doma.shares.len() as f32/sim.tenants.len() as f32,
+        "doma_members_p": sim.doma.shares.len() as f32/population as f32,
         "doma_raised": sim.doma.raised,
+        "doma_round_raised": sim.doma.round.raised,
         "doma_property_fund": sim.doma.funds,
         "doma_p_dividend": 1.0 - sim.doma.p_reserves - sim.doma.p_expenses,
+        "doma_units": n_doma_units,
+        "p_units_doma": n_doma_units/n_units,
+        "doma_total_dividend_payout": sim.doma.last_payout,
+        "doma_mean_rent_discount": doma_mean_rent_discount,
+        "mean_doma_rent_vs_market_rent": doma_rent_vs_market_rent,
         "mean_desirability": mean_desirability/n_parcels,
-        // 'doma_total_dividend_payout': self.doma.last_payout,
-        // 'n_sales': sum(t.sales for t in self.landlords + self.tenants),
+        "desirability_sd": desirability_sd,
         // 'n_moved': sum(1 for t in self.tenants if t.moved),
-        // 'mean_doma_rent_vs_market_rent': 0 if not landlord_units or not self.doma.units else np.mean([u.adjusted_rent_per_area for u in self.doma.units])/np.mean([u.adjusted_rent_per_area for u in landlord_units]),
         "landlords": landlord_stats,
+        "archetypes": archetype_stats,
+        "ties": tie_stats,
+        "screening": screening::audit(&sim.screened, &sim.tenants, &sim.conf),
         "neighborhoods": neighborhood_stats
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gini_bounds() {
+        assert_eq!(gini(&mut [1., 1., 1., 1.]), 0.);
+        assert!((gini(&mut [0., 0., 0., 1.]) - 0.75).abs() < 1e-6);
+        assert_eq!(gini(&mut []), 0.);
+    }
+
+    // A stray NaN, e.g. from a zero-area unit,
+    // doesn't bring down the whole run
+    #[test]
+    fn gini_with_nan() {
+        gini(&mut [1., f32::NAN, 2.]);
+    }
+}
@@ -0,0 +1,249 @@
+use super::config::Config;
+use super::db::{RunDb, RunInfo};
+use super::design::{load_design_file, validate_or_exit, Design};
+use super::events::EventLog;
+use super::generate::{self, CityParams};
+use super::provenance;
+use super::sim::Simulation;
+use super::stats;
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use serde_yaml::Value as YamlValue;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use tracing::info;
+
+// Runs every combination of parameter values
+// for each seed, without any Redis interaction
+#[derive(Deserialize, Debug)]
+pub struct Sweep {
+    // Path to a design JSON file,
+    // or parameters to generate a city from
+    #[serde(default)]
+    pub design: Option<String>,
+    #[serde(default)]
+    pub generate: Option<CityParams>,
+
+    pub steps: usize,
+    pub seeds: Vec<u64>,
+
+    // Values to try for each parameter. Config keys
+    // are uppercase as in config.yaml (e.g. `MOVING_PENALTY`),
+    // design keys lowercase (e.g. `city.landlords`), and
+    // city generation keys prefixed with `generate.`
+    // (e.g. `generate.density_gradient`).
+    // Nested values are given as dotted paths
+    #[serde(default)]
+    pub params: BTreeMap<String, Vec<YamlValue>>,
+}
+
+pub fn load_sweep(path: &str) -> Sweep {
+    let file = File::open(path).expect("could not open sweep file");
+    let reader = BufReader::new(file);
+    serde_yaml::from_reader(reader).expect("error while reading yaml")
+}
+
+// Every combination of parameter values
+pub(crate) fn combinations(params: &BTreeMap<String, Vec<YamlValue>>) -> Vec<Vec<(String, YamlValue)>> {
+    params.iter().fold(vec![Vec::new()], |combos, (key, vals)| {
+        combos
+            .iter()
+            .flat_map(|combo| {
+                vals.iter().map(move |val| {
+                    let mut combo = combo.clone();
+                    combo.push((key.clone(), val.clone()));
+                    combo
+                })
+            })
+            .collect()
+    })
+}
+
+// Set a value by its key, or for dotted keys,
+// by its path through nested mappings
+fn set_value(root: &mut YamlValue, key: &str, val: &YamlValue) {
+    let mut target = root;
+    for part in key.split('.') {
+        target = match target {
+            YamlValue::Mapping(map) => map
+                .get_mut(&YamlValue::String(part.to_string()))
+                .unwrap_or_else(|| panic!("unknown sweep parameter: {}", key)),
+            _ => panic!("unknown sweep parameter: {}", key),
+        };
+    }
+    *target = val.clone();
+}
+
+// Get a value by its key or dotted path
+pub(crate) fn get_value<'a>(root: &'a YamlValue, key: &str) -> Option<&'a YamlValue> {
+    key.split('.').try_fold(root, |target, part| match target {
+        YamlValue::Mapping(map) => map.get(&YamlValue::String(part.to_string())),
+        _ => None,
+    })
+}
+
+fn apply_params(conf: &Config, design: &Design, params: &[(String, YamlValue)]) -> (Config, Design) {
+    let mut conf_val = serde_yaml::to_value(conf).unwrap();
+    let mut design_val = serde_yaml::to_value(design).unwrap();
+    for (key, val) in params {
+        if key.starts_with("generate.") {
+            continue;
+        } else if key.starts_with(char::is_uppercase) {
+            set_value(&mut conf_val, key, val);
+        } else {
+            set_value(&mut design_val, key, val);
+        }
+    }
+    (
+        serde_yaml::from_value(conf_val).expect("invalid config parameter value"),
+        serde_yaml::from_value(design_val).expect("invalid design parameter value"),
+    )
+}
+
+fn generate_design(city_params: &CityParams, params: &[(String, YamlValue)]) -> Design {
+    let mut val = serde_yaml::to_value(city_params).unwrap();
+    for (key, v) in params {
+        if let Some(key) = key.strip_prefix("generate.") {
+            set_value(&mut val, key, v);
+        }
+    }
+    generate::generate(&serde_yaml::from_value(val).expect("invalid city generation parameter value"))
+}
+
+// The design to vary parameters on, or `None`
+// if each run generates its own
+pub(crate) fn base_design(design: &Option<String>, generate: &Option<CityParams>) -> Option<Design> {
+    match (design, generate) {
+        (Some(path), None) => Some(load_design_file(path)),
+        (None, Some(_)) => None,
+        _ => panic!("sweeps need either a design or parameters to generate one"),
+    }
+}
+
+// The config and design for a combination of parameter values,
+// generating the design if the sweep doesn't have one
+pub(crate) fn setup_run(generate: &Option<CityParams>, conf: &Config, design: &Option<Design>, params: &[(String, YamlValue)]) -> (Config, Design) {
+    match design {
+        Some(design) => apply_params(conf, design, params),
+        None => apply_params(conf, &generate_design(generate.as_ref().unwrap(), params), params),
+    }
+}
+
+// Mean and standard deviation of the numeric
+// top-level stats at the end of each run
+fn summarize(finals: &[&Value]) -> Value {
+    let mut summary = Map::new();
+    if let Some(Value::Object(first)) = finals.first() {
+        for key in first.keys() {
+            let vals: Vec<f64> = finals.iter().filter_map(|f| f[key].as_f64()).collect();
+            if vals.len() != finals.len() {
+                continue;
+            }
+            let n = vals.len() as f64;
+            let mean = vals.iter().sum::<f64>() / n;
+            let var = vals.iter().fold(0., |acc, v| acc + (v - mean).powi(2)) / n;
+            summary.insert(key.clone(), json!({
+                "mean": mean,
+                "std": var.sqrt()
+            }));
+        }
+    }
+    Value::Object(summary)
+}
+
+pub fn run_sweep(sweep: &Sweep, conf: &Config, db: Option<&RunDb>) {
+    let design = base_design(&sweep.design, &sweep.generate);
+    let combos = combinations(&sweep.params);
+    let runs: Vec<(usize, u64)> = (0..combos.len())
+        .flat_map(|i| sweep.seeds.iter().map(move |&seed| (i, seed)))
+        .collect();
+
+    // Check every combination's design
+    // up front, rather than partway through
+    for params in &combos {
+        validate_or_exit(&setup_run(&sweep.generate, conf, &design, params).1);
+    }
+
+    let now: DateTime<Utc> = Utc::now();
+    let dir = format!("runs/sweep_{}", now.format("%Y.%m.%d.%H.%M.%S"));
+    fs::create_dir_all(&dir).unwrap();
+    info!("Running {:?} runs ({:?} combinations x {:?} seeds)...", runs.len(), combos.len(), sweep.seeds.len());
+
+    let finals: Vec<Value> = runs
+        .par_iter()
+        .enumerate()
+        .map(|(i, &(combo_id, seed))| {
+            let params = &combos[combo_id];
+            let (mut run_conf, run_design) = setup_run(&sweep.generate, conf, &design, params);
+            run_conf.seed = seed;
+            run_conf.steps = sweep.steps;
+
+            let mut rng: StdRng = SeedableRng::seed_from_u64(seed);
+            let mut sim = Simulation::new(run_design, run_conf, &mut rng);
+            let init = stats::init_stats(&sim);
+            let param_vals: BTreeMap<&String, &YamlValue> = params.iter().map(|(k, v)| (k, v)).collect();
+            let fname = format!("{}/run_{}.json", dir, i);
+            let db_run = db.map(|db| (db, db.start_run(&RunInfo {
+                kind: "sweep",
+                dir: Some(&fname),
+                params: Some(json!(param_vals)),
+                conf: &sim.conf,
+                init: &init,
+            }).unwrap()));
+
+            let mut history = Vec::with_capacity(sweep.steps);
+            let mut events = EventLog::new();
+            for _ in 0..sweep.steps {
+                sim.step();
+                let step_stats = stats::stats(&sim);
+                if let Some((db, run)) = db_run {
+                    events.record(sim.time, &sim.city.events);
+                    db.record(run, sim.time, &step_stats, events.recent(sim.city.events.len())).unwrap();
+                }
+                history.push(step_stats);
+            }
+            if let Some((db, run)) = db_run {
+                db.finish_run(run, sim.time).unwrap();
+            }
+
+            let results = json!({
+                "params": param_vals,
+                "seed": seed,
+                "init": init,
+                "history": history,
+                "provenance": provenance::provenance(&sim.conf),
+            });
+            fs::write(&fname, results.to_string()).expect("Unable to write file");
+            info!("Finished run {:?} (seed {:?}, params {:?})", i, seed, param_vals);
+            history.pop().unwrap_or(Value::Null)
+        })
+        .collect();
+
+    let summary: Vec<Value> = combos
+        .iter()
+        .enumerate()
+        .map(|(combo_id, params)| {
+            let run_finals: Vec<&Value> = runs
+                .iter()
+                .zip(finals.iter())
+                .filter(|((c, _), _)| *c == combo_id)
+                .map(|(_, f)| f)
+                .collect();
+            let param_vals: BTreeMap<&String, &YamlValue> = params.iter().map(|(k, v)| (k, v)).collect();
+            json!({
+                "params": param_vals,
+                "seeds": sweep.seeds,
+                "final": summarize(&run_finals)
+            })
+        })
+        .collect();
+    let fname = format!("{}/summary.json", dir);
+    fs::write(fname, Value::Array(summary).to_string()).expect("Unable to write file");
+    info!("Wrote sweep output to {:?}", dir);
+}
@@ -1,14 +1,54 @@
+use super::agent::{Landlord, Tenant};
+use super::arena::{Arena, TenantId, UnitId};
+use super::backend::{SyncBackend, SyncConfig, SyncResult};
 use super::city::City;
 use super::design::Design;
+use super::events::EventLog;
+use super::profile::Profiler;
+use fnv::FnvHashSet;
 use md5::Digest;
-use redis::Commands;
-use serde_json::{json, Value};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
+use tracing::info_span;
 
-pub fn jsonify(month: usize, city: &City, design: &Design, stats: Value) -> Value {
+// Owners shown on the spectator leaderboard
+static LEADERBOARD_SIZE: usize = 10;
+
+// How payloads for the frontend are encoded
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Json,
+    Msgpack,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Vec<u8> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).unwrap(),
+            Encoding::Msgpack => rmp_serde::to_vec_named(value).unwrap(),
+            Encoding::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::ser::into_writer(value, &mut encoded).unwrap();
+                encoded
+            }
+        }
+    }
+}
+
+// Tell the frontend how to decode payloads.
+// This is always JSON, so it can be read first
+pub fn set_meta(backend: &dyn SyncBackend, conf: &SyncConfig) -> SyncResult<()> {
+    let meta = json!({ "encoding": conf.encoding });
+    backend.set("meta", meta.to_string().as_bytes())
+}
+
+pub fn jsonify(month: usize, city: &City, tenants: &Arena<TenantId, Tenant>, design: &Design, stats: Value) -> Value {
     let mut parcels: HashMap<isize, HashMap<isize, Value>> = HashMap::new();
     let mut buildings: HashMap<String, Value> = HashMap::new();
-    let mut units: HashMap<usize, Value> = HashMap::new();
+    let mut units: HashMap<UnitId, Value> = HashMap::new();
 
     for (pos, parcel) in city.parcels.iter() {
         let g = parcels.entry(pos.0).or_insert(HashMap::new());
@@ -20,7 +60,9 @@ pub fn jsonify(month: usize, city: &City, design: &Design, stats: Value) -> Valu
                     None => -1
                 },
                 "type": parcel.typ.to_string(),
-                "desirability": parcel.desirability
+                "desirability": parcel.desirability,
+                "amenities": parcel.amenities,
+                "zoning": parcel.zoning
             }),
         );
         match &city.buildings.get(&pos) {
@@ -42,14 +84,19 @@ pub fn jsonify(month: usize, city: &City, design: &Design, stats: Value) -> Valu
                             "id": u,
                             "rent": unit.rent,
                             "tenants": unit.tenants.len(),
+                            "households": unit.tenants.iter()
+                                .filter_map(|&t_id| tenants[t_id].household)
+                                .collect::<FnvHashSet<usize>>(),
                             "condition": unit.condition,
+                            "yearBuilt": unit.year_built,
                             "spaciousness": unit.area/unit.occupancy as f32,
                             "occupancy": unit.occupancy,
                             "owner": json!({
                                 "id": unit.owner.1,
                                 "type": unit.owner.0.to_string()
                             }),
-                            "monthsVacant": unit.months_vacant
+                            "monthsVacant": unit.months_vacant,
+                            "shortTerm": unit.short_term
                         }),
                     );
                 }
@@ -61,6 +108,7 @@ pub fn jsonify(month: usize, city: &City, design: &Design, stats: Value) -> Valu
         "time": month,
         "name": design.city.name,
         "map": {
+            "version": city.map_version,
             "rows": city.grid.rows,
             "cols": city.grid.cols,
             "parcels": parcels,
@@ -68,20 +116,183 @@ pub fn jsonify(month: usize, city: &City, design: &Design, stats: Value) -> Valu
         },
         "buildings": buildings,
         "neighborhoods": design.neighborhoods,
+        "amenities": design.amenities,
         "units": units,
         "stats": stats,
     })
 }
 
-pub fn sync(month: usize, city: &City, design: &Design, stats: Value) -> redis::RedisResult<()> {
-    let client = redis::Client::open("redis://127.0.0.1/1")?;
-    let con = client.get_connection()?;
+// Sends the state to the frontend as periodic full keyframes,
+// with only what changed since the previous sync in between.
+// Changes are JSON merge patches (RFC 7386): changed fields
+// are set and removed fields are null. Each sync has a version,
+// and each delta the version it applies to, so clients that
+// miss one can tell and request a resync.
+// The latest full state is always kept too
+pub struct Syncer {
+    version: usize,
+    encoding: Encoding,
+    keyframe_interval: usize,
+    event_feed_size: usize,
+    since_keyframe: usize,
+    last: Option<Value>,
+
+    // Time spent in the last sync
+    profiler: Profiler,
+}
+
+impl Syncer {
+    pub fn new(conf: &SyncConfig) -> Syncer {
+        Syncer {
+            version: 0,
+            encoding: conf.encoding,
+            keyframe_interval: conf.keyframe_interval,
+            event_feed_size: conf.event_feed_size,
+            since_keyframe: 0,
+            last: None,
+            profiler: Profiler::default(),
+        }
+    }
+
+    // Send the full state next sync
+    pub fn request_keyframe(&mut self) {
+        self.last = None;
+    }
+
+    pub fn sync(&mut self, backend: &dyn SyncBackend, month: usize, city: &City, tenants: &Arena<TenantId, Tenant>, design: &Design, stats: Value) -> SyncResult<()> {
+        let _span = info_span!("sync", month = month, version = self.version + 1).entered();
+        self.profiler.start();
+        let state = jsonify(month, city, tenants, design, stats);
+        self.version += 1;
+        let mut snapshot = state.clone();
+        snapshot["version"] = json!(self.version);
+        let snapshot = self.encoding.encode(&snapshot);
+        match &self.last {
+            Some(last) if self.since_keyframe < self.keyframe_interval => {
+                let mut delta = diff(last, &state).unwrap_or_else(|| json!({}));
+                delta["version"] = json!(self.version);
+                delta["base"] = json!(self.version - 1);
+                let delta = self.encoding.encode(&delta);
+                self.profiler.lap("sync_serialize");
+                backend.lpush("state:deltas", &delta)?;
+                self.since_keyframe += 1;
+            }
+            _ => {
+                let hash = md5::Md5::digest(&snapshot);
+                self.profiler.lap("sync_serialize");
+                backend.set("state", &snapshot)?;
+                backend.set("state:key", format!("{:X}", hash).as_bytes())?;
+                backend.del("state:deltas")?;
+                self.since_keyframe = 0;
+            }
+        }
+
+        // The full state as of every sync, so late
+        // joiners and spectators needn't wait for
+        // a keyframe or replay deltas
+        backend.set("state:snapshot", &snapshot)?;
+        backend.set("state:version", self.version.to_string().as_bytes())?;
+        self.profiler.lap("sync_writes");
+        self.last = Some(state);
+
+        Ok(())
+    }
+
+    pub fn timings(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    // The latest events, for the activity feed,
+    // and every message for players so far
+    pub fn sync_events(&self, backend: &dyn SyncBackend, events: &EventLog) -> SyncResult<()> {
+        backend.set("events", &self.encoding.encode(&events.recent(self.event_feed_size)))?;
+        backend.set("narration", &self.encoding.encode(&events.narration()))
+    }
 
-    let state_serialized = jsonify(month, city, design, stats).to_string();
-    let hash = md5::Md5::digest(state_serialized.as_bytes());
+    // A read-only view for projectors and observers,
+    // from the last synced state: citywide and neighborhood
+    // stats, the biggest owners, and the event feed.
+    // It leaves out what only a player should see,
+    // like landlords' finances
+    pub fn sync_spectator(&self, backend: &dyn SyncBackend, landlords: &[Landlord], events: &EventLog) -> SyncResult<()> {
+        let state = match &self.last {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let stats = &state["stats"];
+        let citywide: Map<String, Value> = stats.as_object().into_iter().flatten()
+            .filter(|(_, val)| !val.is_object())
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
 
-    con.set("state", state_serialized)?;
-    con.set("state:key", format!("{:X}", hash))?;
+        // DOMA is listed under id -1
+        let mut owners: Vec<Value> = stats["landlords"].as_object().into_iter().flatten()
+            .filter_map(|(id, owner)| {
+                let id: isize = id.parse().ok()?;
+                let player = id >= 0 && landlords.get(id as usize).is_some_and(|l| l.player);
+                Some(json!({
+                    "id": id,
+                    "type": if id < 0 { "DOMA" } else { "Landlord" },
+                    "player": player,
+                    "n_units": owner["n_units"],
+                    "p_units": owner["p_units"],
+                    "mean_condition": owner["mean_condition"],
+                }))
+            })
+            .collect();
+        owners.sort_by(|a, b| {
+            let n_units = |owner: &Value| owner["n_units"].as_f64().unwrap_or(0.);
+            n_units(b).partial_cmp(&n_units(a)).unwrap()
+        });
+        owners.truncate(LEADERBOARD_SIZE);
+
+        let view = json!({
+            "time": state["time"],
+            "name": state["name"],
+            "version": self.version,
+            "stats": citywide,
+            "neighborhoods": state["neighborhoods"],
+            "neighborhood_stats": stats["neighborhoods"],
+            "leaderboard": owners,
+            "events": events.recent(self.event_feed_size),
+        });
+        backend.set("spectator", &self.encoding.encode(&view))
+    }
+}
 
-    Ok(())
+// Merge patch from `prev` to `next`, if they differ.
+// Objects are compared field by field; anything
+// else is replaced whole
+fn diff(prev: &Value, next: &Value) -> Option<Value> {
+    match (prev, next) {
+        (Value::Object(prev), Value::Object(next)) => {
+            let mut patch = Map::new();
+            for (key, next_val) in next {
+                let changed = match prev.get(key) {
+                    Some(prev_val) => diff(prev_val, next_val),
+                    None => Some(next_val.clone()),
+                };
+                if let Some(change) = changed {
+                    patch.insert(key.clone(), change);
+                }
+            }
+            for key in prev.keys() {
+                if !next.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            if patch.is_empty() {
+                None
+            } else {
+                Some(Value::Object(patch))
+            }
+        }
+        _ => {
+            if prev == next {
+                None
+            } else {
+                Some(next.clone())
+            }
+        }
+    }
 }
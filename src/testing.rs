@@ -0,0 +1,101 @@
+#[cfg(feature = "play")]
+use super::backend::{SyncBackend, SyncError, SyncResult};
+use super::config::{self, Config};
+use super::design::Design;
+use super::generate;
+use super::sim::Simulation;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+#[cfg(feature = "play")]
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "play")]
+use std::collections::{HashMap, VecDeque};
+
+// A small generated design
+pub fn design() -> Design {
+    let params = generate::load_params("benches/fixtures/small.yaml");
+    generate::generate(&params)
+}
+
+// The default config, seeded
+// so tests are reproducible
+pub fn config() -> Config {
+    let mut conf = config::load_config("config.yaml");
+    conf.seed = 0;
+    conf
+}
+
+// A small city with the given config
+pub fn simulation(conf: Config) -> Simulation {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(conf.seed);
+    Simulation::new(design(), conf, &mut rng)
+}
+
+// A small city with the default config
+pub fn fixture() -> Simulation {
+    simulation(config())
+}
+
+// Keeps synced state in memory, and can be taken
+// down to act as if Redis were disconnected: reads
+// fail, and writes go through as if buffered
+#[cfg(feature = "play")]
+#[derive(Default)]
+pub struct MemoryBackend {
+    pub down: Cell<bool>,
+    values: RefCell<HashMap<String, String>>,
+    lists: RefCell<HashMap<String, VecDeque<String>>>,
+    hashes: RefCell<HashMap<String, HashMap<String, String>>>,
+}
+
+#[cfg(feature = "play")]
+impl MemoryBackend {
+    fn check(&self) -> SyncResult<()> {
+        match self.down.get() {
+            true => Err(SyncError::Disconnected),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "play")]
+impl SyncBackend for MemoryBackend {
+    fn get(&self, key: &str) -> SyncResult<Option<String>> {
+        self.check()?;
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.values.borrow_mut().insert(key.to_string(), String::from_utf8_lossy(value).into_owned());
+        Ok(())
+    }
+
+    fn del(&self, key: &str) -> SyncResult<()> {
+        self.values.borrow_mut().remove(key);
+        self.lists.borrow_mut().remove(key);
+        self.hashes.borrow_mut().remove(key);
+        Ok(())
+    }
+
+    fn lpush(&self, key: &str, value: &[u8]) -> SyncResult<()> {
+        self.lists.borrow_mut().entry(key.to_string()).or_default()
+            .push_front(String::from_utf8_lossy(value).into_owned());
+        Ok(())
+    }
+
+    fn lpop(&self, key: &str) -> SyncResult<Option<String>> {
+        self.check()?;
+        Ok(self.lists.borrow_mut().get_mut(key).and_then(|list| list.pop_front()))
+    }
+
+    fn hset(&self, key: &str, field: &str, value: &[u8]) -> SyncResult<()> {
+        self.hashes.borrow_mut().entry(key.to_string()).or_default()
+            .insert(field.to_string(), String::from_utf8_lossy(value).into_owned());
+        Ok(())
+    }
+
+    fn hgetall(&self, key: &str) -> SyncResult<HashMap<String, String>> {
+        self.check()?;
+        Ok(self.hashes.borrow().get(key).cloned().unwrap_or_default())
+    }
+}
@@ -0,0 +1,130 @@
+use super::arena::{Arena, ParcelId};
+use super::config::Config;
+use super::design::TransitLine;
+use super::grid::{HexGrid, Position};
+use petgraph::algo::dijkstra;
+use petgraph::graph::{Graph, NodeIndex};
+use rayon::prelude::*;
+
+// Travel times (in minutes) from each work
+// location to every position on the grid,
+// walking between adjacent passable parcels
+// and riding transit lines.
+// Looked up for every tenant and unit they
+// consider, so kept by parcel id rather than hashed
+#[derive(Default)]
+pub struct CommuteTimes {
+    cols: usize,
+    walk_minutes: f32,
+    times: Vec<Arena<ParcelId, f32>>,
+
+    // Which of `times` is for each
+    // position, if it's a work location
+    work: Arena<ParcelId, Option<usize>>,
+
+    // From each position to
+    // its closest work location
+    nearest: Arena<ParcelId, f32>,
+}
+
+impl CommuteTimes {
+    pub fn new<F>(grid: &HexGrid, lines: &[TransitLine], work_positions: &[Position], passable: F, conf: &Config) -> CommuteTimes
+        where F: Fn(Position) -> bool {
+        let index = |pos: Position| pos.0 as usize * grid.cols + pos.1 as usize;
+
+        // The first nodes are the grid positions,
+        // in the same order as their indices
+        let mut graph: Graph<(), f32> = Graph::new();
+        for _ in 0..grid.rows * grid.cols {
+            graph.add_node(());
+        }
+        for r in 0..grid.rows {
+            for c in 0..grid.cols {
+                let pos = (r as isize, c as isize);
+                if !passable(pos) {
+                    continue;
+                }
+                for adj in grid.adjacent(pos).into_iter().filter(|&adj| passable(adj)) {
+                    graph.add_edge(NodeIndex::new(index(pos)), NodeIndex::new(index(adj)), conf.walk_minutes);
+                }
+            }
+        }
+
+        // Each stop gets its own node so that
+        // boarding costs a wait but staying on doesn't
+        for line in lines {
+            let stops: Vec<NodeIndex> = line.stops.iter().map(|&(r, c)| {
+                let cell = NodeIndex::new(index((r as isize, c as isize)));
+                let stop = graph.add_node(());
+                graph.add_edge(cell, stop, conf.transit_wait_minutes);
+                graph.add_edge(stop, cell, 0.);
+                stop
+            }).collect();
+            for (i, pair) in stops.windows(2).enumerate() {
+                let (a, b) = (line.stops[i], line.stops[i + 1]);
+                let dist = grid.distance((a.0 as isize, a.1 as isize), (b.0 as isize, b.1 as isize));
+                let minutes = dist * conf.transit_minutes;
+                graph.add_edge(pair[0], pair[1], minutes);
+                graph.add_edge(pair[1], pair[0], minutes);
+            }
+        }
+
+        // Every trip waits once per boarding either way,
+        // so searching out from each work location
+        // gives the commute from every position
+        let n_cells = grid.rows * grid.cols;
+        let times: Vec<Arena<ParcelId, f32>> = work_positions.par_iter().map(|&pos| {
+            let scores = dijkstra(&graph, NodeIndex::new(index(pos)), None, |e| *e.weight());
+            (0..n_cells)
+                .map(|i| *scores.get(&NodeIndex::new(i)).unwrap_or(&f32::INFINITY))
+                .collect()
+        }).collect();
+        let mut work: Arena<ParcelId, Option<usize>> = vec![None; n_cells].into();
+        for (i, &pos) in work_positions.iter().enumerate() {
+            work[grid.parcel_id(pos)] = Some(i);
+        }
+        let nearest = (0..n_cells).map(ParcelId)
+            .map(|id| times.iter().map(|t| t[id]).fold(f32::INFINITY, f32::min))
+            .collect();
+
+        CommuteTimes {
+            cols: grid.cols,
+            walk_minutes: conf.walk_minutes,
+            times: times,
+            work: work,
+            nearest: nearest,
+        }
+    }
+
+    fn parcel_id(&self, pos: Position) -> ParcelId {
+        ParcelId(pos.0 as usize * self.cols + pos.1 as usize)
+    }
+
+    pub fn get(&self, work: Position, home: Position) -> f32 {
+        match self.work.get(self.parcel_id(work)).cloned().flatten() {
+            Some(i) => self.times[i][self.parcel_id(home)],
+
+            // Not a cached work location,
+            // so assume walking the whole way
+            None => (((work.0 - home.0).pow(2) + (work.1 - home.1).pow(2)) as f32).sqrt() * self.walk_minutes
+        }
+    }
+
+    // Commute from a position to the closest
+    // job, infinite if there's no way there
+    pub fn nearest_work(&self, home: Position) -> f32 {
+        self.nearest.get(self.parcel_id(home)).cloned().unwrap_or(f32::INFINITY)
+    }
+
+    // How easy the commute is, from 1 for
+    // no commute to nearly 0 for a very long one.
+    // Scaled so walking to an adjacent parcel is 1
+    pub fn ease(&self, work: Position, home: Position) -> f32 {
+        let minutes = self.get(work, home);
+        if minutes <= self.walk_minutes {
+            1.
+        } else {
+            self.walk_minutes / minutes
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use super::agent::{AgentType, Landlord, Tenant};
+use super::arena::{Arena, TenantId, UnitId};
+use super::city::City;
+use super::config::Config;
+use super::events::SimEvent;
+use super::grid::Position;
+use fnv::FnvHashMap;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct UnionConfig {
+    // Tenants of a building organize when
+    // a rent there is raised by more than this
+    // multiple, e.g. 1.1, if at least
+    // `MIN_MEMBERS` of them live there
+    pub hike_threshold: f32,
+    pub min_members: usize,
+
+    // Most a union accepts rents going up in
+    // a year, e.g. 1.03, prorated to the lease
+    pub max_increase: f32,
+
+    // Share of the landlord's rent in the building
+    // withheld during a strike, and for how long
+    pub strike_share: f32,
+    pub strike_months: usize,
+
+    // Neighbors a player organizing their
+    // building recruits per turn
+    pub recruits_per_turn: usize,
+
+    // Extra months members of a union that
+    // bargains can be behind on rent
+    // before they're evicted
+    pub eviction_protection_months: usize,
+}
+
+// A strike against one of the
+// building's landlords
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Strike {
+    pub landlord: usize,
+
+    // Including this step
+    pub steps_left: usize,
+}
+
+// A building's tenant union. Members who
+// move out leave it. It bargains only once it
+// has at least `MIN_MEMBERS`; until then it's
+// an association still organizing
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Union {
+    pub formed: usize,
+    pub members: Vec<TenantId>,
+    pub strike: Option<Strike>,
+}
+
+impl Union {
+    pub fn bargains(&self, conf: &UnionConfig) -> bool {
+        self.members.len() >= conf.min_members
+    }
+}
+
+// Tenant unions, by building. A union bargains
+// over rent raised past what it accepts: landlords
+// back down if holding out would cost them more
+// to a strike than the raise brings in
+#[derive(Serialize, Deserialize, Default)]
+pub struct Unions {
+    pub unions: BTreeMap<Position, Union>,
+
+    // Raises negotiated down this step,
+    // and rent withheld by strikes
+    pub n_negotiated: usize,
+    pub withheld: f32,
+}
+
+impl Unions {
+    pub fn new() -> Unions {
+        Unions::default()
+    }
+
+    pub fn n_striking(&self) -> usize {
+        self.unions.values().filter(|u| u.strike.is_some()).count()
+    }
+
+    // Organize a building's tenants, if there
+    // are enough of them and they aren't already.
+    // Returns whether a union formed
+    pub fn form(&mut self, pos: Position, city: &mut City, time: usize, conf: &UnionConfig) -> bool {
+        let members = building_tenants(pos, city);
+        if members.len() < conf.min_members || self.unions.contains_key(&pos) {
+            return false;
+        }
+        city.events.push(SimEvent::UnionFormed {
+            building: pos,
+            members: members.len(),
+        });
+        self.unions.insert(pos, Union {
+            formed: time,
+            members: members,
+            strike: None,
+        });
+        true
+    }
+
+    // A tenant recruiting neighbors into their building's
+    // association, starting one if there isn't one yet.
+    // Returns how many joined
+    pub fn organize(&mut self, organizer: TenantId, tenants: &Arena<TenantId, Tenant>, city: &mut City, time: usize, rng: &mut StdRng, conf: &UnionConfig) -> usize {
+        let pos = match tenants[organizer].unit {
+            Some(u_id) => city.units[u_id].pos,
+            None => return 0,
+        };
+        let union = self.unions.entry(pos).or_insert_with(|| Union {
+            formed: time,
+            members: vec![organizer],
+            strike: None,
+        });
+        if !union.members.contains(&organizer) {
+            union.members.push(organizer);
+        }
+        let was_bargaining = union.bargains(conf);
+        let neighbors: Vec<TenantId> = building_tenants(pos, city).into_iter()
+            .filter(|t_id| !union.members.contains(t_id))
+            .collect();
+        let recruits: Vec<TenantId> = neighbors.choose_multiple(rng, conf.recruits_per_turn).cloned().collect();
+        union.members.extend(&recruits);
+        if !was_bargaining && union.bargains(conf) {
+            city.events.push(SimEvent::UnionFormed {
+                building: pos,
+                members: union.members.len(),
+            });
+        }
+        recruits.len()
+    }
+
+    // Members of a union that bargains
+    // have longer to catch up on rent
+    pub fn protects(&self, pos: Position, tenant: TenantId, conf: &UnionConfig) -> bool {
+        self.unions.get(&pos).is_some_and(|union| union.bargains(conf) && union.members.contains(&tenant))
+    }
+
+    // End strikes that have run their course, respond to
+    // this step's rent raises, then withhold rent wherever
+    // tenants are striking
+    pub fn update(&mut self, city: &mut City, landlords: &mut [Landlord], tenants: &mut Arena<TenantId, Tenant>, time: usize, conf: &Config) {
+        let union_conf = match &conf.unions {
+            Some(union_conf) => union_conf,
+            None => return,
+        };
+        for (&pos, union) in &mut self.unions {
+            if let Some(strike) = &mut union.strike {
+                strike.steps_left = strike.steps_left.saturating_sub(1);
+                if strike.steps_left == 0 {
+                    city.events.push(SimEvent::StrikeEnded {
+                        building: pos,
+                        landlord: strike.landlord,
+                    });
+                    union.strike = None;
+                }
+            }
+        }
+
+        let steps_per_year = conf.time_step.steps_per_year() as f32;
+        let raises: Vec<(UnitId, f32, f32)> = city.events.iter().filter_map(|event| match event {
+            SimEvent::RentRaised { unit, from, to } => Some((*unit, *from, *to)),
+            _ => None,
+        }).collect();
+
+        // Raises past what unions accept, by building and landlord:
+        // (unit id, raised rent, accepted rent)
+        let mut disputed: FnvHashMap<(Position, usize), Vec<_>> = FnvHashMap::default();
+        for &(u_id, from, to) in &raises {
+            let unit = &city.units[u_id];
+            let landlord_id = match unit.owner {
+                (AgentType::Landlord, id) => id,
+                _ => continue,
+            };
+            match self.unions.get(&unit.pos) {
+                Some(union) if union.strike.is_none() && union.bargains(union_conf) => {},
+                _ => continue,
+            }
+            let lease_steps = unit.lease.as_ref().map_or(1, |lease| lease.period(time));
+            let accepted = from * union_conf.max_increase.powf(lease_steps as f32 / steps_per_year);
+            if to > accepted {
+                disputed.entry((unit.pos, landlord_id)).or_default().push((u_id, to, accepted));
+            }
+        }
+
+        // Landlords weigh what their raises bring in
+        // over the lease against rent lost to a strike
+        self.n_negotiated = 0;
+        for ((pos, landlord_id), raised) in disputed {
+            let forgone = raised.iter().fold(0., |acc, &(u_id, to, accepted)| {
+                let lease_steps = city.units[u_id].lease.as_ref().map_or(1, |lease| lease.period(time));
+                acc + (to - accepted) * lease_steps as f32 * conf.time_step.months()
+            });
+            let strike_cost = union_conf.strike_share
+                * landlord_rent(pos, landlord_id, city)
+                * union_conf.strike_months as f32;
+            if forgone <= strike_cost {
+                for (u_id, to, accepted) in raised {
+                    city.units[u_id].set_rent(accepted);
+                    self.n_negotiated += 1;
+                    city.events.push(SimEvent::RentNegotiated {
+                        unit: u_id,
+                        from: to,
+                        to: accepted,
+                    });
+                }
+            } else if let Some(union) = self.unions.get_mut(&pos) {
+                union.strike = Some(Strike {
+                    landlord: landlord_id,
+                    steps_left: conf.steps(union_conf.strike_months),
+                });
+                city.events.push(SimEvent::StrikeStarted {
+                    building: pos,
+                    landlord: landlord_id,
+                });
+            }
+        }
+
+        // Steep raises in buildings without
+        // a union get tenants organizing
+        for &(u_id, from, to) in &raises {
+            if to / from > union_conf.hike_threshold {
+                self.form(city.units[u_id].pos, city, time, union_conf);
+            }
+        }
+
+        // Striking tenants keep the rent they
+        // withhold, split as they split the rent
+        self.withheld = 0.;
+        for (&pos, union) in &self.unions {
+            let strike = match &union.strike {
+                Some(strike) => strike,
+                None => continue,
+            };
+            let building = match city.buildings.get(&pos) {
+                Some(building) => building,
+                None => continue,
+            };
+            for &u_id in &building.units {
+                let unit = &city.units[u_id];
+                if unit.owner != (AgentType::Landlord, strike.landlord) || unit.vacant() || unit.short_term {
+                    continue;
+                }
+                let withheld = union_conf.strike_share * unit.rent * conf.time_step.months();
+                let weights = unit.tenants.iter().fold(0., |acc, &t_id| acc + tenants[t_id].rent_weight);
+                for &t_id in &unit.tenants {
+                    tenants[t_id].savings += withheld * tenants[t_id].rent_weight / weights;
+                }
+                landlords[strike.landlord].cash -= withheld;
+                self.withheld += withheld;
+            }
+        }
+
+        // Unions don't outlast their members
+        self.unions.retain(|&pos, union| {
+            union.members.retain(|&t_id| {
+                tenants[t_id].unit.is_some_and(|u_id| city.units[u_id].pos == pos)
+            });
+            !union.members.is_empty()
+        });
+    }
+}
+
+fn building_tenants(pos: Position, city: &City) -> Vec<TenantId> {
+    city.buildings.get(&pos).map_or(Vec::new(), |building| {
+        building.units.iter().flat_map(|&u_id| city.units[u_id].tenants.iter().cloned()).collect()
+    })
+}
+
+// Monthly rent a landlord collects in a building
+fn landlord_rent(pos: Position, landlord_id: usize, city: &City) -> f32 {
+    city.buildings.get(&pos).map_or(0., |building| {
+        building.units.iter()
+            .map(|&u_id| &city.units[u_id])
+            .filter(|unit| unit.owner == (AgentType::Landlord, landlord_id) && !unit.vacant() && !unit.short_term)
+            .map(|unit| unit.rent)
+            .sum()
+    })
+}